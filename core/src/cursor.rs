@@ -0,0 +1,24 @@
+//! Opaque pagination cursors. Clients only ever hand back what a listing gave them, so the
+//! position is serialized as JSON and base64 encoded to keep it URL safe and unparsed.
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("invalid cursor {0:?}")]
+pub struct CursorError(pub String);
+
+pub fn encode<T: Serialize>(position: &T) -> String {
+    let json = serde_json::to_vec(position).expect("cursor positions serialize to JSON");
+    URL_SAFE_NO_PAD.encode(json)
+}
+
+pub fn decode<T: DeserializeOwned>(cursor: &str) -> Result<T, CursorError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| CursorError(cursor.to_string()))
+}
@@ -0,0 +1,192 @@
+//! Received mail the inbox couldn't deliver, kept along with its SES record until
+//! `inbox reprocess` runs it through ingestion again. The raw message itself stays where SES put
+//! it in `MAIL_BUCKET`.
+use crate::parse::{addresses, format_addresses};
+use aws_lambda_events::ses::{
+    SimpleEmailCommonHeaders, SimpleEmailHeader, SimpleEmailMessage, SimpleEmailReceipt,
+    SimpleEmailReceiptAction, SimpleEmailRecord, SimpleEmailService, SimpleEmailVerdict,
+};
+use chrono::{DateTime, Utc};
+use mail_parser::{HeaderValue, Message};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DeadLetterError {
+    #[error("dead letter storage error: {0}")]
+    Storage(#[from] std::io::Error),
+    #[error("malformed dead letter: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    /// The record as SES handed it over, reprocessing delivers it to all of its recipients
+    /// again and the ones that have it already skip it.
+    pub record: SimpleEmailRecord,
+    /// Mailboxes it didn't make it into.
+    pub mailboxes: Vec<String>,
+    pub error: String,
+    /// Unix timestamp in seconds.
+    pub failed_at: i64,
+}
+
+impl DeadLetter {
+    /// Key of the raw message in `MAIL_BUCKET`.
+    pub fn message_id(&self) -> &str {
+        self.record
+            .ses
+            .mail
+            .message_id
+            .as_deref()
+            .unwrap_or_default()
+    }
+}
+
+/// Dead letters as JSON files in a local directory, named after their message id. Stands in for
+/// the queue when there's none, e.g. when running the inbox locally.
+#[derive(Debug, Clone)]
+pub struct DirQueue {
+    dir: PathBuf,
+}
+
+impl DirQueue {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DirQueue { dir: dir.into() }
+    }
+
+    fn path(&self, message_id: &str) -> PathBuf {
+        // message ids come from SES, but don't let one walk out of the directory
+        let name = message_id.replace(['/', '\\'], "_");
+        self.dir.join(format!("{name}.json"))
+    }
+
+    /// Keeps `letter`, replacing an earlier one for the same message.
+    pub fn push(&self, letter: &DeadLetter) -> Result<(), DeadLetterError> {
+        std::fs::create_dir_all(&self.dir)?;
+        let json = serde_json::to_vec_pretty(letter)?;
+        std::fs::write(self.path(letter.message_id()), json)?;
+        Ok(())
+    }
+
+    /// Every dead letter, oldest failure first. Nothing when the directory doesn't exist yet.
+    pub fn list(&self) -> Result<Vec<DeadLetter>, DeadLetterError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut letters = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                letters.push(serde_json::from_slice::<DeadLetter>(&std::fs::read(path)?)?);
+            }
+        }
+        letters.sort_by_key(|letter| letter.failed_at);
+        Ok(letters)
+    }
+
+    /// Forgets the dead letter of `message_id`, if there is one.
+    pub fn remove(&self, message_id: &str) -> Result<(), DeadLetterError> {
+        match std::fs::remove_file(self.path(message_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// An SES record for the raw message stored under `message_id`, for reprocessing mail that has
+/// no dead letter. SES only tells the Lambda who the mail was for, so the `recipients` it's
+/// delivered to have to be picked by the caller, see [`header_recipients`]. The verdicts are
+/// left empty, the mail passed them when SES accepted it.
+pub fn record_of(
+    message_id: &str,
+    message: &Message,
+    recipients: &[String],
+    received_at: DateTime<Utc>,
+) -> SimpleEmailRecord {
+    let from = format_addresses(message.from());
+    let return_path = match message.return_path() {
+        HeaderValue::Text(path) => Some(path.to_string()),
+        _ => None,
+    };
+    let source = return_path.clone().or_else(|| {
+        addresses(message.from())
+            .find_map(|addr| addr.address.as_ref())
+            .map(|address| address.to_string())
+    });
+    let verdict = || SimpleEmailVerdict { status: None };
+    SimpleEmailRecord {
+        event_version: Some("1.0".to_string()),
+        event_source: Some("aws:ses".to_string()),
+        ses: SimpleEmailService {
+            mail: SimpleEmailMessage {
+                common_headers: SimpleEmailCommonHeaders {
+                    from,
+                    to: format_addresses(message.to()),
+                    return_path,
+                    message_id: message.message_id().map(|id| format!("<{id}>")),
+                    date: message.date().map(|date| date.to_rfc822()),
+                    subject: message.subject().map(str::to_string),
+                },
+                source,
+                timestamp: received_at,
+                destination: recipients.to_vec(),
+                headers: message
+                    .headers_raw()
+                    .map(|(name, value)| SimpleEmailHeader {
+                        name: Some(name.to_string()),
+                        value: Some(value.trim().to_string()),
+                    })
+                    .collect(),
+                headers_truncated: false,
+                message_id: Some(message_id.to_string()),
+            },
+            receipt: SimpleEmailReceipt {
+                recipients: recipients.to_vec(),
+                timestamp: received_at,
+                spam_verdict: verdict(),
+                dkim_verdict: verdict(),
+                dmarc_verdict: verdict(),
+                dmarc_policy: None,
+                spf_verdict: verdict(),
+                virus_verdict: verdict(),
+                action: SimpleEmailReceiptAction {
+                    type_: None,
+                    topic_arn: None,
+                    bucket_name: None,
+                    object_key: None,
+                    smtp_reply_code: None,
+                    status_code: None,
+                    message: None,
+                    sender: None,
+                    invocation_type: None,
+                    function_arn: None,
+                    organization_arn: None,
+                },
+                processing_time_millis: 0,
+            },
+            content: None,
+        },
+    }
+}
+
+/// Addresses in the `To` and `Cc` headers of `message`, each once. Bcc recipients aren't in
+/// there, they need naming when reprocessing.
+pub fn header_recipients(message: &Message) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+    for addr in addresses(message.to()).chain(addresses(message.cc())) {
+        let Some(address) = &addr.address else {
+            continue;
+        };
+        if !recipients.iter().any(|r| r.eq_ignore_ascii_case(address)) {
+            recipients.push(address.to_string());
+        }
+    }
+    recipients
+}
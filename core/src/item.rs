@@ -0,0 +1,669 @@
+use crate::cursor::{self, CursorError};
+use crate::mail::{label_partition, labels_of, Flags, Label, Mail, User};
+use crate::parse::normalize_subject;
+#[cfg(feature = "rules")]
+use crate::rules::Rule;
+use aws_lambda_events::ses::{SimpleEmailCommonHeaders, SimpleEmailMessage, SimpleEmailService};
+use aws_sdk_dynamodb::error::BuildError;
+use aws_sdk_dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use aws_sdk_dynamodb::types::{
+    AttributeValue, CancellationReason, Put, ReturnValuesOnConditionCheckFailure,
+    TransactWriteItem, Update,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+pub type Item = HashMap<String, AttributeValue>;
+
+/// Partition key shared by every row of the user table, the address is the sort key.
+pub const USER_PK: &str = "USER";
+
+/// Partition key of the filtering rules in the user table, the address is the sort key.
+pub const RULES_PK: &str = "RULES";
+
+/// Partition key of the Sieve scripts in the user table, the address is the sort key.
+pub const SIEVE_PK: &str = "SIEVE";
+
+/// Partition key of the accounts that log in to the web app, the username is the sort key.
+pub const ACCOUNT_PK: &str = "ACCOUNT";
+
+/// Partition key of the login sessions, the hash of the session token is the sort key.
+pub const SESSION_PK: &str = "SESSION";
+
+/// Partition key of the API keys, the key id is the sort key.
+pub const API_KEY_PK: &str = "API_KEY";
+
+/// Prefix of the partition keys that note which senders a mailbox sent a vacation reply to, see
+/// [`vacation_of`].
+pub const VACATION_PREFIX: &str = "VACATION#";
+
+/// Partition key of the vacation replies `mailbox` sent, the sender and the handle of the reply
+/// are the sort key.
+pub fn vacation_of(mailbox: &str) -> String {
+    format!("{VACATION_PREFIX}{mailbox}")
+}
+
+/// Prefix of the partition keys that note which messages were delivered to a mailbox, see
+/// [`delivered_to`].
+pub const DELIVERED_PREFIX: &str = "DELIVERED#";
+
+/// Partition key of the messages delivered to `mailbox`, the message id is the sort key.
+pub fn delivered_to(mailbox: &str) -> String {
+    format!("{DELIVERED_PREFIX}{mailbox}")
+}
+
+#[derive(Debug, Error)]
+pub enum ItemError {
+    #[error("SES record has no {0}")]
+    MissingField(&'static str),
+    #[error("malformed item: {0}")]
+    Malformed(#[from] serde_dynamo::Error),
+    #[error(transparent)]
+    Cursor(#[from] CursorError),
+    #[error("malformed rules: {0}")]
+    Rules(#[from] serde_json::Error),
+    #[error("malformed request: {0}")]
+    Request(#[from] BuildError),
+}
+
+/// Encodes a `LastEvaluatedKey` as an opaque pagination cursor.
+pub fn key_to_cursor(key: Item) -> Result<String, ItemError> {
+    let key: serde_json::Value = serde_dynamo::from_item(key)?;
+    Ok(cursor::encode(&key))
+}
+
+/// Decodes a cursor from [`key_to_cursor`] back into an `ExclusiveStartKey`.
+pub fn cursor_to_key(cursor: &str) -> Result<Item, ItemError> {
+    let key: serde_json::Value = cursor::decode(cursor)?;
+    Ok(serde_dynamo::to_item(key)?)
+}
+
+/// A mail item in `MAIL_DB`, partitioned by recipient address and sorted by receive time.
+/// The raw message itself lives in `MAIL_BUCKET` under `message_id`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MailItem {
+    pub pk: String,
+    pub sk: i64,
+    pub message_id: String,
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw: Option<SimpleEmailMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_sentence: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread_id: Option<String>,
+    /// See [`MailItem::subject_key`], what a reply missing its threading headers is matched by.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject_key: Option<String>,
+    #[serde(flatten)]
+    pub flags: Flags,
+    /// Ids of the labels the mail is filed under, a string set so they're added and removed in
+    /// place. Each also has an [`AssignmentItem`].
+    #[serde(
+        with = "serde_dynamo::string_set",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub labels: Vec<String>,
+}
+
+impl MailItem {
+    /// Projection reading just enough of an item to build a [`Mail`], see [`Self::LIST_NAMES`].
+    pub const LIST_PROJECTION: &'static str = concat!(
+        "pk, message_id, sk, subject, #r.#ch.#f, first_sentence, thread_id, ",
+        "#seen, #flagged, #archived, #deleted, labels"
+    );
+    /// Global secondary index on `thread_id` (hash) and `sk` (range), listing a conversation
+    /// across every mailbox it was delivered to.
+    pub const THREAD_INDEX: &'static str = "thread-index";
+    /// Global secondary index on `message_id` (hash) and `pk` (range), finding the key of a mail
+    /// in a mailbox when all we have is its message id.
+    pub const MESSAGE_INDEX: &'static str = "message-index";
+    /// Global secondary index on `subject_key` (hash) and `sk` (range), finding the earlier mail
+    /// of a mailbox a reply without threading headers answers.
+    pub const SUBJECT_INDEX: &'static str = "subject-index";
+    /// Expression attribute names used by [`Self::LIST_PROJECTION`], the flags are also named
+    /// this way in conditions and updates.
+    pub const LIST_NAMES: [(&'static str, &'static str); 7] = [
+        ("#r", "raw"),
+        ("#ch", "commonHeaders"),
+        ("#f", "from"),
+        ("#seen", "seen"),
+        ("#flagged", "flagged"),
+        ("#archived", "archived"),
+        ("#deleted", "deleted"),
+    ];
+
+    /// Mailbox `pk` and [`normalize_subject`] of `subject`, the same for a mail and its replies.
+    pub fn subject_key(pk: &str, subject: &str) -> String {
+        format!("{pk}#{}", normalize_subject(subject))
+    }
+
+    /// One item per recipient of a received mail, each listing the same object in
+    /// `MAIL_BUCKET`. Recipients differing only in case get a single item, keyed lowercase.
+    pub fn from_ses(ses: &SimpleEmailService) -> Result<Vec<Self>, ItemError> {
+        let message_id = ses
+            .mail
+            .message_id
+            .as_ref()
+            .ok_or(ItemError::MissingField("messageId"))?;
+        // mailboxes are keyed lowercase, however the sender spelled the address
+        let mut recipients: Vec<String> = Vec::new();
+        for recipient in &ses.receipt.recipients {
+            let recipient = recipient.to_lowercase();
+            if !recipients.contains(&recipient) {
+                recipients.push(recipient);
+            }
+        }
+        if recipients.is_empty() {
+            return Err(ItemError::MissingField("recipient"));
+        }
+        let subject = ses.mail.common_headers.subject.clone().unwrap_or_default();
+        Ok(recipients
+            .into_iter()
+            .map(|pk| MailItem {
+                subject_key: Some(Self::subject_key(&pk, &subject)),
+                pk,
+                sk: ses.mail.timestamp.timestamp(),
+                message_id: message_id.to_string(),
+                subject: subject.clone(),
+                raw: Some(ses.mail.clone()),
+                first_sentence: None,
+                thread_id: None,
+                flags: Flags::default(),
+                labels: Vec::new(),
+            })
+            .collect())
+    }
+
+    /// Item of a mail we wrote ourselves, `raw` carries just the headers a listing reads.
+    pub fn from_mail(mail: &Mail) -> Self {
+        let timestamp = chrono::DateTime::from_timestamp(mail.sk, 0).unwrap_or_default();
+        MailItem {
+            pk: mail.pk.clone(),
+            sk: mail.sk,
+            message_id: mail.message_id.clone(),
+            subject: mail.subject.clone(),
+            raw: Some(SimpleEmailMessage {
+                common_headers: SimpleEmailCommonHeaders {
+                    from: mail.from.clone(),
+                    to: Vec::new(),
+                    return_path: None,
+                    message_id: Some(mail.message_id.clone()),
+                    date: Some(timestamp.to_rfc2822()),
+                    subject: Some(mail.subject.clone()),
+                },
+                source: None,
+                timestamp,
+                destination: Vec::new(),
+                headers: Vec::new(),
+                headers_truncated: false,
+                message_id: Some(mail.message_id.clone()),
+            }),
+            first_sentence: Some(mail.first_sentence.clone()),
+            thread_id: Some(mail.thread_id.clone()).filter(|id| !id.is_empty()),
+            subject_key: Some(Self::subject_key(&mail.pk, &mail.subject)),
+            flags: mail.flags,
+            // filing needs an AssignmentItem each, mail is written before it's filed
+            labels: Vec::new(),
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+
+    /// Writes listing the mail in its mailbox in `mail_db` and counting it in the mailbox's
+    /// [`UserItem`] in `user_db`, to run as one transaction. The first write marks the message
+    /// as delivered to the mailbox under [`delivered_to`], so delivering it again, whatever its
+    /// sort key, fails the transaction and counts nothing, see [`MailItem::redelivered`]. So does
+    /// another mail listed under the same sort key, see [`MailItem::collided`]. The
+    /// mailbox has to be registered first, counting labels needs its `label_counts`. Unread mail
+    /// also counts towards its `unread_count`.
+    pub fn delivery(
+        &self,
+        mail_db: &str,
+        user_db: &str,
+    ) -> Result<Vec<TransactWriteItem>, ItemError> {
+        let marker = self.marker(user_db)?;
+        let put = Put::builder()
+            .table_name(mail_db)
+            .set_item(Some(self.to_item()?))
+            .condition_expression("attribute_not_exists(pk)")
+            // tells the same mail listed before markers from another one that arrived in the same
+            // second
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .build()?;
+        let mut added = vec!["message_count :one".to_string()];
+        if self.flags.is_unread() {
+            added.push("unread_count :one".to_string());
+        }
+        let mut count = Update::builder()
+            .table_name(user_db)
+            .key("pk", AttributeValue::S(USER_PK.to_string()))
+            .key("sk", AttributeValue::S(self.pk.clone()))
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":sk", AttributeValue::N(self.sk.to_string()));
+        // label ids are names in the expression, they aren't restricted to what one can hold
+        for (i, label_id) in self.labels.iter().enumerate() {
+            added.push(format!("label_counts.#label{i} :one"));
+            count = count.expression_attribute_names(format!("#label{i}"), label_id);
+        }
+        let count = count
+            .update_expression(format!("ADD {} SET last_received = :sk", added.join(", ")))
+            .build()?;
+        Ok(vec![
+            TransactWriteItem::builder().put(marker).build(),
+            TransactWriteItem::builder().put(put).build(),
+            TransactWriteItem::builder().update(count).build(),
+        ])
+    }
+
+    /// Marks the mail as delivered to its mailbox without listing it, for mail the mailbox's rules
+    /// or script dropped. Like [`MailItem::delivery`] it fails when the mail was delivered or
+    /// dropped there before.
+    pub fn dropping(&self, user_db: &str) -> Result<Vec<TransactWriteItem>, ItemError> {
+        Ok(vec![TransactWriteItem::builder()
+            .put(self.marker(user_db)?)
+            .build()])
+    }
+
+    /// Marker of the message in [`delivered_to`] its mailbox, only written once.
+    fn marker(&self, user_db: &str) -> Result<Put, ItemError> {
+        Ok(Put::builder()
+            .table_name(user_db)
+            .item("pk", AttributeValue::S(delivered_to(&self.pk)))
+            .item("sk", AttributeValue::S(self.message_id.clone()))
+            .item("listed_at", AttributeValue::N(self.sk.to_string()))
+            .condition_expression("attribute_not_exists(pk)")
+            .build()?)
+    }
+
+    /// Whether `error` cancelled a [`MailItem::delivery`] or [`MailItem::dropping`] because the
+    /// mail was delivered to its mailbox before, by the marker or by the listing itself for mail
+    /// older than markers.
+    pub fn redelivered(&self, error: &TransactWriteItemsError) -> bool {
+        // reasons are in the order of the writes
+        let reasons = cancellation_reasons(error);
+        reasons.first().is_some_and(failed)
+            || reasons
+                .get(1)
+                .is_some_and(|reason| failed(reason) && !self.listed_other(reason))
+    }
+
+    /// Whether `error` cancelled a [`MailItem::delivery`] because another mail is listed in the
+    /// mailbox under the same sort key, it arrived in the same second.
+    pub fn collided(&self, error: &TransactWriteItemsError) -> bool {
+        let reasons = cancellation_reasons(error);
+        !reasons.first().is_some_and(failed)
+            && reasons
+                .get(1)
+                .is_some_and(|reason| failed(reason) && self.listed_other(reason))
+    }
+
+    /// Whether the listing `reason` failed on is of another message.
+    fn listed_other(&self, reason: &CancellationReason) -> bool {
+        reason
+            .item()
+            .and_then(|item| item.get("message_id"))
+            .and_then(|message_id| message_id.as_s().ok())
+            .is_some_and(|message_id| *message_id != self.message_id)
+    }
+}
+
+fn cancellation_reasons(error: &TransactWriteItemsError) -> &[CancellationReason] {
+    match error {
+        TransactWriteItemsError::TransactionCanceledException(error) => {
+            error.cancellation_reasons()
+        }
+        _ => &[],
+    }
+}
+
+fn failed(reason: &CancellationReason) -> bool {
+    reason.code.as_deref() == Some("ConditionalCheckFailed")
+}
+
+#[derive(Deserialize)]
+struct ListedMail {
+    pk: String,
+    sk: i64,
+    message_id: String,
+    subject: String,
+    // both are optional on MailItem, a listing shouldn't fail because one item lacks them
+    #[serde(default)]
+    raw: ListedRaw,
+    #[serde(default)]
+    first_sentence: String,
+    #[serde(default)]
+    thread_id: Option<String>,
+    // items written before flags existed have none of them
+    #[serde(flatten)]
+    flags: Flags,
+    #[serde(default)]
+    labels: Vec<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct ListedRaw {
+    common_headers: ListedHeaders,
+}
+
+#[derive(Deserialize, Default)]
+struct ListedHeaders {
+    from: Vec<String>,
+}
+
+impl TryFrom<Item> for Mail {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        let listed: ListedMail = serde_dynamo::from_item(item)?;
+        Ok(Mail {
+            pk: listed.pk,
+            sk: listed.sk,
+            message_id: listed.message_id.clone(),
+            subject: listed.subject,
+            from: listed.raw.common_headers.from,
+            first_sentence: listed.first_sentence,
+            // items written before threading are conversations of their own
+            thread_id: listed.thread_id.unwrap_or(listed.message_id),
+            flags: listed.flags,
+            labels: listed.labels,
+        })
+    }
+}
+
+/// A [`Label`] in the user table, next to the users under the [`labels_of`] its address.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelItem {
+    pub pk: String,
+    /// The label id.
+    pub sk: String,
+    pub name: String,
+    pub color: String,
+}
+
+impl LabelItem {
+    pub fn new(address: &str, label: &Label) -> Self {
+        LabelItem {
+            pk: labels_of(address),
+            sk: label.label_id.clone(),
+            name: label.name.clone(),
+            color: label.color.clone(),
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+/// A mail filed under a label, listed newest first in the [`label_partition`] of its mailbox.
+/// It has no `message_id`, so it stays out of [`MailItem::MESSAGE_INDEX`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentItem {
+    pub pk: String,
+    /// The `sk` of the mail, which is read from `mailbox`.
+    pub sk: i64,
+    pub mailbox: String,
+}
+
+impl AssignmentItem {
+    pub fn new(mailbox: &str, label_id: &str, sk: i64) -> Self {
+        AssignmentItem {
+            pk: label_partition(mailbox, label_id),
+            sk,
+            mailbox: mailbox.to_string(),
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+/// The filtering rules of an address, kept as JSON since they're only ever read as a whole.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RulesItem {
+    pub pk: String,
+    /// The address.
+    pub sk: String,
+    pub rules: String,
+}
+
+#[cfg(feature = "rules")]
+impl RulesItem {
+    pub fn new(address: &str, rules: &[Rule]) -> Result<Self, ItemError> {
+        Ok(RulesItem {
+            pk: RULES_PK.to_string(),
+            sk: address.to_string(),
+            rules: serde_json::to_string(rules)?,
+        })
+    }
+
+    pub fn rules(&self) -> Result<Vec<Rule>, ItemError> {
+        Ok(serde_json::from_str(&self.rules)?)
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+impl TryFrom<Item> for RulesItem {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
+
+/// The Sieve script of an address, kept as written so that it reads back the same.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SieveItem {
+    pub pk: String,
+    /// The address.
+    pub sk: String,
+    pub script: String,
+}
+
+impl SieveItem {
+    pub fn new(address: &str, script: &str) -> Self {
+        SieveItem {
+            pk: SIEVE_PK.to_string(),
+            sk: address.to_string(),
+            script: script.to_string(),
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+impl TryFrom<Item> for SieveItem {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
+
+/// An account of the web app and the mailboxes it may read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountItem {
+    pub pk: String,
+    /// The username.
+    pub sk: String,
+    /// PHC string of the password hash.
+    pub password_hash: String,
+    /// Addresses, a string set so a mailbox is granted or taken away in place.
+    #[serde(
+        with = "serde_dynamo::string_set",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub mailboxes: Vec<String>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl AccountItem {
+    pub fn new(username: &str, password_hash: &str, mailboxes: &[String], admin: bool) -> Self {
+        AccountItem {
+            pk: ACCOUNT_PK.to_string(),
+            sk: username.to_string(),
+            password_hash: password_hash.to_string(),
+            mailboxes: mailboxes.to_vec(),
+            admin,
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+impl TryFrom<Item> for AccountItem {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
+
+/// A mailbox in the user table, put once when its first mail arrives. Its counters and
+/// `last_received` are kept by [`MailItem::delivery`] from then on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserItem {
+    pub pk: String,
+    /// The address.
+    pub sk: String,
+    pub message_count: i64,
+    /// Unix timestamps in seconds.
+    pub first_seen: i64,
+    pub last_received: i64,
+    /// Mails per label id, see [`User::label_counts`].
+    pub label_counts: BTreeMap<String, i64>,
+    /// Mails that are [`Flags::is_unread`], kept up by delivery and every change of flags.
+    /// Mailboxes registered before it was kept have none until it's first read.
+    #[serde(default)]
+    pub unread_count: i64,
+}
+
+impl UserItem {
+    /// A mailbox that nothing was delivered to yet, its first mail arriving at `received`.
+    pub fn new(mailbox: &str, received: i64) -> Self {
+        UserItem {
+            pk: USER_PK.to_string(),
+            sk: mailbox.to_string(),
+            message_count: 0,
+            first_seen: received,
+            last_received: received,
+            label_counts: BTreeMap::new(),
+            unread_count: 0,
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+/// A login session of an account. Only the hash of its token is kept, and the table's time to
+/// live removes it some time after `expires_at`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionItem {
+    pub pk: String,
+    /// The hash of the session token.
+    pub sk: String,
+    pub username: String,
+    /// Unix timestamp in seconds.
+    pub expires_at: i64,
+}
+
+impl SessionItem {
+    pub fn new(id: &str, username: &str, expires_at: i64) -> Self {
+        SessionItem {
+            pk: SESSION_PK.to_string(),
+            sk: id.to_string(),
+            username: username.to_string(),
+            expires_at,
+        }
+    }
+
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+impl TryFrom<Item> for SessionItem {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
+
+/// An API key of an account. Only the hash of its token is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKeyItem {
+    pub pk: String,
+    /// The key id.
+    pub sk: String,
+    pub username: String,
+    pub name: String,
+    pub key_hash: String,
+    /// Scopes as written, `read:<address>`, `send:<address>` or `admin`.
+    pub scopes: Vec<String>,
+    /// Unix timestamps in seconds.
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+}
+
+impl ApiKeyItem {
+    pub fn to_item(&self) -> Result<Item, ItemError> {
+        Ok(serde_dynamo::to_item(self)?)
+    }
+}
+
+impl TryFrom<Item> for ApiKeyItem {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
+
+impl TryFrom<Item> for Label {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        let item: LabelItem = serde_dynamo::from_item(item)?;
+        Ok(Label {
+            label_id: item.sk,
+            name: item.name,
+            color: item.color,
+        })
+    }
+}
+
+impl TryFrom<Item> for User {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
+
+impl TryFrom<Item> for Flags {
+    type Error = ItemError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        Ok(serde_dynamo::from_item(item)?)
+    }
+}
@@ -0,0 +1,18 @@
+//! Mail record schema shared by the inbox Lambda and the web server.
+//!
+//! The inbox writes what the web server reads, so both sides go through the types and
+//! (de)serialization in here instead of each hand-rolling the DynamoDB item layout.
+pub mod cursor;
+#[cfg(feature = "dead_letter")]
+pub mod dead_letter;
+#[cfg(feature = "dynamodb")]
+pub mod item;
+pub mod mail;
+#[cfg(feature = "parse")]
+pub mod parse;
+#[cfg(feature = "rules")]
+pub mod rules;
+#[cfg(feature = "search")]
+pub mod search;
+#[cfg(feature = "rules")]
+pub mod sieve;
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// A mail as shown in a mailbox listing.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Mail {
+    pub pk: String,
+    pub sk: i64,
+    pub message_id: String,
+    pub subject: String,
+    pub from: Vec<String>,
+    pub first_sentence: String,
+    /// Conversation the mail belongs to, see `parse::thread_id`.
+    #[serde(default)]
+    pub thread_id: String,
+    #[serde(default)]
+    pub flags: Flags,
+    /// Ids of the [`Label`]s the mail is filed under.
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+/// What the owner of a mailbox did with a mail, all unset when it arrives.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Flags {
+    #[serde(default)]
+    pub seen: bool,
+    #[serde(default)]
+    pub flagged: bool,
+    #[serde(default)]
+    pub archived: bool,
+    /// In the trash, the mail itself is kept.
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+impl Flags {
+    /// Whether the mail counts as unread: not seen, and neither archived nor in the trash.
+    pub fn is_unread(&self) -> bool {
+        !self.seen && !self.archived && !self.deleted
+    }
+}
+
+/// A conversation in a mailbox, listed at the position of its newest mail.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Thread {
+    pub thread_id: String,
+    pub latest: Mail,
+    pub message_count: i64,
+}
+
+/// A mailbox known to the user table, `sk` is its address.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct User {
+    pub pk: String,
+    pub sk: String,
+    pub message_count: i64,
+    /// Unix timestamps in seconds of the first and the latest mail delivered to the mailbox,
+    /// unknown for mailboxes registered before they were kept.
+    #[serde(default)]
+    pub first_seen: Option<i64>,
+    #[serde(default)]
+    pub last_received: Option<i64>,
+    /// Number of mails filed under each label id, by the mailbox's rules or by hand.
+    #[serde(default)]
+    pub label_counts: BTreeMap<String, i64>,
+}
+
+/// A label of a mailbox, mails are filed under any number of them.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub label_id: String,
+    pub name: String,
+    /// CSS color of its badge, `#rrggbb`.
+    pub color: String,
+}
+
+/// Prefix of the partitions holding sent mail, they sit next to the mailboxes in `MAIL_DB`.
+pub const SENT_PREFIX: &str = "SENT#";
+
+/// Partition key of the mail sent from `address`.
+pub fn sent_mailbox(address: &str) -> String {
+    format!("{SENT_PREFIX}{}", address.to_lowercase())
+}
+
+/// Address a mailbox partition belongs to, the inverse of [`sent_mailbox`] for sent mail.
+pub fn mailbox_address(pk: &str) -> &str {
+    pk.strip_prefix(SENT_PREFIX).unwrap_or(pk)
+}
+
+/// Prefix of the partitions listing the mail filed under a label.
+pub const LABEL_PREFIX: &str = "LABEL#";
+
+/// Partition key of the mail of `mailbox` filed under `label_id`.
+pub fn label_partition(mailbox: &str, label_id: &str) -> String {
+    format!("{LABEL_PREFIX}{mailbox}#{label_id}")
+}
+
+/// Partition key of the labels defined for `address`.
+pub fn labels_of(address: &str) -> String {
+    format!("{LABEL_PREFIX}{address}")
+}
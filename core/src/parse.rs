@@ -0,0 +1,99 @@
+use mail_parser::{Addr, HeaderValue, Message};
+
+/// Every address in an address header, flattening groups.
+pub fn addresses<'a>(value: &'a HeaderValue<'a>) -> Box<dyn Iterator<Item = &'a Addr<'a>> + 'a> {
+    match value {
+        HeaderValue::Address(addr) => Box::new(std::iter::once(addr)),
+        HeaderValue::AddressList(list) => Box::new(list.iter()),
+        HeaderValue::Group(group) => Box::new(group.addresses.iter()),
+        HeaderValue::GroupList(groups) => {
+            Box::new(groups.iter().flat_map(|group| group.addresses.iter()))
+        }
+        _ => Box::new(std::iter::empty()),
+    }
+}
+
+/// Formats addresses the way SES writes them into `commonHeaders`.
+pub fn format_addresses(value: &HeaderValue) -> Vec<String> {
+    addresses(value).map(format_address).collect()
+}
+
+pub fn format_address(addr: &Addr) -> String {
+    match (&addr.name, &addr.address) {
+        (Some(name), Some(address)) => format!("\"{name}\" <{address}>"),
+        (None, Some(address)) => address.to_string(),
+        (Some(name), None) => name.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+/// The first three non-empty lines of the text body, used as the listing preview.
+pub fn first_sentence(message: &Message) -> String {
+    message
+        .body_text(0)
+        .map(|body| {
+            body.split("\r\n")
+                .map(|s| s.trim())
+                .filter(|x| !x.is_empty())
+                .take(3)
+                .collect::<String>()
+        })
+        .unwrap_or_default()
+}
+
+/// Prefix of the thread id of a reply missing its threading headers, see [`thread_id`].
+pub const SUBJECT_THREAD: &str = "subject:";
+
+/// Conversation `message` belongs to: the root of its `References` chain, else the mail it
+/// answers, else its own `Message-ID`, all in angle brackets. A reply missing all threading
+/// headers falls back to its [`normalize_subject`], prefixed with [`SUBJECT_THREAD`]. Stores
+/// join it to the thread of an earlier mail with the same subject in its mailbox when there is
+/// one.
+pub fn thread_id(message: &Message) -> String {
+    let root = message
+        .references()
+        .as_text_list()
+        .and_then(|references| references.first().copied())
+        .or_else(|| message.in_reply_to().as_text_list()?.first().copied());
+    let subject = message.subject().unwrap_or_default();
+    let id = match root {
+        Some(root) => Some(root),
+        // a reply to something we can't identify is better matched by its subject
+        None if strip_prefixes(subject) != subject.trim() => None,
+        None => message.message_id(),
+    };
+    match id {
+        Some(id) => format!("<{id}>"),
+        None => format!("{SUBJECT_THREAD}{}", normalize_subject(subject)),
+    }
+}
+
+/// `subject` without its reply and forward prefixes, whitespace collapsed and lowercased.
+pub fn normalize_subject(subject: &str) -> String {
+    strip_prefixes(subject)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn strip_prefixes(subject: &str) -> &str {
+    const PREFIXES: [&str; 5] = ["re:", "fwd:", "fw:", "aw:", "sv:"];
+    let mut subject = subject.trim();
+    while let Some(prefix) = PREFIXES.iter().find(|prefix| {
+        subject
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+    }) {
+        subject = subject[prefix.len()..].trim_start();
+    }
+    subject
+}
+
+/// Whether `message` came in through a mail server, which adds a `Received` header. The copies
+/// of mail we send are stored as they were written and have none.
+pub fn is_received(message: &Message) -> bool {
+    message
+        .headers_raw()
+        .any(|(name, _)| name.eq_ignore_ascii_case("Received"))
+}
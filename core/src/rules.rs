@@ -0,0 +1,237 @@
+//! Filtering rules the inbox applies to every received mail before it's stored.
+//!
+//! A mailbox has one ordered list of [`Rule`]s. Each rule whose conditions all hold adds its
+//! actions to the [`Outcome`], until a rule that `stop`s or drops the mail.
+use crate::parse::addresses;
+use mail_parser::{HeaderValue, Message};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub name: String,
+    /// All of them have to hold, a rule without conditions applies to every mail.
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<RuleAction>,
+    /// Later rules aren't looked at once this one applied.
+    #[serde(default)]
+    pub stop: bool,
+}
+
+/// What a [`Rule`] tests, text is compared ignoring case.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Condition {
+    /// Any sender, by address or display name.
+    From(TextMatch),
+    /// Any recipient in `To` or `Cc`, by address or display name.
+    To(TextMatch),
+    Subject(TextMatch),
+    /// Any header named `name`.
+    Header {
+        name: String,
+        #[serde(flatten)]
+        matches: TextMatch,
+    },
+    /// The raw message is larger than `bytes`.
+    LargerThan {
+        bytes: usize,
+    },
+    /// The raw message is smaller than `bytes`.
+    SmallerThan {
+        bytes: usize,
+    },
+    HasAttachment,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TextMatch {
+    pub op: TextOp,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TextOp {
+    Contains,
+    Is,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    /// Files the mail under a label of the mailbox.
+    AddLabel {
+        label_id: String,
+    },
+    MarkRead,
+    Archive,
+    /// Sends a copy to `to`, from the mailbox with the sender as `Reply-To`.
+    Forward {
+        to: String,
+    },
+    /// Doesn't store the mail at all, later rules aren't looked at.
+    Drop,
+}
+
+/// What the rules that applied to a mail do with it, together.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// Names of the rules that applied, in order.
+    pub matched: Vec<String>,
+    /// Label ids, each once.
+    pub labels: Vec<String>,
+    pub seen: bool,
+    pub archived: bool,
+    /// Addresses, each once.
+    pub forward: Vec<String>,
+    pub drop: bool,
+}
+
+impl TextMatch {
+    pub fn matches(&self, text: &str) -> bool {
+        let (text, value) = (text.to_lowercase(), self.value.to_lowercase());
+        match self.op {
+            TextOp::Contains => text.contains(&value),
+            TextOp::Is => text.trim() == value.trim(),
+            TextOp::StartsWith => text.trim_start().starts_with(&value),
+            TextOp::EndsWith => text.trim_end().ends_with(&value),
+        }
+    }
+}
+
+impl Condition {
+    /// Whether the condition holds for `message`, `size` bytes long as received.
+    pub fn matches(&self, message: &Message, size: usize) -> bool {
+        match self {
+            Condition::From(matches) => address_matches(message.from(), matches),
+            Condition::To(matches) => {
+                address_matches(message.to(), matches) || address_matches(message.cc(), matches)
+            }
+            Condition::Subject(matches) => matches.matches(message.subject().unwrap_or_default()),
+            Condition::Header { name, matches } => header_values(message, name)
+                .iter()
+                .any(|value| matches.matches(value)),
+            Condition::LargerThan { bytes } => size > *bytes,
+            Condition::SmallerThan { bytes } => size < *bytes,
+            Condition::HasAttachment => message.attachment_count() > 0,
+        }
+    }
+}
+
+impl Rule {
+    pub fn matches(&self, message: &Message, size: usize) -> bool {
+        self.conditions
+            .iter()
+            .all(|condition| condition.matches(message, size))
+    }
+}
+
+/// Runs `rules` in order over `message`, `size` bytes long as received.
+pub fn evaluate(rules: &[Rule], message: &Message, size: usize) -> Outcome {
+    let mut outcome = Outcome::default();
+    for rule in rules {
+        if !rule.matches(message, size) {
+            continue;
+        }
+        outcome.matched.push(rule.name.clone());
+        for action in &rule.actions {
+            match action {
+                RuleAction::AddLabel { label_id } => push_once(&mut outcome.labels, label_id),
+                RuleAction::MarkRead => outcome.seen = true,
+                RuleAction::Archive => outcome.archived = true,
+                RuleAction::Forward { to } => push_once(&mut outcome.forward, to),
+                RuleAction::Drop => outcome.drop = true,
+            }
+        }
+        if rule.stop || outcome.drop {
+            break;
+        }
+    }
+    outcome
+}
+
+/// Header naming every mailbox a mail was forwarded by, in [`forwarded`] copies.
+pub const FORWARDED_HEADER: &str = "X-Forwarded-For";
+
+/// `raw` rewritten to be sent on by `mailbox`: SES only sends from verified addresses, so the
+/// original `From` becomes the `Reply-To` (unless there's one) and the signatures and bounce
+/// address that no longer hold are removed.
+pub fn forwarded(raw: &[u8], mailbox: &str) -> Vec<u8> {
+    let Some(message) = Message::parse(raw) else {
+        return raw.to_vec();
+    };
+    let mut forwarded =
+        format!("From: {mailbox}\r\n{FORWARDED_HEADER}: {mailbox}\r\n").into_bytes();
+    let has_reply_to = message
+        .headers()
+        .iter()
+        .any(|header| header.name().eq_ignore_ascii_case("Reply-To"));
+    let mut body_start = raw.len();
+    for header in message.headers() {
+        let name = header.name();
+        let field = &raw[header.offset_field..header.offset_end];
+        if name.eq_ignore_ascii_case("From") {
+            if !has_reply_to {
+                forwarded.extend_from_slice(b"Reply-To:");
+                forwarded.extend_from_slice(&raw[header.offset_start..header.offset_end]);
+            }
+        } else if !["DKIM-Signature", "Return-Path", "Sender"]
+            .iter()
+            .any(|dropped| name.eq_ignore_ascii_case(dropped))
+        {
+            forwarded.extend_from_slice(field);
+        }
+        body_start = header.offset_end;
+    }
+    // the blank line ending the header and everything after it
+    forwarded.extend_from_slice(&raw[body_start.min(raw.len())..]);
+    forwarded
+}
+
+/// Whether `message` is a copy `mailbox` forwarded already, forwarding it again would loop.
+pub fn forwarded_by(message: &Message, mailbox: &str) -> bool {
+    header_values(message, FORWARDED_HEADER)
+        .iter()
+        .any(|value| value.trim().eq_ignore_ascii_case(mailbox))
+}
+
+fn address_matches(value: &HeaderValue, matches: &TextMatch) -> bool {
+    addresses(value).any(|addr| {
+        addr.address
+            .as_deref()
+            .is_some_and(|address| matches.matches(address))
+            || addr
+                .name
+                .as_deref()
+                .is_some_and(|name| matches.matches(name))
+    })
+}
+
+/// Every value of the headers called `name`, decoded when the parser understood them.
+pub(crate) fn header_values(message: &Message, name: &str) -> Vec<String> {
+    message
+        .headers()
+        .iter()
+        .filter(|header| header.name().eq_ignore_ascii_case(name))
+        .map(|header| match &header.value {
+            HeaderValue::Text(text) => text.to_string(),
+            HeaderValue::TextList(list) => list.join(", "),
+            _ => {
+                let raw = &message.raw_message[header.offset_start..header.offset_end];
+                String::from_utf8_lossy(raw)
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+        })
+        .collect()
+}
+
+pub(crate) fn push_once(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|existing| existing == value) {
+        list.push(value.to_string());
+    }
+}
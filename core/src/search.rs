@@ -0,0 +1,597 @@
+//! Full-text search over received mail.
+//!
+//! The inbox adds every mail to a tantivy index as it's ingested and keeps the index files in
+//! `MAIL_BUCKET` under [`S3_PREFIX`], the web server downloads them to answer searches. Locally
+//! the index just lives in a directory. Whatever updates the index in the bucket takes turns
+//! through a [`Lease`].
+use crate::mail::{Flags, Mail};
+use crate::parse::{first_sentence, format_addresses, thread_id};
+use aws_sdk_s3 as s3;
+use mail_parser::Message;
+use std::collections::HashSet;
+use std::ops::Bound;
+use std::path::Path;
+use std::str::FromStr;
+use tantivy::collector::{Count, TopDocs};
+use tantivy::directory::MmapDirectory;
+use tantivy::query::{BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery};
+use tantivy::schema::{
+    Field, IndexRecordOption, Schema, Value, FAST, INDEXED, STORED, STRING, TEXT,
+};
+use tantivy::{
+    DocAddress, Index, IndexReader, IndexWriter, Order, ReloadPolicy, TantivyDocument, Term,
+};
+use thiserror::Error;
+
+/// Where the index files are kept in `MAIL_BUCKET`.
+pub const S3_PREFIX: &str = "search/";
+
+/// Name of the lease on the index in `MAIL_BUCKET`, kept with the index files but not one of them.
+const LEASE: &str = "lease";
+
+/// How long a [`Lease`] lasts when it's never released, an update takes well under that.
+pub const LEASE_SECONDS: u64 = 10 * 60;
+
+/// Memory given to an index writer, well above tantivy's per thread minimum.
+const WRITER_MEMORY: usize = 50_000_000;
+
+#[derive(Debug, Error)]
+pub enum SearchError {
+    #[error("invalid search: {0}")]
+    InvalidQuery(String),
+    #[error("search index error: {0}")]
+    Index(#[from] tantivy::TantivyError),
+    #[error("search index storage error: {0}")]
+    Storage(String),
+}
+
+/// A search as typed in the search bar: free text plus Gmail-style `from:`, `to:`,
+/// `has:attachment`, `before:` and `after:` operators. Values can be quoted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub text: Vec<String>,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub has_attachment: bool,
+    /// Received before midnight UTC starting this day, as a timestamp.
+    pub before: Option<i64>,
+    /// Received on or after midnight UTC starting this day, as a timestamp.
+    pub after: Option<i64>,
+}
+
+impl FromStr for SearchQuery {
+    type Err = SearchError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = SearchQuery::default();
+        for word in words(s) {
+            let Some((operator, value)) = word.split_once(':') else {
+                query.text.push(word);
+                continue;
+            };
+            let value = value.trim_matches('"').to_string();
+            match operator.to_lowercase().as_str() {
+                "from" => query.from.push(value),
+                "to" => query.to.push(value),
+                "has" if value.eq_ignore_ascii_case("attachment") => query.has_attachment = true,
+                "has" => {
+                    return Err(SearchError::InvalidQuery(format!(
+                        "has:{value} isn't supported, only has:attachment"
+                    )))
+                }
+                "before" => query.before = Some(parse_date(&value)?),
+                "after" => query.after = Some(parse_date(&value)?),
+                // anything else is just text with a colon in it
+                _ => query.text.push(word.trim_matches('"').to_string()),
+            }
+        }
+        Ok(query)
+    }
+}
+
+/// Splits on whitespace, keeping `"quoted parts"` (also after an operator) together.
+fn words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    words.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        words.push(current);
+    }
+    words
+}
+
+/// `YYYY/MM/DD` or `YYYY-MM-DD` as the timestamp of its midnight UTC.
+fn parse_date(value: &str) -> Result<i64, SearchError> {
+    let invalid = || SearchError::InvalidQuery(format!("{value:?} is not a YYYY/MM/DD date"));
+    let mut parts = value.split(['/', '-']).map(|part| part.parse::<u32>());
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day)), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    chrono::NaiveDate::from_ymd_opt(year as i32, month, day)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|date| date.and_utc().timestamp())
+        .ok_or_else(invalid)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fields {
+    key: Field,
+    mailbox: Field,
+    sk: Field,
+    subject: Field,
+    from: Field,
+    to: Field,
+    body: Field,
+    has_attachment: Field,
+    first_sentence: Field,
+    thread_id: Field,
+}
+
+impl Fields {
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            key: builder.add_text_field("key", STRING | STORED),
+            // one value per mailbox the mail was delivered to, lowercased
+            mailbox: builder.add_text_field("mailbox", STRING),
+            sk: builder.add_i64_field("sk", INDEXED | STORED | FAST),
+            subject: builder.add_text_field("subject", TEXT | STORED),
+            from: builder.add_text_field("from", TEXT | STORED),
+            to: builder.add_text_field("to", TEXT),
+            body: builder.add_text_field("body", TEXT),
+            has_attachment: builder.add_bool_field("has_attachment", INDEXED),
+            first_sentence: builder.add_text_field("first_sentence", STORED),
+            thread_id: builder.add_text_field("thread_id", STORED),
+        };
+        (builder.build(), fields)
+    }
+}
+
+/// The tantivy index in a local directory.
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    fields: Fields,
+}
+
+impl std::fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SearchIndex").finish_non_exhaustive()
+    }
+}
+
+impl SearchIndex {
+    /// Opens the index in `dir`, creating an empty one if there's none yet.
+    pub fn open(dir: &Path) -> Result<Self, SearchError> {
+        std::fs::create_dir_all(dir).map_err(|e| SearchError::Storage(e.to_string()))?;
+        let (schema, fields) = Fields::schema();
+        let directory = MmapDirectory::open(dir).map_err(tantivy::TantivyError::from)?;
+        let index = Index::open_or_create(directory, schema)?;
+        // reloaded by hand after a commit or a download, see `reload`
+        let reader = index
+            .reader_builder()
+            .reload_policy(ReloadPolicy::Manual)
+            .try_into()?;
+        Ok(SearchIndex {
+            index,
+            reader,
+            fields,
+        })
+    }
+
+    pub fn writer(&self) -> Result<IndexWriter, SearchError> {
+        Ok(self.index.writer(WRITER_MEMORY)?)
+    }
+
+    /// Picks up what was committed or downloaded since the index was opened.
+    pub fn reload(&self) -> Result<(), SearchError> {
+        Ok(self.reader.reload()?)
+    }
+
+    pub fn contains(&self, key: &str) -> Result<bool, SearchError> {
+        let query = TermQuery::new(
+            Term::from_field_text(self.fields.key, key),
+            IndexRecordOption::Basic,
+        );
+        Ok(self.reader.searcher().search(&query, &Count)? > 0)
+    }
+
+    /// Adds mail `key` delivered to `mailboxes` at `sk`, replacing what was indexed under `key`.
+    /// Only visible to searches once `writer` is committed.
+    pub fn add(
+        &self,
+        writer: &IndexWriter,
+        key: &str,
+        mailboxes: &[String],
+        sk: i64,
+        message: &Message,
+    ) -> Result<(), SearchError> {
+        let fields = self.fields;
+        let mut document = TantivyDocument::new();
+        document.add_text(fields.key, key);
+        let mailboxes: HashSet<String> = mailboxes
+            .iter()
+            .map(|mailbox| mailbox.to_lowercase())
+            .collect();
+        for mailbox in mailboxes {
+            document.add_text(fields.mailbox, mailbox);
+        }
+        document.add_i64(fields.sk, sk);
+        document.add_text(fields.subject, message.subject().unwrap_or_default());
+        for from in format_addresses(message.from()) {
+            document.add_text(fields.from, from);
+        }
+        for to in format_addresses(message.to())
+            .into_iter()
+            .chain(format_addresses(message.cc()))
+        {
+            document.add_text(fields.to, to);
+        }
+        document.add_text(fields.body, message.body_text(0).unwrap_or_default());
+        document.add_bool(fields.has_attachment, message.attachment_count() > 0);
+        document.add_text(fields.first_sentence, first_sentence(message));
+        document.add_text(fields.thread_id, thread_id(message));
+
+        writer.delete_term(Term::from_field_text(fields.key, key));
+        writer.add_document(document)?;
+        Ok(())
+    }
+
+    /// Mail of `mailbox` matching `query`, newest first. Returns one page of `limit` mails after
+    /// skipping `offset`, and whether there are more.
+    pub fn search(
+        &self,
+        mailbox: &str,
+        query: &SearchQuery,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<Mail>, bool), SearchError> {
+        let fields = self.fields;
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_text(fields.mailbox, &mailbox.to_lowercase()),
+                IndexRecordOption::Basic,
+            )),
+        )];
+        if let Some(text) = self.text_query(query)? {
+            clauses.push((Occur::Must, text));
+        }
+        if query.has_attachment {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_bool(fields.has_attachment, true),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        if query.before.is_some() || query.after.is_some() {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_i64_bounds(
+                    "sk".to_string(),
+                    query.after.map_or(Bound::Unbounded, Bound::Included),
+                    query.before.map_or(Bound::Unbounded, Bound::Excluded),
+                )),
+            ));
+        }
+        let query = BooleanQuery::new(clauses);
+
+        let searcher = self.reader.searcher();
+        let top = TopDocs::with_limit(limit + 1)
+            .and_offset(offset)
+            .order_by_fast_field::<i64>("sk", Order::Desc);
+        let hits: Vec<(i64, DocAddress)> = searcher.search(&query, &top)?;
+        let has_more = hits.len() > limit;
+        let mails = hits
+            .into_iter()
+            .take(limit)
+            .map(|(_, address)| {
+                let document: TantivyDocument = searcher.doc(address)?;
+                Ok(self.mail(mailbox, &document))
+            })
+            .collect::<Result<_, SearchError>>()?;
+        Ok((mails, has_more))
+    }
+
+    /// Free text over every text field and `from:`/`to:` on theirs, all required to match.
+    fn text_query(&self, query: &SearchQuery) -> Result<Option<Box<dyn Query>>, SearchError> {
+        let fields = self.fields;
+        // every value becomes a quoted phrase, nothing typed is read as query syntax
+        let phrase = |value: &str| {
+            let value: String = value.chars().filter(|c| !matches!(c, '"' | '\\')).collect();
+            value
+                .chars()
+                .any(char::is_alphanumeric)
+                .then(|| format!("\"{value}\""))
+        };
+        let parts: Vec<String> = query
+            .text
+            .iter()
+            .filter_map(|text| phrase(text))
+            .chain(
+                query
+                    .from
+                    .iter()
+                    .filter_map(|from| Some(format!("from:{}", phrase(from)?))),
+            )
+            .chain(
+                query
+                    .to
+                    .iter()
+                    .filter_map(|to| Some(format!("to:{}", phrase(to)?))),
+            )
+            .collect();
+        if parts.is_empty() {
+            return Ok(None);
+        }
+        let mut parser = QueryParser::for_index(
+            &self.index,
+            vec![fields.subject, fields.from, fields.to, fields.body],
+        );
+        parser.set_conjunction_by_default();
+        let parsed = parser
+            .parse_query(&parts.join(" "))
+            .map_err(|e| SearchError::InvalidQuery(e.to_string()))?;
+        Ok(Some(parsed))
+    }
+
+    fn mail(&self, mailbox: &str, document: &TantivyDocument) -> Mail {
+        let fields = self.fields;
+        let text = |field| {
+            document
+                .get_first(field)
+                .and_then(|value| value.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        Mail {
+            pk: mailbox.to_lowercase(),
+            sk: document
+                .get_first(fields.sk)
+                .and_then(|value| value.as_i64())
+                .unwrap_or_default(),
+            message_id: text(fields.key),
+            subject: text(fields.subject),
+            from: document
+                .get_all(fields.from)
+                .filter_map(|value| value.as_str())
+                .map(str::to_string)
+                .collect(),
+            first_sentence: text(fields.first_sentence),
+            thread_id: text(fields.thread_id),
+            // flags and labels change after ingestion, they aren't indexed
+            flags: Flags::default(),
+            labels: Vec::new(),
+        }
+    }
+}
+
+/// Brings `dir` up to date with the index in `bucket`: new segment files, then the metadata
+/// pointing at them. Does nothing when there's no index in the bucket yet.
+pub async fn download(client: &s3::Client, bucket: &str, dir: &Path) -> Result<(), SearchError> {
+    std::fs::create_dir_all(dir).map_err(storage)?;
+    let remote = list(client, bucket).await?;
+    let (metadata, segments): (Vec<_>, Vec<_>) = remote.iter().partition(|name| is_metadata(name));
+    for name in segments.into_iter().chain(metadata) {
+        // segment files never change once written, only the metadata is fetched every time
+        if !is_metadata(name) && dir.join(name).exists() {
+            continue;
+        }
+        let object = client
+            .get_object()
+            .bucket(bucket)
+            .key(format!("{S3_PREFIX}{name}"))
+            .send()
+            .await
+            .map_err(|e| storage(e.into_service_error()))?;
+        let data = object.body.collect().await.map_err(storage)?;
+        std::fs::write(dir.join(name), data.into_bytes()).map_err(storage)?;
+    }
+    Ok(())
+}
+
+/// Copies a committed index in `dir` to `bucket`, the metadata last so a concurrent
+/// [`download`] never sees segments that aren't there yet. Segments tantivy merged away are
+/// deleted from the bucket afterwards.
+pub async fn upload(client: &s3::Client, bucket: &str, dir: &Path) -> Result<(), SearchError> {
+    let remote = list(client, bucket).await?;
+    let mut local = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(storage)? {
+        let name = entry.map_err(storage)?.file_name();
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        // lock files only mean something to the process holding them
+        if !name.starts_with(".tantivy-") {
+            local.push(name.to_string());
+        }
+    }
+    let (metadata, segments): (Vec<_>, Vec<_>) = local.iter().partition(|name| is_metadata(name));
+    for name in segments.into_iter().chain(metadata) {
+        if !is_metadata(name) && remote.contains(name) {
+            continue;
+        }
+        let data = std::fs::read(dir.join(name)).map_err(storage)?;
+        client
+            .put_object()
+            .bucket(bucket)
+            .key(format!("{S3_PREFIX}{name}"))
+            .body(data.into())
+            .send()
+            .await
+            .map_err(|e| storage(e.into_service_error()))?;
+    }
+    for name in remote.difference(&local.iter().cloned().collect()) {
+        client
+            .delete_object()
+            .bucket(bucket)
+            .key(format!("{S3_PREFIX}{name}"))
+            .send()
+            .await
+            .map_err(|e| storage(e.into_service_error()))?;
+    }
+    Ok(())
+}
+
+/// ETag of the index metadata in `bucket`, it changes with every upload.
+pub async fn version(client: &s3::Client, bucket: &str) -> Result<Option<String>, SearchError> {
+    match client
+        .head_object()
+        .bucket(bucket)
+        .key(format!("{S3_PREFIX}meta.json"))
+        .send()
+        .await
+    {
+        Ok(head) => Ok(head.e_tag),
+        Err(e) => {
+            let e = e.into_service_error();
+            if e.is_not_found() {
+                Ok(None)
+            } else {
+                Err(storage(e))
+            }
+        }
+    }
+}
+
+/// The right to update the index in a bucket. Whoever downloads, adds to and uploads the index
+/// without it can upload over mail somebody else just added.
+#[derive(Debug)]
+pub struct Lease {
+    e_tag: String,
+}
+
+/// Takes the lease on the index in `bucket`, `None` while somebody else holds it. A lease held
+/// longer than [`LEASE_SECONDS`] is taken over.
+pub async fn lease(client: &s3::Client, bucket: &str) -> Result<Option<Lease>, SearchError> {
+    let expires_at = now() + LEASE_SECONDS;
+    let put = || {
+        client
+            .put_object()
+            .bucket(bucket)
+            .key(format!("{S3_PREFIX}{LEASE}"))
+            .body(expires_at.to_string().into_bytes().into())
+    };
+    match put().if_none_match("*").send().await {
+        Ok(output) => return Ok(Some(Lease::of(output))),
+        Err(e) if status(&e).is_some_and(taken) => {}
+        Err(e) => return Err(storage(e.into_service_error())),
+    }
+
+    let held = match client
+        .get_object()
+        .bucket(bucket)
+        .key(format!("{S3_PREFIX}{LEASE}"))
+        .send()
+        .await
+    {
+        Ok(held) => held,
+        // released in the meantime, the next try gets it
+        Err(e) if status(&e) == Some(404) => return Ok(None),
+        Err(e) => return Err(storage(e.into_service_error())),
+    };
+    let e_tag = held.e_tag.clone().unwrap_or_default();
+    let data = held.body.collect().await.map_err(storage)?;
+    let held_until: u64 = std::str::from_utf8(&data.into_bytes())
+        .ok()
+        .and_then(|until| until.parse().ok())
+        .unwrap_or_default();
+    if held_until > now() {
+        return Ok(None);
+    }
+    match put().if_match(e_tag).send().await {
+        Ok(output) => Ok(Some(Lease::of(output))),
+        Err(e) if status(&e).is_some_and(taken) => Ok(None),
+        Err(e) => Err(storage(e.into_service_error())),
+    }
+}
+
+impl Lease {
+    fn of(output: s3::operation::put_object::PutObjectOutput) -> Self {
+        Lease {
+            e_tag: output.e_tag.unwrap_or_default(),
+        }
+    }
+
+    /// Gives the lease up, unless it ran out and was taken over since.
+    pub async fn release(self, client: &s3::Client, bucket: &str) -> Result<(), SearchError> {
+        match client
+            .delete_object()
+            .bucket(bucket)
+            .key(format!("{S3_PREFIX}{LEASE}"))
+            .if_match(self.e_tag)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) if status(&e).is_some_and(|status| taken(status) || status == 404) => Ok(()),
+            Err(e) => Err(storage(e.into_service_error())),
+        }
+    }
+}
+
+/// Whether a conditional write lost to somebody else's: 412 when the condition doesn't hold, 409
+/// when another write to the object is under way.
+fn taken(status: u16) -> bool {
+    status == 409 || status == 412
+}
+
+fn status<E>(e: &s3::error::SdkError<E, s3::config::http::HttpResponse>) -> Option<u16> {
+    e.raw_response().map(|response| response.status().as_u16())
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default()
+}
+
+/// File names of the index in `bucket`, without the prefix.
+async fn list(client: &s3::Client, bucket: &str) -> Result<HashSet<String>, SearchError> {
+    let mut names = HashSet::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(S3_PREFIX)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|e| storage(e.into_service_error()))?;
+        for object in page.contents() {
+            if let Some(name) = object.key().and_then(|key| key.strip_prefix(S3_PREFIX)) {
+                if name == LEASE {
+                    continue;
+                }
+                names.insert(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+fn is_metadata(name: &str) -> bool {
+    name == "meta.json" || name == ".managed.json"
+}
+
+fn storage(e: impl std::fmt::Display) -> SearchError {
+    SearchError::Storage(e.to_string())
+}
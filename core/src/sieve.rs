@@ -0,0 +1,1413 @@
+//! Sieve (RFC 5228) scripts, the filtering rules power users write by hand.
+//!
+//! [`Script::parse`] checks a script once, [`Script::run`] applies it to a received mail. Next to
+//! the base language the `fileinto`, `reject` (RFC 5429), `envelope`, `vacation` (RFC 5230),
+//! `imap4flags` (RFC 5232) and `copy` (RFC 3894) extensions are understood. What a script decides
+//! comes back as an [`Outcome`], acting on it is up to the caller.
+use crate::parse::addresses;
+use crate::rules::{header_values, push_once};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use mail_parser::{HeaderValue, Message};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Extensions a script can `require`.
+pub const EXTENSIONS: &[&str] = &[
+    "fileinto",
+    "reject",
+    "envelope",
+    "vacation",
+    "imap4flags",
+    "copy",
+    "comparator-i;octet",
+    "comparator-i;ascii-casemap",
+];
+
+/// Days a vacation reply isn't sent again to the same sender, when the script doesn't say.
+pub const VACATION_DAYS: u64 = 7;
+/// Most days a script can ask for between two vacation replies.
+pub const MAX_VACATION_DAYS: u64 = 30;
+
+/// Why a script was refused, `line` counts from 1.
+#[derive(Debug, Error, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[error("line {line}: {message}")]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, ScriptError> {
+    Err(ScriptError {
+        line,
+        message: message.into(),
+    })
+}
+
+/// A script that parsed, ready to run on any number of mails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Script {
+    commands: Vec<Command>,
+}
+
+/// The addresses SES received a mail for, as opposed to the ones in its header.
+#[derive(Debug, Clone, Copy)]
+pub struct Envelope<'a> {
+    /// The bounce address, empty for the null sender.
+    pub from: &'a str,
+    /// The mailbox the script belongs to.
+    pub to: &'a str,
+}
+
+/// What a script does with a mail.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// The mail is delivered to the mailbox, by `keep` or because nothing else was done with it.
+    pub keep: bool,
+    /// Folders it's filed into, each once.
+    pub folders: Vec<String>,
+    /// IMAP flags of the delivered mail, like `\Seen`, each once.
+    pub flags: Vec<String>,
+    /// Addresses it's redirected to, each once.
+    pub redirect: Vec<String>,
+    /// Why it's refused, to be told to the sender.
+    pub reject: Option<String>,
+    pub vacation: Option<VacationReply>,
+}
+
+/// An automatic answer to the sender, see [`VacationReply::message`].
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct VacationReply {
+    /// The sender, from the envelope.
+    pub to: String,
+    /// Sends the reply from this address rather than from the mailbox, one of the `:addresses`
+    /// of the script.
+    pub from: Option<String>,
+    pub subject: String,
+    pub reason: String,
+    /// `reason` is a MIME entity with its own header, not plain text.
+    pub mime: bool,
+    /// The sender isn't answered again under the same `handle` before this many days passed.
+    pub days: u64,
+    pub handle: String,
+}
+
+impl Outcome {
+    /// Whether the mail ends up in the mailbox at all.
+    pub fn delivered(&self) -> bool {
+        self.keep || !self.folders.is_empty()
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|f| f.eq_ignore_ascii_case(flag))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    If {
+        branches: Vec<(Test, Vec<Command>)>,
+        otherwise: Option<Vec<Command>>,
+    },
+    Stop,
+    Keep {
+        flags: Option<Vec<String>>,
+    },
+    Discard,
+    FileInto {
+        folder: String,
+        flags: Option<Vec<String>>,
+        copy: bool,
+    },
+    Redirect {
+        address: String,
+        copy: bool,
+    },
+    Reject {
+        reason: String,
+    },
+    Vacation(Vacation),
+    SetFlag(Vec<String>),
+    AddFlag(Vec<String>),
+    RemoveFlag(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Vacation {
+    days: Option<u64>,
+    subject: Option<String>,
+    from: Option<String>,
+    addresses: Vec<String>,
+    mime: bool,
+    handle: Option<String>,
+    reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Test {
+    Address {
+        matcher: Matcher,
+        part: AddressPart,
+        headers: Vec<String>,
+        keys: Vec<String>,
+    },
+    Envelope {
+        matcher: Matcher,
+        part: AddressPart,
+        /// `true` for `to`, `false` for `from`.
+        to: Vec<bool>,
+        keys: Vec<String>,
+    },
+    Header {
+        matcher: Matcher,
+        headers: Vec<String>,
+        keys: Vec<String>,
+    },
+    Exists(Vec<String>),
+    Size {
+        over: bool,
+        limit: u64,
+    },
+    AllOf(Vec<Test>),
+    AnyOf(Vec<Test>),
+    Not(Box<Test>),
+    True,
+    False,
+    HasFlag {
+        matcher: Matcher,
+        keys: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Matcher {
+    match_type: MatchType,
+    /// `i;octet` compares exactly, `i;ascii-casemap` (the default) ignores ASCII case.
+    octet: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MatchType {
+    Is,
+    Contains,
+    Matches,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressPart {
+    All,
+    LocalPart,
+    Domain,
+}
+
+impl Script {
+    pub fn parse(script: &str) -> Result<Script, ScriptError> {
+        let mut parser = Parser {
+            tokens: tokenize(script)?,
+            pos: 0,
+        };
+        let generic = parser.commands(false)?;
+        let mut checker = Checker {
+            capabilities: Vec::new(),
+        };
+        Ok(Script {
+            commands: checker.commands(generic, true)?,
+        })
+    }
+
+    /// Runs the script over `message`, `size` bytes long as received.
+    pub fn run(&self, message: &Message, envelope: &Envelope, size: usize) -> Outcome {
+        let mut run = Run {
+            message,
+            envelope,
+            size,
+            flags: Vec::new(),
+            implicit_keep: true,
+            outcome: Outcome::default(),
+        };
+        run.commands(&self.commands);
+        if run.implicit_keep {
+            run.outcome.keep = true;
+            let flags = run.flags.clone();
+            run.deliver_with(&flags);
+        }
+        run.outcome
+    }
+
+    /// The `vacation :from` addresses other than `mailbox`, the ones whoever saves the script
+    /// has to be allowed to send as. An error names a `:from` that's neither the mailbox nor
+    /// among the `:addresses` of its command, those replies would never be sent from it.
+    pub fn vacation_senders(&self, mailbox: &str) -> Result<Vec<&str>, String> {
+        let mut senders = Vec::new();
+        let mut commands: Vec<&Command> = self.commands.iter().collect();
+        while let Some(command) = commands.pop() {
+            match command {
+                Command::If {
+                    branches,
+                    otherwise,
+                } => {
+                    commands.extend(branches.iter().flat_map(|(_, block)| block));
+                    commands.extend(otherwise.iter().flatten());
+                }
+                Command::Vacation(Vacation {
+                    from: Some(from),
+                    addresses,
+                    ..
+                }) => {
+                    if from.eq_ignore_ascii_case(mailbox) {
+                        continue;
+                    }
+                    if !addresses.iter().any(|own| own.eq_ignore_ascii_case(from)) {
+                        return Err(format!(
+                            "vacation replies can only come from {mailbox} or its :addresses, \
+                             not \"{from}\""
+                        ));
+                    }
+                    if !senders
+                        .iter()
+                        .any(|sender: &&str| sender.eq_ignore_ascii_case(from))
+                    {
+                        senders.push(from.as_str());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(senders)
+    }
+}
+
+impl VacationReply {
+    /// The reply to `message` sent from `mailbox`, unless the script named another sender.
+    pub fn message(&self, mailbox: &str, message: &Message) -> Vec<u8> {
+        let from = self.from.as_deref().unwrap_or(mailbox);
+        reply(
+            from,
+            &self.to,
+            &self.subject,
+            &self.reason,
+            self.mime,
+            message,
+        )
+    }
+}
+
+/// Tells `sender` that `mailbox` refused `message` and why.
+pub fn rejection(mailbox: &str, sender: &str, reason: &str, message: &Message) -> Vec<u8> {
+    let subject = format!("Rejected: {}", message.subject().unwrap_or("(no subject)"));
+    let body = format!("Your message to {mailbox} was rejected:\r\n\r\n{reason}");
+    reply(mailbox, sender, &subject, &body, false, message)
+}
+
+/// An answer to `message`, marked as automatic so that it isn't answered automatically itself.
+fn reply(
+    from: &str,
+    to: &str,
+    subject: &str,
+    body: &str,
+    mime: bool,
+    message: &Message,
+) -> Vec<u8> {
+    let mut reply = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nAuto-Submitted: auto-replied\r\n",
+        one_line(from),
+        one_line(to),
+        encode_word(&one_line(subject)),
+    );
+    if let Some(id) = message.message_id() {
+        let mut references: Vec<String> = message
+            .references()
+            .as_text_list()
+            .map(|ids| ids.iter().map(|id| format!("<{id}>")).collect())
+            .unwrap_or_default();
+        references.push(format!("<{id}>"));
+        reply.push_str(&format!(
+            "In-Reply-To: <{id}>\r\nReferences: {}\r\n",
+            one_line(&references.join(" "))
+        ));
+    }
+    reply.push_str("MIME-Version: 1.0\r\n");
+    let body = body.replace("\r\n", "\n").replace('\n', "\r\n");
+    if mime {
+        // the reason starts with the header of its own entity
+        reply.push_str(&body);
+    } else {
+        reply.push_str("Content-Type: text/plain; charset=utf-8\r\n\r\n");
+        reply.push_str(&body);
+    }
+    reply.into_bytes()
+}
+
+/// `value` without the line breaks that would start another header field.
+fn one_line(value: &str) -> String {
+    value.split(['\r', '\n']).collect::<Vec<_>>().join(" ")
+}
+
+/// `value` as an RFC 2047 encoded word when it isn't plain ASCII.
+fn encode_word(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?utf-8?b?{}?=", BASE64_STANDARD.encode(value))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    /// Without the leading colon.
+    Tag(String),
+    Number(u64),
+    String(String),
+    Punct(char),
+}
+
+fn tokenize(script: &str) -> Result<Vec<(Token, usize)>, ScriptError> {
+    let chars: Vec<char> = script.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line) = (0, 1);
+    while let Some(&c) = chars.get(i) {
+        match c {
+            '\n' => {
+                line += 1;
+                i += 1;
+            }
+            c if c.is_whitespace() => i += 1,
+            '#' => {
+                while chars.get(i).is_some_and(|c| *c != '\n') {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                let start = line;
+                i += 2;
+                loop {
+                    match chars.get(i) {
+                        None => return error(start, "unterminated comment"),
+                        Some('*') if chars.get(i + 1) == Some(&'/') => {
+                            i += 2;
+                            break;
+                        }
+                        Some(c) => {
+                            line += usize::from(*c == '\n');
+                            i += 1;
+                        }
+                    }
+                }
+            }
+            '[' | ']' | '(' | ')' | '{' | '}' | ',' | ';' => {
+                tokens.push((Token::Punct(c), line));
+                i += 1;
+            }
+            '"' => {
+                let start = line;
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    let c = match chars.get(i) {
+                        None => return error(start, "unterminated string"),
+                        Some('"') => break,
+                        // any character can be escaped, it stands for itself
+                        Some('\\') if i + 1 < chars.len() => {
+                            i += 1;
+                            chars[i]
+                        }
+                        Some(c) => *c,
+                    };
+                    line += usize::from(c == '\n');
+                    value.push(c);
+                    i += 1;
+                }
+                i += 1;
+                tokens.push((Token::String(value), start));
+            }
+            ':' => {
+                let name = word(&chars[i + 1..]);
+                if name.is_empty() {
+                    return error(line, "expected a tag name after \":\"");
+                }
+                i += 1 + name.len();
+                tokens.push((Token::Tag(name.to_ascii_lowercase()), line));
+            }
+            c if c.is_ascii_digit() => {
+                let digits: String = chars[i..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect();
+                i += digits.len();
+                let shift = match chars.get(i).map(char::to_ascii_uppercase) {
+                    Some('K') => 10,
+                    Some('M') => 20,
+                    Some('G') => 30,
+                    _ => 0,
+                };
+                i += usize::from(shift > 0);
+                let number = digits
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|number| number.checked_mul(1 << shift));
+                match number {
+                    Some(number) => tokens.push((Token::Number(number), line)),
+                    None => return error(line, format!("number {digits} is too large")),
+                }
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let name = word(&chars[i..]);
+                i += name.len();
+                if name.eq_ignore_ascii_case("text") && chars.get(i) == Some(&':') {
+                    i += 1;
+                    let start = line;
+                    tokens.push((Token::String(multi_line(&chars, &mut i, &mut line)?), start));
+                } else {
+                    tokens.push((Token::Identifier(name.to_ascii_lowercase()), line));
+                }
+            }
+            c => return error(line, format!("unexpected character {c:?}")),
+        }
+    }
+    Ok(tokens)
+}
+
+fn word(chars: &[char]) -> String {
+    chars
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+        .collect()
+}
+
+/// The lines after `text:` up to the one holding only a dot, a leading dot of the others is
+/// dropped as it doubles one in the text.
+fn multi_line(chars: &[char], i: &mut usize, line: &mut usize) -> Result<String, ScriptError> {
+    let start = *line;
+    // only a comment can follow `text:` on its line
+    while let Some(&c) = chars.get(*i) {
+        match c {
+            '\n' => break,
+            '#' => {
+                while chars.get(*i).is_some_and(|c| *c != '\n') {
+                    *i += 1;
+                }
+            }
+            c if c.is_whitespace() => *i += 1,
+            _ => return error(start, "expected a line break after \"text:\""),
+        }
+    }
+    let mut value = String::new();
+    loop {
+        if *i >= chars.len() {
+            return error(start, "unterminated multi-line string");
+        }
+        // skips the line break ending the previous line
+        *i += 1;
+        *line += 1;
+        let end = chars[*i..]
+            .iter()
+            .position(|c| *c == '\n')
+            .map_or(chars.len(), |end| *i + end);
+        let text: String = chars[*i..end].iter().collect();
+        let text = text.strip_suffix('\r').unwrap_or(&text);
+        *i = end;
+        if text == "." {
+            return Ok(value);
+        }
+        value.push_str(text.strip_prefix('.').unwrap_or(text));
+        value.push_str("\r\n");
+    }
+}
+
+#[derive(Debug)]
+enum Argument {
+    Strings(Vec<String>),
+    Number(u64),
+    Tag(String),
+}
+
+#[derive(Debug)]
+struct Generic {
+    name: String,
+    line: usize,
+    arguments: Vec<Argument>,
+    tests: Vec<Generic>,
+    block: Option<Vec<Generic>>,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    /// Line of the next token, or of the last one at the end.
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(token, _)| token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn commands(&mut self, nested: bool) -> Result<Vec<Generic>, ScriptError> {
+        let mut commands = Vec::new();
+        loop {
+            match self.peek() {
+                None if nested => return error(self.line(), "expected \"}\""),
+                None => return Ok(commands),
+                Some(Token::Punct('}')) if nested => {
+                    self.pos += 1;
+                    return Ok(commands);
+                }
+                Some(Token::Identifier(_)) => commands.push(self.command()?),
+                Some(_) => return error(self.line(), "expected a command"),
+            }
+        }
+    }
+
+    fn command(&mut self) -> Result<Generic, ScriptError> {
+        let mut command = self.test()?;
+        match self.next() {
+            Some(Token::Punct(';')) => {}
+            Some(Token::Punct('{')) => command.block = Some(self.commands(true)?),
+            _ => {
+                return error(
+                    command.line,
+                    format!("expected \";\" after {}", command.name),
+                )
+            }
+        }
+        Ok(command)
+    }
+
+    /// A name and its arguments, the start of a command as well.
+    fn test(&mut self) -> Result<Generic, ScriptError> {
+        let line = self.line();
+        let Some(Token::Identifier(name)) = self.next() else {
+            return error(line, "expected a test");
+        };
+        let mut arguments = Vec::new();
+        let mut tests = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::String(_)) | Some(Token::Punct('[')) => {
+                    arguments.push(Argument::Strings(self.strings()?))
+                }
+                Some(Token::Number(number)) => {
+                    arguments.push(Argument::Number(*number));
+                    self.pos += 1;
+                }
+                Some(Token::Tag(tag)) => {
+                    arguments.push(Argument::Tag(tag.clone()));
+                    self.pos += 1;
+                }
+                Some(Token::Identifier(_)) => {
+                    tests.push(self.test()?);
+                    break;
+                }
+                Some(Token::Punct('(')) => {
+                    self.pos += 1;
+                    loop {
+                        tests.push(self.test()?);
+                        match self.next() {
+                            Some(Token::Punct(',')) => {}
+                            Some(Token::Punct(')')) => break,
+                            _ => return error(line, "expected \",\" or \")\" in a test list"),
+                        }
+                    }
+                    break;
+                }
+                _ => break,
+            }
+        }
+        Ok(Generic {
+            name,
+            line,
+            arguments,
+            tests,
+            block: None,
+        })
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, ScriptError> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::String(value)) => Ok(vec![value]),
+            Some(Token::Punct('[')) => {
+                let mut strings = Vec::new();
+                loop {
+                    match self.next() {
+                        Some(Token::String(value)) => strings.push(value),
+                        _ => return error(line, "expected a string in a string list"),
+                    }
+                    match self.next() {
+                        Some(Token::Punct(',')) => {}
+                        Some(Token::Punct(']')) => return Ok(strings),
+                        _ => return error(line, "expected \",\" or \"]\" in a string list"),
+                    }
+                }
+            }
+            _ => error(line, "expected a string"),
+        }
+    }
+}
+
+/// The arguments of one command or test, taken in order.
+struct Arguments {
+    name: String,
+    line: usize,
+    arguments: std::iter::Peekable<std::vec::IntoIter<Argument>>,
+}
+
+impl Arguments {
+    fn new(generic: &mut Generic) -> Self {
+        Arguments {
+            name: generic.name.clone(),
+            line: generic.line,
+            arguments: std::mem::take(&mut generic.arguments)
+                .into_iter()
+                .peekable(),
+        }
+    }
+
+    fn tag(&mut self) -> Option<String> {
+        match self.arguments.peek() {
+            Some(Argument::Tag(tag)) => {
+                let tag = tag.clone();
+                self.arguments.next();
+                Some(tag)
+            }
+            _ => None,
+        }
+    }
+
+    fn unexpected<T>(&self, tag: &str) -> Result<T, ScriptError> {
+        error(self.line, format!("{} doesn't take :{tag}", self.name))
+    }
+
+    fn strings(&mut self, what: &str) -> Result<Vec<String>, ScriptError> {
+        match self.arguments.next() {
+            Some(Argument::Strings(strings)) => Ok(strings),
+            _ => error(self.line, format!("{} expects {what}", self.name)),
+        }
+    }
+
+    fn string(&mut self, what: &str) -> Result<String, ScriptError> {
+        match self.strings(what)?.as_slice() {
+            [value] => Ok(value.clone()),
+            _ => error(self.line, format!("{} expects a single {what}", self.name)),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<u64, ScriptError> {
+        match self.arguments.next() {
+            Some(Argument::Number(number)) => Ok(number),
+            _ => error(self.line, format!("{} expects {what}", self.name)),
+        }
+    }
+
+    fn end(mut self) -> Result<(), ScriptError> {
+        match self.arguments.next() {
+            None => Ok(()),
+            Some(_) => error(self.line, format!("too many arguments to {}", self.name)),
+        }
+    }
+}
+
+/// Turns what parsed into commands, checking each is known, used right and required.
+struct Checker {
+    capabilities: Vec<String>,
+}
+
+impl Checker {
+    fn require(&self, extension: &str, line: usize, what: &str) -> Result<(), ScriptError> {
+        if self.capabilities.iter().any(|c| c == extension) {
+            Ok(())
+        } else {
+            error(line, format!("{what} needs require \"{extension}\""))
+        }
+    }
+
+    fn commands(&mut self, generic: Vec<Generic>, top: bool) -> Result<Vec<Command>, ScriptError> {
+        let mut commands: Vec<Command> = Vec::new();
+        // whether an elsif or else can follow
+        let mut open_if = false;
+        let mut seen_other = false;
+        for mut generic in generic {
+            let line = generic.line;
+            let name = generic.name.clone();
+            if name != "require" {
+                seen_other = true;
+            }
+            let chains = matches!(name.as_str(), "elsif" | "else");
+            if matches!(name.as_str(), "if" | "elsif" | "else") {
+                let Some(block) = generic.block.take() else {
+                    return error(line, format!("{name} needs a block"));
+                };
+                let test = if name == "else" {
+                    if !generic.tests.is_empty() {
+                        return error(line, "else doesn't take a test");
+                    }
+                    None
+                } else {
+                    Some(self.single_test(&mut generic)?)
+                };
+                Arguments::new(&mut generic).end()?;
+                let block = self.commands(block, false)?;
+                if !chains {
+                    commands.push(Command::If {
+                        branches: vec![(test.expect("if has a test"), block)],
+                        otherwise: None,
+                    });
+                    open_if = true;
+                    continue;
+                }
+                let Some(Command::If {
+                    branches,
+                    otherwise,
+                }) = commands.last_mut().filter(|_| open_if)
+                else {
+                    return error(line, format!("{name} has to follow if or elsif"));
+                };
+                match test {
+                    Some(test) => branches.push((test, block)),
+                    None => {
+                        *otherwise = Some(block);
+                        open_if = false;
+                    }
+                }
+                continue;
+            }
+            open_if = false;
+            if generic.block.is_some() {
+                return error(line, format!("{name} doesn't take a block"));
+            }
+            if !generic.tests.is_empty() {
+                return error(line, format!("{name} doesn't take a test"));
+            }
+            let mut args = Arguments::new(&mut generic);
+            let command = match name.as_str() {
+                "require" => {
+                    if !top || seen_other {
+                        return error(line, "require has to come before any other command");
+                    }
+                    for extension in args.strings("a list of extensions")? {
+                        if !EXTENSIONS.contains(&extension.as_str()) {
+                            return error(line, format!("unsupported extension \"{extension}\""));
+                        }
+                        self.capabilities.push(extension);
+                    }
+                    args.end()?;
+                    continue;
+                }
+                "stop" => Command::Stop,
+                "discard" => Command::Discard,
+                "keep" => {
+                    let mut flags = None;
+                    while let Some(tag) = args.tag() {
+                        match tag.as_str() {
+                            "flags" => {
+                                self.require("imap4flags", line, ":flags")?;
+                                flags = Some(args.strings("a list of flags")?);
+                            }
+                            _ => return args.unexpected(&tag),
+                        }
+                    }
+                    Command::Keep { flags }
+                }
+                "fileinto" => {
+                    self.require("fileinto", line, "fileinto")?;
+                    let (mut flags, mut copy) = (None, false);
+                    while let Some(tag) = args.tag() {
+                        match tag.as_str() {
+                            "flags" => {
+                                self.require("imap4flags", line, ":flags")?;
+                                flags = Some(args.strings("a list of flags")?);
+                            }
+                            "copy" => {
+                                self.require("copy", line, ":copy")?;
+                                copy = true;
+                            }
+                            _ => return args.unexpected(&tag),
+                        }
+                    }
+                    let folder = args.string("a folder")?;
+                    if folder.trim().is_empty() {
+                        return error(line, "fileinto needs a folder name");
+                    }
+                    Command::FileInto {
+                        folder,
+                        flags,
+                        copy,
+                    }
+                }
+                "redirect" => {
+                    let mut copy = false;
+                    while let Some(tag) = args.tag() {
+                        match tag.as_str() {
+                            "copy" => {
+                                self.require("copy", line, ":copy")?;
+                                copy = true;
+                            }
+                            _ => return args.unexpected(&tag),
+                        }
+                    }
+                    let address = args.string("an address")?;
+                    if !is_address(&address) {
+                        return error(line, format!("\"{address}\" is not an address"));
+                    }
+                    Command::Redirect { address, copy }
+                }
+                "reject" => {
+                    self.require("reject", line, "reject")?;
+                    Command::Reject {
+                        reason: args.string("a reason")?,
+                    }
+                }
+                "vacation" => {
+                    self.require("vacation", line, "vacation")?;
+                    let mut vacation = Vacation {
+                        days: None,
+                        subject: None,
+                        from: None,
+                        addresses: Vec::new(),
+                        mime: false,
+                        handle: None,
+                        reason: String::new(),
+                    };
+                    while let Some(tag) = args.tag() {
+                        match tag.as_str() {
+                            "days" => vacation.days = Some(args.number("a number of days")?),
+                            "subject" => vacation.subject = Some(args.string("a subject")?),
+                            "from" => {
+                                let from = args.string("an address")?;
+                                if !is_address(&from) {
+                                    return error(line, format!("\"{from}\" is not an address"));
+                                }
+                                vacation.from = Some(from);
+                            }
+                            "addresses" => vacation.addresses = args.strings("addresses")?,
+                            "mime" => vacation.mime = true,
+                            "handle" => vacation.handle = Some(args.string("a handle")?),
+                            _ => return args.unexpected(&tag),
+                        }
+                    }
+                    vacation.reason = args.string("a reason")?;
+                    Command::Vacation(vacation)
+                }
+                "setflag" | "addflag" | "removeflag" => {
+                    self.require("imap4flags", line, &name)?;
+                    let flags = args.strings("a list of flags")?;
+                    match name.as_str() {
+                        "setflag" => Command::SetFlag(flags),
+                        "addflag" => Command::AddFlag(flags),
+                        _ => Command::RemoveFlag(flags),
+                    }
+                }
+                _ => return error(line, format!("unknown command {name}")),
+            };
+            args.end()?;
+            commands.push(command);
+        }
+        Ok(commands)
+    }
+
+    fn single_test(&mut self, generic: &mut Generic) -> Result<Test, ScriptError> {
+        let tests = std::mem::take(&mut generic.tests);
+        match <[Generic; 1]>::try_from(tests) {
+            Ok([test]) => self.test(test),
+            Err(_) => error(
+                generic.line,
+                format!("{} needs a single test", generic.name),
+            ),
+        }
+    }
+
+    fn test(&mut self, mut generic: Generic) -> Result<Test, ScriptError> {
+        let line = generic.line;
+        let name = generic.name.clone();
+        match name.as_str() {
+            "allof" | "anyof" => {
+                let tests = std::mem::take(&mut generic.tests)
+                    .into_iter()
+                    .map(|test| self.test(test))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Arguments::new(&mut generic).end()?;
+                if tests.is_empty() {
+                    return error(line, format!("{name} needs a list of tests"));
+                }
+                return Ok(if name == "allof" {
+                    Test::AllOf(tests)
+                } else {
+                    Test::AnyOf(tests)
+                });
+            }
+            "not" => {
+                let test = self.single_test(&mut generic)?;
+                Arguments::new(&mut generic).end()?;
+                return Ok(Test::Not(Box::new(test)));
+            }
+            _ => {}
+        }
+        if !generic.tests.is_empty() {
+            return error(line, format!("{name} doesn't take a test"));
+        }
+        let mut args = Arguments::new(&mut generic);
+        let test = match name.as_str() {
+            "true" => Test::True,
+            "false" => Test::False,
+            "exists" => Test::Exists(args.strings("a list of header names")?),
+            "size" => {
+                let over = match args.tag().as_deref() {
+                    Some("over") => true,
+                    Some("under") => false,
+                    _ => return error(line, "size expects :over or :under"),
+                };
+                Test::Size {
+                    over,
+                    limit: args.number("a size")?,
+                }
+            }
+            "header" => {
+                let (matcher, _) = self.matcher(&mut args, false)?;
+                Test::Header {
+                    matcher,
+                    headers: args.strings("a list of header names")?,
+                    keys: args.strings("a list of keys")?,
+                }
+            }
+            "address" => {
+                let (matcher, part) = self.matcher(&mut args, true)?;
+                Test::Address {
+                    matcher,
+                    part,
+                    headers: args.strings("a list of header names")?,
+                    keys: args.strings("a list of keys")?,
+                }
+            }
+            "envelope" => {
+                self.require("envelope", line, "envelope")?;
+                let (matcher, part) = self.matcher(&mut args, true)?;
+                let to = args
+                    .strings("a list of envelope parts")?
+                    .iter()
+                    .map(|part| match part.to_ascii_lowercase().as_str() {
+                        "from" => Ok(false),
+                        "to" => Ok(true),
+                        _ => error(line, format!("unsupported envelope part \"{part}\"")),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Test::Envelope {
+                    matcher,
+                    part,
+                    to,
+                    keys: args.strings("a list of keys")?,
+                }
+            }
+            "hasflag" => {
+                self.require("imap4flags", line, "hasflag")?;
+                let (matcher, _) = self.matcher(&mut args, false)?;
+                Test::HasFlag {
+                    matcher,
+                    keys: args.strings("a list of flags")?,
+                }
+            }
+            _ => return error(line, format!("unknown test {name}")),
+        };
+        args.end()?;
+        Ok(test)
+    }
+
+    /// The leading match type, comparator and, where `addresses`, address part tags.
+    fn matcher(
+        &self,
+        args: &mut Arguments,
+        addresses: bool,
+    ) -> Result<(Matcher, AddressPart), ScriptError> {
+        let mut matcher = Matcher {
+            match_type: MatchType::Is,
+            octet: false,
+        };
+        let mut part = AddressPart::All;
+        while let Some(tag) = args.tag() {
+            match tag.as_str() {
+                "is" => matcher.match_type = MatchType::Is,
+                "contains" => matcher.match_type = MatchType::Contains,
+                "matches" => matcher.match_type = MatchType::Matches,
+                "comparator" => match args.string("a comparator")?.as_str() {
+                    "i;ascii-casemap" => matcher.octet = false,
+                    "i;octet" => {
+                        self.require("comparator-i;octet", args.line, "i;octet")?;
+                        matcher.octet = true;
+                    }
+                    comparator => {
+                        return error(
+                            args.line,
+                            format!("unsupported comparator \"{comparator}\""),
+                        )
+                    }
+                },
+                "all" if addresses => part = AddressPart::All,
+                "localpart" if addresses => part = AddressPart::LocalPart,
+                "domain" if addresses => part = AddressPart::Domain,
+                _ => return args.unexpected(&tag),
+            }
+        }
+        Ok((matcher, part))
+    }
+}
+
+fn is_address(value: &str) -> bool {
+    value
+        .split_once('@')
+        .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+        && !value.contains(|c: char| c.is_whitespace() || c == ',')
+}
+
+impl Matcher {
+    fn matches(&self, value: &str, key: &str) -> bool {
+        let (value, key) = if self.octet {
+            (value.to_string(), key.to_string())
+        } else {
+            (value.to_ascii_lowercase(), key.to_ascii_lowercase())
+        };
+        match self.match_type {
+            MatchType::Is => value == key,
+            MatchType::Contains => value.contains(&key),
+            MatchType::Matches => wildcard(&value, &key),
+        }
+    }
+
+    fn any<S: AsRef<str>>(&self, values: &[S], keys: &[String]) -> bool {
+        values
+            .iter()
+            .any(|value| keys.iter().any(|key| self.matches(value.as_ref(), key)))
+    }
+}
+
+/// Whether `value` matches `pattern`, where `*` stands for any text, `?` for one character and
+/// `\` escapes the next one.
+fn wildcard(value: &str, pattern: &str) -> bool {
+    enum Piece {
+        Many,
+        One,
+        Char(char),
+    }
+    let mut pieces = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        pieces.push(match c {
+            '*' => Piece::Many,
+            '?' => Piece::One,
+            '\\' => Piece::Char(chars.next().unwrap_or('\\')),
+            c => Piece::Char(c),
+        });
+    }
+    let value: Vec<char> = value.chars().collect();
+    let (mut v, mut p) = (0, 0);
+    // the last `*` and where in `value` it's tried to end next
+    let mut star = None;
+    while v < value.len() {
+        match pieces.get(p) {
+            Some(Piece::Many) => {
+                star = Some((p, v));
+                p += 1;
+            }
+            Some(Piece::One) => {
+                v += 1;
+                p += 1;
+            }
+            Some(Piece::Char(c)) if *c == value[v] => {
+                v += 1;
+                p += 1;
+            }
+            _ => match star {
+                Some((star_p, star_v)) => {
+                    star = Some((star_p, star_v + 1));
+                    p = star_p + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pieces[p..].iter().all(|piece| matches!(piece, Piece::Many))
+}
+
+/// The state of one run of a script.
+struct Run<'a, 'm> {
+    message: &'a Message<'m>,
+    envelope: &'a Envelope<'a>,
+    size: usize,
+    /// The flags `keep` and `fileinto` use without `:flags`.
+    flags: Vec<String>,
+    implicit_keep: bool,
+    outcome: Outcome,
+}
+
+impl Run<'_, '_> {
+    /// Runs `commands`, `false` once `stop` was reached.
+    fn commands(&mut self, commands: &[Command]) -> bool {
+        for command in commands {
+            match command {
+                Command::If {
+                    branches,
+                    otherwise,
+                } => {
+                    let block = branches
+                        .iter()
+                        .find(|(test, _)| self.test(test))
+                        .map(|(_, block)| block)
+                        .or(otherwise.as_ref());
+                    if let Some(block) = block {
+                        if !self.commands(block) {
+                            return false;
+                        }
+                    }
+                }
+                Command::Stop => return false,
+                Command::Keep { flags } => {
+                    self.implicit_keep = false;
+                    self.outcome.keep = true;
+                    let flags = flags.clone().unwrap_or_else(|| self.flags.clone());
+                    self.deliver_with(&flags);
+                }
+                Command::Discard => self.implicit_keep = false,
+                Command::FileInto {
+                    folder,
+                    flags,
+                    copy,
+                } => {
+                    self.implicit_keep &= *copy;
+                    push_once(&mut self.outcome.folders, folder);
+                    let flags = flags.clone().unwrap_or_else(|| self.flags.clone());
+                    self.deliver_with(&flags);
+                }
+                Command::Redirect { address, copy } => {
+                    self.implicit_keep &= *copy;
+                    push_once(&mut self.outcome.redirect, address);
+                }
+                Command::Reject { reason } => {
+                    self.implicit_keep = false;
+                    self.outcome.reject.get_or_insert_with(|| reason.clone());
+                }
+                Command::Vacation(vacation) => {
+                    if self.outcome.vacation.is_none() {
+                        self.outcome.vacation = self.vacation(vacation);
+                    }
+                }
+                Command::SetFlag(flags) => {
+                    self.flags.clear();
+                    add_flags(&mut self.flags, flags);
+                }
+                Command::AddFlag(flags) => add_flags(&mut self.flags, flags),
+                Command::RemoveFlag(flags) => {
+                    let removed = split_flags(flags);
+                    self.flags
+                        .retain(|flag| !removed.iter().any(|r| r.eq_ignore_ascii_case(flag)));
+                }
+            }
+        }
+        true
+    }
+
+    /// Adds `flags` to the ones of the delivered mail, which is stored only once.
+    fn deliver_with(&mut self, flags: &[String]) {
+        add_flags(&mut self.outcome.flags, flags);
+    }
+
+    fn test(&self, test: &Test) -> bool {
+        match test {
+            Test::Address {
+                matcher,
+                part,
+                headers,
+                keys,
+            } => {
+                let values: Vec<String> = headers
+                    .iter()
+                    .flat_map(|name| address_values(self.message, name))
+                    .map(|address| address_part(&address, *part).to_string())
+                    .collect();
+                matcher.any(&values, keys)
+            }
+            Test::Envelope {
+                matcher,
+                part,
+                to,
+                keys,
+            } => {
+                let values: Vec<&str> = to
+                    .iter()
+                    .map(|to| {
+                        let address = if *to {
+                            self.envelope.to
+                        } else {
+                            self.envelope.from
+                        };
+                        address_part(address, *part)
+                    })
+                    .collect();
+                matcher.any(&values, keys)
+            }
+            Test::Header {
+                matcher,
+                headers,
+                keys,
+            } => {
+                let values: Vec<String> = headers
+                    .iter()
+                    .flat_map(|name| header_values(self.message, name))
+                    .collect();
+                matcher.any(&values, keys)
+            }
+            Test::Exists(headers) => headers.iter().all(|name| {
+                self.message
+                    .headers()
+                    .iter()
+                    .any(|header| header.name().eq_ignore_ascii_case(name))
+            }),
+            Test::Size { over, limit } => {
+                let size = self.size as u64;
+                if *over {
+                    size > *limit
+                } else {
+                    size < *limit
+                }
+            }
+            Test::AllOf(tests) => tests.iter().all(|test| self.test(test)),
+            Test::AnyOf(tests) => tests.iter().any(|test| self.test(test)),
+            Test::Not(test) => !self.test(test),
+            Test::True => true,
+            Test::False => false,
+            Test::HasFlag { matcher, keys } => matcher.any(&self.flags, keys),
+        }
+    }
+
+    /// The reply `vacation` asks for, unless the mail shouldn't be answered automatically: it
+    /// isn't addressed to the mailbox, comes from a list or another robot, or has no sender.
+    fn vacation(&self, vacation: &Vacation) -> Option<VacationReply> {
+        let sender = self.envelope.from.trim_matches(['<', '>']);
+        let local = address_part(sender, AddressPart::LocalPart).to_ascii_lowercase();
+        if sender.is_empty()
+            || local.starts_with("owner-")
+            || local.ends_with("-request")
+            || ["mailer-daemon", "listserv", "majordomo"].contains(&local.as_str())
+        {
+            return None;
+        }
+        let automatic = self.message.headers().iter().any(|header| {
+            let name = header.name();
+            name.to_ascii_lowercase().starts_with("list-")
+                || (name.eq_ignore_ascii_case("Auto-Submitted")
+                    && header_values(self.message, name)
+                        .iter()
+                        .any(|value| !value.trim().eq_ignore_ascii_case("no")))
+                || (name.eq_ignore_ascii_case("Precedence")
+                    && header_values(self.message, name).iter().any(|value| {
+                        ["bulk", "list", "junk"]
+                            .contains(&value.trim().to_ascii_lowercase().as_str())
+                    }))
+        });
+        if automatic {
+            return None;
+        }
+        let own: Vec<&str> = std::iter::once(self.envelope.to)
+            .chain(vacation.addresses.iter().map(String::as_str))
+            .collect();
+        let is_own = |address: &str| own.iter().any(|own| own.eq_ignore_ascii_case(address));
+        let addressed = ["To", "Cc", "Bcc", "Resent-To", "Resent-Cc", "Resent-Bcc"]
+            .iter()
+            .flat_map(|name| address_values(self.message, name))
+            .any(|address| is_own(&address));
+        if !addressed || is_own(sender) {
+            return None;
+        }
+
+        let subject = vacation.subject.clone().unwrap_or_else(|| {
+            format!("Auto: {}", self.message.subject().unwrap_or("(no subject)"))
+        });
+        let handle = vacation.handle.clone().unwrap_or_else(|| {
+            // the same arguments make the same reply, so the same handle
+            let text = format!(
+                "{}\0{}\0{}",
+                vacation.subject.as_deref().unwrap_or_default(),
+                vacation.from.as_deref().unwrap_or_default(),
+                vacation.reason
+            );
+            format!("{:016x}", fnv1a(text.as_bytes()))
+        });
+        Some(VacationReply {
+            to: sender.to_string(),
+            // anything else is left for the mailbox to answer from, see Script::vacation_senders
+            from: vacation.from.clone().filter(|from| is_own(from)),
+            subject,
+            reason: vacation.reason.clone(),
+            mime: vacation.mime,
+            days: vacation
+                .days
+                .unwrap_or(VACATION_DAYS)
+                .clamp(1, MAX_VACATION_DAYS),
+            handle,
+        })
+    }
+}
+
+/// Every address in the headers called `name`, a header the parser didn't read as addresses
+/// counts as one.
+fn address_values(message: &Message, name: &str) -> Vec<String> {
+    message
+        .headers()
+        .iter()
+        .filter(|header| header.name().eq_ignore_ascii_case(name))
+        .flat_map(|header| match &header.value {
+            HeaderValue::Text(text) => vec![text.trim().to_string()],
+            value => addresses(value)
+                .filter_map(|addr| addr.address.as_deref().map(str::to_string))
+                .collect(),
+        })
+        .collect()
+}
+
+fn address_part(address: &str, part: AddressPart) -> &str {
+    match (part, address.rsplit_once('@')) {
+        (AddressPart::All, _) => address,
+        (AddressPart::LocalPart, Some((local, _))) => local,
+        (AddressPart::Domain, Some((_, domain))) => domain,
+        (AddressPart::LocalPart, None) => address,
+        (AddressPart::Domain, None) => "",
+    }
+}
+
+/// Adds each flag in `flags`, where a string can hold several separated by spaces, once
+/// ignoring case.
+fn add_flags(list: &mut Vec<String>, flags: &[String]) {
+    for flag in split_flags(flags) {
+        if !list
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(&flag))
+        {
+            list.push(flag);
+        }
+    }
+}
+
+fn split_flags(flags: &[String]) -> Vec<String> {
+    flags
+        .iter()
+        .flat_map(|flags| flags.split_whitespace())
+        .map(str::to_string)
+        .collect()
+}
+
+/// 64-bit FNV-1a, stable across builds unlike the hasher of the standard library.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
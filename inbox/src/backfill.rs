@@ -0,0 +1,429 @@
+//! `inbox backfill`, rebuilds what's kept about the mail in `MAIL_BUCKET` from the raw messages,
+//! for when `MAIL_DB` was lost or its items need writing anew:
+//!
+//! - `inbox backfill [--prefix <prefix>]` walks every mail, or those whose id starts with `prefix`
+//! - `--concurrency <n>` how many mails are read and written at once, 8 by default
+//! - `--checkpoint <path>` where the walk is recorded, it resumes from there unless `--restart`
+//! - `--domain <domain>` also delivers to addresses at `domain` that have no mailbox yet
+//! - `--recount` counts every mailbox's mail afresh once the walk is done
+//!
+//! Received mail is listed in the mailboxes of its `To` and `Cc` addresses and added to the search
+//! index, copies of sent mail are listed in the sent folder of their sender. A mail is delivered
+//! once per mailbox and message id, so mail already listed is left alone even when the time it's
+//! listed under differs from the object's, flags and labels included, and neither rules nor
+//! scripts run again. Mail that can't be listed is kept as a dead letter for `inbox reprocess`.
+//! The search index is updated under the lease the inbox Lambda takes too, so the two can run at
+//! once.
+use crate::dead_letter::DeadLetters;
+use crate::{deliver, get_summary, index_mails, users, Inbox};
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3 as s3;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use lambda_runtime::Error;
+use mail_parser::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+use supermailer_core::dead_letter::{header_recipients, record_of, DeadLetter};
+use supermailer_core::item::MailItem;
+use supermailer_core::mail::{sent_mailbox, Flags, Mail};
+use supermailer_core::parse::{addresses, format_addresses, is_received};
+use supermailer_core::search;
+
+const USAGE: &str = "usage: inbox backfill [--prefix <prefix>] [--concurrency <n>] \
+    [--checkpoint <path>] [--restart] [--domain <domain>]... [--recount]";
+
+struct Options {
+    prefix: String,
+    concurrency: usize,
+    checkpoint: PathBuf,
+    restart: bool,
+    domains: Vec<String>,
+    recount: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, Error> {
+    let mut options = Options {
+        prefix: String::new(),
+        concurrency: 8,
+        checkpoint: env::temp_dir().join("supermailer-backfill.json"),
+        restart: false,
+        domains: Vec::new(),
+        recount: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--prefix" => options.prefix = args.next().ok_or(USAGE)?.clone(),
+            "--concurrency" => {
+                options.concurrency = args.next().ok_or(USAGE)?.parse()?;
+                if options.concurrency == 0 {
+                    return Err(USAGE.into());
+                }
+            }
+            "--checkpoint" => options.checkpoint = args.next().ok_or(USAGE)?.into(),
+            "--restart" => options.restart = true,
+            "--domain" => options
+                .domains
+                .push(args.next().ok_or(USAGE)?.to_lowercase()),
+            "--recount" => options.recount = true,
+            _ => return Err(USAGE.into()),
+        }
+    }
+    Ok(options)
+}
+
+/// How far a walk got, saved after every page of the listing. Objects are listed in key order,
+/// so everything up to `start_after` is done.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Checkpoint {
+    bucket: String,
+    prefix: String,
+    /// Key of the last object walked.
+    start_after: Option<String>,
+    /// Mails that were missing from at least one mailbox.
+    added: usize,
+    /// Mails that were listed everywhere already.
+    known: usize,
+    /// Mails with nobody to list them for, or that don't parse.
+    skipped: usize,
+    failed: usize,
+}
+
+impl Checkpoint {
+    /// The checkpoint at `path`, a fresh one when there's none.
+    fn load(path: &Path, bucket: &str, prefix: &str) -> Result<Self, Error> {
+        let checkpoint = match fs::read(path) {
+            Ok(json) => serde_json::from_slice::<Checkpoint>(&json)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Checkpoint {
+                bucket: bucket.to_string(),
+                prefix: prefix.to_string(),
+                ..Checkpoint::default()
+            },
+            Err(e) => return Err(e.into()),
+        };
+        if checkpoint.bucket != bucket || checkpoint.prefix != prefix {
+            return Err(format!(
+                "{} is a checkpoint of {:?} in {:?}, pass --restart to start over",
+                path.display(),
+                checkpoint.prefix,
+                checkpoint.bucket
+            )
+            .into());
+        }
+        Ok(checkpoint)
+    }
+
+    fn save(&self, path: &Path) -> Result<(), Error> {
+        // a walk stopped halfway through writing it would otherwise start over
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(partial, path)?;
+        Ok(())
+    }
+}
+
+/// A received mail, the mailboxes it's listed in and its contents.
+type Indexed = (MailItem, Vec<String>, Vec<u8>);
+
+/// What became of a mail.
+enum Outcome {
+    /// `added` is false when every mailbox had it already. Received mail comes with what to
+    /// index it with.
+    Listed {
+        added: bool,
+        indexed: Option<Box<Indexed>>,
+    },
+    Skipped,
+    Failed(Option<Box<DeadLetter>>),
+}
+
+pub async fn run(args: &[String]) -> Result<(), Error> {
+    let options = parse_args(args)?;
+    let inbox = Inbox::from_env().await;
+    let dead_letters = DeadLetters::from_env(&inbox.aws_config);
+    let s3 = s3::Client::new(&inbox.aws_config);
+
+    let mut checkpoint = if options.restart {
+        Checkpoint {
+            bucket: inbox.mail_bucket.clone(),
+            prefix: options.prefix.clone(),
+            ..Checkpoint::default()
+        }
+    } else {
+        Checkpoint::load(&options.checkpoint, &inbox.mail_bucket, &options.prefix)?
+    };
+    if let Some(key) = &checkpoint.start_after {
+        println!("Resuming after {key:?}");
+    }
+
+    // by lowercase address, mail is listed under the spelling the mailbox was registered with
+    let mailboxes: HashMap<String, String> = users::mailboxes(&inbox.client, &inbox.user_db)
+        .await?
+        .into_iter()
+        .map(|mailbox| (mailbox.to_lowercase(), mailbox))
+        .collect();
+    let recipients = Recipients {
+        mailboxes,
+        domains: options.domains.clone(),
+    };
+
+    let mut pages = s3
+        .list_objects_v2()
+        .bucket(&inbox.mail_bucket)
+        .prefix(&options.prefix)
+        .set_start_after(checkpoint.start_after.clone())
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|e| e.into_service_error())?;
+        let keys: Vec<&str> = page.contents().iter().filter_map(|o| o.key()).collect();
+        let Some(last) = keys.last().map(|key| key.to_string()) else {
+            continue;
+        };
+        let outcomes: Vec<Outcome> = stream::iter(
+            keys.iter()
+                .filter(|key| !key.starts_with(search::S3_PREFIX))
+                .map(|key| backfill(&inbox, &s3, &recipients, key)),
+        )
+        .buffer_unordered(options.concurrency)
+        .collect()
+        .await;
+
+        let mut indexed = Vec::new();
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Listed {
+                    added,
+                    indexed: mail,
+                } => {
+                    if added {
+                        checkpoint.added += 1;
+                    } else {
+                        checkpoint.known += 1;
+                    }
+                    indexed.extend(mail.map(|mail| *mail));
+                }
+                Outcome::Skipped => checkpoint.skipped += 1,
+                Outcome::Failed(letter) => {
+                    if let Some(letter) = letter {
+                        dead_letters.push(&letter).await?;
+                    }
+                    checkpoint.failed += 1;
+                }
+            }
+        }
+        // adding a mail again replaces it, a page indexed twice after a resume is fine
+        if !indexed.is_empty() {
+            let items: Vec<&MailItem> = indexed.iter().map(|(item, _, _)| item).collect();
+            let recipients: Vec<Vec<String>> =
+                indexed.iter().map(|(_, to, _)| to.clone()).collect();
+            let contents: Vec<&[u8]> = indexed.iter().map(|(_, _, c)| c.as_slice()).collect();
+            index_mails(
+                &items,
+                &recipients,
+                &contents,
+                &inbox.mail_bucket,
+                &inbox.aws_config,
+            )
+            .await?;
+        }
+
+        checkpoint.start_after = Some(last);
+        checkpoint.save(&options.checkpoint)?;
+        println!(
+            "Walked up to {:?}: {} added, {} known, {} skipped, {} failed",
+            checkpoint.start_after,
+            checkpoint.added,
+            checkpoint.known,
+            checkpoint.skipped,
+            checkpoint.failed
+        );
+    }
+
+    if options.recount {
+        for mailbox in users::mailboxes(&inbox.client, &inbox.user_db).await? {
+            let count =
+                users::recount(&inbox.client, &inbox.mail_db, &inbox.user_db, &mailbox).await?;
+            println!("Counted {count} mails in {mailbox:?}");
+        }
+    }
+
+    println!("Backfilled {:?}", inbox.mail_bucket);
+    if checkpoint.failed > 0 {
+        return Err(format!("{} mails failed to backfill", checkpoint.failed).into());
+    }
+    Ok(())
+}
+
+/// Who received mail is listed for.
+struct Recipients {
+    mailboxes: HashMap<String, String>,
+    domains: Vec<String>,
+}
+
+impl Recipients {
+    /// The mailbox of `address`, when it has one or is at one of the domains.
+    fn mailbox(&self, address: &str) -> Option<String> {
+        let address = address.to_lowercase();
+        if let Some(mailbox) = self.mailboxes.get(&address) {
+            return Some(mailbox.clone());
+        }
+        let (_, domain) = address.rsplit_once('@')?;
+        self.domains.iter().any(|d| d == domain).then_some(address)
+    }
+}
+
+/// Lists the mail stored under `key` wherever it's missing.
+async fn backfill(inbox: &Inbox, s3: &s3::Client, recipients: &Recipients, key: &str) -> Outcome {
+    let object = match s3
+        .get_object()
+        .bucket(&inbox.mail_bucket)
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(object) => object,
+        Err(error) => {
+            println!("Error reading {key:?}: {:?}", error.into_service_error());
+            return Outcome::Failed(None);
+        }
+    };
+    let stored_at = object
+        .last_modified()
+        .and_then(|at| DateTime::from_timestamp(at.secs(), 0))
+        .unwrap_or_else(Utc::now);
+    let contents = match object.body.collect().await {
+        Ok(data) => data.into_bytes().to_vec(),
+        Err(error) => {
+            println!("Error reading {key:?}: {error:?}");
+            return Outcome::Failed(None);
+        }
+    };
+    let Some(message) = Message::parse(&contents) else {
+        println!("Skipping {key:?}: not a valid message");
+        return Outcome::Skipped;
+    };
+
+    if !is_received(&message) {
+        let sender = addresses(message.from())
+            .find_map(|addr| addr.address.as_deref())
+            .and_then(|address| recipients.mailbox(address));
+        if let Some(sender) = sender {
+            return list_sent(inbox, key, &message, &contents, &sender, stored_at).await;
+        }
+    }
+
+    let mailboxes: Vec<String> = header_recipients(&message)
+        .iter()
+        .filter_map(|address| recipients.mailbox(address))
+        .collect();
+    if mailboxes.is_empty() {
+        println!("Skipping {key:?}: nobody to list it for");
+        return Outcome::Skipped;
+    }
+    let record = record_of(key, &message, &mailboxes, stored_at);
+    let items = match MailItem::from_ses(&record.ses) {
+        Ok(items) => items,
+        Err(error) => {
+            println!("Error listing {key:?}: {error:?}");
+            return Outcome::Failed(None);
+        }
+    };
+    let (first_sentence, thread_id) = get_summary(&contents);
+
+    let mut added = false;
+    let mut failure: Option<DeadLetter> = None;
+    for item in &items {
+        let item = MailItem {
+            first_sentence: Some(first_sentence.clone()),
+            thread_id: thread_id.clone(),
+            ..item.clone()
+        };
+        match deliver(&inbox.client, &item, &inbox.mail_db, &inbox.user_db).await {
+            Ok(listed) => added |= listed.is_some(),
+            Err(error) => {
+                println!("Error listing {key:?} in {:?}: {error:?}", item.pk);
+                failure
+                    .get_or_insert_with(|| DeadLetter {
+                        record: record.clone(),
+                        mailboxes: Vec::new(),
+                        error: error.to_string(),
+                        failed_at: Utc::now().timestamp(),
+                    })
+                    .mailboxes
+                    .push(item.pk.clone());
+            }
+        }
+    }
+    if failure.is_some() {
+        return Outcome::Failed(failure.map(Box::new));
+    }
+    Outcome::Listed {
+        added,
+        indexed: Some(Box::new((items[0].clone(), mailboxes, contents))),
+    }
+}
+
+/// Lists a copy of mail sent from `sender` in its sent folder, the way the web app does after
+/// sending it.
+async fn list_sent(
+    inbox: &Inbox,
+    key: &str,
+    message: &Message<'_>,
+    contents: &[u8],
+    sender: &str,
+    stored_at: DateTime<Utc>,
+) -> Outcome {
+    let (first_sentence, thread_id) = get_summary(contents);
+    let sent = Mail {
+        pk: sent_mailbox(sender),
+        sk: message
+            .date()
+            .map(|date| date.to_timestamp())
+            .unwrap_or_else(|| stored_at.timestamp()),
+        message_id: key.to_string(),
+        subject: message.subject().unwrap_or_default().to_string(),
+        from: format_addresses(message.from()),
+        first_sentence,
+        thread_id: thread_id.unwrap_or_default(),
+        flags: Flags {
+            seen: true,
+            ..Flags::default()
+        },
+        labels: Vec::new(),
+    };
+    let item = match MailItem::from_mail(&sent).to_item() {
+        Ok(item) => item,
+        Err(error) => {
+            println!("Error listing {key:?}: {error:?}");
+            return Outcome::Failed(None);
+        }
+    };
+    let result = inbox
+        .client
+        .put_item()
+        .table_name(&inbox.mail_db)
+        .set_item(Some(item))
+        .condition_expression("attribute_not_exists(pk) OR message_id <> :message_id")
+        .expression_attribute_values(":message_id", AttributeValue::S(key.to_string()))
+        .send()
+        .await;
+    match result.map_err(|e| e.into_service_error()) {
+        Ok(_) => Outcome::Listed {
+            added: true,
+            indexed: None,
+        },
+        Err(PutItemError::ConditionalCheckFailedException(_)) => Outcome::Listed {
+            added: false,
+            indexed: None,
+        },
+        Err(error) => {
+            println!("Error listing {key:?} in {:?}: {error:?}", sent.pk);
+            Outcome::Failed(None)
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Where mail the inbox couldn't deliver is kept: the SQS queue `DEAD_LETTER_QUEUE` names, or
+//! the directory `DEAD_LETTER_DIR` standing in for it when there's no queue.
+use aws_config::SdkConfig;
+use aws_sdk_sqs as sqs;
+use lambda_runtime::Error;
+use std::env;
+use std::path::PathBuf;
+use supermailer_core::dead_letter::{DeadLetter, DirQueue};
+
+/// How long a dead letter taken off the queue stays hidden from everyone else, long enough for
+/// `inbox reprocess` to deliver a few thousand.
+const TAKEN_SECONDS: i32 = 15 * 60;
+
+pub enum DeadLetters {
+    Queue { client: sqs::Client, url: String },
+    Dir(DirQueue),
+}
+
+/// A dead letter taken off the store, to be removed once it's delivered or released otherwise.
+pub struct Taken {
+    pub letter: DeadLetter,
+    /// Receipt handle of the queue message.
+    receipt: Option<String>,
+}
+
+impl DeadLetters {
+    pub fn from_env(aws_config: &SdkConfig) -> Self {
+        match env::var("DEAD_LETTER_QUEUE") {
+            Ok(url) => DeadLetters::Queue {
+                client: sqs::Client::new(aws_config),
+                url,
+            },
+            Err(_) => DeadLetters::Dir(DirQueue::new(
+                env::var("DEAD_LETTER_DIR")
+                    .map(PathBuf::from)
+                    .unwrap_or_else(|_| env::temp_dir().join("supermailer-dead-letters")),
+            )),
+        }
+    }
+
+    pub async fn push(&self, letter: &DeadLetter) -> Result<(), Error> {
+        match self {
+            DeadLetters::Queue { client, url } => {
+                client
+                    .send_message()
+                    .queue_url(url)
+                    .message_body(serde_json::to_string(letter)?)
+                    .send()
+                    .await?;
+            }
+            DeadLetters::Dir(dir) => dir.push(letter)?,
+        }
+        Ok(())
+    }
+
+    /// Every dead letter, oldest failure first for the directory and in no particular order for
+    /// the queue. Messages that aren't dead letters are left on the queue.
+    pub async fn take_all(&self) -> Result<Vec<Taken>, Error> {
+        let (client, url) = match self {
+            DeadLetters::Queue { client, url } => (client, url),
+            DeadLetters::Dir(dir) => {
+                return Ok(dir
+                    .list()?
+                    .into_iter()
+                    .map(|letter| Taken {
+                        letter,
+                        receipt: None,
+                    })
+                    .collect())
+            }
+        };
+        let mut taken = Vec::new();
+        // long polling, a short poll samples a few servers and can come back empty early
+        loop {
+            let received = client
+                .receive_message()
+                .queue_url(url)
+                .max_number_of_messages(10)
+                .visibility_timeout(TAKEN_SECONDS)
+                .wait_time_seconds(20)
+                .send()
+                .await?;
+            let messages = received.messages();
+            if messages.is_empty() {
+                return Ok(taken);
+            }
+            for message in messages {
+                let letter = message
+                    .body()
+                    .and_then(|body| serde_json::from_str::<DeadLetter>(body).ok());
+                match letter {
+                    Some(letter) => taken.push(Taken {
+                        letter,
+                        receipt: message.receipt_handle().map(str::to_string),
+                    }),
+                    None => println!("Skipping {:?}: not a dead letter", message.message_id()),
+                }
+            }
+        }
+    }
+
+    /// Forgets `taken`, its mail was delivered.
+    pub async fn remove(&self, taken: &Taken) -> Result<(), Error> {
+        match self {
+            DeadLetters::Queue { client, url } => {
+                client
+                    .delete_message()
+                    .queue_url(url)
+                    .set_receipt_handle(taken.receipt.clone())
+                    .send()
+                    .await?;
+            }
+            DeadLetters::Dir(dir) => dir.remove(taken.letter.message_id())?,
+        }
+        Ok(())
+    }
+
+    /// Replaces `taken` with `letter`, the same mail failing again.
+    pub async fn replace(&self, taken: &Taken, letter: &DeadLetter) -> Result<(), Error> {
+        self.push(letter).await?;
+        // the directory keeps a single file per mail, which was just overwritten
+        if let DeadLetters::Queue { .. } = self {
+            self.remove(taken).await?;
+        }
+        Ok(())
+    }
+
+    /// Puts `taken` back for later, untouched.
+    pub async fn release(&self, taken: &Taken) -> Result<(), Error> {
+        if let DeadLetters::Queue { client, url } = self {
+            client
+                .change_message_visibility()
+                .queue_url(url)
+                .set_receipt_handle(taken.receipt.clone())
+                .visibility_timeout(0)
+                .send()
+                .await?;
+        }
+        Ok(())
+    }
+}
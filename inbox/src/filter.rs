@@ -0,0 +1,271 @@
+//! Runs the filtering rules and then the Sieve script of a mailbox over a received mail, see
+//! `supermailer_core::rules` and `supermailer_core::sieve`.
+use crate::users;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_sesv2 as sesv2;
+use lambda_runtime::Error;
+use mail_parser::Message;
+use sesv2::primitives::Blob;
+use sesv2::types::{Destination, EmailContent, RawMessage};
+use std::time::{SystemTime, UNIX_EPOCH};
+use supermailer_core::item::{
+    vacation_of, AssignmentItem, Item, MailItem, RulesItem, SieveItem, RULES_PK, SIEVE_PK,
+};
+use supermailer_core::mail::{labels_of, Flags, Label};
+use supermailer_core::rules;
+use supermailer_core::sieve::{self, Envelope, Script, VacationReply};
+
+/// What the rules and the Sieve script of a mailbox do with a received mail, together.
+#[derive(Debug, Default)]
+pub struct Delivery {
+    pub drop: bool,
+    /// Label ids, each once.
+    pub labels: Vec<String>,
+    /// Set on top of the ones the mail arrived with.
+    pub flags: Flags,
+    /// Addresses, each once.
+    pub forward: Vec<String>,
+    /// Why the script refused the mail, to be told to the sender.
+    pub reject: Option<String>,
+    pub vacation: Option<VacationReply>,
+}
+
+/// What happens to the raw message `contents` `sender` sent to `mailbox`, going by what's set up
+/// for it in `user_db`. The script only sees the mail the rules keep, and labels deleted since
+/// a rule or script named them are left out.
+pub async fn delivery(
+    client: &Client,
+    user_db: &str,
+    mailbox: &str,
+    sender: &str,
+    contents: &[u8],
+) -> Result<Delivery, Error> {
+    let Some(message) = Message::parse(contents) else {
+        return Ok(Delivery::default());
+    };
+    let rules = match get(client, user_db, RULES_PK, mailbox).await? {
+        Some(item) => RulesItem::try_from(item)?.rules()?,
+        None => Vec::new(),
+    };
+    let outcome = rules::evaluate(&rules, &message, contents.len());
+    let mut delivery = Delivery {
+        drop: outcome.drop,
+        labels: outcome.labels,
+        flags: Flags {
+            seen: outcome.seen,
+            archived: outcome.archived,
+            ..Flags::default()
+        },
+        forward: outcome.forward,
+        ..Delivery::default()
+    };
+
+    let mut folders = Vec::new();
+    if !delivery.drop {
+        if let Some(item) = get(client, user_db, SIEVE_PK, mailbox).await? {
+            let envelope = Envelope {
+                from: sender,
+                to: mailbox,
+            };
+            let outcome = Script::parse(&SieveItem::try_from(item)?.script)?.run(
+                &message,
+                &envelope,
+                contents.len(),
+            );
+            delivery.drop = !outcome.delivered();
+            delivery.flags.seen |= outcome.has_flag("\\Seen");
+            delivery.flags.flagged |= outcome.has_flag("\\Flagged");
+            delivery.flags.deleted |= outcome.has_flag("\\Deleted");
+            for address in outcome.redirect {
+                if !delivery.forward.contains(&address) {
+                    delivery.forward.push(address);
+                }
+            }
+            delivery.reject = outcome.reject;
+            delivery.vacation = outcome.vacation;
+            // the mail is stored once, a copy kept in the inbox can't be archived as well
+            if !outcome.keep {
+                delivery.flags.archived |= has_folder(&outcome.folders, "Archive");
+                delivery.flags.deleted |= has_folder(&outcome.folders, "Trash");
+            }
+            folders = outcome.folders;
+        }
+    }
+
+    if !delivery.labels.is_empty() || !folders.is_empty() {
+        let labels = list_labels(client, user_db, mailbox).await?;
+        delivery
+            .labels
+            .retain(|label_id| labels.iter().any(|label| &label.label_id == label_id));
+        // a script files into folders, which are the labels of the mailbox by name
+        for folder in &folders {
+            match labels
+                .iter()
+                .find(|label| label.name.eq_ignore_ascii_case(folder))
+            {
+                Some(label) if !delivery.labels.contains(&label.label_id) => {
+                    delivery.labels.push(label.label_id.clone())
+                }
+                Some(_) => {}
+                None if ["INBOX", "Archive", "Trash"]
+                    .iter()
+                    .any(|special| special.eq_ignore_ascii_case(folder)) => {}
+                None => println!("No label {folder:?} in {mailbox:?} to file into"),
+            }
+        }
+    }
+    Ok(delivery)
+}
+
+fn has_folder(folders: &[String], name: &str) -> bool {
+    folders
+        .iter()
+        .any(|folder| folder.eq_ignore_ascii_case(name))
+}
+
+/// The item under `pk` for `address` in the user table.
+async fn get(
+    client: &Client,
+    user_db: &str,
+    pk: &str,
+    address: &str,
+) -> Result<Option<Item>, Error> {
+    let resp = client
+        .get_item()
+        .table_name(user_db)
+        .key("pk", AttributeValue::S(pk.to_string()))
+        .key("sk", AttributeValue::S(address.to_string()))
+        .send()
+        .await?;
+    Ok(resp.item)
+}
+
+async fn list_labels(client: &Client, user_db: &str, mailbox: &str) -> Result<Vec<Label>, Error> {
+    let resp = client
+        .query()
+        .table_name(user_db)
+        .key_condition_expression("pk = :pk")
+        .expression_attribute_values(":pk", AttributeValue::S(labels_of(mailbox)))
+        .send()
+        .await?;
+    Ok(resp
+        .items
+        .unwrap_or_default()
+        .into_iter()
+        .map(Label::try_from)
+        .collect::<Result<_, _>>()?)
+}
+
+/// Lists `item` in the partitions of the labels it was filed under, it carries them already.
+pub async fn file_under_labels(client: &Client, item: &MailItem, table: &str) -> Result<(), Error> {
+    for label_id in &item.labels {
+        client
+            .put_item()
+            .table_name(table)
+            .set_item(Some(
+                AssignmentItem::new(&item.pk, label_id, item.sk).to_item()?,
+            ))
+            .send()
+            .await?;
+    }
+    Ok(())
+}
+
+/// Sends `contents` on to `to` from `mailbox`.
+pub async fn forward(
+    ses: &sesv2::Client,
+    mailbox: &str,
+    to: &[String],
+    contents: &[u8],
+) -> Result<(), Error> {
+    send(ses, mailbox, to, rules::forwarded(contents, mailbox)).await
+}
+
+/// Tells `sender` why `mailbox` refused `message`.
+pub async fn reject(
+    ses: &sesv2::Client,
+    mailbox: &str,
+    sender: &str,
+    reason: &str,
+    message: &Message<'_>,
+) -> Result<(), Error> {
+    let raw = sieve::rejection(mailbox, sender, reason, message);
+    send(ses, mailbox, &[sender.to_string()], raw).await
+}
+
+/// Sends the vacation reply to `message`, unless `mailbox` sent the same one to the same sender
+/// within the days it asks for. Returns whether it was sent.
+pub async fn vacation(
+    client: &Client,
+    user_db: &str,
+    ses: &sesv2::Client,
+    mailbox: &str,
+    reply: &VacationReply,
+    message: &Message<'_>,
+) -> Result<bool, Error> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    // noting the reply first means two deliveries racing can't both send it
+    let noted = client
+        .put_item()
+        .table_name(user_db)
+        .item("pk", AttributeValue::S(vacation_of(mailbox)))
+        .item(
+            "sk",
+            AttributeValue::S(format!("{}#{}", reply.to.to_lowercase(), reply.handle)),
+        )
+        .item(
+            "until",
+            AttributeValue::N((now + reply.days * 24 * 60 * 60).to_string()),
+        )
+        .condition_expression("attribute_not_exists(pk) OR #until <= :now")
+        .expression_attribute_names("#until", "until")
+        .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+        .send()
+        .await;
+    match noted {
+        Ok(_) => {}
+        Err(error)
+            if error
+                .as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+        {
+            return Ok(false)
+        }
+        Err(error) => return Err(error.into()),
+    }
+    // whoever saved the script was allowed to send as `from` then, which may have changed since
+    let allowed = match reply.from.as_deref() {
+        Some(from) => users::may_send_as(client, user_db, mailbox, from).await?,
+        None => true,
+    };
+    let reply = &VacationReply {
+        from: reply.from.clone().filter(|_| allowed),
+        ..reply.clone()
+    };
+    let from = reply.from.as_deref().unwrap_or(mailbox);
+    send(
+        ses,
+        from,
+        std::slice::from_ref(&reply.to),
+        reply.message(mailbox, message),
+    )
+    .await?;
+    Ok(true)
+}
+
+async fn send(ses: &sesv2::Client, from: &str, to: &[String], raw: Vec<u8>) -> Result<(), Error> {
+    let raw = RawMessage::builder().data(Blob::new(raw)).build()?;
+    ses.send_email()
+        .from_email_address(from)
+        .destination(
+            Destination::builder()
+                .set_to_addresses(Some(to.to_vec()))
+                .build(),
+        )
+        .content(EmailContent::builder().raw(raw).build())
+        .set_configuration_set_name(std::env::var("SES_CONFIGURATION_SET").ok())
+        .send()
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,523 @@
+//! The inbox Lambda, which delivers the mail SES receives to the mailboxes in `MAIL_DB`, and the
+//! `inbox reprocess` and `inbox backfill` commands of its binary.
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_lambda_events::ses::SimpleEmailEvent;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client;
+use aws_sdk_s3 as s3;
+use aws_sdk_sesv2 as sesv2;
+use dotenvy::dotenv;
+use futures::future::join_all;
+use lambda_runtime::Error;
+use mail_parser::Message;
+use supermailer_core::dead_letter::DeadLetter;
+use supermailer_core::item::MailItem;
+use supermailer_core::parse::{first_sentence, thread_id, SUBJECT_THREAD};
+use supermailer_core::rules;
+use supermailer_core::search::{self, Lease, SearchError, SearchIndex};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+pub mod backfill;
+pub mod dead_letter;
+pub mod filter;
+pub mod reprocess;
+pub mod users;
+
+/// How long ingestion waits for the lease on the search index, a backfill holds it for a page of
+/// mail at a time.
+const LEASE_WAIT: Duration = Duration::from_secs(60);
+
+/// How many sort keys a delivery tries when other mail is listed under them, one a second after
+/// the other.
+const SAME_SECOND_TRIES: u32 = 10;
+
+/// Where received mail is read from and delivered to, set up from the environment.
+pub struct Inbox {
+    pub mail_bucket: String,
+    pub mail_db: String,
+    pub user_db: String,
+    pub aws_config: SdkConfig,
+    pub client: Client,
+}
+
+impl Inbox {
+    pub async fn from_env() -> Self {
+        #[cfg(debug_assertions)]
+        {
+            dotenv().expect(".env file not found");
+        }
+        let aws_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
+            .profile_name("alvinjanuar.com")
+            .load()
+            .await;
+        Inbox {
+            mail_bucket: env::var("MAIL_BUCKET").expect("MAIL_BUCKET not set"),
+            mail_db: env::var("MAIL_DB").expect("MAIL_DB not set"),
+            user_db: env::var("USER_DB").expect("USER_DB not set"),
+            client: Client::new(&aws_config),
+            aws_config,
+        }
+    }
+}
+
+/// Delivers the mails in `payload` to their recipients' mailboxes. Returns a dead letter for each
+/// one that didn't make it into every mailbox, running it again delivers the rest.
+pub async fn ingest(inbox: &Inbox, payload: &SimpleEmailEvent) -> Result<Vec<DeadLetter>, Error> {
+    let Inbox {
+        mail_bucket,
+        mail_db,
+        user_db,
+        aws_config,
+        client,
+    } = inbox;
+
+    // a mail to several of our addresses is stored once and listed in each of their mailboxes
+    let mut messages: Vec<Vec<MailItem>> = payload
+        .records
+        .iter()
+        .map(|x| MailItem::from_ses(&x.ses))
+        .collect::<Result<_, _>>()?;
+
+    // `from_ses` returns an item for at least one recipient
+    let fetched = join_all(
+        messages
+            .iter()
+            .map(|items| get_email(items[0].message_id.clone(), mail_bucket, aws_config)),
+    )
+    .await;
+    // a mail that can't be read is a dead letter for every mailbox, the others go on without it
+    let mut failures: BTreeMap<usize, DeadLetter> = BTreeMap::new();
+    let mut message_contents: Vec<Vec<u8>> = Vec::with_capacity(fetched.len());
+    for (origin, (items, contents)) in messages.iter_mut().zip(fetched).enumerate() {
+        match contents {
+            Ok(contents) => message_contents.push(contents),
+            Err(error) => {
+                println!("Error reading {:?}: {:?}", items[0].message_id, error);
+                failures.insert(
+                    origin,
+                    DeadLetter {
+                        record: payload.records[origin].clone(),
+                        mailboxes: items.drain(..).map(|item| item.pk).collect(),
+                        error: error.to_string(),
+                        failed_at: chrono::Utc::now().timestamp(),
+                    },
+                );
+                message_contents.push(Vec::new());
+            }
+        }
+    }
+
+    // from here on there's a record for each mailbox a mail is delivered to
+    let records: Vec<MailItem> = messages.iter().flatten().cloned().collect();
+    // which of the SES records each of them came from
+    let origins: Vec<usize> = messages
+        .iter()
+        .enumerate()
+        .flat_map(|(i, items)| items.iter().map(move |_| i))
+        .collect();
+    let contents: Vec<&[u8]> = messages
+        .iter()
+        .zip(&message_contents)
+        .flat_map(|(items, contents)| items.iter().map(move |_| contents.as_slice()))
+        .collect();
+    let senders: Vec<&str> = messages
+        .iter()
+        .zip(&payload.records)
+        .flat_map(|(items, x)| {
+            let sender = x.ses.mail.source.as_deref().unwrap_or_default();
+            items.iter().map(move |_| sender)
+        })
+        .collect();
+
+    // a mail the rules or script couldn't run on is delivered as if there were none
+    let deliveries: Vec<filter::Delivery> =
+        join_all(records.iter().zip(&senders).zip(&contents).map(
+            |((record, sender), contents)| async {
+                filter::delivery(client, user_db, &record.pk, sender, contents)
+                    .await
+                    .unwrap_or_else(|error| {
+                        println!("Error filtering mail of {:?}: {:?}", record.pk, error);
+                        filter::Delivery::default()
+                    })
+            },
+        ))
+        .await;
+
+    // `None` for dropped mail
+    let items: Vec<Option<MailItem>> = records
+        .iter()
+        .zip(&contents)
+        .zip(&deliveries)
+        .map(|((record, contents), delivery)| {
+            if delivery.drop {
+                return None;
+            }
+            let (first_sentence, thread_id) = get_summary(contents);
+            let mut flags = record.flags;
+            flags.seen |= delivery.flags.seen;
+            flags.flagged |= delivery.flags.flagged;
+            flags.archived |= delivery.flags.archived;
+            flags.deleted |= delivery.flags.deleted;
+            Some(MailItem {
+                first_sentence: Some(first_sentence),
+                thread_id,
+                flags,
+                labels: delivery.labels.clone(),
+                ..record.clone()
+            })
+        })
+        .collect();
+
+    // mail an earlier attempt already delivered or dropped is left alone and its forwards and
+    // replies aren't sent again, whether SES retried the event or it's reprocessed
+    let mut first_delivery = Vec::with_capacity(items.len());
+    // as they were listed, under the sort key and thread they ended up with
+    let mut listed = Vec::new();
+    for ((record, item), origin) in records.iter().zip(&items).zip(&origins) {
+        let delivered = match item {
+            Some(item) => deliver(client, item, mail_db, user_db).await.map(|item| {
+                let first = item.is_some();
+                listed.extend(item);
+                first
+            }),
+            None => drop_mail(client, record, user_db).await,
+        };
+        match delivered {
+            Ok(first) => first_delivery.push(first),
+            Err(error) => {
+                println!(
+                    "Error delivering {:?} to {:?}: {:?}",
+                    record.message_id, record.pk, error
+                );
+                let letter = failures.entry(*origin).or_insert_with(|| DeadLetter {
+                    record: payload.records[*origin].clone(),
+                    mailboxes: Vec::new(),
+                    error: error.to_string(),
+                    failed_at: chrono::Utc::now().timestamp(),
+                });
+                letter.mailboxes.push(record.pk.clone());
+                first_delivery.push(false);
+            }
+        }
+    }
+
+    // the mail is in its mailbox either way, only missing from the label listing
+    for item in &listed {
+        if let Err(error) = filter::file_under_labels(client, item, mail_db).await {
+            println!(
+                "Error filing {:?} under labels: {:?}",
+                item.message_id, error
+            );
+        }
+    }
+
+    // a mail can be forwarded and dropped, forwarding doesn't depend on keeping it
+    let ses = sesv2::Client::new(aws_config);
+    for ((((record, sender), contents), delivery), _) in records
+        .iter()
+        .zip(&senders)
+        .zip(&contents)
+        .zip(&deliveries)
+        .zip(&first_delivery)
+        .filter(|(_, first)| **first)
+    {
+        let Some(message) = Message::parse(contents) else {
+            continue;
+        };
+        if !delivery.forward.is_empty() {
+            if rules::forwarded_by(&message, &record.pk) {
+                println!(
+                    "Not forwarding {:?} again from {:?}",
+                    record.message_id, record.pk
+                );
+            } else if let Err(error) =
+                filter::forward(&ses, &record.pk, &delivery.forward, contents).await
+            {
+                println!("Error forwarding {:?}: {:?}", record.message_id, error);
+            }
+        }
+        // nobody to tell for bounces, which have no sender
+        if let Some(reason) = delivery.reject.as_ref().filter(|_| !sender.is_empty()) {
+            if let Err(error) = filter::reject(&ses, &record.pk, sender, reason, &message).await {
+                println!("Error rejecting {:?}: {:?}", record.message_id, error);
+            }
+        }
+        if let Some(reply) = &delivery.vacation {
+            match filter::vacation(client, user_db, &ses, &record.pk, reply, &message).await {
+                Ok(sent) => println!(
+                    "Vacation reply to {:?} from {:?} sent: {sent}",
+                    reply.to, record.pk
+                ),
+                Err(error) => println!("Error replying to {:?}: {:?}", record.message_id, error),
+            }
+        }
+    }
+
+    // dropped mail isn't searchable either, kept mail is indexed once under each mailbox that
+    // kept it
+    let mut kept = Vec::new();
+    let mut mailboxes = Vec::new();
+    let mut kept_contents = Vec::new();
+    let mut delivered = deliveries.as_slice();
+    for (items, contents) in messages.iter().zip(&message_contents) {
+        let (message_deliveries, rest) = delivered.split_at(items.len());
+        delivered = rest;
+        let kept_by: Vec<String> = items
+            .iter()
+            .zip(message_deliveries)
+            .filter(|(_, delivery)| !delivery.drop)
+            .map(|(item, _)| item.pk.clone())
+            .collect();
+        if !kept_by.is_empty() {
+            // under the sort key it was listed with, a second later for mail that arrived in the
+            // same second as another
+            let listed_as = listed
+                .iter()
+                .find(|item| item.message_id == items[0].message_id);
+            kept.push(listed_as.unwrap_or(&items[0]));
+            mailboxes.push(kept_by);
+            kept_contents.push(contents.as_slice());
+        }
+    }
+    // a mail missing from search is still in its mailbox, don't fail the delivery over it. Adding
+    // a mail again replaces it, so retries are fine here. It's indexed last, waiting for the
+    // lease and syncing the index mustn't hold up delivery or run it into the Lambda's timeout.
+    if let Err(error) =
+        index_mails(&kept, &mailboxes, &kept_contents, mail_bucket, aws_config).await
+    {
+        println!("Error indexing mail: {:?}", error);
+    }
+
+    Ok(failures.into_values().collect())
+}
+
+/// Lists `item` in its mailbox and counts it, see [`MailItem::delivery`]. Returns the item as
+/// listed, `None` when the message was delivered to the mailbox before, by an earlier attempt at
+/// the same event or anything else.
+async fn deliver(
+    client: &Client,
+    item: &MailItem,
+    mail_db: &str,
+    user_db: &str,
+) -> Result<Option<MailItem>, Error> {
+    if listed(client, mail_db, item).await? {
+        println!("Mail {:?} is already in {:?}", item.message_id, item.pk);
+        return Ok(None);
+    }
+    users::register(client, mail_db, user_db, item).await?;
+    let thread_id = subject_thread(client, mail_db, item).await?;
+    let mut item = MailItem {
+        thread_id: thread_id.or_else(|| item.thread_id.clone()),
+        ..item.clone()
+    };
+    let mut tries = 1;
+    loop {
+        let result = client
+            .transact_write_items()
+            .set_transact_items(Some(item.delivery(mail_db, user_db)?))
+            .send()
+            .await;
+        let e = match result {
+            Ok(_) => {
+                println!(
+                    "Added mail {:?} at {:?} w/ key: {:?}",
+                    item.pk, item.sk, item.message_id
+                );
+                return Ok(Some(item));
+            }
+            Err(e) => e.into_service_error(),
+        };
+        if item.redelivered(&e) {
+            println!("Mail {:?} is already in {:?}", item.message_id, item.pk);
+            return Ok(None);
+        }
+        if !item.collided(&e) || tries == SAME_SECOND_TRIES {
+            return Err(e.into());
+        }
+        // the sort key is the second the mail arrived in, it's listed a second later instead
+        println!(
+            "Another mail is in {:?} at {:?}, listing {:?} after it",
+            item.pk, item.sk, item.message_id
+        );
+        item.sk += 1;
+        tries += 1;
+    }
+}
+
+/// Marks `item` as delivered without listing it, see [`MailItem::dropping`]. False when the
+/// message was delivered to or dropped from the mailbox before.
+async fn drop_mail(client: &Client, item: &MailItem, user_db: &str) -> Result<bool, Error> {
+    let result = client
+        .transact_write_items()
+        .set_transact_items(Some(item.dropping(user_db)?))
+        .send()
+        .await;
+    match result {
+        Ok(_) => {
+            println!("Dropped mail {:?} for {:?}", item.message_id, item.pk);
+            Ok(true)
+        }
+        Err(e) => {
+            let e = e.into_service_error();
+            if item.redelivered(&e) {
+                println!(
+                    "Mail {:?} was already dropped for {:?}",
+                    item.message_id, item.pk
+                );
+                Ok(false)
+            } else {
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Whether the message of `item` is listed in its mailbox already. Mail delivered before there
+/// were delivery markers has none, this keeps it from being listed twice under another sort key.
+async fn listed(client: &Client, mail_db: &str, item: &MailItem) -> Result<bool, Error> {
+    let listed = client
+        .query()
+        .table_name(mail_db)
+        .index_name(MailItem::MESSAGE_INDEX)
+        .key_condition_expression("message_id = :message_id AND pk = :pk")
+        .expression_attribute_values(":message_id", AttributeValue::S(item.message_id.clone()))
+        .expression_attribute_values(":pk", AttributeValue::S(item.pk.clone()))
+        .limit(1)
+        .send()
+        .await?;
+    Ok(listed.count() > 0)
+}
+
+/// Thread of the latest mail before `item` in its mailbox with the same subject, when `item` is
+/// a reply missing its threading headers, see [`thread_id`]. `None` when it has them or nothing
+/// earlier matches.
+async fn subject_thread(
+    client: &Client,
+    mail_db: &str,
+    item: &MailItem,
+) -> Result<Option<String>, Error> {
+    let (Some(thread_id), Some(subject_key)) = (&item.thread_id, &item.subject_key) else {
+        return Ok(None);
+    };
+    if !thread_id.starts_with(SUBJECT_THREAD) {
+        return Ok(None);
+    }
+    let mut pages = client
+        .query()
+        .table_name(mail_db)
+        .index_name(MailItem::SUBJECT_INDEX)
+        .key_condition_expression("subject_key = :subject_key AND sk <= :sk")
+        .filter_expression("message_id <> :message_id")
+        .projection_expression("thread_id")
+        .expression_attribute_values(":subject_key", AttributeValue::S(subject_key.clone()))
+        .expression_attribute_values(":sk", AttributeValue::N(item.sk.to_string()))
+        .expression_attribute_values(":message_id", AttributeValue::S(item.message_id.clone()))
+        .scan_index_forward(false)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(aws_sdk_dynamodb::Error::from)?;
+        if let Some(AttributeValue::S(thread_id)) = page
+            .items()
+            .first()
+            .and_then(|found| found.get("thread_id"))
+        {
+            return Ok(Some(thread_id.clone()));
+        }
+    }
+    Ok(None)
+}
+
+// fn get_file_as_byte_vec(filename: &String) -> Vec<u8> {
+//     let mut f = File::open(&filename).expect("no file found");
+//     let metadata = fs::metadata(&filename).expect("unable to read metadata");
+//     let mut buffer = vec![0; metadata.len() as usize];
+//     f.read(&mut buffer).expect("buffer overflow");
+//
+//     buffer
+// }
+
+/// Listing preview and thread id of a raw message, no thread id when it doesn't parse.
+fn get_summary(contents: &[u8]) -> (String, Option<String>) {
+    Message::parse(contents)
+        .map(|message| (first_sentence(&message), Some(thread_id(&message))))
+        .unwrap_or_default()
+}
+
+pub async fn get_email(
+    key_id: String,
+    mail_bucket: &String,
+    aws_config: &SdkConfig,
+) -> Result<Vec<u8>, Error> {
+    let client = s3::Client::new(aws_config);
+    let call = client.get_object().bucket(mail_bucket).key(key_id);
+
+    let response = call.send().await.map_err(|e| e.into_service_error())?;
+    let data = response.body.collect().await?;
+    Ok(data.into_bytes().to_vec())
+}
+
+/// Adds the received mails to the search index in `mail_bucket`, each under the mailboxes it
+/// was delivered to. The index is synced through `SEARCH_INDEX`, which a warm Lambda keeps
+/// between invocations so only new segments are downloaded. It's updated under the lease on the
+/// index, `inbox backfill` takes turns with the Lambda through it.
+async fn index_mails(
+    records: &[&MailItem],
+    recipients: &[Vec<String>],
+    contents: &[&[u8]],
+    mail_bucket: &str,
+    aws_config: &SdkConfig,
+) -> Result<(), SearchError> {
+    let client = s3::Client::new(aws_config);
+    let lease = lease_index(&client, mail_bucket).await?;
+    let indexed = update_index(&client, records, recipients, contents, mail_bucket).await;
+    lease.release(&client, mail_bucket).await?;
+    indexed
+}
+
+/// Waits up to [`LEASE_WAIT`] for the lease on the search index in `mail_bucket`.
+async fn lease_index(client: &s3::Client, mail_bucket: &str) -> Result<Lease, SearchError> {
+    let started = Instant::now();
+    loop {
+        if let Some(lease) = search::lease(client, mail_bucket).await? {
+            return Ok(lease);
+        }
+        if started.elapsed() > LEASE_WAIT {
+            return Err(SearchError::Storage(
+                "the search index is leased to somebody else".to_string(),
+            ));
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn update_index(
+    client: &s3::Client,
+    records: &[&MailItem],
+    recipients: &[Vec<String>],
+    contents: &[&[u8]],
+    mail_bucket: &str,
+) -> Result<(), SearchError> {
+    let dir = env::var("SEARCH_INDEX")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir().join("supermailer-search"));
+    search::download(client, mail_bucket, &dir).await?;
+
+    let index = SearchIndex::open(&dir)?;
+    let mut writer = index.writer()?;
+    for ((record, recipients), contents) in records.iter().zip(recipients).zip(contents) {
+        let Some(message) = Message::parse(contents) else {
+            continue;
+        };
+        index.add(&writer, &record.message_id, recipients, record.sk, &message)?;
+    }
+    writer.commit()?;
+    // merges still running would delete segment files while they're uploaded
+    writer.wait_merging_threads()?;
+
+    search::upload(client, mail_bucket, &dir).await
+}
@@ -0,0 +1,211 @@
+//! `inbox reprocess`, runs mail in `MAIL_BUCKET` through ingestion again:
+//!
+//! - `inbox reprocess --dead-letters` every dead letter, see [`DeadLetters`]
+//! - `inbox reprocess <message id>...` the mails with these ids
+//! - `inbox reprocess --prefix <prefix>` every mail whose id starts with `prefix`
+//!
+//! Mail with a dead letter is delivered from the record kept with it. Other mail is delivered to
+//! the `--recipient <address>` given, the option repeats, or else to the `To` and `Cc` addresses
+//! that have a mailbox. A mail is delivered once per mailbox and message id, whatever time it's
+//! listed under, so running it twice is fine.
+use crate::dead_letter::{DeadLetters, Taken};
+use crate::{ingest, users, Inbox};
+use aws_lambda_events::ses::{SimpleEmailEvent, SimpleEmailRecord};
+use aws_sdk_s3 as s3;
+use lambda_runtime::Error;
+use mail_parser::Message;
+use std::collections::HashMap;
+use supermailer_core::dead_letter::{header_recipients, record_of};
+use supermailer_core::search;
+
+enum Targets {
+    DeadLetters,
+    Ids(Vec<String>),
+    Prefix(String),
+}
+
+struct Options {
+    targets: Targets,
+    recipients: Vec<String>,
+}
+
+const USAGE: &str =
+    "usage: inbox reprocess (--dead-letters | --prefix <prefix> | <message id>...) \
+    [--recipient <address>]...";
+
+fn parse_args(args: &[String]) -> Result<Options, Error> {
+    let mut dead_letters = false;
+    let mut prefix = None;
+    let mut ids = Vec::new();
+    let mut recipients = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dead-letters" => dead_letters = true,
+            "--prefix" => prefix = Some(args.next().ok_or(USAGE)?.clone()),
+            "--recipient" => recipients.push(args.next().ok_or(USAGE)?.clone()),
+            option if option.starts_with("--") => return Err(USAGE.into()),
+            id => ids.push(id.to_string()),
+        }
+    }
+    let targets = match (dead_letters, prefix, ids.is_empty()) {
+        (true, None, true) => Targets::DeadLetters,
+        (false, Some(prefix), true) => Targets::Prefix(prefix),
+        (false, None, false) => Targets::Ids(ids),
+        _ => return Err(USAGE.into()),
+    };
+    Ok(Options {
+        targets,
+        recipients,
+    })
+}
+
+pub async fn run(args: &[String]) -> Result<(), Error> {
+    let options = parse_args(args)?;
+    let inbox = Inbox::from_env().await;
+    let dead_letters = DeadLetters::from_env(&inbox.aws_config);
+    let s3 = s3::Client::new(&inbox.aws_config);
+
+    // taken off the store up front so nothing else picks them up meanwhile
+    let mut taken: HashMap<String, Taken> = HashMap::new();
+    let mut oldest_first = Vec::new();
+    for letter in dead_letters.take_all().await? {
+        let message_id = letter.letter.message_id().to_string();
+        oldest_first.push(message_id.clone());
+        taken.insert(message_id, letter);
+    }
+    let message_ids = match options.targets {
+        Targets::DeadLetters => oldest_first,
+        Targets::Ids(ids) => ids,
+        Targets::Prefix(prefix) => list_messages(&s3, &inbox.mail_bucket, &prefix).await?,
+    };
+
+    let (mut delivered, mut skipped, mut failed) = (0, 0, 0);
+    for message_id in &message_ids {
+        let letter = taken.remove(message_id);
+        let record = match &letter {
+            Some(letter) => Some(letter.letter.record.clone()),
+            None => stored_record(&inbox, &s3, message_id, &options.recipients).await,
+        };
+        let Some(record) = record else {
+            println!("Skipping {message_id:?}: nobody to deliver it to");
+            skipped += 1;
+            continue;
+        };
+        let payload = SimpleEmailEvent {
+            records: vec![record],
+        };
+        match ingest(&inbox, &payload).await {
+            Ok(failures) => match (failures.first(), &letter) {
+                (None, Some(letter)) => {
+                    dead_letters.remove(letter).await?;
+                    delivered += 1;
+                }
+                (None, None) => delivered += 1,
+                (Some(failure), Some(letter)) => {
+                    dead_letters.replace(letter, failure).await?;
+                    failed += 1;
+                }
+                (Some(failure), None) => {
+                    dead_letters.push(failure).await?;
+                    failed += 1;
+                }
+            },
+            Err(error) => {
+                println!("Error reprocessing {message_id:?}: {error:?}");
+                if let Some(letter) = &letter {
+                    dead_letters.release(letter).await?;
+                }
+                failed += 1;
+            }
+        }
+    }
+    for letter in taken.values() {
+        dead_letters.release(letter).await?;
+    }
+
+    println!("Reprocessed {delivered} mails, skipped {skipped}, {failed} failed");
+    if failed > 0 {
+        return Err(format!("{failed} mails failed to reprocess").into());
+    }
+    Ok(())
+}
+
+/// Ids of the mails in `bucket` starting with `prefix`, leaving out the search index.
+async fn list_messages(
+    client: &s3::Client,
+    bucket: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Error> {
+    let mut message_ids = Vec::new();
+    let mut pages = client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|e| e.into_service_error())?;
+        for key in page.contents().iter().filter_map(|object| object.key()) {
+            if !key.starts_with(search::S3_PREFIX) {
+                message_ids.push(key.to_string());
+            }
+        }
+    }
+    Ok(message_ids)
+}
+
+/// A record for the mail stored under `message_id` that has no dead letter, received when it was
+/// stored. `None` when it isn't there, doesn't parse or has nobody to deliver it to.
+async fn stored_record(
+    inbox: &Inbox,
+    s3: &s3::Client,
+    message_id: &str,
+    recipients: &[String],
+) -> Option<SimpleEmailRecord> {
+    let object = match s3
+        .get_object()
+        .bucket(&inbox.mail_bucket)
+        .key(message_id)
+        .send()
+        .await
+    {
+        Ok(object) => object,
+        Err(error) => {
+            println!(
+                "Error reading {message_id:?}: {:?}",
+                error.into_service_error()
+            );
+            return None;
+        }
+    };
+    let received_at = object
+        .last_modified()
+        .and_then(|at| chrono::DateTime::from_timestamp(at.secs(), 0))
+        .unwrap_or_else(chrono::Utc::now);
+    let contents = match object.body.collect().await {
+        Ok(data) => data.into_bytes(),
+        Err(error) => {
+            println!("Error reading {message_id:?}: {error:?}");
+            return None;
+        }
+    };
+    let message = Message::parse(&contents)?;
+    let recipients = if recipients.is_empty() {
+        let mut registered = Vec::new();
+        for address in header_recipients(&message) {
+            match users::is_registered(&inbox.client, &inbox.user_db, &address).await {
+                Ok(true) => registered.push(address),
+                Ok(false) => {}
+                Err(error) => println!("Error looking up {address:?}: {error:?}"),
+            }
+        }
+        registered
+    } else {
+        recipients.to_vec()
+    };
+    if recipients.is_empty() {
+        return None;
+    }
+    Some(record_of(message_id, &message, &recipients, received_at))
+}
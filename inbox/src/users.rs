@@ -0,0 +1,187 @@
+//! The mailboxes in `USER_DB`, the ones listed by the web app. A mailbox is registered when its
+//! first mail arrives, after that every delivery counts itself, see `MailItem::delivery`.
+use aws_sdk_dynamodb::operation::put_item::PutItemError;
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValuesOnConditionCheckFailure};
+use aws_sdk_dynamodb::Client;
+use lambda_runtime::Error;
+use std::collections::BTreeMap;
+use supermailer_core::item::{MailItem, UserItem, ACCOUNT_PK, USER_PK};
+use supermailer_core::mail::Flags;
+
+/// Registers the mailbox `item` is delivered to unless it's known already. True when it wasn't.
+pub async fn register(
+    client: &Client,
+    mail_db: &str,
+    user_db: &str,
+    item: &MailItem,
+) -> Result<bool, Error> {
+    let result = client
+        .put_item()
+        .table_name(user_db)
+        .set_item(Some(UserItem::new(&item.pk, item.sk).to_item()?))
+        // two mails arriving at once can't both register it, nor reset the other's count
+        .condition_expression("attribute_not_exists(pk)")
+        .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+        .send()
+        .await;
+    let e = match result {
+        Ok(_) => {
+            println!("Added user {:?}", item.pk);
+            return Ok(true);
+        }
+        Err(e) => e.into_service_error(),
+    };
+    let known = match &e {
+        PutItemError::ConditionalCheckFailedException(known) => known.item(),
+        _ => return Err(e.into()),
+    };
+    // mailboxes registered before labels or unread mail were counted start from a recount
+    if known.is_some_and(|user| {
+        !user.contains_key("label_counts") || !user.contains_key("unread_count")
+    }) {
+        recount(client, mail_db, user_db, &item.pk).await?;
+    }
+    Ok(false)
+}
+
+/// Whether `address` has a mailbox, i.e. mail to it was delivered before.
+pub async fn is_registered(client: &Client, user_db: &str, address: &str) -> Result<bool, Error> {
+    let user = client
+        .get_item()
+        .table_name(user_db)
+        .key("pk", AttributeValue::S(USER_PK.to_string()))
+        .key("sk", AttributeValue::S(address.to_string()))
+        .projection_expression("pk")
+        .send()
+        .await
+        .map_err(aws_sdk_dynamodb::Error::from)?;
+    Ok(user.item().is_some())
+}
+
+/// Whether an account reading `mailbox` also reads `address`, and so may send as it.
+pub async fn may_send_as(
+    client: &Client,
+    user_db: &str,
+    mailbox: &str,
+    address: &str,
+) -> Result<bool, Error> {
+    let mut pages = client
+        .query()
+        .table_name(user_db)
+        .key_condition_expression("pk = :pk")
+        .expression_attribute_values(":pk", AttributeValue::S(ACCOUNT_PK.to_string()))
+        .projection_expression("mailboxes")
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(aws_sdk_dynamodb::Error::from)?;
+        for account in page.items() {
+            let Some(AttributeValue::Ss(mailboxes)) = account.get("mailboxes") else {
+                continue;
+            };
+            let reads = |wanted: &str| mailboxes.iter().any(|m| m.eq_ignore_ascii_case(wanted));
+            if reads(mailbox) && reads(address) {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Every registered mailbox, as its first mail spelled it.
+pub async fn mailboxes(client: &Client, user_db: &str) -> Result<Vec<String>, Error> {
+    let mut mailboxes = Vec::new();
+    let mut pages = client
+        .query()
+        .table_name(user_db)
+        .key_condition_expression("pk = :pk")
+        .expression_attribute_values(":pk", AttributeValue::S(USER_PK.to_string()))
+        .projection_expression("sk")
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(aws_sdk_dynamodb::Error::from)?;
+        for user in page.items() {
+            if let Some(AttributeValue::S(mailbox)) = user.get("sk") {
+                mailboxes.push(mailbox.clone());
+            }
+        }
+    }
+    Ok(mailboxes)
+}
+
+/// Counts the mail listed in `mailbox` afresh and replaces its counters with that, for when they
+/// drifted from the mail table. Returns the number of mails.
+pub async fn recount(
+    client: &Client,
+    mail_db: &str,
+    user_db: &str,
+    mailbox: &str,
+) -> Result<i64, Error> {
+    let mut message_count = 0;
+    let mut unread_count = 0;
+    let mut label_counts: BTreeMap<String, i64> = BTreeMap::new();
+    let mut received: Option<(i64, i64)> = None;
+    // oldest first, the sort key is the receive time
+    let mut pages = client
+        .query()
+        .table_name(mail_db)
+        .key_condition_expression("pk = :pk")
+        .expression_attribute_values(":pk", AttributeValue::S(mailbox.to_string()))
+        .projection_expression("sk, labels, #seen, #archived, #deleted")
+        .expression_attribute_names("#seen", "seen")
+        .expression_attribute_names("#archived", "archived")
+        .expression_attribute_names("#deleted", "deleted")
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(aws_sdk_dynamodb::Error::from)?;
+        for mail in page.items() {
+            let Some(sk) = mail
+                .get("sk")
+                .and_then(|sk| sk.as_n().ok())
+                .and_then(|sk| sk.parse::<i64>().ok())
+            else {
+                continue;
+            };
+            message_count += 1;
+            if Flags::try_from(mail.clone())?.is_unread() {
+                unread_count += 1;
+            }
+            received = Some((received.map_or(sk, |(first, _)| first), sk));
+            if let Some(AttributeValue::Ss(labels)) = mail.get("labels") {
+                for label_id in labels {
+                    *label_counts.entry(label_id.clone()).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    let label_counts = label_counts
+        .into_iter()
+        .map(|(label_id, count)| (label_id, AttributeValue::N(count.to_string())))
+        .collect();
+    let mut update = client
+        .update_item()
+        .table_name(user_db)
+        .key("pk", AttributeValue::S(USER_PK.to_string()))
+        .key("sk", AttributeValue::S(mailbox.to_string()))
+        .expression_attribute_values(":count", AttributeValue::N(message_count.to_string()))
+        .expression_attribute_values(":unread", AttributeValue::N(unread_count.to_string()))
+        .expression_attribute_values(":labels", AttributeValue::M(label_counts));
+    // an empty mailbox keeps when it was last seen
+    update = match received {
+        Some((first, last)) => update
+            .update_expression(
+                "SET message_count = :count, unread_count = :unread, label_counts = :labels, \
+                 first_seen = :first, last_received = :last",
+            )
+            .expression_attribute_values(":first", AttributeValue::N(first.to_string()))
+            .expression_attribute_values(":last", AttributeValue::N(last.to_string())),
+        None => update.update_expression(
+            "SET message_count = :count, unread_count = :unread, label_counts = :labels",
+        ),
+    };
+    update.send().await.map_err(aws_sdk_dynamodb::Error::from)?;
+    Ok(message_count)
+}
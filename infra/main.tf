@@ -30,6 +30,11 @@ variable "domain" {
   default = "alvinjanuar.com"
 }
 
+# signs the image URLs of mail bodies, any long random string
+variable "url_signing_key" {
+  sensitive = true
+}
+
 locals {
   domain_name = [
     "courriel.alvinjanuar.com"
@@ -252,6 +257,8 @@ resource "aws_lambda_function" "api_server" {
       MAIL_BUCKET="${aws_s3_bucket.mail-bucket.bucket}"
       MAIL_DB="${aws_dynamodb_table.example.name}"
       USER_DB="${aws_dynamodb_table.user.name}"
+      SES_CONFIGURATION_SET="${aws_ses_configuration_set.alvinjanuar.name}"
+      URL_SIGNING_KEY=var.url_signing_key
     }
   }
 
@@ -301,12 +308,19 @@ data "archive_file" "inbox_lambda_zip" {
   output_path = "./inbox_lambda.zip"                                                                                                                                                                         
 } 
 
+resource "aws_sqs_queue" "inbox_dead_letters" {
+  name                      = "inbox_dead_letters"
+  # the longest SQS keeps anything, reprocess before then
+  message_retention_seconds = 1209600
+}
+
 resource "aws_lambda_function" "inbox_lambda" {
   architectures                  = ["arm64"]
   function_name                  = "inbox_lambda"
   handler                        = "bootstrap"
   memory_size                    = "2048"
-  reserved_concurrent_executions = "-1"
+  # one invocation at a time, each rewrites the search index in the mail bucket
+  reserved_concurrent_executions = "1"
   role                           = aws_iam_role.api_server_role.arn
   depends_on                     = [aws_iam_role_policy_attachment.api_server_policy_role_attachment]
   runtime                        = "provided.al2023"
@@ -320,6 +334,12 @@ resource "aws_lambda_function" "inbox_lambda" {
       # TF_LOG="trace"
       MAIL_BUCKET="${aws_s3_bucket.mail-bucket.bucket}"
       MAIL_DB="${aws_dynamodb_table.example.name}"
+      # filtering rules, Sieve scripts and labels of each mailbox
+      USER_DB="${aws_dynamodb_table.user.name}"
+      # used when a rule or script forwards mail, rejects it or replies to it
+      SES_CONFIGURATION_SET="${aws_ses_configuration_set.alvinjanuar.name}"
+      # mail that couldn't be delivered, for `inbox reprocess`
+      DEAD_LETTER_QUEUE="${aws_sqs_queue.inbox_dead_letters.url}"
     }
   }
 
@@ -368,6 +388,49 @@ resource "aws_dynamodb_table" "example" {
     name = "sk"
     type = "N"
   }
+
+  attribute {
+    name = "thread_id"
+    type = "S"
+  }
+
+  attribute {
+    name = "message_id"
+    type = "S"
+  }
+
+  attribute {
+    name = "subject_key"
+    type = "S"
+  }
+
+  # conversations across mailboxes, items without a thread_id aren't indexed
+  global_secondary_index {
+    name            = "thread-index"
+    hash_key        = "thread_id"
+    range_key       = "sk"
+    projection_type = "ALL"
+  }
+
+  # the key of a mail in a mailbox by its message id, for updating its flags and labels.
+  # Mail filed under a label is also listed in LABEL#<mailbox>#<label id> partitions, those
+  # items carry no message_id so they stay out of this index.
+  global_secondary_index {
+    name            = "message-index"
+    hash_key        = "message_id"
+    range_key       = "pk"
+    projection_type = "KEYS_ONLY"
+  }
+
+  # earlier mail of a mailbox by <mailbox>#<normalized subject>, joining replies that lack
+  # threading headers to the thread they answer
+  global_secondary_index {
+    name               = "subject-index"
+    hash_key           = "subject_key"
+    range_key          = "sk"
+    projection_type    = "INCLUDE"
+    non_key_attributes = ["message_id", "thread_id"]
+  }
 }
 
 resource "aws_dynamodb_table" "user" {
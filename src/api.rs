@@ -1,16 +1,33 @@
-use crate::api_types::{ListEmailsResponse, ListUsersResponse, Mail, User};
+use crate::api_types::{
+    Attachment, Flags, FlagsUpdate, Folder, Label, LabelRequest, ListAttachmentsResponse,
+    ListEmailsResponse, ListLabelsResponse, ListThreadsResponse, ListUsersResponse, Mail,
+    MailDetail, MailFilter, MailLabelsResponse, ReplyMode, SendRequest, SendResponse,
+    UnreadResponse,
+};
+use crate::auth::Access;
+use crate::body;
+use crate::error::ApiError;
+use crate::html::{self, Images};
+use crate::reply;
+use crate::send;
 use crate::state::AppState;
-use aws_sdk_dynamodb as dynamodb;
-use aws_sdk_s3 as s3;
+use crate::store::Page;
 use axum::{
-    extract::{Path, State},
-    response::Html,
+    body::Bytes,
+    extract::{
+        multipart::MultipartError, FromRequest, Multipart, OriginalUri, Path, Query, Request, State,
+    },
+    http::{header, StatusCode, Uri},
+    response::{Html, IntoResponse},
     Json,
 };
-use dynamodb::types::AttributeValue;
-use mail_parser::Message;
+use mail_parser::{Message, MessagePart, MimeHeaders};
 use serde::{Deserialize, Serialize};
-use serde_json::{json, Value};
+use std::collections::HashMap;
+use supermailer_core::parse::{first_sentence, format_addresses, thread_id};
+use supermailer_core::rules::{self, Condition, Outcome, Rule, RuleAction};
+use supermailer_core::search::SearchQuery;
+use supermailer_core::sieve::{Script, ScriptError};
 // use leptos::*;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -38,116 +55,1023 @@ pub struct Test {
 //     Ok(ret)
 // }
 
+/// Query of the mail endpoints, remote images are only loaded (through the proxy) when asked for.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct EmailQuery {
+    #[serde(default)]
+    pub load_images: bool,
+}
+
+impl EmailQuery {
+    /// How the HTML body of mail `key_id` is rewritten, `cid:` links go to its attachments.
+    fn options(&self, state: &AppState, key_id: &str, message: &Message) -> html::Options {
+        let images = if self.load_images {
+            Images::Proxied
+        } else {
+            Images::Blocked
+        };
+        let inline = message
+            .attachments()
+            .enumerate()
+            .filter_map(|(index, part)| {
+                let content_id = part.content_id()?;
+                Some((content_id.to_string(), Attachment::url(key_id, index)))
+            })
+            .collect::<HashMap<_, _>>();
+        html::Options {
+            images,
+            inline,
+            signer: Some(state.url_signer.clone()),
+        }
+    }
+}
+
 pub async fn get_email_html_api(
     Path(key_id): Path<String>,
+    Query(query): Query<EmailQuery>,
     State(state): State<AppState>,
-) -> Html<String> {
-    let response = get_email_html(key_id, state).await;
-    Html(response)
+    access: Access,
+) -> Result<impl IntoResponse, ApiError> {
+    let response = get_email_html(key_id, state, &access, query).await?;
+    // opened on its own the body gets the same treatment as inside the UI's sandboxed iframe
+    let policy = format!("{}; {}", html::CONTENT_SECURITY_POLICY, html::SANDBOX);
+    Ok((
+        [
+            (header::CONTENT_SECURITY_POLICY, policy),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::REFERRER_POLICY, "no-referrer".to_string()),
+        ],
+        Html(response),
+    ))
+}
+
+pub async fn get_email_html(
+    key_id: String,
+    state: AppState,
+    access: &Access,
+    query: EmailQuery,
+) -> Result<String, ApiError> {
+    access.check_mail(&state, &key_id).await?;
+    let contents = state.mail_store.get_raw(&key_id).await?;
+
+    let message = parse_message(&key_id, &contents)?;
+    let body = body::render(&message, query.options(&state, &key_id, &message))
+        .ok_or_else(|| ApiError::NoBody(key_id.clone()))?;
+    Ok(body.html)
 }
 
-pub async fn get_email_html(key_id: String, state: AppState) -> String {
-    let client = s3::Client::new(&state.aws_config);
-    let call = client
-        .get_object()
-        .bucket(&state.mail_config.mail_bucket)
-        .key(key_id);
+pub async fn get_email(
+    key_id: String,
+    state: AppState,
+    access: &Access,
+    query: EmailQuery,
+) -> Result<MailDetail, ApiError> {
+    access.check_mail(&state, &key_id).await?;
+    let contents = state.mail_store.get_raw(&key_id).await?;
 
-    let response = call.clone().send().await.unwrap();
-    let data = response.body.collect().await.expect("error reading data");
-    let contents = data.into_bytes();
+    let message = parse_message(&key_id, &contents)?;
+    let body = body::render(&message, query.options(&state, &key_id, &message));
+    let attachments = attachments(&message);
 
-    let message = Message::parse(&contents).unwrap();
-    let raw_body = message.body_html(0).unwrap().to_string();
-    raw_body
+    Ok(MailDetail {
+        subject: message.subject().unwrap_or_default().to_string(),
+        from: format_addresses(message.from()),
+        to: format_addresses(message.to()),
+        cc: format_addresses(message.cc()),
+        date: message.date().map(|date| date.to_timestamp()),
+        format: body.as_ref().map(|body| body.format),
+        html: body.as_ref().map(|body| body.html.clone()),
+        text: body.and_then(|body| body.text),
+        attachments,
+        message_id: key_id,
+    })
 }
 
-pub async fn list_emails_api(
-    Path(email): Path<String>,
+pub async fn list_attachments_api(
+    Path(key_id): Path<String>,
     State(state): State<AppState>,
-) -> Json<ListEmailsResponse> {
-    // let client = s3::Client::new(&state.aws_config);
-    // let call = client.list_objects_v2().bucket(&state.mail_bucket);
-    //
-    // let response = call.clone().send().await.unwrap();
-    // let array = response.contents();
-    // let parsed: Vec<String> = array.iter().map(|x| x.key.clone().unwrap()).collect();
-    // println!("{:#?}", parsed);
-    let response = list_emails(state, email).await;
-    Json(response)
-}
-
-pub async fn list_emails(state: AppState, email: String) -> ListEmailsResponse {
-    let _client = dynamodb::Client::new(&state.aws_config);
-    let call = _client
-        .query()
-        .table_name(&state.mail_config.mail_db)
-        .key_condition_expression("pk = :pk")
-        .projection_expression("pk, message_id, sk, subject, #r.#ch.#f, first_sentence")
-        .expression_attribute_names("#r", "raw")
-        .expression_attribute_names("#ch", "commonHeaders")
-        .expression_attribute_names("#f", "from")
-        .expression_attribute_values(":pk", AttributeValue::S(email))
-        .scan_index_forward(false)
-        .limit(20);
-
-    let resp = call.send().await.unwrap();
-    let mails: Vec<Mail> = resp
-        .items()
-        .iter()
-        .map(|x| Mail {
-            pk: x.get("pk").unwrap().as_s().unwrap().to_string(),
-            sk: x.get("sk").unwrap().as_n().unwrap().parse::<i64>().unwrap(),
-            message_id: x.get("message_id").unwrap().as_s().unwrap().to_string(),
-            subject: x.get("subject").unwrap().as_s().unwrap().to_string(),
-            from: x
-                .get("raw")
-                .unwrap()
-                .as_m()
-                .unwrap()
-                .get("commonHeaders")
-                .unwrap()
-                .as_m()
-                .unwrap()
-                .get("from")
-                .unwrap()
-                .as_l()
-                .unwrap()
-                .to_owned()
-                .iter()
-                .map(|x| x.as_s().unwrap().to_owned())
-                .collect::<Vec<String>>(),
-            first_sentence: x.get("first_sentence").unwrap().as_s().unwrap().to_string(),
+    access: Access,
+) -> Result<Json<ListAttachmentsResponse>, ApiError> {
+    access.check_mail(&state, &key_id).await?;
+    let contents = state.mail_store.get_raw(&key_id).await?;
+
+    let message = parse_message(&key_id, &contents)?;
+    Ok(Json(ListAttachmentsResponse {
+        data: attachments(&message),
+    }))
+}
+
+/// Downloads the decoded contents of one attachment, `index` as listed by
+/// [`list_attachments_api`]. Inline images of a body come with a signed URL instead of the session.
+pub async fn get_attachment_api(
+    Path((key_id, index)): Path<(String, usize)>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    access: Result<Access, ApiError>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !is_signed(&state, &uri) {
+        access?.check_mail(&state, &key_id).await?;
+    }
+    let contents = state.mail_store.get_raw(&key_id).await?;
+
+    let message = parse_message(&key_id, &contents)?;
+    let part = message
+        .attachment(index)
+        .ok_or_else(|| ApiError::NotFound(format!("attachment {index} of mail {key_id}")))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type(part)),
+            (header::CONTENT_DISPOSITION, content_disposition(part)),
+            // same as the HTML body, an attachment opened on its own doesn't get our origin
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; style-src 'unsafe-inline'; sandbox".to_string(),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        part.contents().to_vec(),
+    ))
+}
+
+/// Query of the draft endpoint, `mailbox` is the partition (`Mail.pk`) the mail was opened from
+/// and decides which address the answer is sent from.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DraftQuery {
+    #[serde(default)]
+    pub mode: ReplyMode,
+    pub mailbox: String,
+}
+
+pub async fn get_draft_api(
+    Path(key_id): Path<String>,
+    Query(query): Query<DraftQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<SendRequest>, ApiError> {
+    let draft = get_draft(key_id, state, &access, query).await?;
+    Ok(Json(draft))
+}
+
+/// A reply to or forward of mail `key_id`, to be edited in the compose view before sending.
+pub async fn get_draft(
+    key_id: String,
+    state: AppState,
+    access: &Access,
+    query: DraftQuery,
+) -> Result<SendRequest, ApiError> {
+    access.check(&query.mailbox)?;
+    access.check_mail(&state, &key_id).await?;
+    let contents = state.mail_store.get_raw(&key_id).await?;
+
+    let message = parse_message(&key_id, &contents)?;
+    Ok(reply::draft(&message, &key_id, &query.mailbox, query.mode))
+}
+
+fn attachments(message: &Message) -> Vec<Attachment> {
+    message
+        .attachments()
+        .enumerate()
+        .map(|(index, part)| Attachment {
+            index,
+            name: attachment_name(part).to_string(),
+            content_type: content_type(part),
+            size: part.len(),
+            content_id: part.content_id().map(|id| id.to_string()),
+        })
+        .collect()
+}
+
+fn attachment_name<'x>(part: &'x MessagePart) -> &'x str {
+    part.attachment_name().unwrap_or("attachment")
+}
+
+fn content_type(part: &MessagePart) -> String {
+    part.content_type()
+        .map(|ct| match ct.subtype() {
+            Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+            None => ct.ctype().to_string(),
+        })
+        .unwrap_or("application/octet-stream".to_string())
+}
+
+/// Always a download, with an ASCII `filename` for old clients and the real one in `filename*`
+/// (RFC 6266).
+fn content_disposition(part: &MessagePart) -> String {
+    let name = attachment_name(part);
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
         })
         .collect();
-    ListEmailsResponse { data: mails }
-}
-
-pub async fn list_users(state: AppState) -> ListUsersResponse {
-    let _client = dynamodb::Client::new(&state.aws_config);
-    let call = _client
-        .query()
-        .table_name(&state.mail_config.user_db)
-        .key_condition_expression("pk = :pk")
-        .projection_expression("pk, sk, message_count")
-        .expression_attribute_values(":pk", AttributeValue::S("USER".to_string()))
-        .limit(20);
-
-    let resp = call.send().await.unwrap();
-    let users: Vec<User> = resp
-        .items()
-        .iter()
-        .map(|x| User {
-            pk: x.get("pk").unwrap().as_s().unwrap().to_string(),
-            sk: x.get("sk").unwrap().as_s().unwrap().to_string(),
-            message_count: x
-                .get("message_count")
-                .unwrap()
-                .as_n()
-                .unwrap()
-                .parse::<i64>()
-                .unwrap(),
+    format!(
+        "attachment; filename=\"{fallback}\"; filename*=UTF-8''{}",
+        urlencoding::encode(name)
+    )
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProxyQuery {
+    pub url: String,
+}
+
+/// Serves a remote image from our origin, see [`crate::proxy`]. Only for those logged in or the
+/// signed URLs of a body, so it can't be used as an open proxy.
+pub async fn proxy_image_api(
+    Query(query): Query<ProxyQuery>,
+    State(state): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    access: Result<Access, ApiError>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !is_signed(&state, &uri) {
+        access?;
+    }
+    let image = state.image_proxy.fetch(&query.url).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, image.content_type),
+            // an SVG opened on its own would run its scripts on our origin
+            (
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'none'; style-src 'unsafe-inline'; sandbox".to_string(),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CACHE_CONTROL, "private, max-age=86400".to_string()),
+        ],
+        image.data,
+    ))
+}
+
+/// Whether `uri` is one [`html::sanitize`] signed, the sandboxed body loads those without the
+/// session cookie.
+fn is_signed(state: &AppState, uri: &Uri) -> bool {
+    uri.path_and_query()
+        .is_some_and(|url| state.url_signer.verify(url.as_str()))
+}
+
+fn parse_message<'x>(key_id: &str, contents: &'x [u8]) -> Result<Message<'x>, ApiError> {
+    Message::parse(contents).ok_or_else(|| ApiError::InvalidMessage(key_id.to_string()))
+}
+
+/// Page size used when the caller doesn't ask for one.
+pub const DEFAULT_LIMIT: i32 = 20;
+pub const MAX_LIMIT: i32 = 100;
+
+/// Pagination parameters of the listing endpoints, `cursor` is a previous `next_cursor`.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ListQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i32>,
+}
+
+impl ListQuery {
+    fn page(self) -> Result<Page, ApiError> {
+        let limit = self.limit.unwrap_or(DEFAULT_LIMIT);
+        if !(1..=MAX_LIMIT).contains(&limit) {
+            return Err(ApiError::InvalidParameter(format!(
+                "limit must be between 1 and {MAX_LIMIT}, got {limit}"
+            )));
+        }
+        Ok(Page {
+            cursor: self.cursor.filter(|cursor| !cursor.is_empty()),
+            limit,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct FolderQuery {
+    #[serde(default)]
+    pub folder: Folder,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct FilterQuery {
+    #[serde(default)]
+    pub filter: MailFilter,
+}
+
+/// Query of the mail listing, only mail filed under `label` when given.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct LabelQuery {
+    pub label: Option<String>,
+}
+
+pub async fn list_emails_api(
+    Path(email): Path<String>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    Query(FilterQuery { filter }): Query<FilterQuery>,
+    Query(LabelQuery { label }): Query<LabelQuery>,
+    Query(query): Query<ListQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListEmailsResponse>, ApiError> {
+    let response = list_emails(state, &access, email, folder, filter, label, query).await?;
+    Ok(Json(response))
+}
+
+pub async fn list_emails(
+    state: AppState,
+    access: &Access,
+    email: String,
+    folder: Folder,
+    filter: MailFilter,
+    label: Option<String>,
+    query: ListQuery,
+) -> Result<ListEmailsResponse, ApiError> {
+    access.check(&email)?;
+    let page = state
+        .metadata_store
+        .list_emails(
+            &folder.mailbox(&email),
+            filter,
+            label.as_deref(),
+            query.page()?,
+        )
+        .await?;
+    Ok(ListEmailsResponse {
+        data: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+pub async fn update_flags_api(
+    Path((email, key_id)): Path<(String, String)>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    State(state): State<AppState>,
+    access: Access,
+    Json(update): Json<FlagsUpdate>,
+) -> Result<Json<Flags>, ApiError> {
+    let response = update_flags(state, &access, email, folder, key_id, update).await?;
+    Ok(Json(response))
+}
+
+/// Changes the flags of mail `key_id` as listed in `folder` of `email`.
+pub async fn update_flags(
+    state: AppState,
+    access: &Access,
+    email: String,
+    folder: Folder,
+    key_id: String,
+    update: FlagsUpdate,
+) -> Result<Flags, ApiError> {
+    access.check_write(&email)?;
+    if update.changes().is_empty() {
+        return Err(ApiError::InvalidParameter(
+            "no flags to change, set one of seen, flagged, archived or deleted".to_string(),
+        ));
+    }
+    Ok(state
+        .metadata_store
+        .update_flags(&folder.mailbox(&email), &key_id, &update)
+        .await?)
+}
+
+pub async fn count_unread_api(
+    Path(email): Path<String>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<UnreadResponse>, ApiError> {
+    let response = count_unread(state, &access, email, folder).await?;
+    Ok(Json(response))
+}
+
+pub async fn count_unread(
+    state: AppState,
+    access: &Access,
+    email: String,
+    folder: Folder,
+) -> Result<UnreadResponse, ApiError> {
+    access.check(&email)?;
+    let unread = state
+        .metadata_store
+        .count_unread(&folder.mailbox(&email))
+        .await?;
+    Ok(UnreadResponse { unread })
+}
+
+pub const MAX_LABEL_NAME: usize = 64;
+
+impl LabelRequest {
+    /// The name, trimmed, checked when given.
+    fn name(&self) -> Result<Option<String>, ApiError> {
+        let Some(name) = &self.name else {
+            return Ok(None);
+        };
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_LABEL_NAME {
+            return Err(ApiError::InvalidParameter(format!(
+                "label name must be 1 to {MAX_LABEL_NAME} characters"
+            )));
+        }
+        Ok(Some(name.to_string()))
+    }
+
+    /// The color, checked when given.
+    fn color(&self) -> Result<Option<String>, ApiError> {
+        let Some(color) = &self.color else {
+            return Ok(None);
+        };
+        let valid = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err(ApiError::InvalidParameter(format!(
+                "label color must look like #rrggbb, got {color:?}"
+            )));
+        }
+        Ok(Some(color.to_ascii_lowercase()))
+    }
+}
+
+pub async fn list_labels_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListLabelsResponse>, ApiError> {
+    let response = list_labels(state, &access, email).await?;
+    Ok(Json(response))
+}
+
+/// Labels of `email`, shared by its inbox and sent folder. There are few, no pagination.
+pub async fn list_labels(
+    state: AppState,
+    access: &Access,
+    email: String,
+) -> Result<ListLabelsResponse, ApiError> {
+    access.check(&email)?;
+    let labels = state.metadata_store.list_labels(&email).await?;
+    Ok(ListLabelsResponse { data: labels })
+}
+
+pub async fn create_label_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<LabelRequest>,
+) -> Result<Json<Label>, ApiError> {
+    let response = create_label(state, &access, email, request).await?;
+    Ok(Json(response))
+}
+
+pub async fn create_label(
+    state: AppState,
+    access: &Access,
+    email: String,
+    request: LabelRequest,
+) -> Result<Label, ApiError> {
+    access.check_write(&email)?;
+    let (Some(name), Some(color)) = (request.name()?, request.color()?) else {
+        return Err(ApiError::InvalidParameter(
+            "a new label needs a name and a color".to_string(),
+        ));
+    };
+    let label = Label {
+        label_id: uuid::Uuid::new_v4().simple().to_string(),
+        name,
+        color,
+    };
+    state.metadata_store.put_label(&email, &label).await?;
+    Ok(label)
+}
+
+pub async fn update_label_api(
+    Path((email, label_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<LabelRequest>,
+) -> Result<Json<Label>, ApiError> {
+    let response = update_label(state, &access, email, label_id, request).await?;
+    Ok(Json(response))
+}
+
+/// Renames or recolors label `label_id`, what's left out keeps its value.
+pub async fn update_label(
+    state: AppState,
+    access: &Access,
+    email: String,
+    label_id: String,
+    request: LabelRequest,
+) -> Result<Label, ApiError> {
+    access.check_write(&email)?;
+    let update = LabelRequest {
+        name: request.name()?,
+        color: request.color()?,
+    };
+    if update.name.is_none() && update.color.is_none() {
+        return Err(ApiError::InvalidParameter(
+            "nothing to change, set a name or a color".to_string(),
+        ));
+    }
+    Ok(state
+        .metadata_store
+        .update_label(&email, &label_id, &update)
+        .await?)
+}
+
+pub async fn delete_label_api(
+    Path((email, label_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<StatusCode, ApiError> {
+    delete_label(state, &access, email, label_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_label(
+    state: AppState,
+    access: &Access,
+    email: String,
+    label_id: String,
+) -> Result<(), ApiError> {
+    access.check_write(&email)?;
+    Ok(state.metadata_store.delete_label(&email, &label_id).await?)
+}
+
+pub async fn add_label_api(
+    Path((email, key_id, label_id)): Path<(String, String, String)>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<MailLabelsResponse>, ApiError> {
+    let response = set_label(state, &access, email, folder, key_id, label_id, true).await?;
+    Ok(Json(response))
+}
+
+pub async fn remove_label_api(
+    Path((email, key_id, label_id)): Path<(String, String, String)>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<MailLabelsResponse>, ApiError> {
+    let response = set_label(state, &access, email, folder, key_id, label_id, false).await?;
+    Ok(Json(response))
+}
+
+/// Files mail `key_id` as listed in `folder` of `email` under `label_id`, or takes it off.
+pub async fn set_label(
+    state: AppState,
+    access: &Access,
+    email: String,
+    folder: Folder,
+    key_id: String,
+    label_id: String,
+    filed: bool,
+) -> Result<MailLabelsResponse, ApiError> {
+    access.check_write(&email)?;
+    let labels = state
+        .metadata_store
+        .set_label(&folder.mailbox(&email), &key_id, &label_id, filed)
+        .await?;
+    Ok(MailLabelsResponse { labels })
+}
+
+pub const MAX_RULES: usize = 100;
+
+/// The filtering rules of a mailbox, in the order they run.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+}
+
+pub async fn get_rules_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<RuleSet>, ApiError> {
+    let response = get_rules(state, &access, email).await?;
+    Ok(Json(response))
+}
+
+pub async fn get_rules(
+    state: AppState,
+    access: &Access,
+    email: String,
+) -> Result<RuleSet, ApiError> {
+    access.check(&email)?;
+    let rules = state.metadata_store.get_rules(&email).await?;
+    Ok(RuleSet { rules })
+}
+
+pub async fn put_rules_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<RuleSet>,
+) -> Result<Json<RuleSet>, ApiError> {
+    let response = put_rules(state, &access, email, request).await?;
+    Ok(Json(response))
+}
+
+/// Replaces the rules of `email`, the inbox runs them on the mail received from then on.
+pub async fn put_rules(
+    state: AppState,
+    access: &Access,
+    email: String,
+    request: RuleSet,
+) -> Result<RuleSet, ApiError> {
+    access.check_write(&email)?;
+    validate_rules(&state, &email, &request).await?;
+    state
+        .metadata_store
+        .put_rules(&email, &request.rules)
+        .await?;
+    Ok(request)
+}
+
+/// Checks `request` can be run for `email`, at most [`MAX_RULES`] each valid for its labels.
+async fn validate_rules(state: &AppState, email: &str, request: &RuleSet) -> Result<(), ApiError> {
+    if request.rules.len() > MAX_RULES {
+        return Err(ApiError::InvalidParameter(format!(
+            "at most {MAX_RULES} rules are allowed"
+        )));
+    }
+    let labels = state.metadata_store.list_labels(email).await?;
+    for rule in &request.rules {
+        validate_rule(rule, |label_id| {
+            labels.iter().any(|label| label.label_id == label_id)
         })
+        .map_err(|e| ApiError::InvalidParameter(format!("rule {:?}: {e}", rule.name)))?;
+    }
+    Ok(())
+}
+
+/// Why `rule` can't be run, `is_label` tells the labels of the mailbox apart.
+fn validate_rule(rule: &Rule, is_label: impl Fn(&str) -> bool) -> Result<(), String> {
+    if rule.name.trim().is_empty() {
+        return Err("a rule needs a name".to_string());
+    }
+    if rule.actions.is_empty() {
+        return Err("a rule needs at least one action".to_string());
+    }
+    for condition in &rule.conditions {
+        match condition {
+            Condition::From(matches) | Condition::To(matches) | Condition::Subject(matches)
+                if matches.value.trim().is_empty() =>
+            {
+                return Err("text conditions need a value".to_string());
+            }
+            Condition::Header { name, matches }
+                if name.trim().is_empty() || matches.value.trim().is_empty() =>
+            {
+                return Err("header conditions need a name and a value".to_string());
+            }
+            _ => {}
+        }
+    }
+    for action in &rule.actions {
+        match action {
+            RuleAction::AddLabel { label_id } if !is_label(label_id) => {
+                return Err(format!("label {label_id} not found"));
+            }
+            RuleAction::Forward { to } if crate::send::domain_of(to.trim()).is_none() => {
+                return Err(format!("{to:?} is not a valid address"));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Takes the raw message as the body, or a `multipart/form-data` form with the message in an
+/// `eml` part and, to try rules before saving them, a [`RuleSet`] as JSON in a `rules` part.
+pub async fn dry_run_rules_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+    request: Request,
+) -> Result<Json<Outcome>, ApiError> {
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("multipart/form-data"));
+    let (eml, rules) = if is_form {
+        let invalid = |e: MultipartError| ApiError::InvalidParameter(e.body_text());
+        let mut form = Multipart::from_request(request, &state)
+            .await
+            .map_err(|e| ApiError::InvalidParameter(e.body_text()))?;
+        let (mut eml, mut rules) = (None, None);
+        while let Some(field) = form.next_field().await.map_err(invalid)? {
+            match field.name() {
+                Some("eml") => eml = Some(field.bytes().await.map_err(invalid)?),
+                Some("rules") => {
+                    let json = field.bytes().await.map_err(invalid)?;
+                    rules = Some(serde_json::from_slice::<RuleSet>(&json).map_err(|e| {
+                        ApiError::InvalidParameter(format!("malformed rules: {e}"))
+                    })?);
+                }
+                _ => {}
+            }
+        }
+        let eml =
+            eml.ok_or_else(|| ApiError::InvalidParameter("the form has no eml part".to_string()))?;
+        (eml, rules)
+    } else {
+        let eml = Bytes::from_request(request, &state)
+            .await
+            .map_err(|e| ApiError::InvalidParameter(e.body_text()))?;
+        (eml, None)
+    };
+    let response = dry_run_rules(state, &access, email, &eml, rules).await?;
+    Ok(Json(response))
+}
+
+/// What `rules`, or the saved rules of `email` when there are none, would do with the raw message
+/// `eml`, without doing it. Rules given are checked the way saving them would.
+pub async fn dry_run_rules(
+    state: AppState,
+    access: &Access,
+    email: String,
+    eml: &[u8],
+    rules: Option<RuleSet>,
+) -> Result<Outcome, ApiError> {
+    access.check(&email)?;
+    let message = parse_message("uploaded", eml)?;
+    let rules = match rules {
+        Some(request) => {
+            validate_rules(&state, &email, &request).await?;
+            request.rules
+        }
+        None => state.metadata_store.get_rules(&email).await?,
+    };
+    Ok(rules::evaluate(&rules, &message, eml.len()))
+}
+
+/// Largest Sieve script accepted, in bytes.
+pub const MAX_SCRIPT_SIZE: usize = 64 * 1024;
+
+/// The Sieve script of a mailbox, empty when there's none.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SieveScript {
+    pub script: String,
+}
+
+/// Whether a Sieve script would be accepted, and where it's wrong when it isn't.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SieveValidation {
+    pub valid: bool,
+    pub error: Option<ScriptError>,
+}
+
+pub async fn get_sieve_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<SieveScript>, ApiError> {
+    let response = get_sieve(state, &access, email).await?;
+    Ok(Json(response))
+}
+
+pub async fn get_sieve(
+    state: AppState,
+    access: &Access,
+    email: String,
+) -> Result<SieveScript, ApiError> {
+    access.check(&email)?;
+    let script = state.metadata_store.get_sieve(&email).await?;
+    Ok(SieveScript { script })
+}
+
+pub async fn put_sieve_api(
+    Path(email): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<SieveScript>,
+) -> Result<Json<SieveScript>, ApiError> {
+    let response = put_sieve(state, &access, email, request).await?;
+    Ok(Json(response))
+}
+
+/// Replaces the Sieve script of `email`, the inbox runs it after the rules on the mail received
+/// from then on. An empty script removes it.
+pub async fn put_sieve(
+    state: AppState,
+    access: &Access,
+    email: String,
+    request: SieveScript,
+) -> Result<SieveScript, ApiError> {
+    access.check_write(&email)?;
+    let script = parse_sieve(&request.script)?
+        .map_err(|error| ApiError::InvalidParameter(format!("invalid script, {error}")))?;
+    // vacation replies are sent as whoever the script says, which has to be someone we can be
+    for sender in script
+        .vacation_senders(&email)
+        .map_err(ApiError::InvalidParameter)?
+    {
+        access.check_send(sender)?;
+    }
+    state
+        .metadata_store
+        .put_sieve(&email, &request.script)
+        .await?;
+    Ok(request)
+}
+
+pub async fn validate_sieve_api(
+    _access: Access,
+    script: String,
+) -> Result<Json<SieveValidation>, ApiError> {
+    Ok(Json(validate_sieve(&script)?))
+}
+
+/// Checks `script` the way the inbox reads it, without storing it.
+pub fn validate_sieve(script: &str) -> Result<SieveValidation, ApiError> {
+    let error = parse_sieve(script)?.err();
+    Ok(SieveValidation {
+        valid: error.is_none(),
+        error,
+    })
+}
+
+/// `script` parsed, unless it's too long to be read at all.
+fn parse_sieve(script: &str) -> Result<Result<Script, ScriptError>, ApiError> {
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(ApiError::InvalidParameter(format!(
+            "scripts can be at most {MAX_SCRIPT_SIZE} bytes"
+        )));
+    }
+    Ok(Script::parse(script))
+}
+
+pub async fn list_threads_api(
+    Path(email): Path<String>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    Query(query): Query<ListQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListThreadsResponse>, ApiError> {
+    let response = list_threads(state, &access, email, folder, query).await?;
+    Ok(Json(response))
+}
+
+pub async fn list_threads(
+    state: AppState,
+    access: &Access,
+    email: String,
+    folder: Folder,
+    query: ListQuery,
+) -> Result<ListThreadsResponse, ApiError> {
+    access.check(&email)?;
+    let page = state
+        .metadata_store
+        .list_threads(&folder.mailbox(&email), query.page()?)
+        .await?;
+    Ok(ListThreadsResponse {
+        data: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+pub async fn list_thread_api(
+    Path((email, thread_id)): Path<(String, String)>,
+    Query(FolderQuery { folder }): Query<FolderQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListEmailsResponse>, ApiError> {
+    let response = list_thread(state, &access, email, folder, thread_id).await?;
+    Ok(Json(response))
+}
+
+/// Every mail of one conversation, oldest first. Threads are small, there's no pagination.
+pub async fn list_thread(
+    state: AppState,
+    access: &Access,
+    email: String,
+    folder: Folder,
+    thread_id: String,
+) -> Result<ListEmailsResponse, ApiError> {
+    access.check(&email)?;
+    let mails = state
+        .metadata_store
+        .list_thread(&folder.mailbox(&email), &thread_id)
+        .await?;
+    if mails.is_empty() {
+        return Err(ApiError::NotFound(format!("thread {thread_id}")));
+    }
+    Ok(ListEmailsResponse {
+        data: mails,
+        next_cursor: None,
+    })
+}
+
+/// Query of the search endpoint: `q` as typed in the search bar, searched in mailbox `email`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SearchParams {
+    pub q: String,
+    pub email: String,
+}
+
+pub async fn search_api(
+    Query(SearchParams { q, email }): Query<SearchParams>,
+    Query(query): Query<ListQuery>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListEmailsResponse>, ApiError> {
+    let response = search(state, &access, email, q, query).await?;
+    Ok(Json(response))
+}
+
+pub async fn search(
+    state: AppState,
+    access: &Access,
+    email: String,
+    q: String,
+    query: ListQuery,
+) -> Result<ListEmailsResponse, ApiError> {
+    access.check(&email)?;
+    let search_query = q
+        .parse::<SearchQuery>()
+        .map_err(|e| ApiError::InvalidParameter(e.to_string()))?;
+    let page = state
+        .search_store
+        .search(&email, &search_query, query.page()?)
+        .await?;
+    Ok(ListEmailsResponse {
+        data: page.items,
+        next_cursor: page.next_cursor,
+    })
+}
+
+pub async fn list_users(
+    state: AppState,
+    access: &Access,
+    query: ListQuery,
+) -> Result<ListUsersResponse, ApiError> {
+    let page = state.metadata_store.list_users(query.page()?).await?;
+    // mailboxes that may not be read are left out, a page can come back short of the limit
+    let users = page
+        .items
+        .into_iter()
+        .filter(|user| access.can_read(&user.sk))
         .collect();
-    ListUsersResponse { data: users }
+    Ok(ListUsersResponse {
+        data: users,
+        next_cursor: page.next_cursor,
+    })
+}
+
+pub async fn send_email_api(
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<SendRequest>,
+) -> Result<Json<SendResponse>, ApiError> {
+    let response = send_email(state, &access, request).await?;
+    Ok(Json(response))
+}
+
+/// Sends `request` and files a copy in the Sent folder of its sender, who has to be one of the
+/// addresses that may be sent from.
+pub async fn send_email(
+    state: AppState,
+    access: &Access,
+    request: SendRequest,
+) -> Result<SendResponse, ApiError> {
+    access.check_send(request.from.trim())?;
+    let timestamp = chrono::Utc::now().timestamp();
+    let attachments = match &request.attachments_from {
+        Some(key_id) => {
+            access.check_mail(&state, key_id).await?;
+            let contents = state.mail_store.get_raw(key_id).await?;
+            let message = parse_message(key_id, &contents)?;
+            message
+                .attachments()
+                .map(|part| send::Attachment {
+                    name: attachment_name(part).to_string(),
+                    content_type: content_type(part),
+                    contents: part.contents().to_vec(),
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    };
+    let outgoing = send::build_message(&request, &attachments, timestamp)?;
+    let key = state.mail_sender.send(&outgoing).await?;
+
+    // the mail is out at this point, failing to keep a copy shouldn't look like a failed send
+    let message = Message::parse(&outgoing.raw);
+    let sent = Mail {
+        pk: Folder::Sent.mailbox(&outgoing.from),
+        sk: timestamp,
+        message_id: key.clone(),
+        subject: request.subject,
+        from: vec![outgoing.from.clone()],
+        first_sentence: message.as_ref().map(first_sentence).unwrap_or_default(),
+        thread_id: message.as_ref().map(thread_id).unwrap_or_default(),
+        flags: Flags {
+            seen: true,
+            ..Flags::default()
+        },
+        labels: Vec::new(),
+    };
+    let stored = match state.mail_store.put_raw(&key, outgoing.raw).await {
+        Ok(()) => state.metadata_store.put_email(&sent).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = stored {
+        log::error!("sent {key} but couldn't store it: {e}");
+    }
+    Ok(SendResponse { message_id: key })
 }
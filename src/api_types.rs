@@ -1,28 +1,452 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use supermailer_core::mail::{sent_mailbox, SENT_PREFIX};
+pub use supermailer_core::mail::{Flags, Label, Mail, Thread, User};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Mail {
-    pub pk: String,
-    pub sk: i64,
+pub struct ListEmailsResponse {
+    pub data: Vec<Mail>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListThreadsResponse {
+    pub data: Vec<Thread>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListUsersResponse {
+    pub data: Vec<User>,
+    pub next_cursor: Option<String>,
+}
+
+/// Which side of a mailbox a listing shows.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Folder {
+    #[default]
+    Inbox,
+    Sent,
+}
+
+impl Folder {
+    /// Partition key holding this folder of `address`.
+    pub fn mailbox(self, address: &str) -> String {
+        match self {
+            Folder::Inbox => address.to_string(),
+            Folder::Sent => sent_mailbox(address),
+        }
+    }
+
+    /// The folder partition key `pk` holds, the inverse of [`Self::mailbox`].
+    pub fn of(pk: &str) -> Self {
+        if pk.starts_with(SENT_PREFIX) {
+            Folder::Sent
+        } else {
+            Folder::Inbox
+        }
+    }
+}
+
+// the UI keeps the folder in the query string
+impl fmt::Display for Folder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Folder::Inbox => "inbox",
+            Folder::Sent => "sent",
+        })
+    }
+}
+
+impl FromStr for Folder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inbox" => Ok(Folder::Inbox),
+            "sent" => Ok(Folder::Sent),
+            _ => Err(format!("unknown folder {s:?}")),
+        }
+    }
+}
+
+/// Which mails of a folder a listing shows, by their [`Flags`].
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MailFilter {
+    /// Everything that's neither archived nor deleted.
+    #[default]
+    All,
+    Unread,
+    Flagged,
+    Archived,
+    Deleted,
+}
+
+impl MailFilter {
+    /// Whether a mail with `flags` is shown, the stores query the same conditions.
+    pub fn matches(self, flags: &Flags) -> bool {
+        match self {
+            MailFilter::All => !flags.archived && !flags.deleted,
+            MailFilter::Unread => !flags.seen && !flags.archived && !flags.deleted,
+            MailFilter::Flagged => flags.flagged && !flags.deleted,
+            MailFilter::Archived => flags.archived && !flags.deleted,
+            MailFilter::Deleted => flags.deleted,
+        }
+    }
+}
+
+impl fmt::Display for MailFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MailFilter::All => "all",
+            MailFilter::Unread => "unread",
+            MailFilter::Flagged => "flagged",
+            MailFilter::Archived => "archived",
+            MailFilter::Deleted => "deleted",
+        })
+    }
+}
+
+impl FromStr for MailFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(MailFilter::All),
+            "unread" => Ok(MailFilter::Unread),
+            "flagged" => Ok(MailFilter::Flagged),
+            "archived" => Ok(MailFilter::Archived),
+            "deleted" => Ok(MailFilter::Deleted),
+            _ => Err(format!("unknown filter {s:?}")),
+        }
+    }
+}
+
+/// Flags to change on a mail, the ones left out keep their value.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlagsUpdate {
+    #[serde(default)]
+    pub seen: Option<bool>,
+    #[serde(default)]
+    pub flagged: Option<bool>,
+    #[serde(default)]
+    pub archived: Option<bool>,
+    #[serde(default)]
+    pub deleted: Option<bool>,
+}
+
+impl FlagsUpdate {
+    /// The flags being changed, as named in the mail item.
+    pub fn changes(&self) -> Vec<(&'static str, bool)> {
+        [
+            ("seen", self.seen),
+            ("flagged", self.flagged),
+            ("archived", self.archived),
+            ("deleted", self.deleted),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?)))
+        .collect()
+    }
+
+    /// `flags` with the changes made.
+    pub fn apply(&self, flags: Flags) -> Flags {
+        Flags {
+            seen: self.seen.unwrap_or(flags.seen),
+            flagged: self.flagged.unwrap_or(flags.flagged),
+            archived: self.archived.unwrap_or(flags.archived),
+            deleted: self.deleted.unwrap_or(flags.deleted),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnreadResponse {
+    pub unread: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListLabelsResponse {
+    pub data: Vec<Label>,
+}
+
+/// A label to create, or the parts of one to change when they're set.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LabelRequest {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+/// Labels a mail is filed under after adding or removing one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MailLabelsResponse {
+    pub labels: Vec<String>,
+}
+
+/// A single mail with its headers and body, as shown by the message view.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MailDetail {
     pub message_id: String,
     pub subject: String,
     pub from: Vec<String>,
-    pub first_sentence: String,
+    pub to: Vec<String>,
+    pub cc: Vec<String>,
+    pub date: Option<i64>,
+    /// What the sender wrote, `html` is converted from the text when it's [`BodyFormat::Text`].
+    /// All three are `None` for a mail without a body.
+    pub format: Option<BodyFormat>,
+    pub html: Option<String>,
+    pub text: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyFormat {
+    Html,
+    Text,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ListEmailsResponse {
-    pub data: Vec<Mail>,
+pub struct Attachment {
+    pub index: usize,
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+    /// Set on parts the HTML body can refer to with a `cid:` URL.
+    pub content_id: Option<String>,
+}
+
+impl Attachment {
+    /// Where attachment `index` of mail `message_id` is downloaded from.
+    pub fn url(message_id: &str, index: usize) -> String {
+        format!(
+            "/api/email/{}/attachments/{index}",
+            urlencoding::encode(message_id)
+        )
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct User {
-    pub pk: String,
-    pub sk: String,
-    pub message_count: i64,
+pub struct ListAttachmentsResponse {
+    pub data: Vec<Attachment>,
+}
+
+/// A new mail written in the compose view. Addresses are bare `local@domain`.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SendRequest {
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(default)]
+    pub cc: Vec<String>,
+    #[serde(default)]
+    pub bcc: Vec<String>,
+    pub subject: String,
+    pub text: String,
+    /// `Message-ID` of the mail being answered, without the angle brackets.
+    #[serde(default)]
+    pub in_reply_to: Option<String>,
+    #[serde(default)]
+    pub references: Vec<String>,
+    /// Key of a stored mail whose attachments are sent along, set when forwarding.
+    #[serde(default)]
+    pub attachments_from: Option<String>,
+}
+
+/// How a draft answers the mail it's created from.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyMode {
+    #[default]
+    Reply,
+    ReplyAll,
+    Forward,
+}
+
+impl fmt::Display for ReplyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReplyMode::Reply => "reply",
+            ReplyMode::ReplyAll => "reply_all",
+            ReplyMode::Forward => "forward",
+        })
+    }
+}
+
+impl FromStr for ReplyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reply" => Ok(ReplyMode::Reply),
+            "reply_all" => Ok(ReplyMode::ReplyAll),
+            "forward" => Ok(ReplyMode::Forward),
+            _ => Err(format!("unknown reply mode {s:?}")),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ListUsersResponse {
-    pub data: Vec<User>,
+pub struct SendResponse {
+    /// Key of the sent mail, it can be opened like any received one.
+    pub message_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// An account as shown to itself and to admins, without its password.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AccountInfo {
+    pub username: String,
+    /// Addresses whose inbox and sent folder it may read.
+    pub mailboxes: Vec<String>,
+    pub admin: bool,
+}
+
+/// An account to create or replace. The password can be left out to keep the current one.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AccountRequest {
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub mailboxes: Vec<String>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListAccountsResponse {
+    pub data: Vec<AccountInfo>,
+}
+
+/// What an API key may do, written `read:<address>`, `write:<address>`, `send:<address>` or
+/// `admin`.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Scope {
+    /// Listing, reading and searching the inbox and sent folder of the address.
+    Read(String),
+    /// Changing flags, labels, rules and the Sieve script of the address, along with `read`.
+    Write(String),
+    /// Sending mail from the address.
+    Send(String),
+    /// Managing accounts.
+    Admin,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Read(address) => write!(f, "read:{address}"),
+            Scope::Write(address) => write!(f, "write:{address}"),
+            Scope::Send(address) => write!(f, "send:{address}"),
+            Scope::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("read", address)) if !address.is_empty() => {
+                Ok(Scope::Read(address.to_lowercase()))
+            }
+            Some(("write", address)) if !address.is_empty() => {
+                Ok(Scope::Write(address.to_lowercase()))
+            }
+            Some(("send", address)) if !address.is_empty() => {
+                Ok(Scope::Send(address.to_lowercase()))
+            }
+            None if s == "admin" => Ok(Scope::Admin),
+            _ => Err(format!("unknown scope {s:?}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Scope> for String {
+    fn from(scope: Scope) -> Self {
+        scope.to_string()
+    }
+}
+
+/// An API key as listed, its token is only shown once when it's created.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ApiKeyInfo {
+    pub key_id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Unix timestamps in seconds, a key without `expires_at` lasts until it's revoked.
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    #[serde(default)]
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateApiKeyResponse {
+    pub key: ApiKeyInfo,
+    /// Sent as `Authorization: Bearer <token>`.
+    pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ListApiKeysResponse {
+    pub data: Vec<ApiKeyInfo>,
+}
+
+/// Body of every error returned by the HTTP API. The server functions wrap the same value in
+/// `ServerFnError::WrappedServerError`, so it has to survive a `Display`/`FromStr` round trip.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub status: u16,
+    pub error: String,
+    pub message: String,
+}
+
+impl fmt::Display for ErrorResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}: {}", self.status, self.error, self.message)
+    }
+}
+
+impl FromStr for ErrorResponse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (status, rest) = s.split_once(' ')?;
+            let (error, message) = rest.split_once(": ")?;
+            Some(ErrorResponse {
+                status: status.parse().ok()?,
+                error: error.to_string(),
+                message: message.to_string(),
+            })
+        };
+        parse().ok_or_else(|| format!("malformed error response {s:?}"))
+    }
 }
@@ -0,0 +1,522 @@
+//! Logins to the web app. An [`Account`] may read the mailboxes it was given, logging in starts a
+//! [`Session`] kept in the [`SESSION_COOKIE`], and every API handler is given the [`Access`] of
+//! the session the request carries.
+use crate::api_types::{AccountInfo, AccountRequest, ListAccountsResponse, LoginRequest};
+use crate::error::ApiError;
+use crate::send::domain_of;
+use crate::state::AppState;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, State},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use supermailer_core::mail::mailbox_address;
+
+/// Cookie holding the session token.
+pub const SESSION_COOKIE: &str = "session";
+/// How long a login lasts.
+pub const SESSION_DAYS: i64 = 30;
+pub const MIN_PASSWORD: usize = 8;
+pub const MAX_USERNAME: usize = 64;
+
+/// Someone who can log in, and the addresses whose inbox and sent folder they may read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub username: String,
+    /// PHC string of the Argon2 hash.
+    pub password_hash: String,
+    pub mailboxes: Vec<String>,
+    /// Admins manage the accounts, they read the mailboxes they were given like everyone else.
+    pub admin: bool,
+}
+
+impl Account {
+    pub fn info(&self) -> AccountInfo {
+        AccountInfo {
+            username: self.username.clone(),
+            mailboxes: self.mailboxes.clone(),
+            admin: self.admin,
+        }
+    }
+}
+
+/// A login. `id` is the hash of the token handed out, so the stored sessions can't be used to
+/// log in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub id: String,
+    pub username: String,
+    /// Unix timestamp in seconds.
+    pub expires_at: i64,
+}
+
+/// How a request was authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    Session,
+    ApiKey,
+}
+
+/// What the session or API key of a request may do, resolved again on every request so that
+/// changes to an account apply right away.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Access {
+    pub username: String,
+    pub mailboxes: Vec<String>,
+    /// Mailboxes whose flags, labels, rules and Sieve script may be changed, the same as
+    /// `mailboxes` for a session.
+    pub writable: Vec<String>,
+    /// Addresses mail may be sent from, the same as `mailboxes` for a session.
+    pub senders: Vec<String>,
+    pub admin: bool,
+    pub method: AuthMethod,
+}
+
+impl Access {
+    pub fn of(account: &Account) -> Self {
+        Access {
+            username: account.username.clone(),
+            mailboxes: account.mailboxes.clone(),
+            writable: account.mailboxes.clone(),
+            senders: account.mailboxes.clone(),
+            admin: account.admin,
+            method: AuthMethod::Session,
+        }
+    }
+
+    /// Whether `mailbox`, an address or the partition key of one of its folders, may be read.
+    pub fn can_read(&self, mailbox: &str) -> bool {
+        let address = mailbox_address(mailbox);
+        self.mailboxes
+            .iter()
+            .any(|readable| readable.eq_ignore_ascii_case(address))
+    }
+
+    pub fn check(&self, mailbox: &str) -> Result<(), ApiError> {
+        if self.can_read(mailbox) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!(
+                "mailbox {}",
+                mailbox_address(mailbox)
+            )))
+        }
+    }
+
+    /// Whether `mailbox` may be read and changed, see [`Access::can_read`].
+    pub fn can_write(&self, mailbox: &str) -> bool {
+        let address = mailbox_address(mailbox);
+        self.can_read(mailbox)
+            && self
+                .writable
+                .iter()
+                .any(|writable| writable.eq_ignore_ascii_case(address))
+    }
+
+    pub fn check_write(&self, mailbox: &str) -> Result<(), ApiError> {
+        self.check(mailbox)?;
+        if self.can_write(mailbox) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!(
+                "changing mailbox {}",
+                mailbox_address(mailbox)
+            )))
+        }
+    }
+
+    pub fn can_send(&self, address: &str) -> bool {
+        self.senders
+            .iter()
+            .any(|sender| sender.eq_ignore_ascii_case(address))
+    }
+
+    pub fn check_send(&self, address: &str) -> Result<(), ApiError> {
+        if self.can_send(address) {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden(format!("sending as {address}")))
+        }
+    }
+
+    pub fn check_admin(&self) -> Result<(), ApiError> {
+        if self.admin {
+            Ok(())
+        } else {
+            Err(ApiError::Forbidden("accounts".to_string()))
+        }
+    }
+
+    /// Fails unless the request came with a session, for what an API key mustn't do even when
+    /// its scopes would cover it.
+    pub fn check_session(&self) -> Result<(), ApiError> {
+        match self.method {
+            AuthMethod::Session => Ok(()),
+            AuthMethod::ApiKey => Err(ApiError::Forbidden(
+                "API keys, log in to manage them".to_string(),
+            )),
+        }
+    }
+
+    /// Fails like a missing mail would unless mail `key_id` is listed in a mailbox that may be
+    /// read, keys of other mailboxes aren't told apart from made up ones.
+    pub async fn check_mail(&self, state: &AppState, key_id: &str) -> Result<(), ApiError> {
+        let mailboxes = state.metadata_store.mailboxes_of(key_id).await?;
+        if mailboxes.iter().any(|mailbox| self.can_read(mailbox)) {
+            Ok(())
+        } else {
+            Err(ApiError::NotFound(format!("mail {key_id}")))
+        }
+    }
+
+    /// Access of the session whose token is in the cookies of `headers`.
+    pub async fn of_request(state: &AppState, headers: &HeaderMap) -> Result<Access, ApiError> {
+        let token = session_token(headers).ok_or(ApiError::Unauthorized)?;
+        let store = &state.metadata_store;
+        let session = store
+            .get_session(&token_hash(&token))
+            .await?
+            .filter(|session| session.expires_at > chrono::Utc::now().timestamp())
+            .ok_or(ApiError::Unauthorized)?;
+        // sessions of a deleted account end with it
+        let account = store
+            .get_account(&session.username)
+            .await?
+            .ok_or(ApiError::Unauthorized)?;
+        Ok(Access::of(&account))
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Access {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        // an API key was already checked by `keys::api_key_layer`
+        if let Some(access) = parts.extensions.get::<Access>() {
+            return Ok(access.clone());
+        }
+        Access::of_request(state, &parts.headers).await
+    }
+}
+
+/// State and access of the request a server function runs for.
+pub async fn server_context() -> Result<(AppState, Access), ApiError> {
+    let state = leptos::prelude::use_context::<AppState>().ok_or(ApiError::MissingState)?;
+    let headers: HeaderMap = leptos_axum::extract()
+        .await
+        .map_err(|_| ApiError::MissingState)?;
+    let access = Access::of_request(&state, &headers).await?;
+    Ok((state, access))
+}
+
+/// The value of the [`SESSION_COOKIE`] among the cookies of `headers`.
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    cookie(headers, SESSION_COOKIE)
+}
+
+/// The value of cookie `name` among the cookies of `headers`, unless it's empty.
+pub(crate) fn cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Id a session is stored under, the SHA-256 of its token. API keys keep the same hash.
+pub(crate) fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// `Set-Cookie` value handing out `token`. It's out of reach of scripts and not sent along with
+/// requests other sites make, outside of development it's only sent over HTTPS.
+pub fn session_cookie(token: &str) -> String {
+    let secure = if cfg!(debug_assertions) {
+        ""
+    } else {
+        "; Secure"
+    };
+    format!(
+        "{SESSION_COOKIE}={token}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+        SESSION_DAYS * 24 * 60 * 60
+    )
+}
+
+/// `Set-Cookie` value removing the session cookie.
+pub fn expired_cookie() -> String {
+    format!("{SESSION_COOKIE}=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax")
+}
+
+pub fn hash_password(password: &str) -> Result<String, ApiError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| ApiError::InvalidParameter(format!("password can't be hashed, {e}")))
+}
+
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    PasswordHash::new(password_hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// Hash checked against when there's no such account, so that a login takes as long either way.
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("not a password").unwrap_or_default())
+}
+
+/// Starts a session for `username`, returning its token.
+pub async fn start_session(state: &AppState, username: &str) -> Result<String, ApiError> {
+    let token = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    let session = Session {
+        id: token_hash(&token),
+        username: username.to_string(),
+        expires_at: chrono::Utc::now().timestamp() + SESSION_DAYS * 24 * 60 * 60,
+    };
+    state.metadata_store.put_session(&session).await?;
+    Ok(token)
+}
+
+pub async fn login_api(
+    State(state): State<AppState>,
+    Json(request): Json<LoginRequest>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (account, token) = login(state, request).await?;
+    Ok((
+        [(header::SET_COOKIE, session_cookie(&token))],
+        Json(account),
+    ))
+}
+
+/// Checks the password of `request.username` and starts a session, returning its token.
+pub async fn login(
+    state: AppState,
+    request: LoginRequest,
+) -> Result<(AccountInfo, String), ApiError> {
+    let account = state.metadata_store.get_account(&request.username).await?;
+    let password_hash = match &account {
+        Some(account) => account.password_hash.as_str(),
+        None => dummy_hash(),
+    };
+    let verified = verify_password(password_hash, &request.password);
+    let Some(account) = account.filter(|_| verified) else {
+        return Err(ApiError::InvalidCredentials);
+    };
+    let token = start_session(&state, &account.username).await?;
+    Ok((account.info(), token))
+}
+
+pub async fn logout_api(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    logout(state, &headers).await?;
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(header::SET_COOKIE, expired_cookie())],
+    ))
+}
+
+/// Ends the session whose token is in the cookies of `headers`, if there's one.
+pub async fn logout(state: AppState, headers: &HeaderMap) -> Result<(), ApiError> {
+    if let Some(token) = session_token(headers) {
+        state
+            .metadata_store
+            .delete_session(&token_hash(&token))
+            .await?;
+    }
+    Ok(())
+}
+
+/// The account that's logged in.
+pub async fn session_api(access: Access) -> Json<AccountInfo> {
+    Json(AccountInfo {
+        username: access.username,
+        mailboxes: access.mailboxes,
+        admin: access.admin,
+    })
+}
+
+pub async fn list_accounts_api(
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListAccountsResponse>, ApiError> {
+    let response = list_accounts(state, &access).await?;
+    Ok(Json(response))
+}
+
+/// Every account, by username. There are few, no pagination.
+pub async fn list_accounts(
+    state: AppState,
+    access: &Access,
+) -> Result<ListAccountsResponse, ApiError> {
+    access.check_admin()?;
+    let accounts = state.metadata_store.list_accounts().await?;
+    Ok(ListAccountsResponse {
+        data: accounts.iter().map(Account::info).collect(),
+    })
+}
+
+pub async fn put_account_api(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<AccountRequest>,
+) -> Result<Json<AccountInfo>, ApiError> {
+    let response = put_account(state, &access, username, request).await?;
+    Ok(Json(response))
+}
+
+/// Creates or replaces account `username`, which needs a password when it's new.
+pub async fn put_account(
+    state: AppState,
+    access: &Access,
+    username: String,
+    request: AccountRequest,
+) -> Result<AccountInfo, ApiError> {
+    access.check_admin()?;
+    validate_username(&username)?;
+    if username == access.username && !request.admin {
+        return Err(ApiError::InvalidParameter(
+            "admins can't take away their own admin rights".to_string(),
+        ));
+    }
+    let mailboxes = mailboxes(&request.mailboxes)?;
+    let password_hash = match (
+        &request.password,
+        state.metadata_store.get_account(&username).await?,
+    ) {
+        (Some(password), _) => {
+            validate_password(password)?;
+            hash_password(password)?
+        }
+        (None, Some(existing)) => existing.password_hash,
+        (None, None) => {
+            return Err(ApiError::InvalidParameter(
+                "a new account needs a password".to_string(),
+            ))
+        }
+    };
+    let account = Account {
+        username,
+        password_hash,
+        mailboxes,
+        admin: request.admin,
+    };
+    state.metadata_store.put_account(&account).await?;
+    Ok(account.info())
+}
+
+pub async fn delete_account_api(
+    Path(username): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<StatusCode, ApiError> {
+    delete_account(state, &access, username).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Deletes account `username`, its sessions end with it.
+pub async fn delete_account(
+    state: AppState,
+    access: &Access,
+    username: String,
+) -> Result<(), ApiError> {
+    access.check_admin()?;
+    if username == access.username {
+        return Err(ApiError::InvalidParameter(
+            "admins can't delete their own account".to_string(),
+        ));
+    }
+    if state.metadata_store.get_account(&username).await?.is_none() {
+        return Err(ApiError::NotFound(format!("account {username}")));
+    }
+    Ok(state.metadata_store.delete_account(&username).await?)
+}
+
+/// Creates admin account `username` unless it exists, so that there's someone to create the
+/// others. Returns whether it was created.
+pub async fn bootstrap_admin(
+    state: &AppState,
+    username: &str,
+    password: &str,
+    mailboxes: &[String],
+) -> Result<bool, ApiError> {
+    if state.metadata_store.get_account(username).await?.is_some() {
+        return Ok(false);
+    }
+    validate_username(username)?;
+    validate_password(password)?;
+    let account = Account {
+        username: username.to_string(),
+        password_hash: hash_password(password)?,
+        mailboxes: self::mailboxes(mailboxes)?,
+        admin: true,
+    };
+    state.metadata_store.put_account(&account).await?;
+    Ok(true)
+}
+
+fn validate_username(username: &str) -> Result<(), ApiError> {
+    let valid = !username.is_empty()
+        && username.len() <= MAX_USERNAME
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._@+-".contains(c));
+    if valid {
+        Ok(())
+    } else {
+        Err(ApiError::InvalidParameter(format!(
+            "usernames are 1 to {MAX_USERNAME} letters, digits or ._@+-, got {username:?}"
+        )))
+    }
+}
+
+fn validate_password(password: &str) -> Result<(), ApiError> {
+    if password.chars().count() < MIN_PASSWORD {
+        return Err(ApiError::InvalidParameter(format!(
+            "passwords need at least {MIN_PASSWORD} characters"
+        )));
+    }
+    Ok(())
+}
+
+/// `addresses` checked, lowercased and each once.
+fn mailboxes(addresses: &[String]) -> Result<Vec<String>, ApiError> {
+    let mut mailboxes: Vec<String> = Vec::new();
+    for address in addresses {
+        let address = address.trim().to_lowercase();
+        if domain_of(&address).is_none() {
+            return Err(ApiError::InvalidParameter(format!(
+                "{address:?} is not a valid address"
+            )));
+        }
+        if !mailboxes.contains(&address) {
+            mailboxes.push(address);
+        }
+    }
+    Ok(mailboxes)
+}
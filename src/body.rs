@@ -0,0 +1,158 @@
+//! Choosing which part of a mail to show and turning it into a sanitized HTML document.
+use crate::api_types::BodyFormat;
+use crate::html;
+use mail_parser::Message;
+
+/// The displayable body of a mail. `html` is always a full sanitized document, converted from
+/// the plain text when that's all the mail has.
+#[derive(Debug, Clone)]
+pub struct Body {
+    pub format: BodyFormat,
+    pub html: String,
+    pub text: Option<String>,
+}
+
+/// Picks the best representation of `message`: the HTML alternative of a multipart/alternative
+/// when there is one, the plain text otherwise. `None` when the mail has no body at all.
+pub fn render(message: &Message, options: html::Options) -> Option<Body> {
+    // body_html/body_text convert between each other when a part is missing, check the part
+    // itself to know what the sender actually wrote
+    let text = message
+        .text_part(0)
+        .filter(|part| part.is_text())
+        .and_then(|_| message.body_text(0))
+        .map(|body| body.to_string());
+    let html = message
+        .html_part(0)
+        .filter(|part| part.is_text_html())
+        .and_then(|_| message.body_html(0));
+
+    match (html, text) {
+        (Some(html), text) => Some(Body {
+            format: BodyFormat::Html,
+            html: html::document(&html, options),
+            // mail-parser derives a text version of HTML-only mail
+            text: text.or_else(|| message.body_text(0).map(|body| body.to_string())),
+        }),
+        (None, Some(text)) => Some(Body {
+            format: BodyFormat::Text,
+            html: html::document(&text_to_html(&text), options),
+            text: Some(text),
+        }),
+        (None, None) => None,
+    }
+}
+
+/// Escapes plain text for HTML, links its URLs and folds quoted replies into a `<details>`.
+pub fn text_to_html(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut out = String::from("<div style=\"white-space: pre-wrap; font-family: sans-serif\">");
+    let mut i = 0;
+    while i < lines.len() {
+        if !is_quote(lines[i]) {
+            // an "On ..., someone wrote:" line becomes the summary of the quote right after it
+            let attribution = lines[i].trim_end().ends_with("wrote:")
+                && lines.get(i + 1).is_some_and(|line| is_quote(line));
+            if !attribution {
+                out.push_str(&linkify(lines[i]));
+                out.push('\n');
+                i += 1;
+                continue;
+            }
+        }
+
+        let summary = if is_quote(lines[i]) {
+            "Quoted text".to_string()
+        } else {
+            i += 1;
+            escape(lines[i - 1].trim_end())
+        };
+        let start = i;
+        while i < lines.len() && is_quote(lines[i]) {
+            i += 1;
+        }
+        let quoted = lines[start..i]
+            .iter()
+            .map(|line| linkify(unquote(line)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        out.push_str(&format!(
+            "<details><summary>{summary}</summary><blockquote>{quoted}</blockquote></details>"
+        ));
+    }
+    out.push_str("</div>");
+    out
+}
+
+fn is_quote(line: &str) -> bool {
+    line.trim_start().starts_with('>')
+}
+
+/// Removes one level of quoting, nested quotes keep their remaining `>`.
+fn unquote(line: &str) -> &str {
+    let line = line.trim_start().strip_prefix('>').unwrap_or(line);
+    line.strip_prefix(' ').unwrap_or(line)
+}
+
+/// Escapes `line` and wraps every http(s) or www. URL in it in a link.
+fn linkify(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = find_url(rest) {
+        let candidate = &rest[start..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+            .unwrap_or(candidate.len());
+        // punctuation closing the sentence isn't part of the URL
+        let url = candidate[..end].trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '\'']);
+        out.push_str(&escape(&rest[..start]));
+        let href = if url.starts_with("www.") {
+            format!("https://{url}")
+        } else {
+            url.to_string()
+        };
+        out.push_str(&format!(
+            "<a href=\"{}\">{}</a>",
+            escape(&href),
+            escape(url)
+        ));
+        rest = &rest[start + url.len()..];
+    }
+    out.push_str(&escape(rest));
+    out
+}
+
+/// Start of the first URL in `s` that begins a word.
+fn find_url(s: &str) -> Option<usize> {
+    ["https://", "http://", "www."]
+        .iter()
+        .filter_map(|prefix| {
+            s.match_indices(prefix)
+                .map(|(index, _)| index)
+                .find(|&index| {
+                    let word_start = s[..index]
+                        .chars()
+                        .next_back()
+                        .is_none_or(|c| !c.is_alphanumeric() && c != '/' && c != '.');
+                    // a bare scheme or "www." on its own isn't a link
+                    word_start
+                        && s[index + prefix.len()..].starts_with(|c: char| c.is_alphanumeric())
+                })
+        })
+        .min()
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,143 @@
+use crate::api_types::ErrorResponse;
+use crate::oidc::OidcError;
+use crate::proxy::ProxyError;
+use crate::send::SendError;
+use crate::store::StoreError;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use leptos::prelude::ServerFnError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0}")]
+    InvalidParameter(String),
+    #[error("mail {0} is not a valid message")]
+    InvalidMessage(String),
+    #[error("mail {0} has no body")]
+    NoBody(String),
+    #[error("{0}")]
+    Store(String),
+    #[error("{0}")]
+    Proxy(String),
+    #[error("{0}")]
+    MailRejected(String),
+    #[error("{0}")]
+    Send(String),
+    #[error("server state is not available")]
+    MissingState,
+    #[error("not logged in")]
+    Unauthorized,
+    #[error("wrong username or password")]
+    InvalidCredentials,
+    #[error("no access to {0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    SignOn(String),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::InvalidParameter(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidMessage(_) | ApiError::NoBody(_) | ApiError::MailRejected(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            ApiError::Store(_) | ApiError::Proxy(_) | ApiError::Send(_) | ApiError::SignOn(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ApiError::MissingState => StatusCode::INTERNAL_SERVER_ERROR,
+            ApiError::Unauthorized | ApiError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    /// Stable machine readable name of the error, the message is for humans.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::InvalidParameter(_) => "invalid_parameter",
+            ApiError::InvalidMessage(_) => "invalid_message",
+            ApiError::NoBody(_) => "no_body",
+            ApiError::Store(_) => "store_unavailable",
+            ApiError::Proxy(_) => "proxy_failed",
+            ApiError::MailRejected(_) => "mail_rejected",
+            ApiError::Send(_) => "send_failed",
+            ApiError::MissingState => "missing_state",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::SignOn(_) => "sign_on_failed",
+        }
+    }
+
+    pub fn to_response(&self) -> ErrorResponse {
+        ErrorResponse {
+            status: self.status().as_u16(),
+            error: self.code().to_string(),
+            message: self.to_string(),
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::NotFound(key) => ApiError::NotFound(format!("mail {key}")),
+            StoreError::InvalidCursor(_) => ApiError::InvalidParameter(e.to_string()),
+            StoreError::LabelNotFound(label_id) => ApiError::NotFound(format!("label {label_id}")),
+            StoreError::Backend(_) => ApiError::Store(e.to_string()),
+        }
+    }
+}
+
+impl From<ProxyError> for ApiError {
+    fn from(e: ProxyError) -> Self {
+        match e {
+            ProxyError::InvalidUrl(_) => ApiError::InvalidParameter(e.to_string()),
+            _ => ApiError::Proxy(e.to_string()),
+        }
+    }
+}
+
+impl From<SendError> for ApiError {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::InvalidMessage(_) => ApiError::InvalidParameter(e.to_string()),
+            SendError::Rejected(_) => ApiError::MailRejected(e.to_string()),
+            SendError::Backend(_) => ApiError::Send(e.to_string()),
+        }
+    }
+}
+
+impl From<OidcError> for ApiError {
+    fn from(e: OidcError) -> Self {
+        match e {
+            OidcError::Disabled => ApiError::NotFound("single sign-on".to_string()),
+            OidcError::InvalidState => ApiError::InvalidParameter(e.to_string()),
+            OidcError::Denied(what) => ApiError::Forbidden(what),
+            OidcError::Provider(_) => ApiError::SignOn(e.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status().is_server_error() {
+            log::error!("{self}");
+        }
+        (self.status(), Json(self.to_response())).into_response()
+    }
+}
+
+impl From<ApiError> for ServerFnError<ErrorResponse> {
+    fn from(e: ApiError) -> Self {
+        ServerFnError::WrappedServerError(e.to_response())
+    }
+}
@@ -0,0 +1,138 @@
+//! Rewriting of the untrusted HTML found in received mail before it's served from our origin.
+use crate::signing::UrlSigner;
+use ammonia::Builder;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Policy the sanitized documents are served under, both as a response header and inside the
+/// document itself for the `srcdoc` iframe. Nothing is allowed to run or submit, and images only
+/// load through [`PROXY_PATH`] so that `url()`s in the mail's CSS can't reach the sender either.
+pub const CONTENT_SECURITY_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; \
+     img-src 'self' data:; form-action 'none'; base-uri 'none'; frame-ancestors 'self'";
+
+/// `sandbox` directive added to [`CONTENT_SECURITY_POLICY`] when the document is opened directly,
+/// it matches the `sandbox` attribute of the iframe in the UI.
+pub const SANDBOX: &str = "sandbox allow-popups allow-popups-to-escape-sandbox";
+
+/// Route of the image proxy, remote images are fetched from there when they're let through.
+pub const PROXY_PATH: &str = "/api/proxy";
+
+/// Grey box standing in for a blocked image, it keeps the `width`/`height` of the original.
+const PLACEHOLDER: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' \
+     width='24' height='24'%3E%3Crect width='24' height='24' fill='%23e4e4e7'/%3E%3C/svg%3E";
+
+/// What to do with the remote images of a mail. Loading one tells the sender the mail was read,
+/// so they're blocked until the reader asks for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Images {
+    #[default]
+    Blocked,
+    /// Loaded through [`PROXY_PATH`], the sender still learns about the read but not who or
+    /// where from.
+    Proxied,
+}
+
+/// How [`sanitize`] rewrites the URLs of a mail.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub images: Images,
+    /// Content-ID of every part of the mail mapped to where it's served, `cid:` URLs pointing at
+    /// anything else are dropped.
+    pub inline: HashMap<String, String>,
+    /// Signs the proxied and `cid:` URLs, the sandboxed body loads them without the session.
+    pub signer: Option<UrlSigner>,
+}
+
+/// Address of `src` behind the image proxy.
+pub fn proxy_url(src: &str) -> String {
+    format!("{PROXY_PATH}?url={}", urlencoding::encode(src))
+}
+
+/// Whether `url` would resolve against our own origin, the same check ammonia does.
+fn is_relative(url: &str) -> bool {
+    matches!(
+        url::Url::parse(url),
+        Err(url::ParseError::RelativeUrlWithoutBase)
+    )
+}
+
+fn is_remote(src: &str) -> bool {
+    let scheme = src
+        .split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("http" | "https"))
+}
+
+/// The Content-ID a `cid:` URL refers to (RFC 2392).
+fn content_id(url: &str) -> Option<String> {
+    let (scheme, id) = url.split_once(':')?;
+    if !scheme.eq_ignore_ascii_case("cid") {
+        return None;
+    }
+    let id = urlencoding::decode(id).ok()?;
+    Some(id.trim_start_matches('<').trim_end_matches('>').to_string())
+}
+
+fn cleaner(options: Options) -> Builder<'static> {
+    let Options {
+        images,
+        inline,
+        signer,
+    } = options;
+    let signed = move |url: String| match &signer {
+        Some(signer) => signer.sign(&url),
+        None => url,
+    };
+    let mut builder = Builder::default();
+    builder
+        // mail layouts are mostly tables, fonts and inline styles
+        .add_tags(["font", "style"])
+        .rm_clean_content_tags(["style"])
+        .add_tag_attributes("font", ["color", "face", "size"])
+        .add_tag_attributes(
+            "table",
+            ["bgcolor", "border", "cellpadding", "cellspacing", "width"],
+        )
+        .add_tag_attributes("td", ["bgcolor", "valign", "width", "height"])
+        .add_tag_attributes("th", ["bgcolor", "valign", "width", "height"])
+        .add_tag_attributes("tr", ["bgcolor", "valign"])
+        .add_generic_attributes(["style", "align", "dir"])
+        .url_schemes(["http", "https", "mailto", "tel", "cid"].into())
+        .link_rel(Some("noopener noreferrer"))
+        .set_tag_attribute_value("a", "target", "_blank")
+        .attribute_filter(
+            move |element, attribute, value| match (element, attribute) {
+                // a relative URL in the mail would point at our own API. They're dropped here
+                // rather than with `UrlRelative::Deny`, which would also drop the proxy URLs
+                (_, "href" | "src" | "cite") if is_relative(value) => None,
+                ("img", "src") if is_remote(value) => Some(match images {
+                    Images::Blocked => Cow::Borrowed(PLACEHOLDER),
+                    Images::Proxied => Cow::Owned(signed(proxy_url(value))),
+                }),
+                (_, "href" | "src" | "cite") => match content_id(value) {
+                    Some(id) => inline.get(&id).map(|url| Cow::Owned(signed(url.clone()))),
+                    None => Some(Cow::Borrowed(value)),
+                },
+                _ => Some(Cow::Borrowed(value)),
+            },
+        );
+    builder
+}
+
+/// Strips scripts, event handlers, forms and any URL that isn't http(s), mailto or tel, and makes
+/// every link open in a new tab without a reference back to us. Remote images and `cid:` links
+/// are rewritten according to `options`.
+pub fn sanitize(html: &str, options: Options) -> String {
+    cleaner(options).clean(html).to_string()
+}
+
+/// Sanitizes `html` and wraps it in a standalone document carrying [`CONTENT_SECURITY_POLICY`].
+pub fn document(html: &str, options: Options) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"Content-Security-Policy\" content=\"{CONTENT_SECURITY_POLICY}\">\
+         <meta name=\"referrer\" content=\"no-referrer\">\
+         <base target=\"_blank\"></head><body>{}</body></html>",
+        sanitize(html, options)
+    )
+}
@@ -0,0 +1,246 @@
+//! API keys for scripts and CI jobs, sent as `Authorization: Bearer <token>` instead of a session
+//! cookie. A key only does what its [`Scope`]s allow and what its account still may, so taking a
+//! mailbox away from an account takes it away from its keys too.
+use crate::api_types::{
+    ApiKeyInfo, CreateApiKeyRequest, CreateApiKeyResponse, ListApiKeysResponse, Scope,
+};
+use crate::auth::{token_hash, Access, Account, AuthMethod};
+use crate::error::ApiError;
+use crate::state::AppState;
+use axum::{
+    extract::{Path, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+    Json,
+};
+
+/// Start of every token, so that leaked ones are easy to search for.
+pub const TOKEN_PREFIX: &str = "smk_";
+pub const MAX_KEYS: usize = 20;
+pub const MAX_KEY_NAME: usize = 64;
+pub const MAX_EXPIRY_DAYS: i64 = 365;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub key_id: String,
+    /// The account the key acts for.
+    pub username: String,
+    pub name: String,
+    /// Hash of the token, see [`token_hash`].
+    pub key_hash: String,
+    pub scopes: Vec<Scope>,
+    /// Unix timestamps in seconds.
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl ApiKey {
+    pub fn info(&self) -> ApiKeyInfo {
+        ApiKeyInfo {
+            key_id: self.key_id.clone(),
+            name: self.name.clone(),
+            scopes: self.scopes.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// What the key may do on behalf of `account`, never more than the account itself.
+    pub fn access(&self, account: &Account) -> Access {
+        let granted = |scope: fn(String) -> Scope| {
+            account
+                .mailboxes
+                .iter()
+                .filter(|address| self.scopes.contains(&scope(address.to_lowercase())))
+                .cloned()
+                .collect()
+        };
+        Access {
+            username: account.username.clone(),
+            mailboxes: granted(Scope::Read),
+            writable: granted(Scope::Write),
+            senders: granted(Scope::Send),
+            admin: account.admin && self.scopes.contains(&Scope::Admin),
+            method: AuthMethod::ApiKey,
+        }
+    }
+}
+
+/// A new token for key `key_id`: the prefix, the id and a secret.
+fn new_token(key_id: &str) -> String {
+    format!(
+        "{TOKEN_PREFIX}{key_id}_{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// The key id in `token`.
+fn key_id_of(token: &str) -> Option<&str> {
+    let (key_id, secret) = token.strip_prefix(TOKEN_PREFIX)?.split_once('_')?;
+    (!key_id.is_empty() && !secret.is_empty()).then_some(key_id)
+}
+
+/// The token in the `Authorization: Bearer` header of `headers`, when it looks like an API key.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| token.starts_with(TOKEN_PREFIX))
+}
+
+/// Access of the API key `token`.
+pub async fn access_of_token(state: &AppState, token: &str) -> Result<Access, ApiError> {
+    let key_id = key_id_of(token).ok_or(ApiError::Unauthorized)?;
+    let store = &state.metadata_store;
+    let now = chrono::Utc::now().timestamp();
+    let key = store
+        .get_api_key(key_id)
+        .await?
+        .filter(|key| key.key_hash == token_hash(token))
+        .filter(|key| key.expires_at.is_none_or(|expires_at| expires_at > now))
+        .ok_or(ApiError::Unauthorized)?;
+    let account = store
+        .get_account(&key.username)
+        .await?
+        .ok_or(ApiError::Unauthorized)?;
+    Ok(key.access(&account))
+}
+
+/// Checks the API key a request carries, if any, and hands its [`Access`] on to the handler. A
+/// key that's wrong, expired or revoked fails the request rather than falling back to cookies.
+pub async fn api_key_layer(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if let Some(token) = bearer_token(request.headers()) {
+        let access = access_of_token(&state, token).await?;
+        request.extensions_mut().insert(access);
+    }
+    Ok(next.run(request).await)
+}
+
+pub async fn list_api_keys_api(
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<Json<ListApiKeysResponse>, ApiError> {
+    let response = list_api_keys(state, &access).await?;
+    Ok(Json(response))
+}
+
+/// Keys of the account that's logged in, oldest first.
+pub async fn list_api_keys(
+    state: AppState,
+    access: &Access,
+) -> Result<ListApiKeysResponse, ApiError> {
+    let mut keys = state.metadata_store.list_api_keys(&access.username).await?;
+    keys.sort_by_key(|key| key.created_at);
+    Ok(ListApiKeysResponse {
+        data: keys.iter().map(ApiKey::info).collect(),
+    })
+}
+
+pub async fn create_api_key_api(
+    State(state): State<AppState>,
+    access: Access,
+    Json(request): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreateApiKeyResponse>, ApiError> {
+    let response = create_api_key(state, &access, request).await?;
+    Ok(Json(response))
+}
+
+/// Creates a key for the account that's logged in. It can't be given a scope the caller doesn't
+/// have, and keys can't make keys at all.
+pub async fn create_api_key(
+    state: AppState,
+    access: &Access,
+    request: CreateApiKeyRequest,
+) -> Result<CreateApiKeyResponse, ApiError> {
+    access.check_session()?;
+    let name = request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_KEY_NAME {
+        return Err(ApiError::InvalidParameter(format!(
+            "key name must be 1 to {MAX_KEY_NAME} characters"
+        )));
+    }
+    if request.scopes.is_empty() {
+        return Err(ApiError::InvalidParameter(
+            "a key needs at least one scope".to_string(),
+        ));
+    }
+    for scope in &request.scopes {
+        let held = match scope {
+            Scope::Read(address) => access.can_read(address),
+            Scope::Write(address) => access.can_write(address),
+            Scope::Send(address) => access.can_send(address),
+            Scope::Admin => access.admin,
+        };
+        if !held {
+            return Err(ApiError::Forbidden(format!("scope {scope}")));
+        }
+    }
+    let now = chrono::Utc::now().timestamp();
+    let expires_at = match request.expires_in_days {
+        Some(days) if (1..=MAX_EXPIRY_DAYS).contains(&days) => Some(now + days * 24 * 60 * 60),
+        Some(days) => {
+            return Err(ApiError::InvalidParameter(format!(
+                "keys expire in 1 to {MAX_EXPIRY_DAYS} days, got {days}"
+            )))
+        }
+        None => None,
+    };
+    let store = &state.metadata_store;
+    if store.list_api_keys(&access.username).await?.len() >= MAX_KEYS {
+        return Err(ApiError::InvalidParameter(format!(
+            "an account can have at most {MAX_KEYS} keys, revoke one first"
+        )));
+    }
+
+    let key_id = uuid::Uuid::new_v4().simple().to_string();
+    let token = new_token(&key_id);
+    let key = ApiKey {
+        key_id,
+        username: access.username.clone(),
+        name: name.to_string(),
+        key_hash: token_hash(&token),
+        scopes: request.scopes,
+        created_at: now,
+        expires_at,
+    };
+    store.put_api_key(&key).await?;
+    Ok(CreateApiKeyResponse {
+        key: key.info(),
+        token,
+    })
+}
+
+pub async fn revoke_api_key_api(
+    Path(key_id): Path<String>,
+    State(state): State<AppState>,
+    access: Access,
+) -> Result<StatusCode, ApiError> {
+    revoke_api_key(state, &access, key_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Revokes key `key_id`, which takes effect on the next request made with it. Admins can revoke
+/// the keys of every account, from a session like every key management.
+pub async fn revoke_api_key(
+    state: AppState,
+    access: &Access,
+    key_id: String,
+) -> Result<(), ApiError> {
+    access.check_session()?;
+    let store = &state.metadata_store;
+    let key = store
+        .get_api_key(&key_id)
+        .await?
+        .filter(|key| key.username == access.username || access.admin)
+        .ok_or_else(|| ApiError::NotFound(format!("key {key_id}")))?;
+    Ok(store.delete_api_key(&key.key_id).await?)
+}
@@ -7,8 +7,9 @@ cfg_if! {
             body::Body as AxumBody,
             extract::{Path, State},
             http::Request,
+            middleware,
             response::{IntoResponse, Response},
-            routing::get,
+            routing::{delete, get, patch, post, put},
             Router,
         };
         use dotenvy::dotenv;
@@ -17,9 +18,39 @@ cfg_if! {
         };
         use leptos_axum::{generate_route_list_with_exclusions, handle_server_fns_with_context, LeptosRoutes};
         use std::env;
+        use std::path::PathBuf;
+        use std::sync::Arc;
+        use supermailer::oidc::{oidc_callback_api, oidc_login_api, Oidc, OidcConfig};
+        use supermailer::proxy::ImageProxy;
+        use supermailer::send::{LogSender, MailSender, SesSender};
+        use supermailer::signing::UrlSigner;
         use supermailer::state::{AppState, MailConfig};
+        use supermailer::store::{
+            aws::{DynamoMetadataStore, S3MailStore, S3SearchStore},
+            local::{FsMailStore, LocalSearchStore, SqliteMetadataStore},
+            MailStore, MetadataStore, SearchStore,
+        };
         use supermailer::{ui::*};
-        use supermailer::api::{list_emails_api, get_email_html_api};
+        use supermailer::api::{
+            get_attachment_api, get_draft_api, get_email_html_api, list_attachments_api, list_emails_api, proxy_image_api,
+            list_thread_api, list_threads_api, search_api, send_email_api, update_flags_api, count_unread_api,
+            list_labels_api, create_label_api, update_label_api, delete_label_api, add_label_api, remove_label_api,
+            get_rules_api, put_rules_api, dry_run_rules_api, get_sieve_api, put_sieve_api,
+            validate_sieve_api,
+        };
+        use supermailer::auth::{
+            bootstrap_admin, delete_account_api, list_accounts_api, login_api, logout_api,
+            put_account_api, session_api,
+        };
+        use supermailer::keys::{api_key_layer, create_api_key_api, list_api_keys_api, revoke_api_key_api};
+
+        /// Where mail is read from, written to and sent through, picked by `MAIL_STORE`.
+        type Backends = (
+            Arc<dyn MailStore>,
+            Arc<dyn MetadataStore>,
+            Arc<dyn MailSender>,
+            Arc<dyn SearchStore>,
+        );
 
         async fn server_fn_handler(
             State(app_state): State<AppState>,
@@ -65,10 +96,89 @@ cfg_if! {
             let user_db = env::var("USER_DB").expect("USER_DB not set");
             // let aws_profile_name = env::var("AWS_PROFILE").expect("AWS_PROFILE not set");
 
-            let aws_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
-                // .profile_name(aws_profile_name)
-                .load()
-                .await;
+            let configuration_set = env::var("SES_CONFIGURATION_SET").ok();
+            let search_index = env::var("SEARCH_INDEX")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| env::temp_dir().join("supermailer-search"));
+
+            let mail_config = MailConfig {
+                mail_bucket,
+                mail_db,
+                user_db,
+                configuration_set,
+                search_index,
+            };
+
+            // MAIL_STORE=local serves a directory of .eml files (MAIL_BUCKET) indexed into a
+            // SQLite database (MAIL_DB) instead of talking to S3 and DynamoDB
+            let (mail_store, metadata_store, mail_sender, search_store): Backends =
+                match env::var("MAIL_STORE").as_deref() {
+                    Ok("local") => {
+                        let mail_store = FsMailStore::new(&mail_config.mail_bucket);
+                        let metadata_store = SqliteMetadataStore::open(&mail_config.mail_db)
+                            .expect("couldn't open local mail database");
+                        let indexed = metadata_store
+                            .index(&mail_store)
+                            .await
+                            .expect("couldn't index local mail directory");
+                        log::info!("indexed {} new mails from {}", indexed, &mail_config.mail_bucket);
+                        let search_store = LocalSearchStore::open(&mail_config.search_index)
+                            .expect("couldn't open local search index");
+                        let searchable = search_store
+                            .index(&mail_store)
+                            .await
+                            .expect("couldn't index local mail directory for search");
+                        log::info!("added {} new mails to the search index", searchable);
+                        (
+                            Arc::new(mail_store),
+                            Arc::new(metadata_store),
+                            Arc::new(LogSender),
+                            Arc::new(search_store),
+                        )
+                    }
+                    _ => {
+                        let aws_config = aws_config::defaults(BehaviorVersion::v2025_01_17())
+                            // .profile_name(aws_profile_name)
+                            .load()
+                            .await;
+                        (
+                            Arc::new(S3MailStore::new(&aws_config, &mail_config)),
+                            Arc::new(DynamoMetadataStore::new(&aws_config, &mail_config)),
+                            Arc::new(SesSender::new(&aws_config, mail_config.configuration_set.clone())),
+                            Arc::new(S3SearchStore::new(&aws_config, &mail_config)),
+                        )
+                    }
+                };
+
+            // OIDC_ISSUER turns on single sign-on, only addresses in OIDC_ALLOWED_DOMAINS get in
+            let oidc = env::var("OIDC_ISSUER").ok().map(|issuer| {
+                let allowed_domains: Vec<String> = env::var("OIDC_ALLOWED_DOMAINS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|domain| domain.trim().to_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                    .collect();
+                if allowed_domains.is_empty() {
+                    log::warn!("OIDC_ALLOWED_DOMAINS not set, nobody can sign on");
+                }
+                Oidc::new(OidcConfig {
+                    issuer,
+                    client_id: env::var("OIDC_CLIENT_ID").expect("OIDC_CLIENT_ID not set"),
+                    client_secret: env::var("OIDC_CLIENT_SECRET").ok(),
+                    redirect_url: env::var("OIDC_REDIRECT_URL").expect("OIDC_REDIRECT_URL not set"),
+                    allowed_domains,
+                    mailbox_claim: env::var("OIDC_MAILBOX_CLAIM").ok(),
+                })
+            });
+
+            // URL_SIGNING_KEY signs the image URLs of mail bodies, every server needs the same one
+            let url_signer = match env::var("URL_SIGNING_KEY") {
+                Ok(key) => UrlSigner::new(key.as_bytes()),
+                Err(_) => {
+                    log::warn!("URL_SIGNING_KEY not set, images of mail bodies only load from this server");
+                    UrlSigner::random()
+                }
+            };
 
             // Setting get_configuration(None) means we'll be using cargo-leptos's env values
             // For deployment these variables are:
@@ -82,22 +192,66 @@ cfg_if! {
             let addr = leptos_options.site_addr;
             let routes = generate_route_list_with_exclusions(Ui, Some(vec!["/api/".to_string(), "/api".to_string()]));
 
-            let mail_config = MailConfig {
-                mail_bucket,
-                mail_db,
-                user_db
-            };
-
             let state = AppState {
-                aws_config,
+                mail_store,
+                metadata_store,
+                mail_sender,
+                search_store,
                 mail_config,
+                image_proxy: ImageProxy::new(),
+                url_signer,
+                oidc,
                 leptos_options,
                 routes: routes.clone(),
             };
 
+            // ADMIN_USERNAME and ADMIN_PASSWORD create the first account, it reads ADMIN_MAILBOXES
+            if let (Ok(username), Ok(password)) = (env::var("ADMIN_USERNAME"), env::var("ADMIN_PASSWORD")) {
+                let mailboxes: Vec<String> = env::var("ADMIN_MAILBOXES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|address| !address.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                let created = bootstrap_admin(&state, &username, &password, &mailboxes)
+                    .await
+                    .expect("couldn't create the admin account");
+                if created {
+                    log::info!("created admin account {}", username);
+                }
+            }
+
             let api_route = Router::new()
+                .route("/login", post(login_api))
+                .route("/logout", post(logout_api))
+                .route("/session", get(session_api))
+                .route("/oidc/login", get(oidc_login_api))
+                .route("/oidc/callback", get(oidc_callback_api))
+                .route("/accounts", get(list_accounts_api))
+                .route("/accounts/:username", put(put_account_api).delete(delete_account_api))
+                .route("/keys", get(list_api_keys_api).post(create_api_key_api))
+                .route("/keys/:key_id", delete(revoke_api_key_api))
+                .route("/proxy", get(proxy_image_api))
+                .route("/send", post(send_email_api))
+                .route("/search", get(search_api))
                 .route("/:email", get(list_emails_api))
+                .route("/:email/unread", get(count_unread_api))
+                .route("/:email/mail/:id", patch(update_flags_api))
+                .route("/:email/mail/:id/labels/:label_id", put(add_label_api).delete(remove_label_api))
+                .route("/:email/labels", get(list_labels_api).post(create_label_api))
+                .route("/:email/labels/:label_id", patch(update_label_api).delete(delete_label_api))
+                .route("/:email/rules", get(get_rules_api).put(put_rules_api))
+                .route("/:email/rules/dry_run", post(dry_run_rules_api))
+                .route("/:email/sieve", get(get_sieve_api).put(put_sieve_api))
+                .route("/:email/sieve/validate", post(validate_sieve_api))
+                .route("/:email/threads", get(list_threads_api))
+                .route("/:email/threads/:thread_id", get(list_thread_api))
                 .route("/email/:id", get(get_email_html_api))
+                .route("/email/:id/draft", get(get_draft_api))
+                .route("/email/:id/attachments", get(list_attachments_api))
+                .route("/email/:id/attachments/:index", get(get_attachment_api))
+                .layer(middleware::from_fn_with_state(state.clone(), api_key_layer))
                 .with_state(state.clone());
 
             // build our application with a route
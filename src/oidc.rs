@@ -0,0 +1,385 @@
+//! Single sign-on through an OpenID Connect provider, with the authorization code flow and PKCE
+//! (RFC 7636). The claims of whoever signs in decide which mailboxes their [`Account`] reads, the
+//! session that follows is the same as after a password login.
+use crate::api_types::AccountInfo;
+use crate::auth::{cookie, session_cookie, start_session, Account};
+use crate::error::ApiError;
+use crate::send::domain_of;
+use crate::state::AppState;
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{AppendHeaders, IntoResponse},
+};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::OnceCell;
+
+/// Cookie holding the state and code verifier of a sign-on in progress.
+pub const OIDC_COOKIE: &str = "oidc";
+/// How long a sign-on may take at the provider.
+pub const SIGN_ON_MINUTES: i64 = 10;
+
+#[derive(Debug, Error)]
+pub enum OidcError {
+    #[error("single sign-on is not configured")]
+    Disabled,
+    #[error("the sign-on expired or was started in another browser, try again")]
+    InvalidState,
+    #[error("{0}")]
+    Denied(String),
+    #[error("identity provider failed: {0}")]
+    Provider(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// Issuer URL, the discovery document is read from below it.
+    pub issuer: String,
+    pub client_id: String,
+    /// Sent to the token endpoint when the client is confidential.
+    pub client_secret: Option<String>,
+    /// Where the provider sends the browser back to, the `/api/oidc/callback` route.
+    pub redirect_url: String,
+    /// Domains whose addresses may sign on, lowercase. Nobody can when there are none.
+    pub allowed_domains: Vec<String>,
+    /// Claim listing addresses the account reads next to its own, a string or an array of them.
+    pub mailbox_claim: Option<String>,
+}
+
+/// The endpoints read from the provider's discovery document.
+#[derive(Debug, Clone, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// Where to send the browser to sign on, and what it has to bring back.
+#[derive(Debug, Clone)]
+pub struct Authorization {
+    pub url: String,
+    pub state: String,
+    pub verifier: String,
+}
+
+/// Who signed on, as told by the userinfo endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub email: String,
+    pub email_verified: bool,
+    /// Addresses in the mailbox claim.
+    pub mailboxes: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Oidc {
+    config: OidcConfig,
+    client: reqwest::Client,
+    /// Fetched on the first sign-on rather than at startup, so a provider that's down doesn't
+    /// keep the app from starting.
+    discovery: Arc<OnceCell<Discovery>>,
+}
+
+impl Oidc {
+    pub fn new(config: OidcConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .user_agent("supermailer-oidc")
+            .build()
+            .expect("couldn't build the OIDC client");
+        Oidc {
+            config,
+            client,
+            discovery: Arc::new(OnceCell::new()),
+        }
+    }
+
+    async fn discovery(&self) -> Result<&Discovery, OidcError> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let issuer = self.config.issuer.trim_end_matches('/');
+                let url = format!("{issuer}/.well-known/openid-configuration");
+                let discovery: Discovery = self.get_json(self.client.get(url)).await?;
+                if discovery.issuer.trim_end_matches('/') != issuer {
+                    return Err(OidcError::Provider(format!(
+                        "discovery document is for issuer {}",
+                        discovery.issuer
+                    )));
+                }
+                Ok(discovery)
+            })
+            .await
+    }
+
+    /// Sends `request` and reads the JSON it answers with.
+    async fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, OidcError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| OidcError::Provider(e.to_string()))?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .map_err(|e| OidcError::Provider(e.to_string()))?;
+        if !status.is_success() {
+            return Err(OidcError::Provider(format!(
+                "{status}: {}",
+                String::from_utf8_lossy(&body)
+            )));
+        }
+        serde_json::from_slice(&body).map_err(|e| OidcError::Provider(e.to_string()))
+    }
+
+    /// Starts a sign-on, with a new state and code verifier.
+    pub async fn authorize(&self) -> Result<Authorization, OidcError> {
+        let discovery = self.discovery().await?;
+        let state = random_token();
+        let verifier = random_token();
+        let mut url = Url::parse(&discovery.authorization_endpoint)
+            .map_err(|e| OidcError::Provider(format!("invalid authorization endpoint, {e}")))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", "openid email profile")
+            .append_pair("state", &state)
+            .append_pair("code_challenge", &code_challenge(&verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok(Authorization {
+            url: url.to_string(),
+            state,
+            verifier,
+        })
+    }
+
+    /// Trades the `code` the provider sent back for the identity of whoever signed on.
+    pub async fn identity(&self, code: &str, verifier: &str) -> Result<Identity, OidcError> {
+        let discovery = self.discovery().await?;
+        let mut form: Vec<(&str, &str)> = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", verifier),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            form.push(("client_secret", secret.as_str()));
+        }
+        let token: TokenResponse = self
+            .get_json(self.client.post(&discovery.token_endpoint).form(&form))
+            .await?;
+        // the claims come straight from the provider over TLS, so the ID token isn't needed
+        let claims: Value = self
+            .get_json(
+                self.client
+                    .get(&discovery.userinfo_endpoint)
+                    .bearer_auth(&token.access_token),
+            )
+            .await?;
+        let email = claims["email"]
+            .as_str()
+            .ok_or_else(|| OidcError::Denied("sign-on without an email claim".to_string()))?;
+        let email_verified = match &claims["email_verified"] {
+            Value::Bool(verified) => *verified,
+            // some providers send it as a string
+            Value::String(verified) => verified == "true",
+            _ => false,
+        };
+        let mailboxes = match self
+            .config
+            .mailbox_claim
+            .as_ref()
+            .map(|claim| &claims[claim.as_str()])
+        {
+            Some(Value::String(address)) => vec![address.clone()],
+            Some(Value::Array(addresses)) => addresses
+                .iter()
+                .filter_map(|address| address.as_str().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(Identity {
+            email: email.trim().to_lowercase(),
+            email_verified,
+            mailboxes,
+        })
+    }
+
+    /// Mailboxes `identity` reads: its own address and those in the mailbox claim, as long as
+    /// they're in an allowed domain. Fails when its own address isn't.
+    pub fn mailboxes(&self, identity: &Identity) -> Result<Vec<String>, OidcError> {
+        if !identity.email_verified {
+            return Err(OidcError::Denied(format!(
+                "sign-on with unverified address {}",
+                identity.email
+            )));
+        }
+        if !self.allowed(&identity.email) {
+            return Err(OidcError::Denied(format!(
+                "sign-on with addresses of {}",
+                domain_of(&identity.email).unwrap_or(&identity.email)
+            )));
+        }
+        let mut mailboxes = vec![identity.email.clone()];
+        for address in &identity.mailboxes {
+            let address = address.trim().to_lowercase();
+            if self.allowed(&address) && !mailboxes.contains(&address) {
+                mailboxes.push(address);
+            }
+        }
+        Ok(mailboxes)
+    }
+
+    fn allowed(&self, address: &str) -> bool {
+        domain_of(address).is_some_and(|domain| {
+            self.config
+                .allowed_domains
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+        })
+    }
+}
+
+/// 64 random characters, enough for a state and in the range a code verifier has to be.
+fn random_token() -> String {
+    format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    )
+}
+
+/// The S256 code challenge of `verifier`.
+pub fn code_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// `Set-Cookie` value keeping a sign-on in progress until the provider sends the browser back.
+/// `SameSite=Lax` still sends it along on that top-level redirect.
+pub fn sign_on_cookie(authorization: &Authorization) -> String {
+    format!(
+        "{OIDC_COOKIE}={}.{}; Path=/api/oidc; Max-Age={}; HttpOnly; SameSite=Lax",
+        authorization.state,
+        authorization.verifier,
+        SIGN_ON_MINUTES * 60
+    )
+}
+
+fn expired_sign_on_cookie() -> String {
+    format!("{OIDC_COOKIE}=; Path=/api/oidc; Max-Age=0; HttpOnly; SameSite=Lax")
+}
+
+/// Sends the browser to the provider to sign on.
+pub async fn oidc_login_api(State(state): State<AppState>) -> Result<impl IntoResponse, ApiError> {
+    let oidc = state.oidc.as_ref().ok_or(OidcError::Disabled)?;
+    let authorization = oidc.authorize().await?;
+    Ok((
+        StatusCode::FOUND,
+        AppendHeaders([
+            (header::LOCATION, authorization.url.clone()),
+            (header::SET_COOKIE, sign_on_cookie(&authorization)),
+        ]),
+    ))
+}
+
+/// What the provider sends the browser back with, `error` instead of `code` when it refused.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct CallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Where the provider sends the browser back to. Starts a session and opens the first mailbox.
+pub async fn oidc_callback_api(
+    Query(query): Query<CallbackQuery>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, ApiError> {
+    let (account, token) = sign_on(&state, query, &headers).await?;
+    let target = match account.mailboxes.first() {
+        Some(mailbox) => format!("/ui?e={}", urlencoding::encode(mailbox)),
+        None => "/ui".to_string(),
+    };
+    Ok((
+        StatusCode::FOUND,
+        AppendHeaders([
+            (header::LOCATION, target),
+            (header::SET_COOKIE, session_cookie(&token)),
+            (header::SET_COOKIE, expired_sign_on_cookie()),
+        ]),
+    ))
+}
+
+/// Finishes the sign-on the cookies of `headers` belong to, returning the account it's for and
+/// the token of its new session. The account is created on the first sign-on and reads whatever
+/// mailboxes the claims list at the latest one. Accounts with a password are never signed on to.
+pub async fn sign_on(
+    state: &AppState,
+    query: CallbackQuery,
+    headers: &HeaderMap,
+) -> Result<(AccountInfo, String), ApiError> {
+    let oidc = state.oidc.as_ref().ok_or(OidcError::Disabled)?;
+    if let Some(error) = query.error {
+        let description = query.error_description.unwrap_or_default();
+        return Err(
+            OidcError::Provider(format!("{error} {description}").trim().to_string()).into(),
+        );
+    }
+    let (expected, verifier) = cookie(headers, OIDC_COOKIE)
+        .and_then(|value| {
+            value
+                .split_once('.')
+                .map(|(state, verifier)| (state.to_string(), verifier.to_string()))
+        })
+        .ok_or(OidcError::InvalidState)?;
+    if query.state.as_deref() != Some(expected.as_str()) {
+        return Err(OidcError::InvalidState.into());
+    }
+    let code = query.code.ok_or(OidcError::InvalidState)?;
+
+    let identity = oidc.identity(&code, &verifier).await?;
+    let mailboxes = oidc.mailboxes(&identity)?;
+    let store = &state.metadata_store;
+    let account = match store.get_account(&identity.email).await? {
+        // an account with a password belongs to whoever knows it, not to the provider
+        Some(account) if !account.password_hash.is_empty() => {
+            return Err(
+                OidcError::Denied(format!("{} signs in with a password", identity.email)).into(),
+            );
+        }
+        Some(account) => Account {
+            mailboxes,
+            ..account
+        },
+        // without a password hash the account can only sign on
+        None => Account {
+            username: identity.email.clone(),
+            password_hash: String::new(),
+            mailboxes,
+            admin: false,
+        },
+    };
+    store.put_account(&account).await?;
+    let token = start_session(state, &account.username).await?;
+    Ok((account.info(), token))
+}
@@ -0,0 +1,188 @@
+//! Fetching of the remote images in mail on behalf of the reader, so the sender never sees their
+//! address, cookies or the page they were reading it on.
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{redirect, Url};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use url::Host;
+
+/// Largest image the proxy passes on.
+pub const MAX_SIZE: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("{0}")]
+    InvalidUrl(String),
+    #[error("fetching image failed: {0}")]
+    Upstream(String),
+    #[error("{0} is not an image")]
+    NotAnImage(String),
+    #[error("{0} is larger than {MAX_SIZE} bytes")]
+    TooLarge(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageProxy {
+    client: reqwest::Client,
+}
+
+impl ImageProxy {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            // every hop gets the same checks as the URL we were given
+            .redirect(redirect::Policy::custom(|attempt| {
+                if attempt.previous().len() >= 5 {
+                    attempt.error(ProxyError::Upstream("too many redirects".to_string()))
+                } else if let Err(e) = check_url(attempt.url()) {
+                    attempt.error(e)
+                } else {
+                    attempt.follow()
+                }
+            }))
+            // names are only connected to when they resolve to public addresses, redirects too
+            .dns_resolver(Arc::new(PublicResolver))
+            .user_agent("supermailer-image-proxy")
+            .build()
+            .expect("couldn't build the image proxy client");
+        ImageProxy { client }
+    }
+
+    /// Downloads the image at `url`. No cookies, referrer or client address are passed along and
+    /// anything that doesn't come back as `image/*` is refused.
+    pub async fn fetch(&self, url: &str) -> Result<Image, ProxyError> {
+        let parsed = Url::parse(url)
+            .map_err(|e| ProxyError::InvalidUrl(format!("invalid url {url:?}: {e}")))?;
+        check_url(&parsed)?;
+
+        let mut response = self
+            .client
+            .get(parsed)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| ProxyError::Upstream(e.to_string()))?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with("image/"))
+            .ok_or_else(|| ProxyError::NotAnImage(url.to_string()))?
+            .to_string();
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_SIZE as u64)
+        {
+            return Err(ProxyError::TooLarge(url.to_string()));
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| ProxyError::Upstream(e.to_string()))?
+        {
+            data.extend_from_slice(&chunk);
+            if data.len() > MAX_SIZE {
+                return Err(ProxyError::TooLarge(url.to_string()));
+            }
+        }
+        Ok(Image { content_type, data })
+    }
+}
+
+impl Default for ImageProxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Resolves names through the system resolver like reqwest would, refusing those that point at
+/// an internal address. Checking the address connected to rather than the URL also catches names
+/// that resolve differently the second time they're looked up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str().to_string();
+            // the port is replaced by the one in the URL
+            let addrs: Vec<SocketAddr> =
+                tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if let Some(addr) = addrs.iter().find(|addr| is_internal(addr.ip())) {
+                return Err(Box::new(ProxyError::InvalidUrl(format!(
+                    "{host} resolves to {}, which is not a public address",
+                    addr.ip()
+                )))
+                    as Box<dyn std::error::Error + Send + Sync>);
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Only plain http(s) to public hosts, the proxy runs next to our own services and the cloud
+/// metadata endpoint. Hosts given as names are checked again once resolved, see
+/// [`PublicResolver`].
+fn check_url(url: &Url) -> Result<(), ProxyError> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ProxyError::InvalidUrl(format!(
+            "unsupported scheme {:?}",
+            url.scheme()
+        )));
+    }
+    let internal = match url.host() {
+        None => true,
+        Some(Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost") || domain.ends_with(".internal")
+        }
+        Some(Host::Ipv4(ip)) => is_internal(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) => is_internal(IpAddr::V6(ip)),
+    };
+    if internal {
+        return Err(ProxyError::InvalidUrl(format!(
+            "{url} is not a public address"
+        )));
+    }
+    Ok(())
+}
+
+/// Loopback, private, link-local (the cloud metadata endpoint among them) and unroutable
+/// addresses.
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // "this network", 0.0.0.0/8
+                || ip.octets()[0] == 0
+                // carrier-grade NAT, 100.64.0.0/10
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_internal(IpAddr::V4(ip)),
+            None => {
+                ip.is_loopback()
+                    || ip.is_unspecified()
+                    // unique local fc00::/7 and link local fe80::/10
+                    || ip.segments()[0] & 0xfe00 == 0xfc00
+                    || ip.segments()[0] & 0xffc0 == 0xfe80
+            }
+        },
+    }
+}
@@ -0,0 +1,150 @@
+//! Drafts answering a received mail: reply, reply-all and forward.
+use crate::api_types::{ReplyMode, SendRequest};
+use mail_parser::{HeaderValue, Message};
+use supermailer_core::mail::mailbox_address;
+use supermailer_core::parse::{addresses, format_addresses};
+
+/// Prefills a [`SendRequest`] answering `message` (stored under `key_id`) from the mailbox
+/// partition `mailbox` it was found in.
+pub fn draft(message: &Message, key_id: &str, mailbox: &str, mode: ReplyMode) -> SendRequest {
+    let own = mailbox_address(mailbox).to_lowercase();
+    let subject = message.subject().unwrap_or_default();
+    let text = message
+        .body_text(0)
+        .map(|body| body.to_string())
+        .unwrap_or_default();
+
+    if mode == ReplyMode::Forward {
+        return SendRequest {
+            from: own,
+            subject: prefixed("Fwd:", subject),
+            text: forwarded(message, &text),
+            attachments_from: message
+                .attachments()
+                .next()
+                .is_some()
+                .then(|| key_id.to_string()),
+            ..Default::default()
+        };
+    }
+
+    let from = bare_addresses(message.from());
+    let to = bare_addresses(message.to());
+    // answering our own sent mail goes back to its recipients, not to ourselves
+    let sent_by_us = from
+        .iter()
+        .any(|address| address.eq_ignore_ascii_case(&own));
+    let reply_to = match message.reply_to() {
+        HeaderValue::Empty => from,
+        reply_to => bare_addresses(reply_to),
+    };
+    let mut recipients = if sent_by_us { to.clone() } else { reply_to };
+    let mut cc = Vec::new();
+    if mode == ReplyMode::ReplyAll {
+        if !sent_by_us {
+            recipients.extend(to);
+        }
+        cc = bare_addresses(message.cc());
+    }
+    let not_own = |address: &String| !address.eq_ignore_ascii_case(&own);
+    let recipients = dedup(recipients.into_iter().filter(not_own), &[]);
+    let cc = dedup(cc.into_iter().filter(not_own), &recipients);
+
+    // References carries the whole chain so far, ending with the mail answered. A mail without
+    // one only names its parent (RFC 5322 3.6.4)
+    let message_id = message.message_id().map(str::to_string);
+    let mut references: Vec<String> = message
+        .references()
+        .as_text_list()
+        .or_else(|| message.in_reply_to().as_text_list())
+        .unwrap_or_default()
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+    references.extend(message_id.clone());
+
+    SendRequest {
+        from: own,
+        to: recipients,
+        cc,
+        subject: prefixed("Re:", subject),
+        text: quoted(message, &text),
+        in_reply_to: message_id,
+        references,
+        ..Default::default()
+    }
+}
+
+fn bare_addresses(value: &HeaderValue) -> Vec<String> {
+    addresses(value)
+        .filter_map(|addr| addr.address.as_deref())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Drops repeated addresses and those already in `exclude`, case-insensitively.
+fn dedup(list: impl Iterator<Item = String>, exclude: &[String]) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for address in list {
+        let seen = out
+            .iter()
+            .chain(exclude)
+            .any(|other| other.eq_ignore_ascii_case(&address));
+        if !seen {
+            out.push(address);
+        }
+    }
+    out
+}
+
+/// `subject` with `prefix` in front, unless it's already there.
+fn prefixed(prefix: &str, subject: &str) -> String {
+    let already = subject
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix));
+    if already {
+        subject.to_string()
+    } else {
+        format!("{prefix} {subject}")
+    }
+}
+
+/// The original body below an attribution line, every line quoted with `>`.
+fn quoted(message: &Message, text: &str) -> String {
+    let sender = format_addresses(message.from()).join(", ");
+    let attribution = match message.date() {
+        Some(date) => format!("On {}, {sender} wrote:", date.to_rfc822()),
+        None => format!("{sender} wrote:"),
+    };
+    let quote = text
+        .lines()
+        .map(|line| format!("> {line}").trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!("\n\n{attribution}\n{quote}\n")
+}
+
+/// The original headers and body as an inline forward.
+fn forwarded(message: &Message, text: &str) -> String {
+    let mut header = String::from("---------- Forwarded message ----------\n");
+    header.push_str(&format!(
+        "From: {}\n",
+        format_addresses(message.from()).join(", ")
+    ));
+    if let Some(date) = message.date() {
+        header.push_str(&format!("Date: {}\n", date.to_rfc822()));
+    }
+    header.push_str(&format!(
+        "Subject: {}\n",
+        message.subject().unwrap_or_default()
+    ));
+    header.push_str(&format!(
+        "To: {}\n",
+        format_addresses(message.to()).join(", ")
+    ));
+    let cc = format_addresses(message.cc());
+    if !cc.is_empty() {
+        header.push_str(&format!("Cc: {}\n", cc.join(", ")));
+    }
+    format!("\n\n{header}\n{text}\n")
+}
@@ -0,0 +1,199 @@
+//! Outbound mail: building the MIME message and handing it to SES.
+use crate::api_types::SendRequest;
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_sesv2 as sesv2;
+use mail_builder::headers::address::Address;
+use mail_builder::MessageBuilder;
+use sesv2::primitives::Blob;
+use sesv2::types::{Destination, EmailContent, RawMessage};
+use std::fmt::Debug;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum SendError {
+    #[error("{0}")]
+    InvalidMessage(String),
+    #[error("mail rejected: {0}")]
+    Rejected(String),
+    #[error("sending failed: {0}")]
+    Backend(String),
+}
+
+/// A message ready to go out, see [`build_message`].
+#[derive(Debug, Clone)]
+pub struct Outgoing {
+    /// `Message-ID` header, without the angle brackets.
+    pub message_id: String,
+    pub from: String,
+    /// Envelope recipients, `bcc` included.
+    pub recipients: Vec<String>,
+    pub raw: Vec<u8>,
+}
+
+/// A file sent along with a mail, see [`SendRequest::attachments_from`].
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub name: String,
+    pub content_type: String,
+    pub contents: Vec<u8>,
+}
+
+/// Delivery of outgoing mail, returns the id the mail is stored under.
+#[async_trait]
+pub trait MailSender: Debug + Send + Sync {
+    async fn send(&self, mail: &Outgoing) -> Result<String, SendError>;
+}
+
+/// Sends through the SES v2 API, as a raw message so the MIME built here goes out untouched.
+#[derive(Debug, Clone)]
+pub struct SesSender {
+    client: sesv2::Client,
+    configuration_set: Option<String>,
+}
+
+impl SesSender {
+    pub fn new(aws_config: &SdkConfig, configuration_set: Option<String>) -> Self {
+        Self::from_client(sesv2::Client::new(aws_config), configuration_set)
+    }
+
+    pub fn from_client(client: sesv2::Client, configuration_set: Option<String>) -> Self {
+        SesSender {
+            client,
+            configuration_set,
+        }
+    }
+}
+
+#[async_trait]
+impl MailSender for SesSender {
+    async fn send(&self, mail: &Outgoing) -> Result<String, SendError> {
+        let raw = RawMessage::builder()
+            .data(Blob::new(mail.raw.clone()))
+            .build()
+            .map_err(|e| SendError::InvalidMessage(e.to_string()))?;
+        let response = self
+            .client
+            .send_email()
+            .from_email_address(&mail.from)
+            .destination(
+                Destination::builder()
+                    .set_to_addresses(Some(mail.recipients.clone()))
+                    .build(),
+            )
+            .content(EmailContent::builder().raw(raw).build())
+            .set_configuration_set_name(self.configuration_set.clone())
+            .send()
+            .await
+            .map_err(|e| {
+                let e = e.into_service_error();
+                if e.is_message_rejected() || e.is_mail_from_domain_not_verified_exception() {
+                    SendError::Rejected(e.to_string())
+                } else {
+                    SendError::Backend(e.to_string())
+                }
+            })?;
+        response
+            .message_id
+            .ok_or_else(|| SendError::Backend("SES returned no message id".to_string()))
+    }
+}
+
+/// Doesn't send anything, for running locally without SES. The mail still lands in Sent.
+#[derive(Debug, Clone, Default)]
+pub struct LogSender;
+
+#[async_trait]
+impl MailSender for LogSender {
+    async fn send(&self, mail: &Outgoing) -> Result<String, SendError> {
+        log::info!(
+            "not sending {} from {} to {}",
+            mail.message_id,
+            mail.from,
+            mail.recipients.join(", ")
+        );
+        Ok(mail.message_id.clone())
+    }
+}
+
+/// Validates `request` and renders it as a MIME message dated `timestamp`, with `attachments`
+/// after the text.
+pub fn build_message(
+    request: &SendRequest,
+    attachments: &[Attachment],
+    timestamp: i64,
+) -> Result<Outgoing, SendError> {
+    let from = request.from.trim();
+    let domain = domain_of(from).ok_or_else(|| invalid_address(from))?;
+    let recipients: Vec<String> = request
+        .to
+        .iter()
+        .chain(&request.cc)
+        .chain(&request.bcc)
+        .map(|address| address.trim().to_string())
+        .collect();
+    if request.to.is_empty() {
+        return Err(SendError::InvalidMessage(
+            "at least one recipient is required".to_string(),
+        ));
+    }
+    if let Some(address) = recipients
+        .iter()
+        .find(|address| domain_of(address).is_none())
+    {
+        return Err(invalid_address(address));
+    }
+
+    let message_id = format!("{}@{domain}", uuid::Uuid::new_v4());
+    let mut builder = MessageBuilder::new()
+        .message_id(message_id.as_str())
+        .from(from)
+        .to(addresses(&request.to))
+        .subject(request.subject.as_str())
+        .date(timestamp)
+        .text_body(request.text.as_str());
+    // Bcc stays out of the headers, it's only part of the envelope
+    if !request.cc.is_empty() {
+        builder = builder.cc(addresses(&request.cc));
+    }
+    if let Some(in_reply_to) = &request.in_reply_to {
+        builder = builder.in_reply_to(in_reply_to.as_str());
+    }
+    if !request.references.is_empty() {
+        builder = builder.references(request.references.as_slice());
+    }
+    for attachment in attachments {
+        builder = builder.attachment(
+            attachment.content_type.as_str(),
+            attachment.name.as_str(),
+            attachment.contents.as_slice(),
+        );
+    }
+    let raw = builder
+        .write_to_vec()
+        .map_err(|e| SendError::InvalidMessage(e.to_string()))?;
+
+    Ok(Outgoing {
+        message_id,
+        from: from.to_string(),
+        recipients,
+        raw,
+    })
+}
+
+fn addresses(list: &[String]) -> Address<'_> {
+    Address::new_list(list.iter().map(|address| address.trim().into()).collect())
+}
+
+/// Domain of a bare `local@domain` address, display names aren't accepted.
+pub(crate) fn domain_of(address: &str) -> Option<&str> {
+    let (local, domain) = address.rsplit_once('@')?;
+    let valid = !local.is_empty()
+        && !domain.is_empty()
+        && !address.contains(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | ',' | '"'));
+    valid.then_some(domain)
+}
+
+fn invalid_address(address: &str) -> SendError {
+    SendError::InvalidMessage(format!("{address:?} is not a valid address"))
+}
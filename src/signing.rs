@@ -0,0 +1,75 @@
+//! Short-lived signed URLs for what a mail body loads. The body is shown in a sandboxed `srcdoc`
+//! iframe with an opaque origin, so the browser doesn't send the session cookie along with its
+//! images and the URLs themselves have to carry the permission.
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+
+/// How long a signed URL works, images are fetched right after their body is loaded.
+pub const SIGNED_URL_SECONDS: i64 = 60 * 60;
+
+/// Signs and checks the URLs [`crate::html::sanitize`] points a body's images at.
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Arc<[u8]>,
+}
+
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlSigner").finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    pub fn new(key: &[u8]) -> Self {
+        UrlSigner { key: key.into() }
+    }
+
+    /// A signer with a key of its own, the URLs it signs don't work on another server.
+    pub fn random() -> Self {
+        let key = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        Self::new(key.as_bytes())
+    }
+
+    /// `url`, a path with or without a query, with an expiry and a signature of both added to
+    /// its query.
+    pub fn sign(&self, url: &str) -> String {
+        let expires = chrono::Utc::now().timestamp() + SIGNED_URL_SECONDS;
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let unsigned = format!("{url}{separator}expires={expires}");
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&unsigned).finalize().into_bytes());
+        format!("{unsigned}&signature={signature}")
+    }
+
+    /// Whether `url`, the path and query of a request, was returned by [`UrlSigner::sign`] and
+    /// hasn't expired yet.
+    pub fn verify(&self, url: &str) -> bool {
+        let Some((unsigned, signature)) = url.rsplit_once("&signature=") else {
+            return false;
+        };
+        let expires = unsigned
+            .rsplit_once("expires=")
+            .and_then(|(_, expires)| expires.parse::<i64>().ok());
+        if !expires.is_some_and(|expires| expires > chrono::Utc::now().timestamp()) {
+            return false;
+        }
+        let Ok(signature) = URL_SAFE_NO_PAD.decode(signature) else {
+            return false;
+        };
+        self.mac(unsigned).verify_slice(&signature).is_ok()
+    }
+
+    fn mac(&self, unsigned: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any length");
+        mac.update(unsigned.as_bytes());
+        mac
+    }
+}
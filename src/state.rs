@@ -1,15 +1,29 @@
-use aws_config::SdkConfig;
+use crate::oidc::Oidc;
+use crate::proxy::ImageProxy;
+use crate::send::MailSender;
+use crate::signing::UrlSigner;
+use crate::store::{MailStore, MetadataStore, SearchStore};
 use axum::extract::FromRef;
 use leptos::prelude::LeptosOptions;
 use leptos_axum::AxumRouteListing;
+use std::path::PathBuf;
+use std::sync::Arc;
 
 /// This takes advantage of Axum's SubStates feature by deriving FromRef. This is the only way to have more than one
 /// item in Axum's State. Leptos requires you to have leptosOptions in your State struct for the leptos route handlers
 // #[derive(Debug, Clone)]
 #[derive(FromRef, Debug, Clone)]
 pub struct AppState {
-    pub aws_config: SdkConfig,
+    pub mail_store: Arc<dyn MailStore>,
+    pub metadata_store: Arc<dyn MetadataStore>,
+    pub mail_sender: Arc<dyn MailSender>,
+    pub search_store: Arc<dyn SearchStore>,
     pub mail_config: MailConfig,
+    pub image_proxy: ImageProxy,
+    /// Signs the URLs mail bodies load their images from.
+    pub url_signer: UrlSigner,
+    /// Single sign-on, when an issuer is configured.
+    pub oidc: Option<Oidc>,
     pub leptos_options: LeptosOptions,
     pub routes: Vec<AxumRouteListing>,
 }
@@ -19,4 +33,8 @@ pub struct MailConfig {
     pub mail_bucket: String,
     pub mail_db: String,
     pub user_db: String,
+    /// SES configuration set outgoing mail is sent with.
+    pub configuration_set: Option<String>,
+    /// Directory of the search index, a copy of the one in `mail_bucket` on AWS.
+    pub search_index: PathBuf,
 }
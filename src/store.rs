@@ -0,0 +1,157 @@
+use crate::api_types::{Flags, FlagsUpdate, Label, LabelRequest, Mail, MailFilter, Thread, User};
+use crate::auth::{Account, Session};
+use crate::keys::ApiKey;
+use async_trait::async_trait;
+use std::fmt::Debug;
+use supermailer_core::cursor;
+use supermailer_core::rules::Rule;
+use supermailer_core::search::{SearchIndex, SearchQuery};
+use thiserror::Error;
+
+pub mod aws;
+pub mod local;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("object {0} not found")]
+    NotFound(String),
+    #[error("invalid cursor {0:?}")]
+    InvalidCursor(String),
+    #[error("label {0} not found")]
+    LabelNotFound(String),
+    #[error("storage backend error: {0}")]
+    Backend(String),
+}
+
+/// Which slice of a listing to return. `cursor` is the opaque `next_cursor` of the previous page.
+#[derive(Debug, Clone, Default)]
+pub struct Page {
+    pub cursor: Option<String>,
+    pub limit: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Paged<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// Storage for the raw RFC 5322 messages, keyed by the SES message id.
+#[async_trait]
+pub trait MailStore: Debug + Send + Sync {
+    async fn get_raw(&self, key: &str) -> Result<Vec<u8>, StoreError>;
+    async fn put_raw(&self, key: &str, raw: Vec<u8>) -> Result<(), StoreError>;
+}
+
+/// Storage for the per-mailbox mail listings and the user table.
+#[async_trait]
+pub trait MetadataStore: Debug + Send + Sync {
+    /// Mails of `mailbox` that `filter` shows, only those filed under `label` when given, newest
+    /// first.
+    async fn list_emails(
+        &self,
+        mailbox: &str,
+        filter: MailFilter,
+        label: Option<&str>,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError>;
+    /// Conversations of `mailbox`, newest first, each listed once at its newest mail.
+    async fn list_threads(&self, mailbox: &str, page: Page) -> Result<Paged<Thread>, StoreError>;
+    /// Every mail of `thread_id` in `mailbox`, oldest first.
+    async fn list_thread(&self, mailbox: &str, thread_id: &str) -> Result<Vec<Mail>, StoreError>;
+    async fn list_users(&self, page: Page) -> Result<Paged<User>, StoreError>;
+    /// Adds a mail we wrote ourselves, `mail.pk` is the mailbox (or sent folder) it goes in.
+    async fn put_email(&self, mail: &Mail) -> Result<(), StoreError>;
+    /// Changes the flags of mail `message_id` in `mailbox`, returning all of them afterwards.
+    /// Fails with [`StoreError::NotFound`] rather than creating the mail.
+    async fn update_flags(
+        &self,
+        mailbox: &str,
+        message_id: &str,
+        update: &FlagsUpdate,
+    ) -> Result<Flags, StoreError>;
+    /// Number of mails in `mailbox` that [`MailFilter::Unread`] shows.
+    async fn count_unread(&self, mailbox: &str) -> Result<i64, StoreError>;
+    /// Labels defined for `address`, by name. They're shared by its inbox and sent folder.
+    async fn list_labels(&self, address: &str) -> Result<Vec<Label>, StoreError>;
+    async fn put_label(&self, address: &str, label: &Label) -> Result<(), StoreError>;
+    /// Renames or recolors a label, returning it afterwards.
+    async fn update_label(
+        &self,
+        address: &str,
+        label_id: &str,
+        update: &LabelRequest,
+    ) -> Result<Label, StoreError>;
+    /// Deletes a label and takes it off every mail filed under it.
+    async fn delete_label(&self, address: &str, label_id: &str) -> Result<(), StoreError>;
+    /// Files mail `message_id` of `mailbox` under a label of its address, or takes it off when
+    /// not `filed`. Returns the labels of the mail afterwards.
+    async fn set_label(
+        &self,
+        mailbox: &str,
+        message_id: &str,
+        label_id: &str,
+        filed: bool,
+    ) -> Result<Vec<String>, StoreError>;
+    /// Filtering rules of `address` in the order they run, none when they were never set.
+    async fn get_rules(&self, address: &str) -> Result<Vec<Rule>, StoreError>;
+    async fn put_rules(&self, address: &str, rules: &[Rule]) -> Result<(), StoreError>;
+    /// Sieve script of `address` as written, empty when it was never set.
+    async fn get_sieve(&self, address: &str) -> Result<String, StoreError>;
+    async fn put_sieve(&self, address: &str, script: &str) -> Result<(), StoreError>;
+    /// Partitions mail `message_id` is listed in, none when it's not listed anywhere.
+    async fn mailboxes_of(&self, message_id: &str) -> Result<Vec<String>, StoreError>;
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, StoreError>;
+    /// Every account, by username.
+    async fn list_accounts(&self) -> Result<Vec<Account>, StoreError>;
+    async fn put_account(&self, account: &Account) -> Result<(), StoreError>;
+    /// Deletes an account along with its sessions and API keys.
+    async fn delete_account(&self, username: &str) -> Result<(), StoreError>;
+    /// The session stored under `id`, expired ones may still be returned.
+    async fn get_session(&self, id: &str) -> Result<Option<Session>, StoreError>;
+    async fn put_session(&self, session: &Session) -> Result<(), StoreError>;
+    async fn delete_session(&self, id: &str) -> Result<(), StoreError>;
+    /// The API key stored under `key_id`, expired ones may still be returned.
+    async fn get_api_key(&self, key_id: &str) -> Result<Option<ApiKey>, StoreError>;
+    /// API keys of account `username`, in no particular order.
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, StoreError>;
+    async fn put_api_key(&self, key: &ApiKey) -> Result<(), StoreError>;
+    async fn delete_api_key(&self, key_id: &str) -> Result<(), StoreError>;
+}
+
+/// Full-text search over received mail, see `supermailer_core::search`.
+#[async_trait]
+pub trait SearchStore: Debug + Send + Sync {
+    /// Mail of `mailbox` matching `query`, newest first.
+    async fn search(
+        &self,
+        mailbox: &str,
+        query: &SearchQuery,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError>;
+}
+
+/// How deep a search pages. Tantivy keeps every hit up to the offset in memory, so a cursor past
+/// this is refused rather than allocated for.
+pub const MAX_SEARCH_OFFSET: usize = 10_000;
+
+/// One page of a search in `index`, the cursor is the offset of the next page.
+fn search_page(
+    index: &SearchIndex,
+    mailbox: &str,
+    query: &SearchQuery,
+    page: Page,
+) -> Result<Paged<Mail>, StoreError> {
+    let offset = match &page.cursor {
+        Some(cursor) => cursor::decode::<usize>(cursor)
+            .ok()
+            .filter(|offset| *offset <= MAX_SEARCH_OFFSET)
+            .ok_or_else(|| StoreError::InvalidCursor(cursor.to_string()))?,
+        None => 0,
+    };
+    let (items, has_more) = index
+        .search(mailbox, query, offset, page.limit as usize)
+        .map_err(|e| StoreError::Backend(e.to_string()))?;
+    let next_cursor = has_more.then(|| cursor::encode(&(offset + items.len())));
+    Ok(Paged { items, next_cursor })
+}
@@ -0,0 +1,1382 @@
+use crate::api_types::{
+    Flags, FlagsUpdate, Label, LabelRequest, Mail, MailFilter, Scope, Thread, User,
+};
+use crate::auth::{Account, Session};
+use crate::keys::ApiKey;
+use crate::state::MailConfig;
+use crate::store::{search_page, MailStore, MetadataStore, Page, Paged, SearchStore, StoreError};
+use async_trait::async_trait;
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb as dynamodb;
+use aws_sdk_s3 as s3;
+use dynamodb::operation::transact_write_items::TransactWriteItemsError;
+use dynamodb::types::{
+    AttributeValue, ConditionCheck, Delete, KeysAndAttributes, Put, TransactWriteItem, Update,
+};
+use futures::future::try_join_all;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use supermailer_core::item::{
+    cursor_to_key, key_to_cursor, AccountItem, ApiKeyItem, AssignmentItem, Item, LabelItem,
+    MailItem, RulesItem, SessionItem, SieveItem, ACCOUNT_PK, API_KEY_PK, RULES_PK, SESSION_PK,
+    SIEVE_PK, USER_PK,
+};
+use supermailer_core::mail::{label_partition, labels_of, mailbox_address, sent_mailbox};
+use supermailer_core::rules::Rule;
+use supermailer_core::search::{self, SearchIndex, SearchQuery};
+use tokio::sync::Mutex;
+
+/// Raw messages as written by the SES receipt rule into `MAIL_BUCKET`.
+#[derive(Debug, Clone)]
+pub struct S3MailStore {
+    client: s3::Client,
+    bucket: String,
+}
+
+impl S3MailStore {
+    pub fn new(aws_config: &SdkConfig, mail_config: &MailConfig) -> Self {
+        S3MailStore {
+            client: s3::Client::new(aws_config),
+            bucket: mail_config.mail_bucket.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl MailStore for S3MailStore {
+    async fn get_raw(&self, key: &str) -> Result<Vec<u8>, StoreError> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                let e = e.into_service_error();
+                if e.is_no_such_key() {
+                    StoreError::NotFound(key.to_string())
+                } else {
+                    StoreError::Backend(e.to_string())
+                }
+            })?;
+        let data = response
+            .body
+            .collect()
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        Ok(data.into_bytes().to_vec())
+    }
+
+    async fn put_raw(&self, key: &str, raw: Vec<u8>) -> Result<(), StoreError> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("message/rfc822")
+            .body(raw.into())
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+}
+
+/// How many times a change of flags is tried while the mail keeps changing underneath it.
+const FLAGS_ATTEMPTS: usize = 3;
+
+/// Mail items and users as written by the inbox Lambda into `MAIL_DB` and `USER_DB`.
+#[derive(Debug, Clone)]
+pub struct DynamoMetadataStore {
+    client: dynamodb::Client,
+    mail_db: String,
+    user_db: String,
+}
+
+impl DynamoMetadataStore {
+    pub fn new(aws_config: &SdkConfig, mail_config: &MailConfig) -> Self {
+        DynamoMetadataStore {
+            client: dynamodb::Client::new(aws_config),
+            mail_db: mail_config.mail_db.clone(),
+            user_db: mail_config.user_db.clone(),
+        }
+    }
+
+    /// Unread mail in `mailbox` counted one by one, see [`MetadataStore::count_unread`].
+    async fn count_listed_unread(&self, mailbox: &str) -> Result<i64, StoreError> {
+        let (filter_expression, flags) = filter_expression(MailFilter::Unread);
+        let mut unread = 0;
+        let mut start_key = None;
+        loop {
+            let mut call = self
+                .client
+                .query()
+                .table_name(&self.mail_db)
+                .key_condition_expression("pk = :pk")
+                .filter_expression(filter_expression)
+                .select(dynamodb::types::Select::Count);
+            for flag in flags {
+                call = call.expression_attribute_names(format!("#{flag}"), *flag);
+            }
+            let resp = call
+                .expression_attribute_values(":pk", AttributeValue::S(mailbox.to_string()))
+                .expression_attribute_values(":true", AttributeValue::Bool(true))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            unread += resp.count as i64;
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(unread);
+            }
+        }
+    }
+
+    /// Every mail of `thread_id` in `mailbox` through the thread index, newest first when
+    /// `newest_first`.
+    async fn thread(
+        &self,
+        mailbox: &str,
+        thread_id: &str,
+        newest_first: bool,
+    ) -> Result<Vec<Mail>, StoreError> {
+        let mut mails = Vec::new();
+        let mut start_key = None;
+        loop {
+            let mut call = self
+                .client
+                .query()
+                .table_name(&self.mail_db)
+                .index_name(MailItem::THREAD_INDEX)
+                .key_condition_expression("thread_id = :thread")
+                .filter_expression("pk = :pk")
+                .projection_expression(MailItem::LIST_PROJECTION);
+            for (name, attribute) in MailItem::LIST_NAMES {
+                call = call.expression_attribute_names(name, attribute);
+            }
+            let resp = call
+                .expression_attribute_values(":thread", AttributeValue::S(thread_id.to_string()))
+                .expression_attribute_values(":pk", AttributeValue::S(mailbox.to_string()))
+                .scan_index_forward(!newest_first)
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            mails.extend(to_mails(resp.items)?);
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(mails);
+            }
+        }
+    }
+
+    /// Primary key of mail `message_id` in `mailbox`, through the message index.
+    async fn mail_key(&self, mailbox: &str, message_id: &str) -> Result<Item, StoreError> {
+        let resp = self
+            .client
+            .query()
+            .table_name(&self.mail_db)
+            .index_name(MailItem::MESSAGE_INDEX)
+            .key_condition_expression("message_id = :id AND pk = :pk")
+            .expression_attribute_values(":id", AttributeValue::S(message_id.to_string()))
+            .expression_attribute_values(":pk", AttributeValue::S(mailbox.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        let Some(key) = resp.items.unwrap_or_default().into_iter().next() else {
+            return Err(StoreError::NotFound(message_id.to_string()));
+        };
+        Ok(key
+            .into_iter()
+            .filter(|(name, _)| name == "pk" || name == "sk")
+            .collect())
+    }
+
+    /// Item `sk` of partition `pk` in the user table.
+    async fn get_user_item(&self, pk: &str, sk: &str) -> Result<Option<Item>, StoreError> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(pk.to_string()))
+            .key("sk", AttributeValue::S(sk.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(resp.item)
+    }
+
+    async fn put_user_item(&self, item: Item) -> Result<(), StoreError> {
+        self.client
+            .put_item()
+            .table_name(&self.user_db)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+
+    async fn delete_user_item(&self, pk: &str, sk: &str) -> Result<(), StoreError> {
+        self.client
+            .delete_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(pk.to_string()))
+            .key("sk", AttributeValue::S(sk.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+
+    /// Every item of partition `pk` in the user table.
+    async fn user_partition(&self, pk: &str) -> Result<Vec<Item>, StoreError> {
+        let mut items = Vec::new();
+        let mut start_key = None;
+        loop {
+            let resp = self
+                .client
+                .query()
+                .table_name(&self.user_db)
+                .key_condition_expression("pk = :pk")
+                .expression_attribute_values(":pk", AttributeValue::S(pk.to_string()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            items.extend(resp.items.unwrap_or_default());
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    /// The mail items at `keys`, in no particular order. Mail deleted since is left out.
+    async fn batch_get(&self, mut keys: Vec<Item>) -> Result<Vec<Mail>, StoreError> {
+        let mut mails = Vec::new();
+        while !keys.is_empty() {
+            let mut request = KeysAndAttributes::builder()
+                .set_keys(Some(keys))
+                .projection_expression(MailItem::LIST_PROJECTION);
+            for (name, attribute) in MailItem::LIST_NAMES {
+                request = request.expression_attribute_names(name, attribute);
+            }
+            let request = request
+                .build()
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            let resp = self
+                .client
+                .batch_get_item()
+                .request_items(&self.mail_db, request)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            mails.extend(to_mails(
+                resp.responses
+                    .and_then(|mut tables| tables.remove(&self.mail_db)),
+            )?);
+            // throttled keys are handed back, ask for them again
+            keys = resp
+                .unprocessed_keys
+                .and_then(|mut tables| tables.remove(&self.mail_db))
+                .map(|request| request.keys)
+                .unwrap_or_default();
+        }
+        Ok(mails)
+    }
+
+    /// Like `list_emails`, walking the partition of `label` and reading its mails from their
+    /// mailbox. The cursor is the key of the last assignment item.
+    async fn list_labeled(
+        &self,
+        mailbox: &str,
+        filter: MailFilter,
+        label_id: &str,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError> {
+        let partition = label_partition(mailbox, label_id);
+        let mut mails = Vec::new();
+        let mut start_key = start_key(&page)?;
+        loop {
+            let resp = self
+                .client
+                .query()
+                .table_name(&self.mail_db)
+                .key_condition_expression("pk = :pk")
+                .projection_expression("sk")
+                .expression_attribute_values(":pk", AttributeValue::S(partition.clone()))
+                .scan_index_forward(false)
+                .set_exclusive_start_key(start_key)
+                .limit(page.limit)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            let keys = resp
+                .items
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|mut item| item.remove("sk"))
+                .map(|sk| {
+                    Item::from([
+                        ("pk".to_string(), AttributeValue::S(mailbox.to_string())),
+                        ("sk".to_string(), sk),
+                    ])
+                })
+                .collect();
+            let mut labeled = self.batch_get(keys).await?;
+            labeled.sort_by_key(|mail| std::cmp::Reverse(mail.sk));
+
+            for mail in labeled {
+                if !filter.matches(&mail.flags) {
+                    continue;
+                }
+                let mut key = mail_key(&mail);
+                key.insert("pk".to_string(), AttributeValue::S(partition.clone()));
+                mails.push(mail);
+                if mails.len() == page.limit as usize {
+                    return Ok(Paged {
+                        items: mails,
+                        next_cursor: next_cursor(Some(key))?,
+                    });
+                }
+            }
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(Paged {
+                    items: mails,
+                    next_cursor: None,
+                });
+            }
+        }
+    }
+
+    /// Takes `label_id` off every mail of `mailbox` filed under it.
+    async fn unfile_all(&self, mailbox: &str, label_id: &str) -> Result<(), StoreError> {
+        let partition = label_partition(mailbox, label_id);
+        let mut start_key = None;
+        loop {
+            let resp = self
+                .client
+                .query()
+                .table_name(&self.mail_db)
+                .key_condition_expression("pk = :pk")
+                .projection_expression("sk")
+                .expression_attribute_values(":pk", AttributeValue::S(partition.clone()))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            for item in resp.items.unwrap_or_default() {
+                let Some(sk) = item.get("sk") else { continue };
+                let mail = Item::from([
+                    ("pk".to_string(), AttributeValue::S(mailbox.to_string())),
+                    ("sk".to_string(), sk.clone()),
+                ]);
+                let assignment = Item::from([
+                    ("pk".to_string(), AttributeValue::S(partition.clone())),
+                    ("sk".to_string(), sk.clone()),
+                ]);
+                for &counted in label_attempts(mailbox) {
+                    let mut call = self
+                        .client
+                        .transact_write_items()
+                        .transact_items(unlabel(&self.mail_db, mail.clone(), label_id)?)
+                        .transact_items(delete_assignment(
+                            &self.mail_db,
+                            assignment.clone(),
+                            counted,
+                        )?);
+                    if counted {
+                        call =
+                            call.transact_items(count_label(&self.user_db, mailbox, label_id, -1)?);
+                    }
+                    match call.send().await {
+                        Ok(_) => break,
+                        Err(e) => {
+                            let e = e.into_service_error();
+                            let uncountable = condition_failed(&e, 1) || condition_failed(&e, 2);
+                            if !(counted && uncountable) {
+                                return Err(StoreError::Backend(e.to_string()));
+                            }
+                        }
+                    }
+                }
+            }
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl MetadataStore for DynamoMetadataStore {
+    async fn list_emails(
+        &self,
+        mailbox: &str,
+        filter: MailFilter,
+        label: Option<&str>,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError> {
+        if let Some(label_id) = label {
+            return self.list_labeled(mailbox, filter, label_id, page).await;
+        }
+        // `limit` caps the items read before the filter, so a page can take several queries
+        let (filter_expression, _) = filter_expression(filter);
+        let mut mails = Vec::new();
+        let mut start_key = start_key(&page)?;
+        loop {
+            let mut call = self
+                .client
+                .query()
+                .table_name(&self.mail_db)
+                .key_condition_expression("pk = :pk")
+                .filter_expression(filter_expression)
+                .projection_expression(MailItem::LIST_PROJECTION);
+            for (name, attribute) in MailItem::LIST_NAMES {
+                call = call.expression_attribute_names(name, attribute);
+            }
+            let resp = call
+                .expression_attribute_values(":pk", AttributeValue::S(mailbox.to_string()))
+                .expression_attribute_values(":true", AttributeValue::Bool(true))
+                .scan_index_forward(false)
+                .set_exclusive_start_key(start_key)
+                .limit(page.limit)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+
+            for mail in to_mails(resp.items)? {
+                let key = mail_key(&mail);
+                mails.push(mail);
+                if mails.len() == page.limit as usize {
+                    return Ok(Paged {
+                        items: mails,
+                        next_cursor: next_cursor(Some(key))?,
+                    });
+                }
+            }
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(Paged {
+                    items: mails,
+                    next_cursor: None,
+                });
+            }
+        }
+    }
+
+    async fn list_threads(&self, mailbox: &str, page: Page) -> Result<Paged<Thread>, StoreError> {
+        // walks the mailbox like list_emails and keeps the mails that are the newest of their
+        // thread, so a thread shows up once whichever page its older mails fall on
+        let mut threads = Vec::new();
+        let mut seen = HashSet::new();
+        let mut start_key = start_key(&page)?;
+        loop {
+            let mut call = self
+                .client
+                .query()
+                .table_name(&self.mail_db)
+                .key_condition_expression("pk = :pk")
+                .projection_expression(MailItem::LIST_PROJECTION);
+            for (name, attribute) in MailItem::LIST_NAMES {
+                call = call.expression_attribute_names(name, attribute);
+            }
+            let resp = call
+                .expression_attribute_values(":pk", AttributeValue::S(mailbox.to_string()))
+                .scan_index_forward(false)
+                .set_exclusive_start_key(start_key)
+                .limit(page.limit)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+
+            // a thread seen further up is newer than any of its mails down here, the rest are
+            // read from the thread index together
+            let mails: Vec<Mail> = to_mails(resp.items)?
+                .into_iter()
+                .filter(|mail| seen.insert(mail.thread_id.clone()))
+                .collect();
+            let members = try_join_all(
+                mails
+                    .iter()
+                    .map(|mail| self.thread(mailbox, &mail.thread_id, true)),
+            )
+            .await?;
+            for (mail, members) in mails.into_iter().zip(members) {
+                // items written before threading aren't in the index, they're alone
+                if members
+                    .first()
+                    .is_some_and(|newest| newest.message_id != mail.message_id)
+                {
+                    continue;
+                }
+                let key = mail_key(&mail);
+                threads.push(Thread {
+                    thread_id: mail.thread_id.clone(),
+                    message_count: members.len().max(1) as i64,
+                    latest: mail,
+                });
+                if threads.len() == page.limit as usize {
+                    return Ok(Paged {
+                        items: threads,
+                        next_cursor: next_cursor(Some(key))?,
+                    });
+                }
+            }
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                return Ok(Paged {
+                    items: threads,
+                    next_cursor: None,
+                });
+            }
+        }
+    }
+
+    async fn list_thread(&self, mailbox: &str, thread_id: &str) -> Result<Vec<Mail>, StoreError> {
+        self.thread(mailbox, thread_id, false).await
+    }
+
+    async fn list_users(&self, page: Page) -> Result<Paged<User>, StoreError> {
+        let call = self
+            .client
+            .query()
+            .table_name(&self.user_db)
+            .key_condition_expression("pk = :pk")
+            .projection_expression("pk, sk, message_count, first_seen, last_received, label_counts")
+            .expression_attribute_values(":pk", AttributeValue::S(USER_PK.to_string()))
+            .set_exclusive_start_key(start_key(&page)?)
+            .limit(page.limit);
+
+        let resp = call
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        let items = resp
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| User::try_from(item).map_err(|e| StoreError::Backend(e.to_string())))
+            .collect::<Result<_, _>>()?;
+        Ok(Paged {
+            items,
+            next_cursor: next_cursor(resp.last_evaluated_key)?,
+        })
+    }
+
+    async fn put_email(&self, mail: &Mail) -> Result<(), StoreError> {
+        let item = MailItem::from_mail(mail)
+            .to_item()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.client
+            .put_item()
+            .table_name(&self.mail_db)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+
+    async fn update_flags(
+        &self,
+        mailbox: &str,
+        message_id: &str,
+        update: &FlagsUpdate,
+    ) -> Result<Flags, StoreError> {
+        let key = self.mail_key(mailbox, message_id).await?;
+        // the unread count moves by what the change does to this mail, so the change only goes
+        // through on the flags it was worked out from
+        for _ in 0..FLAGS_ATTEMPTS {
+            let resp = self
+                .client
+                .get_item()
+                .table_name(&self.mail_db)
+                .set_key(Some(key.clone()))
+                .projection_expression("#seen, #flagged, #archived, #deleted")
+                .expression_attribute_names("#seen", "seen")
+                .expression_attribute_names("#flagged", "flagged")
+                .expression_attribute_names("#archived", "archived")
+                .expression_attribute_names("#deleted", "deleted")
+                .consistent_read(true)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            // the index may lag behind a deleted item
+            let Some(item) = resp.item else {
+                return Err(StoreError::NotFound(message_id.to_string()));
+            };
+            let flags = Flags::try_from(item).map_err(|e| StoreError::Backend(e.to_string()))?;
+            let updated = update.apply(flags);
+
+            let mut assignments = Vec::new();
+            // items written before flags have none, which reads as false like in the filters
+            let mut conditions = vec!["attribute_exists(pk)".to_string()];
+            let mut mail = Update::builder()
+                .table_name(&self.mail_db)
+                .set_key(Some(key.clone()))
+                .expression_attribute_values(":true", AttributeValue::Bool(true));
+            for (flag, value) in [
+                ("seen", flags.seen),
+                ("flagged", flags.flagged),
+                ("archived", flags.archived),
+                ("deleted", flags.deleted),
+            ] {
+                conditions.push(if value {
+                    format!("#{flag} = :true")
+                } else {
+                    format!("NOT #{flag} = :true")
+                });
+                mail = mail.expression_attribute_names(format!("#{flag}"), flag);
+            }
+            for (flag, value) in update.changes() {
+                assignments.push(format!("#{flag} = :{flag}"));
+                mail = mail
+                    .expression_attribute_values(format!(":{flag}"), AttributeValue::Bool(value));
+            }
+            if assignments.is_empty() {
+                return Ok(flags);
+            }
+            let mail = mail
+                .update_expression(format!("SET {}", assignments.join(", ")))
+                .condition_expression(conditions.join(" AND "))
+                .build()
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            let mut call = self
+                .client
+                .transact_write_items()
+                .transact_items(TransactWriteItem::builder().update(mail).build());
+            let unread = i64::from(updated.is_unread()) - i64::from(flags.is_unread());
+            // sent mail isn't counted, its mailbox has no user item
+            if unread != 0 && mailbox_address(mailbox) == mailbox {
+                let count = Update::builder()
+                    .table_name(&self.user_db)
+                    .key("pk", AttributeValue::S(USER_PK.to_string()))
+                    .key("sk", AttributeValue::S(mailbox.to_string()))
+                    .update_expression("ADD unread_count :unread")
+                    .expression_attribute_values(":unread", AttributeValue::N(unread.to_string()))
+                    .build()
+                    .map_err(|e| StoreError::Backend(e.to_string()))?;
+                call = call.transact_items(TransactWriteItem::builder().update(count).build());
+            }
+            match call.send().await {
+                Ok(_) => return Ok(updated),
+                Err(e) => {
+                    let e = e.into_service_error();
+                    // the mail is written first, its flags changed since they were read
+                    let changed = match &e {
+                        TransactWriteItemsError::TransactionCanceledException(e) => e
+                            .cancellation_reasons()
+                            .first()
+                            .and_then(|reason| reason.code.as_deref())
+                            .is_some_and(|code| code == "ConditionalCheckFailed"),
+                        _ => false,
+                    };
+                    if !changed {
+                        return Err(StoreError::Backend(e.to_string()));
+                    }
+                }
+            }
+        }
+        Err(StoreError::Backend(format!(
+            "flags of {message_id} kept changing"
+        )))
+    }
+
+    async fn count_unread(&self, mailbox: &str) -> Result<i64, StoreError> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(USER_PK.to_string()))
+            .key("sk", AttributeValue::S(mailbox.to_string()))
+            .projection_expression("unread_count")
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        match resp.item {
+            Some(user) => match user.get("unread_count") {
+                Some(AttributeValue::N(count)) => count
+                    .parse()
+                    .map_err(|_| StoreError::Backend(format!("bad unread count {count}"))),
+                // registered before it was kept, counted once here
+                _ => {
+                    let unread = self.count_listed_unread(mailbox).await?;
+                    let result = self
+                        .client
+                        .update_item()
+                        .table_name(&self.user_db)
+                        .key("pk", AttributeValue::S(USER_PK.to_string()))
+                        .key("sk", AttributeValue::S(mailbox.to_string()))
+                        .update_expression("SET unread_count = :unread")
+                        // a delivery or change of flags counting meanwhile got there first
+                        .condition_expression("attribute_not_exists(unread_count)")
+                        .expression_attribute_values(
+                            ":unread",
+                            AttributeValue::N(unread.to_string()),
+                        )
+                        .send()
+                        .await;
+                    if let Err(e) = result {
+                        let e = e.into_service_error();
+                        if !e.is_conditional_check_failed_exception() {
+                            return Err(StoreError::Backend(e.to_string()));
+                        }
+                    }
+                    Ok(unread)
+                }
+            },
+            // the sent folders aren't counted, they're small and hardly ever unread
+            None => self.count_listed_unread(mailbox).await,
+        }
+    }
+
+    async fn list_labels(&self, address: &str) -> Result<Vec<Label>, StoreError> {
+        let mut labels = Vec::new();
+        let mut start_key = None;
+        loop {
+            let resp = self
+                .client
+                .query()
+                .table_name(&self.user_db)
+                .key_condition_expression("pk = :pk")
+                .expression_attribute_values(":pk", AttributeValue::S(labels_of(address)))
+                .set_exclusive_start_key(start_key)
+                .send()
+                .await
+                .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+            for item in resp.items.unwrap_or_default() {
+                labels.push(Label::try_from(item).map_err(|e| StoreError::Backend(e.to_string()))?);
+            }
+            start_key = resp.last_evaluated_key;
+            if start_key.is_none() {
+                labels.sort_by(|a, b| a.name.cmp(&b.name).then(a.label_id.cmp(&b.label_id)));
+                return Ok(labels);
+            }
+        }
+    }
+
+    async fn put_label(&self, address: &str, label: &Label) -> Result<(), StoreError> {
+        let item = LabelItem::new(address, label)
+            .to_item()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.client
+            .put_item()
+            .table_name(&self.user_db)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+
+    async fn update_label(
+        &self,
+        address: &str,
+        label_id: &str,
+        update: &LabelRequest,
+    ) -> Result<Label, StoreError> {
+        let mut assignments = Vec::new();
+        let mut call = self
+            .client
+            .update_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(labels_of(address)))
+            .key("sk", AttributeValue::S(label_id.to_string()));
+        if let Some(name) = &update.name {
+            assignments.push("#name = :name");
+            call = call
+                .expression_attribute_names("#name", "name")
+                .expression_attribute_values(":name", AttributeValue::S(name.clone()));
+        }
+        if let Some(color) = &update.color {
+            assignments.push("color = :color");
+            call = call.expression_attribute_values(":color", AttributeValue::S(color.clone()));
+        }
+        let resp = call
+            .update_expression(format!("SET {}", assignments.join(", ")))
+            .condition_expression("attribute_exists(sk)")
+            .return_values(dynamodb::types::ReturnValue::AllNew)
+            .send()
+            .await
+            .map_err(|e| {
+                let e = e.into_service_error();
+                if e.is_conditional_check_failed_exception() {
+                    StoreError::LabelNotFound(label_id.to_string())
+                } else {
+                    StoreError::Backend(e.to_string())
+                }
+            })?;
+        Label::try_from(resp.attributes.unwrap_or_default())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn delete_label(&self, address: &str, label_id: &str) -> Result<(), StoreError> {
+        // mails first, a label that's gone can't be filed under anymore but still taken off
+        self.unfile_all(address, label_id).await?;
+        self.unfile_all(&sent_mailbox(address), label_id).await?;
+        self.client
+            .delete_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(labels_of(address)))
+            .key("sk", AttributeValue::S(label_id.to_string()))
+            .condition_expression("attribute_exists(sk)")
+            .send()
+            .await
+            .map_err(|e| {
+                let e = e.into_service_error();
+                if e.is_conditional_check_failed_exception() {
+                    StoreError::LabelNotFound(label_id.to_string())
+                } else {
+                    StoreError::Backend(e.to_string())
+                }
+            })?;
+        // its count went down to nothing along with its mails
+        let removed = self
+            .client
+            .update_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(USER_PK.to_string()))
+            .key("sk", AttributeValue::S(address.to_string()))
+            .update_expression("REMOVE label_counts.#label")
+            .condition_expression("attribute_exists(label_counts)")
+            .expression_attribute_names("#label", label_id)
+            .send()
+            .await;
+        match removed.map_err(|e| e.into_service_error()) {
+            Err(e) if !e.is_conditional_check_failed_exception() => {
+                Err(StoreError::Backend(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn set_label(
+        &self,
+        mailbox: &str,
+        message_id: &str,
+        label_id: &str,
+        filed: bool,
+    ) -> Result<Vec<String>, StoreError> {
+        let key = self.mail_key(mailbox, message_id).await?;
+        let mut assignment = key.clone();
+        // the key of the AssignmentItem
+        assignment.insert(
+            "pk".to_string(),
+            AttributeValue::S(label_partition(mailbox, label_id)),
+        );
+        let label_check = ConditionCheck::builder()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(labels_of(mailbox_address(mailbox))))
+            .key("sk", AttributeValue::S(label_id.to_string()))
+            .condition_expression("attribute_exists(sk)")
+            .build()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+
+        let sk = match key.get("sk") {
+            Some(AttributeValue::N(sk)) => sk.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| StoreError::Backend(format!("mail {message_id} has no sk")))?;
+        for &counted in label_attempts(mailbox) {
+            let (mail, assignment) = if filed {
+                let update = Update::builder()
+                    .table_name(&self.mail_db)
+                    .set_key(Some(key.clone()))
+                    .update_expression("ADD labels :label")
+                    // the index may lag behind a deleted item, don't bring it back as a stub
+                    .condition_expression("attribute_exists(pk)")
+                    .expression_attribute_values(
+                        ":label",
+                        AttributeValue::Ss(vec![label_id.to_string()]),
+                    )
+                    .build()
+                    .map_err(|e| StoreError::Backend(e.to_string()))?;
+                let mut put = Put::builder().table_name(&self.mail_db).set_item(Some(
+                    AssignmentItem::new(mailbox, label_id, sk)
+                        .to_item()
+                        .map_err(|e| StoreError::Backend(e.to_string()))?,
+                ));
+                if counted {
+                    put = put.condition_expression("attribute_not_exists(pk)");
+                }
+                let put = put
+                    .build()
+                    .map_err(|e| StoreError::Backend(e.to_string()))?;
+                (
+                    TransactWriteItem::builder().update(update).build(),
+                    TransactWriteItem::builder().put(put).build(),
+                )
+            } else {
+                (
+                    unlabel(&self.mail_db, key.clone(), label_id)?,
+                    delete_assignment(&self.mail_db, assignment.clone(), counted)?,
+                )
+            };
+            let mut call = self
+                .client
+                .transact_write_items()
+                .transact_items(
+                    TransactWriteItem::builder()
+                        .condition_check(label_check.clone())
+                        .build(),
+                )
+                .transact_items(mail)
+                .transact_items(assignment);
+            if counted {
+                let delta = if filed { 1 } else { -1 };
+                call = call.transact_items(count_label(&self.user_db, mailbox, label_id, delta)?);
+            }
+            let Err(e) = call.send().await else {
+                break;
+            };
+            let e = e.into_service_error();
+            // reasons are in the order of the items, the label is checked first
+            if condition_failed(&e, 0) {
+                return Err(StoreError::LabelNotFound(label_id.to_string()));
+            } else if condition_failed(&e, 1) {
+                return Err(StoreError::NotFound(message_id.to_string()));
+            } else if !(counted && (condition_failed(&e, 2) || condition_failed(&e, 3))) {
+                return Err(StoreError::Backend(e.to_string()));
+            }
+        }
+
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.mail_db)
+            .set_key(Some(key))
+            .projection_expression("labels")
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(match resp.item.and_then(|mut item| item.remove("labels")) {
+            Some(AttributeValue::Ss(labels)) => labels,
+            _ => Vec::new(),
+        })
+    }
+
+    async fn get_rules(&self, address: &str) -> Result<Vec<Rule>, StoreError> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(RULES_PK.to_string()))
+            .key("sk", AttributeValue::S(address.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        let Some(item) = resp.item else {
+            return Ok(Vec::new());
+        };
+        RulesItem::try_from(item)
+            .and_then(|item| item.rules())
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn put_rules(&self, address: &str, rules: &[Rule]) -> Result<(), StoreError> {
+        let item = RulesItem::new(address, rules)
+            .and_then(|item| item.to_item())
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.client
+            .put_item()
+            .table_name(&self.user_db)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+
+    async fn get_sieve(&self, address: &str) -> Result<String, StoreError> {
+        let resp = self
+            .client
+            .get_item()
+            .table_name(&self.user_db)
+            .key("pk", AttributeValue::S(SIEVE_PK.to_string()))
+            .key("sk", AttributeValue::S(address.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        let Some(item) = resp.item else {
+            return Ok(String::new());
+        };
+        SieveItem::try_from(item)
+            .map(|item| item.script)
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    async fn put_sieve(&self, address: &str, script: &str) -> Result<(), StoreError> {
+        let item = SieveItem::new(address, script)
+            .to_item()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.client
+            .put_item()
+            .table_name(&self.user_db)
+            .set_item(Some(item))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(())
+    }
+
+    async fn mailboxes_of(&self, message_id: &str) -> Result<Vec<String>, StoreError> {
+        let resp = self
+            .client
+            .query()
+            .table_name(&self.mail_db)
+            .index_name(MailItem::MESSAGE_INDEX)
+            .key_condition_expression("message_id = :id")
+            .projection_expression("pk")
+            .expression_attribute_values(":id", AttributeValue::S(message_id.to_string()))
+            .send()
+            .await
+            .map_err(|e| StoreError::Backend(e.into_service_error().to_string()))?;
+        Ok(resp
+            .items
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|mut item| match item.remove("pk") {
+                Some(AttributeValue::S(pk)) => Some(pk),
+                _ => None,
+            })
+            .collect())
+    }
+
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, StoreError> {
+        self.get_user_item(ACCOUNT_PK, username)
+            .await?
+            .map(|item| {
+                AccountItem::try_from(item)
+                    .map(account)
+                    .map_err(|e| StoreError::Backend(e.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn list_accounts(&self) -> Result<Vec<Account>, StoreError> {
+        self.user_partition(ACCOUNT_PK)
+            .await?
+            .into_iter()
+            .map(|item| {
+                AccountItem::try_from(item)
+                    .map(account)
+                    .map_err(|e| StoreError::Backend(e.to_string()))
+            })
+            .collect()
+    }
+
+    async fn put_account(&self, account: &Account) -> Result<(), StoreError> {
+        let item = AccountItem::new(
+            &account.username,
+            &account.password_hash,
+            &account.mailboxes,
+            account.admin,
+        )
+        .to_item()
+        .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.put_user_item(item).await
+    }
+
+    async fn delete_account(&self, username: &str) -> Result<(), StoreError> {
+        self.delete_user_item(ACCOUNT_PK, username).await?;
+        for item in self.user_partition(SESSION_PK).await? {
+            let session =
+                SessionItem::try_from(item).map_err(|e| StoreError::Backend(e.to_string()))?;
+            if session.username == username {
+                self.delete_user_item(SESSION_PK, &session.sk).await?;
+            }
+        }
+        for key in self.list_api_keys(username).await? {
+            self.delete_user_item(API_KEY_PK, &key.key_id).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<Session>, StoreError> {
+        self.get_user_item(SESSION_PK, id)
+            .await?
+            .map(|item| {
+                SessionItem::try_from(item)
+                    .map(|item| Session {
+                        id: item.sk,
+                        username: item.username,
+                        expires_at: item.expires_at,
+                    })
+                    .map_err(|e| StoreError::Backend(e.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn put_session(&self, session: &Session) -> Result<(), StoreError> {
+        let item = SessionItem::new(&session.id, &session.username, session.expires_at)
+            .to_item()
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.put_user_item(item).await
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<(), StoreError> {
+        self.delete_user_item(SESSION_PK, id).await
+    }
+
+    async fn get_api_key(&self, key_id: &str) -> Result<Option<ApiKey>, StoreError> {
+        self.get_user_item(API_KEY_PK, key_id)
+            .await?
+            .map(|item| {
+                ApiKeyItem::try_from(item)
+                    .map(api_key)
+                    .map_err(|e| StoreError::Backend(e.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, StoreError> {
+        let mut keys = Vec::new();
+        for item in self.user_partition(API_KEY_PK).await? {
+            let item =
+                ApiKeyItem::try_from(item).map_err(|e| StoreError::Backend(e.to_string()))?;
+            if item.username == username {
+                keys.push(api_key(item));
+            }
+        }
+        Ok(keys)
+    }
+
+    async fn put_api_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        let item = ApiKeyItem {
+            pk: API_KEY_PK.to_string(),
+            sk: key.key_id.clone(),
+            username: key.username.clone(),
+            name: key.name.clone(),
+            key_hash: key.key_hash.clone(),
+            scopes: key.scopes.iter().map(Scope::to_string).collect(),
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+        }
+        .to_item()
+        .map_err(|e| StoreError::Backend(e.to_string()))?;
+        self.put_user_item(item).await
+    }
+
+    async fn delete_api_key(&self, key_id: &str) -> Result<(), StoreError> {
+        self.delete_user_item(API_KEY_PK, key_id).await
+    }
+}
+
+/// The search index the inbox keeps in `MAIL_BUCKET`, copied into a local directory and
+/// downloaded again whenever the inbox uploaded a newer version.
+#[derive(Debug)]
+pub struct S3SearchStore {
+    client: s3::Client,
+    bucket: String,
+    dir: PathBuf,
+    /// The index once downloaded, with the version of it that was.
+    index: Mutex<Option<(SearchIndex, String)>>,
+}
+
+impl S3SearchStore {
+    pub fn new(aws_config: &SdkConfig, mail_config: &MailConfig) -> Self {
+        S3SearchStore {
+            client: s3::Client::new(aws_config),
+            bucket: mail_config.mail_bucket.clone(),
+            dir: mail_config.search_index.clone(),
+            index: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait]
+impl SearchStore for S3SearchStore {
+    async fn search(
+        &self,
+        mailbox: &str,
+        query: &SearchQuery,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError> {
+        let mut index = self.index.lock().await;
+        let Some(version) = search::version(&self.client, &self.bucket)
+            .await
+            .map_err(|e| StoreError::Backend(e.to_string()))?
+        else {
+            // nothing was ingested yet
+            return Ok(Paged {
+                items: Vec::new(),
+                next_cursor: None,
+            });
+        };
+        if index
+            .as_ref()
+            .is_none_or(|(_, current)| *current != version)
+        {
+            search::download(&self.client, &self.bucket, &self.dir)
+                .await
+                .map_err(|e| StoreError::Backend(e.to_string()))?;
+            let updated = match index.take() {
+                Some((updated, _)) => updated.reload().map(|()| updated),
+                None => SearchIndex::open(&self.dir),
+            }
+            .map_err(|e| StoreError::Backend(e.to_string()))?;
+            *index = Some((updated, version));
+        }
+        let (index, _) = index.as_ref().expect("index was just opened");
+        search_page(index, mailbox, query, page)
+    }
+}
+
+/// Filter expression selecting the mails `filter` shows, with the flags it names. A flag that
+/// was never set is missing from the item, so unset is tested as `NOT #flag = :true`.
+fn filter_expression(filter: MailFilter) -> (&'static str, &'static [&'static str]) {
+    match filter {
+        MailFilter::All => (
+            "NOT #archived = :true AND NOT #deleted = :true",
+            &["archived", "deleted"],
+        ),
+        MailFilter::Unread => (
+            "NOT #seen = :true AND NOT #archived = :true AND NOT #deleted = :true",
+            &["seen", "archived", "deleted"],
+        ),
+        MailFilter::Flagged => (
+            "#flagged = :true AND NOT #deleted = :true",
+            &["flagged", "deleted"],
+        ),
+        MailFilter::Archived => (
+            "#archived = :true AND NOT #deleted = :true",
+            &["archived", "deleted"],
+        ),
+        MailFilter::Deleted => ("#deleted = :true", &["deleted"]),
+    }
+}
+
+/// Takes `label_id` off the mail item at `key`.
+fn unlabel(mail_db: &str, key: Item, label_id: &str) -> Result<TransactWriteItem, StoreError> {
+    let update = Update::builder()
+        .table_name(mail_db)
+        .set_key(Some(key))
+        .update_expression("DELETE labels :label")
+        .condition_expression("attribute_exists(pk)")
+        .expression_attribute_values(":label", AttributeValue::Ss(vec![label_id.to_string()]))
+        .build()
+        .map_err(|e| StoreError::Backend(e.to_string()))?;
+    Ok(TransactWriteItem::builder().update(update).build())
+}
+
+/// Deletes the assignment item at `key` from a label partition. When it's `existing`, the
+/// transaction fails unless there's one to delete.
+fn delete_assignment(
+    mail_db: &str,
+    key: Item,
+    existing: bool,
+) -> Result<TransactWriteItem, StoreError> {
+    let mut delete = Delete::builder().table_name(mail_db).set_key(Some(key));
+    if existing {
+        delete = delete.condition_expression("attribute_exists(pk)");
+    }
+    let delete = delete
+        .build()
+        .map_err(|e| StoreError::Backend(e.to_string()))?;
+    Ok(TransactWriteItem::builder().delete(delete).build())
+}
+
+/// Moves the count of `label_id` on the user item of `mailbox` by `delta`. Fails the transaction
+/// when the user item has no `label_counts`, the inbox counts them in full when it finds them
+/// missing.
+fn count_label(
+    user_db: &str,
+    mailbox: &str,
+    label_id: &str,
+    delta: i64,
+) -> Result<TransactWriteItem, StoreError> {
+    let update = Update::builder()
+        .table_name(user_db)
+        .key("pk", AttributeValue::S(USER_PK.to_string()))
+        .key("sk", AttributeValue::S(mailbox.to_string()))
+        .update_expression("ADD label_counts.#label :delta")
+        .condition_expression("attribute_exists(label_counts)")
+        .expression_attribute_names("#label", label_id)
+        .expression_attribute_values(":delta", AttributeValue::N(delta.to_string()))
+        .build()
+        .map_err(|e| StoreError::Backend(e.to_string()))?;
+    Ok(TransactWriteItem::builder().update(update).build())
+}
+
+/// Whether to count each attempt at changing a label assignment in `mailbox`. Only the inbox is
+/// counted, and only when the assignment changes and there's a count to move, so a counted
+/// attempt that fails on either is made again uncounted.
+fn label_attempts(mailbox: &str) -> &'static [bool] {
+    if mailbox_address(mailbox) == mailbox {
+        &[true, false]
+    } else {
+        &[false]
+    }
+}
+
+/// Whether write `index` of the transaction `error` cancelled failed its condition.
+fn condition_failed(error: &TransactWriteItemsError, index: usize) -> bool {
+    match error {
+        TransactWriteItemsError::TransactionCanceledException(e) => e
+            .cancellation_reasons()
+            .get(index)
+            .and_then(|reason| reason.code.as_deref())
+            .is_some_and(|code| code == "ConditionalCheckFailed"),
+        _ => false,
+    }
+}
+
+fn account(item: AccountItem) -> Account {
+    Account {
+        username: item.sk,
+        password_hash: item.password_hash,
+        mailboxes: item.mailboxes,
+        admin: item.admin,
+    }
+}
+
+/// Scopes that no longer parse are dropped rather than failing the key.
+fn api_key(item: ApiKeyItem) -> ApiKey {
+    ApiKey {
+        key_id: item.sk,
+        username: item.username,
+        name: item.name,
+        key_hash: item.key_hash,
+        scopes: item.scopes.iter().filter_map(|s| s.parse().ok()).collect(),
+        created_at: item.created_at,
+        expires_at: item.expires_at,
+    }
+}
+
+/// Primary key of the item `mail` was read from.
+fn mail_key(mail: &Mail) -> Item {
+    Item::from([
+        ("pk".to_string(), AttributeValue::S(mail.pk.clone())),
+        ("sk".to_string(), AttributeValue::N(mail.sk.to_string())),
+    ])
+}
+
+fn to_mails(items: Option<Vec<Item>>) -> Result<Vec<Mail>, StoreError> {
+    items
+        .unwrap_or_default()
+        .into_iter()
+        .map(|item| Mail::try_from(item).map_err(|e| StoreError::Backend(e.to_string())))
+        .collect()
+}
+
+fn start_key(page: &Page) -> Result<Option<Item>, StoreError> {
+    page.cursor
+        .as_deref()
+        .map(|cursor| {
+            cursor_to_key(cursor).map_err(|_| StoreError::InvalidCursor(cursor.to_string()))
+        })
+        .transpose()
+}
+
+fn next_cursor(last_evaluated_key: Option<Item>) -> Result<Option<String>, StoreError> {
+    last_evaluated_key
+        .map(|key| key_to_cursor(key).map_err(|e| StoreError::Backend(e.to_string())))
+        .transpose()
+}
@@ -0,0 +1,947 @@
+use crate::api_types::{Flags, FlagsUpdate, Label, LabelRequest, Mail, MailFilter, Thread, User};
+use crate::auth::{Account, Session};
+use crate::keys::ApiKey;
+use crate::store::{search_page, MailStore, MetadataStore, Page, Paged, SearchStore, StoreError};
+use async_trait::async_trait;
+use mail_parser::Message;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use supermailer_core::cursor;
+use supermailer_core::mail::{mailbox_address, sent_mailbox, SENT_PREFIX};
+use supermailer_core::parse::{
+    addresses, first_sentence, format_addresses, normalize_subject, thread_id, SUBJECT_THREAD,
+};
+use supermailer_core::rules::Rule;
+use supermailer_core::search::{SearchIndex, SearchQuery};
+
+/// Raw messages stored as `<key>.eml` files in a single directory.
+#[derive(Debug, Clone)]
+pub struct FsMailStore {
+    dir: PathBuf,
+}
+
+impl FsMailStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FsMailStore { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, StoreError> {
+        // keys come straight from the URL, don't let them walk out of the mail directory
+        if key.is_empty() || key.contains(['/', '\\']) || key.starts_with('.') {
+            return Err(StoreError::NotFound(key.to_string()));
+        }
+        Ok(self.dir.join(format!("{key}.eml")))
+    }
+
+    /// Keys of every `.eml` file in the mail directory.
+    pub fn keys(&self) -> Result<Vec<String>, StoreError> {
+        let entries = std::fs::read_dir(&self.dir).map_err(backend)?;
+        let mut keys = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(backend)?.path();
+            if path.extension().is_some_and(|ext| ext == "eml") {
+                if let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    keys.push(stem.to_string());
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl MailStore for FsMailStore {
+    async fn get_raw(&self, key: &str) -> Result<Vec<u8>, StoreError> {
+        let path = self.path(key)?;
+        tokio::fs::read(&path).await.map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => StoreError::NotFound(key.to_string()),
+            _ => backend(e),
+        })
+    }
+
+    async fn put_raw(&self, key: &str, raw: Vec<u8>) -> Result<(), StoreError> {
+        let path = self.path(key)?;
+        tokio::fs::write(&path, raw).await.map_err(backend)
+    }
+}
+
+/// Mail listings kept in a SQLite database, built by indexing a [`FsMailStore`].
+#[derive(Debug)]
+pub struct SqliteMetadataStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteMetadataStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let conn = Connection::open(path).map_err(backend)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS mail (
+                pk TEXT NOT NULL,
+                sk INTEGER NOT NULL,
+                message_id TEXT NOT NULL,
+                subject TEXT NOT NULL,
+                sender TEXT NOT NULL,
+                first_sentence TEXT NOT NULL,
+                PRIMARY KEY (pk, message_id)
+            );
+            CREATE INDEX IF NOT EXISTS mail_pk_sk ON mail (pk, sk);",
+        )
+        .map_err(backend)?;
+        // databases indexed before threading lack the column, their rows stay on their own
+        if conn.prepare("SELECT thread_id FROM mail LIMIT 0").is_err() {
+            conn.execute("ALTER TABLE mail ADD COLUMN thread_id TEXT", [])
+                .map_err(backend)?;
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS mail_pk_thread ON mail (pk, thread_id)",
+            [],
+        )
+        .map_err(backend)?;
+        // nor the flags, everything indexed before them starts out unread
+        if conn.prepare("SELECT seen FROM mail LIMIT 0").is_err() {
+            conn.execute_batch(
+                "ALTER TABLE mail ADD COLUMN seen INTEGER NOT NULL DEFAULT 0;
+                 ALTER TABLE mail ADD COLUMN flagged INTEGER NOT NULL DEFAULT 0;
+                 ALTER TABLE mail ADD COLUMN archived INTEGER NOT NULL DEFAULT 0;
+                 ALTER TABLE mail ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;",
+            )
+            .map_err(backend)?;
+        }
+        // nor the subjects replies without threading headers are matched by, those already
+        // indexed stay on their own
+        if conn
+            .prepare("SELECT normalized_subject FROM mail LIMIT 0")
+            .is_err()
+        {
+            conn.execute("ALTER TABLE mail ADD COLUMN normalized_subject TEXT", [])
+                .map_err(backend)?;
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS mail_pk_subject ON mail (pk, normalized_subject, sk)",
+            [],
+        )
+        .map_err(backend)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS label (
+                address TEXT NOT NULL,
+                label_id TEXT NOT NULL,
+                name TEXT NOT NULL,
+                color TEXT NOT NULL,
+                PRIMARY KEY (address, label_id)
+            );
+            -- its key is the index a listing by label goes through
+            CREATE TABLE IF NOT EXISTS mail_label (
+                pk TEXT NOT NULL,
+                label_id TEXT NOT NULL,
+                message_id TEXT NOT NULL,
+                PRIMARY KEY (pk, label_id, message_id)
+            );
+            CREATE INDEX IF NOT EXISTS mail_label_message ON mail_label (pk, message_id);
+            CREATE TABLE IF NOT EXISTS rules (
+                address TEXT PRIMARY KEY,
+                rules TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS sieve (
+                address TEXT PRIMARY KEY,
+                script TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS account (
+                username TEXT PRIMARY KEY,
+                password_hash TEXT NOT NULL,
+                mailboxes TEXT NOT NULL,
+                admin INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS session (
+                id TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                expires_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS api_key (
+                key_id TEXT PRIMARY KEY,
+                username TEXT NOT NULL,
+                name TEXT NOT NULL,
+                key_hash TEXT NOT NULL,
+                scopes TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                expires_at INTEGER
+            );",
+        )
+        .map_err(backend)?;
+        Ok(SqliteMetadataStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Adds every message in `mail_store` that hasn't been indexed yet, one row per recipient.
+    /// Returns the number of newly indexed messages.
+    pub async fn index(&self, mail_store: &FsMailStore) -> Result<usize, StoreError> {
+        let mut indexed = 0;
+        for key in mail_store.keys()? {
+            if self.contains(&key)? {
+                continue;
+            }
+            let contents = mail_store.get_raw(&key).await?;
+            let Some(message) = Message::parse(&contents) else {
+                log::warn!("skipping {key}: not a valid message");
+                continue;
+            };
+
+            let sk = message
+                .date()
+                .map(|date| date.to_timestamp())
+                .unwrap_or_default();
+            let subject = message.subject().unwrap_or_default();
+            let from = serde_json::to_string(&format_addresses(message.from())).map_err(backend)?;
+            let first_sentence = first_sentence(&message);
+            let thread_id = thread_id(&message);
+
+            let conn = self.conn.lock().unwrap();
+            for recipient in addresses(message.to()).chain(addresses(message.cc())) {
+                let Some(pk) = &recipient.address else {
+                    continue;
+                };
+                conn.execute(
+                    "INSERT OR IGNORE INTO mail
+                     (pk, sk, message_id, subject, sender, first_sentence, thread_id,
+                      normalized_subject)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        pk.to_lowercase(),
+                        sk,
+                        key,
+                        subject,
+                        from,
+                        first_sentence,
+                        thread_id,
+                        normalize_subject(subject)
+                    ],
+                )
+                .map_err(backend)?;
+            }
+            indexed += 1;
+        }
+        // files are read in no particular order, replies can come before what they answer
+        join_subject_threads(&self.conn.lock().unwrap())?;
+        Ok(indexed)
+    }
+
+    fn contains(&self, key: &str) -> Result<bool, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT 1 FROM mail WHERE message_id = ?1 LIMIT 1",
+            params![key],
+            |_| Ok(()),
+        )
+        .optional()
+        .map(|row| row.is_some())
+        .map_err(backend)
+    }
+}
+
+#[async_trait]
+impl MetadataStore for SqliteMetadataStore {
+    async fn list_emails(
+        &self,
+        mailbox: &str,
+        filter: MailFilter,
+        label: Option<&str>,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError> {
+        // keyset pagination on (sk, message_id), the cursor is the last row of the previous page
+        let (sk, message_id) = match &page.cursor {
+            Some(cursor) => cursor::decode::<(i64, String)>(cursor)
+                .map_err(|_| StoreError::InvalidCursor(cursor.to_string()))?,
+            None => (i64::MAX, String::new()),
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {MAIL_COLUMNS} FROM mail
+                 WHERE pk = ?1 AND (sk, message_id) < (?2, ?3) AND {}
+                    AND (?5 IS NULL OR EXISTS (
+                        SELECT 1 FROM mail_label AS l
+                        WHERE l.pk = mail.pk AND l.label_id = ?5 AND l.message_id = mail.message_id
+                    ))
+                 ORDER BY sk DESC, message_id DESC LIMIT ?4",
+                filter_condition(filter)
+            ))
+            .map_err(backend)?;
+        let rows = statement
+            .query_map(
+                params![mailbox, sk, message_id, page.limit + 1, label],
+                mail,
+            )
+            .map_err(backend)?;
+        let items = rows.collect::<Result<_, _>>().map_err(backend)?;
+        Ok(paged(items, page.limit, |mail| {
+            cursor::encode(&(mail.sk, &mail.message_id))
+        }))
+    }
+
+    async fn list_threads(&self, mailbox: &str, page: Page) -> Result<Paged<Thread>, StoreError> {
+        // same keyset as list_emails, on the newest mail of each thread
+        let (sk, message_id) = match &page.cursor {
+            Some(cursor) => cursor::decode::<(i64, String)>(cursor)
+                .map_err(|_| StoreError::InvalidCursor(cursor.to_string()))?,
+            None => (i64::MAX, String::new()),
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {MAIL_COLUMNS}, message_count FROM (
+                    SELECT *,
+                        ROW_NUMBER() OVER thread AS position,
+                        COUNT(*) OVER (PARTITION BY coalesce(thread_id, message_id)) AS message_count
+                    FROM mail WHERE pk = ?1
+                    WINDOW thread AS (
+                        PARTITION BY coalesce(thread_id, message_id)
+                        ORDER BY sk DESC, message_id DESC
+                    )
+                 ) AS mail
+                 WHERE position = 1 AND (sk, message_id) < (?2, ?3)
+                 ORDER BY sk DESC, message_id DESC LIMIT ?4"
+            ))
+            .map_err(backend)?;
+        let rows = statement
+            .query_map(params![mailbox, sk, message_id, page.limit + 1], |row| {
+                let latest = mail(row)?;
+                Ok(Thread {
+                    thread_id: latest.thread_id.clone(),
+                    message_count: row.get(12)?,
+                    latest,
+                })
+            })
+            .map_err(backend)?;
+        let items = rows.collect::<Result<_, _>>().map_err(backend)?;
+        Ok(paged(items, page.limit, |thread| {
+            cursor::encode(&(thread.latest.sk, &thread.latest.message_id))
+        }))
+    }
+
+    async fn list_thread(&self, mailbox: &str, thread_id: &str) -> Result<Vec<Mail>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(&format!(
+                "SELECT {MAIL_COLUMNS} FROM mail
+                 WHERE pk = ?1 AND coalesce(thread_id, message_id) = ?2
+                 ORDER BY sk, message_id"
+            ))
+            .map_err(backend)?;
+        let rows = statement
+            .query_map(params![mailbox, thread_id], mail)
+            .map_err(backend)?;
+        rows.collect::<Result<_, _>>().map_err(backend)
+    }
+
+    async fn list_users(&self, page: Page) -> Result<Paged<User>, StoreError> {
+        let after = match &page.cursor {
+            Some(cursor) => cursor::decode::<String>(cursor)
+                .map_err(|_| StoreError::InvalidCursor(cursor.to_string()))?,
+            None => String::new(),
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT pk, COUNT(*), MIN(sk), MAX(sk) FROM mail WHERE pk > ?1 AND pk NOT LIKE ?2
+                 GROUP BY pk ORDER BY pk LIMIT ?3",
+            )
+            .map_err(backend)?;
+        let rows = statement
+            .query_map(
+                params![after, format!("{SENT_PREFIX}%"), page.limit + 1],
+                |row| {
+                    Ok(User {
+                        pk: "USER".to_string(),
+                        sk: row.get(0)?,
+                        message_count: row.get(1)?,
+                        first_seen: row.get(2)?,
+                        last_received: row.get(3)?,
+                        label_counts: Default::default(),
+                    })
+                },
+            )
+            .map_err(backend)?;
+        let mut items: Vec<User> = rows.collect::<Result<_, _>>().map_err(backend)?;
+        let mut statement = conn
+            .prepare("SELECT label_id, COUNT(*) FROM mail_label WHERE pk = ?1 GROUP BY label_id")
+            .map_err(backend)?;
+        for user in &mut items {
+            user.label_counts = statement
+                .query_map(params![user.sk], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(backend)?
+                .collect::<rusqlite::Result<_>>()
+                .map_err(backend)?;
+        }
+        Ok(paged(items, page.limit, |user| cursor::encode(&user.sk)))
+    }
+
+    async fn put_email(&self, mail: &Mail) -> Result<(), StoreError> {
+        let from = serde_json::to_string(&mail.from).map_err(backend)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO mail
+             (pk, sk, message_id, subject, sender, first_sentence, thread_id,
+              seen, flagged, archived, deleted, normalized_subject)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+            params![
+                mail.pk,
+                mail.sk,
+                mail.message_id,
+                mail.subject,
+                from,
+                mail.first_sentence,
+                mail.thread_id,
+                mail.flags.seen,
+                mail.flags.flagged,
+                mail.flags.archived,
+                mail.flags.deleted,
+                normalize_subject(&mail.subject)
+            ],
+        )
+        .map_err(backend)?;
+        join_subject_threads(&conn)
+    }
+
+    async fn update_flags(
+        &self,
+        mailbox: &str,
+        message_id: &str,
+        update: &FlagsUpdate,
+    ) -> Result<Flags, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "UPDATE mail SET
+                seen = coalesce(?3, seen),
+                flagged = coalesce(?4, flagged),
+                archived = coalesce(?5, archived),
+                deleted = coalesce(?6, deleted)
+             WHERE pk = ?1 AND message_id = ?2
+             RETURNING seen, flagged, archived, deleted",
+            params![
+                mailbox,
+                message_id,
+                update.seen,
+                update.flagged,
+                update.archived,
+                update.deleted
+            ],
+            |row| flags(row, 0),
+        )
+        .optional()
+        .map_err(backend)?
+        .ok_or_else(|| StoreError::NotFound(message_id.to_string()))
+    }
+
+    async fn count_unread(&self, mailbox: &str) -> Result<i64, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM mail WHERE pk = ?1 AND {}",
+                filter_condition(MailFilter::Unread)
+            ),
+            params![mailbox],
+            |row| row.get(0),
+        )
+        .map_err(backend)
+    }
+
+    async fn list_labels(&self, address: &str) -> Result<Vec<Label>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn
+            .prepare(
+                "SELECT label_id, name, color FROM label WHERE address = ?1
+                 ORDER BY name, label_id",
+            )
+            .map_err(backend)?;
+        let rows = statement
+            .query_map(params![address], label)
+            .map_err(backend)?;
+        rows.collect::<Result<_, _>>().map_err(backend)
+    }
+
+    async fn put_label(&self, address: &str, label: &Label) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO label (address, label_id, name, color) VALUES (?1, ?2, ?3, ?4)",
+            params![address, label.label_id, label.name, label.color],
+        )
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn update_label(
+        &self,
+        address: &str,
+        label_id: &str,
+        update: &LabelRequest,
+    ) -> Result<Label, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "UPDATE label SET name = coalesce(?3, name), color = coalesce(?4, color)
+             WHERE address = ?1 AND label_id = ?2
+             RETURNING label_id, name, color",
+            params![address, label_id, update.name, update.color],
+            label,
+        )
+        .optional()
+        .map_err(backend)?
+        .ok_or_else(|| StoreError::LabelNotFound(label_id.to_string()))
+    }
+
+    async fn delete_label(&self, address: &str, label_id: &str) -> Result<(), StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction().map_err(backend)?;
+        let deleted = transaction
+            .execute(
+                "DELETE FROM label WHERE address = ?1 AND label_id = ?2",
+                params![address, label_id],
+            )
+            .map_err(backend)?;
+        if deleted == 0 {
+            return Err(StoreError::LabelNotFound(label_id.to_string()));
+        }
+        transaction
+            .execute(
+                "DELETE FROM mail_label WHERE pk IN (?1, ?2) AND label_id = ?3",
+                params![address, sent_mailbox(address), label_id],
+            )
+            .map_err(backend)?;
+        transaction.commit().map_err(backend)
+    }
+
+    async fn set_label(
+        &self,
+        mailbox: &str,
+        message_id: &str,
+        label_id: &str,
+        filed: bool,
+    ) -> Result<Vec<String>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let defined = conn
+            .query_row(
+                "SELECT 1 FROM label WHERE address = ?1 AND label_id = ?2",
+                params![mailbox_address(mailbox), label_id],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(backend)?;
+        if defined.is_none() {
+            return Err(StoreError::LabelNotFound(label_id.to_string()));
+        }
+        let labels: String = conn
+            .query_row(
+                &format!("SELECT {LABELS_COLUMN} FROM mail WHERE pk = ?1 AND message_id = ?2"),
+                params![mailbox, message_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(backend)?
+            .ok_or_else(|| StoreError::NotFound(message_id.to_string()))?;
+        let mut labels: Vec<String> = serde_json::from_str(&labels).map_err(backend)?;
+
+        let statement = if filed {
+            "INSERT OR IGNORE INTO mail_label (pk, message_id, label_id) VALUES (?1, ?2, ?3)"
+        } else {
+            "DELETE FROM mail_label WHERE pk = ?1 AND message_id = ?2 AND label_id = ?3"
+        };
+        conn.execute(statement, params![mailbox, message_id, label_id])
+            .map_err(backend)?;
+        labels.retain(|label| label != label_id);
+        if filed {
+            labels.push(label_id.to_string());
+        }
+        Ok(labels)
+    }
+
+    async fn get_rules(&self, address: &str) -> Result<Vec<Rule>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let rules: Option<String> = conn
+            .query_row(
+                "SELECT rules FROM rules WHERE address = ?1",
+                params![address],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(backend)?;
+        match rules {
+            Some(rules) => serde_json::from_str(&rules).map_err(backend),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn put_rules(&self, address: &str, rules: &[Rule]) -> Result<(), StoreError> {
+        let rules = serde_json::to_string(rules).map_err(backend)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO rules (address, rules) VALUES (?1, ?2)",
+            params![address, rules],
+        )
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn get_sieve(&self, address: &str) -> Result<String, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let script: Option<String> = conn
+            .query_row(
+                "SELECT script FROM sieve WHERE address = ?1",
+                params![address],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(backend)?;
+        Ok(script.unwrap_or_default())
+    }
+
+    async fn put_sieve(&self, address: &str, script: &str) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO sieve (address, script) VALUES (?1, ?2)",
+            params![address, script],
+        )
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn mailboxes_of(&self, message_id: &str) -> Result<Vec<String>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT pk FROM mail WHERE message_id = ?1")
+            .map_err(backend)?;
+        let mailboxes = stmt
+            .query_map(params![message_id], |row| row.get(0))
+            .map_err(backend)?
+            .collect::<rusqlite::Result<_>>()
+            .map_err(backend)?;
+        Ok(mailboxes)
+    }
+
+    async fn get_account(&self, username: &str) -> Result<Option<Account>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT username, password_hash, mailboxes, admin FROM account WHERE username = ?1",
+            params![username],
+            account,
+        )
+        .optional()
+        .map_err(backend)
+    }
+
+    async fn list_accounts(&self) -> Result<Vec<Account>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT username, password_hash, mailboxes, admin FROM account ORDER BY username",
+            )
+            .map_err(backend)?;
+        let accounts = stmt
+            .query_map([], account)
+            .map_err(backend)?
+            .collect::<rusqlite::Result<_>>()
+            .map_err(backend)?;
+        Ok(accounts)
+    }
+
+    async fn put_account(&self, account: &Account) -> Result<(), StoreError> {
+        let mailboxes = serde_json::to_string(&account.mailboxes).map_err(backend)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO account (username, password_hash, mailboxes, admin)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![
+                account.username,
+                account.password_hash,
+                mailboxes,
+                account.admin
+            ],
+        )
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn delete_account(&self, username: &str) -> Result<(), StoreError> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction().map_err(backend)?;
+        transaction
+            .execute("DELETE FROM account WHERE username = ?1", params![username])
+            .map_err(backend)?;
+        transaction
+            .execute("DELETE FROM session WHERE username = ?1", params![username])
+            .map_err(backend)?;
+        transaction
+            .execute("DELETE FROM api_key WHERE username = ?1", params![username])
+            .map_err(backend)?;
+        transaction.commit().map_err(backend)
+    }
+
+    async fn get_session(&self, id: &str) -> Result<Option<Session>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT id, username, expires_at FROM session WHERE id = ?1",
+            params![id],
+            |row| {
+                Ok(Session {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    expires_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(backend)
+    }
+
+    async fn put_session(&self, session: &Session) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        // there's no time to live here, expired sessions go whenever one is started
+        conn.execute(
+            "DELETE FROM session WHERE expires_at <= ?1",
+            params![chrono::Utc::now().timestamp()],
+        )
+        .map_err(backend)?;
+        conn.execute(
+            "INSERT INTO session (id, username, expires_at) VALUES (?1, ?2, ?3)",
+            params![session.id, session.username, session.expires_at],
+        )
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn delete_session(&self, id: &str) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM session WHERE id = ?1", params![id])
+            .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn get_api_key(&self, key_id: &str) -> Result<Option<ApiKey>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT key_id, username, name, key_hash, scopes, created_at, expires_at
+            FROM api_key WHERE key_id = ?1",
+            params![key_id],
+            api_key,
+        )
+        .optional()
+        .map_err(backend)
+    }
+
+    async fn list_api_keys(&self, username: &str) -> Result<Vec<ApiKey>, StoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT key_id, username, name, key_hash, scopes, created_at, expires_at
+                FROM api_key WHERE username = ?1",
+            )
+            .map_err(backend)?;
+        let keys = stmt
+            .query_map(params![username], api_key)
+            .map_err(backend)?
+            .collect::<rusqlite::Result<_>>()
+            .map_err(backend)?;
+        Ok(keys)
+    }
+
+    async fn put_api_key(&self, key: &ApiKey) -> Result<(), StoreError> {
+        let scopes = serde_json::to_string(&key.scopes).map_err(backend)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO api_key
+            (key_id, username, name, key_hash, scopes, created_at, expires_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                key.key_id,
+                key.username,
+                key.name,
+                key.key_hash,
+                scopes,
+                key.created_at,
+                key.expires_at
+            ],
+        )
+        .map_err(backend)?;
+        Ok(())
+    }
+
+    async fn delete_api_key(&self, key_id: &str) -> Result<(), StoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM api_key WHERE key_id = ?1", params![key_id])
+            .map_err(backend)?;
+        Ok(())
+    }
+}
+
+/// The search index in a local directory, built by indexing a [`FsMailStore`] like
+/// [`SqliteMetadataStore`] is.
+#[derive(Debug)]
+pub struct LocalSearchStore {
+    index: SearchIndex,
+}
+
+impl LocalSearchStore {
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let index = SearchIndex::open(dir.as_ref()).map_err(backend)?;
+        Ok(LocalSearchStore { index })
+    }
+
+    /// Adds every message in `mail_store` that isn't in the index yet, under each recipient in
+    /// `To`/`Cc`. Returns the number of newly indexed messages.
+    pub async fn index(&self, mail_store: &FsMailStore) -> Result<usize, StoreError> {
+        let mut writer = self.index.writer().map_err(backend)?;
+        let mut indexed = 0;
+        for key in mail_store.keys()? {
+            if self.index.contains(&key).map_err(backend)? {
+                continue;
+            }
+            let contents = mail_store.get_raw(&key).await?;
+            let Some(message) = Message::parse(&contents) else {
+                continue;
+            };
+            let mailboxes: Vec<String> = addresses(message.to())
+                .chain(addresses(message.cc()))
+                .filter_map(|recipient| recipient.address.as_deref())
+                .map(str::to_string)
+                .collect();
+            let sk = message
+                .date()
+                .map(|date| date.to_timestamp())
+                .unwrap_or_default();
+            self.index
+                .add(&writer, &key, &mailboxes, sk, &message)
+                .map_err(backend)?;
+            indexed += 1;
+        }
+        writer.commit().map_err(backend)?;
+        self.index.reload().map_err(backend)?;
+        Ok(indexed)
+    }
+}
+
+#[async_trait]
+impl SearchStore for LocalSearchStore {
+    async fn search(
+        &self,
+        mailbox: &str,
+        query: &SearchQuery,
+        page: Page,
+    ) -> Result<Paged<Mail>, StoreError> {
+        search_page(&self.index, mailbox, query, page)
+    }
+}
+
+/// Columns read by [`mail`], rows indexed before threading are a thread of their own. Only
+/// works on a table (or subquery) named `mail`, see [`LABELS_COLUMN`].
+/// Joins the replies missing their threading headers to the thread of the latest earlier mail in
+/// their mailbox with the same subject, the way the inbox does as they arrive.
+fn join_subject_threads(conn: &Connection) -> Result<(), StoreError> {
+    conn.execute(
+        "UPDATE mail SET thread_id = coalesce((
+             SELECT coalesce(earlier.thread_id, earlier.message_id) FROM mail AS earlier
+             WHERE earlier.pk = mail.pk
+               AND earlier.normalized_subject = mail.normalized_subject
+               AND earlier.sk <= mail.sk
+               AND earlier.message_id <> mail.message_id
+               AND coalesce(earlier.thread_id, '') NOT LIKE ?1 || '%'
+             ORDER BY earlier.sk DESC LIMIT 1
+         ), thread_id)
+         WHERE thread_id LIKE ?1 || '%'",
+        params![SUBJECT_THREAD],
+    )
+    .map_err(backend)?;
+    Ok(())
+}
+
+const MAIL_COLUMNS: &str = concat!(
+    "pk, sk, message_id, subject, sender, first_sentence, ",
+    "coalesce(thread_id, message_id), seen, flagged, archived, deleted, ",
+    "(SELECT json_group_array(label_id) FROM mail_label AS l ",
+    "WHERE l.pk = mail.pk AND l.message_id = mail.message_id)"
+);
+
+/// The label ids of a row of `mail` as a JSON array.
+const LABELS_COLUMN: &str = "(SELECT json_group_array(label_id) FROM mail_label AS l \
+    WHERE l.pk = mail.pk AND l.message_id = mail.message_id)";
+
+fn mail(row: &Row) -> rusqlite::Result<Mail> {
+    let from: String = row.get(4)?;
+    Ok(Mail {
+        pk: row.get(0)?,
+        sk: row.get(1)?,
+        message_id: row.get(2)?,
+        subject: row.get(3)?,
+        from: serde_json::from_str(&from).unwrap_or_default(),
+        first_sentence: row.get(5)?,
+        thread_id: row.get(6)?,
+        flags: flags(row, 7)?,
+        labels: serde_json::from_str(&row.get::<_, String>(11)?).unwrap_or_default(),
+    })
+}
+
+fn account(row: &Row) -> rusqlite::Result<Account> {
+    let mailboxes: String = row.get(2)?;
+    Ok(Account {
+        username: row.get(0)?,
+        password_hash: row.get(1)?,
+        mailboxes: serde_json::from_str(&mailboxes).unwrap_or_default(),
+        admin: row.get(3)?,
+    })
+}
+
+fn api_key(row: &Row) -> rusqlite::Result<ApiKey> {
+    let scopes: String = row.get(4)?;
+    Ok(ApiKey {
+        key_id: row.get(0)?,
+        username: row.get(1)?,
+        name: row.get(2)?,
+        key_hash: row.get(3)?,
+        scopes: serde_json::from_str(&scopes).unwrap_or_default(),
+        created_at: row.get(5)?,
+        expires_at: row.get(6)?,
+    })
+}
+
+fn label(row: &Row) -> rusqlite::Result<Label> {
+    Ok(Label {
+        label_id: row.get(0)?,
+        name: row.get(1)?,
+        color: row.get(2)?,
+    })
+}
+
+/// The four flag columns starting at `first`.
+fn flags(row: &Row, first: usize) -> rusqlite::Result<Flags> {
+    Ok(Flags {
+        seen: row.get(first)?,
+        flagged: row.get(first + 1)?,
+        archived: row.get(first + 2)?,
+        deleted: row.get(first + 3)?,
+    })
+}
+
+/// Condition on the flag columns selecting the mails `filter` shows.
+fn filter_condition(filter: MailFilter) -> &'static str {
+    match filter {
+        MailFilter::All => "NOT archived AND NOT deleted",
+        MailFilter::Unread => "NOT seen AND NOT archived AND NOT deleted",
+        MailFilter::Flagged => "flagged AND NOT deleted",
+        MailFilter::Archived => "archived AND NOT deleted",
+        MailFilter::Deleted => "deleted",
+    }
+}
+
+/// Trims a page queried with `limit + 1` rows, the extra row only tells whether there's more.
+fn paged<T>(mut items: Vec<T>, limit: i32, cursor: impl Fn(&T) -> String) -> Paged<T> {
+    let has_more = items.len() > limit as usize;
+    items.truncate(limit as usize);
+    let next_cursor = if has_more {
+        items.last().map(cursor)
+    } else {
+        None
+    };
+    Paged { items, next_cursor }
+}
+
+fn backend(e: impl std::fmt::Display) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
@@ -1,9 +1,17 @@
 use leptos::prelude::*;
 
 #[component]
-pub fn Badge(children: Children) -> impl IntoView {
+pub fn Badge(
+    /// Background instead of white, a label's color.
+    #[prop(optional, into)]
+    color: Option<String>,
+    children: Children,
+) -> impl IntoView {
     view! {
-        <div class="inline-flex items-center justify-start py-0.5 px-2.5 text-xs font-semibold text-black bg-white rounded-full border transition-colors hover:bg-gray-300 focus:ring-2 focus:ring-offset-2 focus:outline-none focus:ring-ring">
+        <div
+            class="inline-flex items-center justify-start py-0.5 px-2.5 text-xs font-semibold text-black bg-white rounded-full border transition-colors hover:bg-gray-300 focus:ring-2 focus:ring-offset-2 focus:outline-none focus:ring-ring"
+            style:background-color=color.unwrap_or_default()
+        >
             {children()}
         </div>
     }
@@ -1,28 +1,214 @@
 use leptos::prelude::*;
-use crate::api_types::Mail;
+use crate::api_types::{ErrorResponse, Flags, FlagsUpdate, Folder, Label, Mail, MailFilter};
 use crate::ui::components::badge::Badge;
+use crate::ui::labels::set_label_fn;
 use chrono::{Duration, Utc};
+use leptos_router::hooks::query_signal;
+use supermailer_core::mail::mailbox_address;
 
+#[server(UpdateFlags, "/api_fn")]
+pub async fn update_flags_fn(
+    email: String,
+    folder: Folder,
+    message_id: String,
+    update: FlagsUpdate,
+) -> Result<Flags, ServerFnError<ErrorResponse>> {
+    use crate::api::update_flags;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(update_flags(state, &access, email, folder, message_id, update).await?)
+}
+
+/// A mail in a listing that shows what `filter` selects (under `label` when given), it's hidden
+/// once archived, deleted or unfiled out of it. `on_change` runs after its flags were changed.
+/// It's filed under and taken off the `labels` of its mailbox, none can be picked without them.
 #[component]
-pub fn Card(mail: Mail) -> impl IntoView {
+pub fn Card(
+    mail: Mail,
+    #[prop(optional)] filter: MailFilter,
+    #[prop(optional_no_strip)] label: Option<String>,
+    #[prop(optional)] labels: Option<Signal<Vec<Label>>>,
+    #[prop(optional)] on_change: Option<Callback<Flags>>,
+) -> impl IntoView {
+    let (_, set_showing) = query_signal::<String>("m");
+    let message_id = mail.message_id.clone();
+    let flags = RwSignal::new(mail.flags);
+    let update = Action::new({
+        let (email, folder) = (mailbox_address(&mail.pk).to_string(), Folder::of(&mail.pk));
+        let message_id = mail.message_id.clone();
+        move |update: &FlagsUpdate| {
+            update_flags_fn(email.clone(), folder, message_id.clone(), *update)
+        }
+    });
+    Effect::new(move |_| {
+        if let Some(Ok(updated)) = update.value().get() {
+            flags.set(updated);
+            if let Some(on_change) = on_change {
+                on_change.run(updated);
+            }
+        }
+    });
+    let filed = RwSignal::new(mail.labels.clone());
+    let set_label = Action::new({
+        let (email, folder) = (mailbox_address(&mail.pk).to_string(), Folder::of(&mail.pk));
+        let message_id = mail.message_id.clone();
+        move |(label_id, filed): &(String, bool)| {
+            set_label_fn(email.clone(), folder, message_id.clone(), label_id.clone(), *filed)
+        }
+    });
+    Effect::new(move |_| {
+        if let Some(Ok(updated)) = set_label.value().get() {
+            filed.set(updated.labels);
+        }
+    });
+    let labels = move || labels.map(|labels| labels.get()).unwrap_or_default();
+    // a mail read from the unread listing stays there until it's loaded again
+    let shown = move || {
+        filter.matches(&Flags {
+            seen: false,
+            ..flags.get()
+        }) && label.as_ref().is_none_or(|label| filed.with(|filed| filed.contains(label)))
+    };
+    let toggle = move |change: fn(bool) -> FlagsUpdate, current: fn(&Flags) -> bool| {
+        move |_| {
+            update.dispatch(change(!current(&flags.get_untracked())));
+        }
+    };
+
     view! {
-        <div class="flex flex-col gap-y-1.5 p-5 sm:p-6 rounded-lg border bg-zinc-950 border-zinc-800">
-            <h1 class="text-lg sm:text-2xl font-semibold line-clamp-2">{mail.from}</h1>
-            <p>{mail.subject}</p>
-            <p class="overflow-y-hidden text-sm sm:text-base text-zinc-400 h-[3lh] sm:h-[2lh] text-ellipsis line-clamp-3 sm:line-clamp-2">
-                {mail.first_sentence}
-            </p>
-            <hr class="my-2.5 w-full border-zinc-800 box-border" />
-            <div class="flex justify-between">
-                <Badge>badge</Badge>
-                <a href="/api/email/".to_string() + &mail.message_id target="_blank">
-                    Open Mail
-                </a>
-                <div class="text-zinc-400">
-                    <RelativeTime timestamp=mail.sk />
+        <Show when=shown>
+            <div class="flex flex-col gap-y-1.5 p-5 sm:p-6 rounded-lg border bg-zinc-950 border-zinc-800">
+                <div class="flex gap-x-2 justify-between items-start">
+                    <h1
+                        class="text-lg sm:text-2xl line-clamp-2"
+                        class=("font-semibold", move || !flags.get().seen)
+                    >
+                        {mail.from.clone()}
+                    </h1>
+                    <button
+                        class="text-xl text-zinc-400 hover:text-white"
+                        class=("text-yellow-400", move || flags.get().flagged)
+                        title="Flag"
+                        on:click=toggle(
+                            |flagged| FlagsUpdate { flagged: Some(flagged), ..Default::default() },
+                            |flags| flags.flagged,
+                        )
+                    >
+                        {move || if flags.get().flagged { "★" } else { "☆" }}
+                    </button>
+                </div>
+                <button
+                    class="text-left hover:underline"
+                    class=("font-semibold", move || !flags.get().seen)
+                    on:click={
+                        let message_id = message_id.clone();
+                        move |_| {
+                            set_showing.set(Some(message_id.clone()));
+                            if !flags.get_untracked().seen {
+                                update.dispatch(FlagsUpdate { seen: Some(true), ..Default::default() });
+                            }
+                        }
+                    }
+                >
+                    {mail.subject.clone()}
+                </button>
+                <p class="overflow-y-hidden text-sm sm:text-base text-zinc-400 h-[3lh] sm:h-[2lh] text-ellipsis line-clamp-3 sm:line-clamp-2">
+                    {mail.first_sentence.clone()}
+                </p>
+                <div class="flex flex-wrap gap-1.5 items-center">
+                    {move || {
+                        // ids of labels deleted meanwhile are left out
+                        labels()
+                            .into_iter()
+                            .filter(|label| filed.with(|filed| filed.contains(&label.label_id)))
+                            .map(|label| {
+                                let label_id = label.label_id.clone();
+                                view! {
+                                    <Badge color=label.color>
+                                        {label.name}
+                                        <button
+                                            class="ml-1"
+                                            title="Remove label"
+                                            on:click=move |_| {
+                                                set_label.dispatch((label_id.clone(), false));
+                                            }
+                                        >
+                                            "×"
+                                        </button>
+                                    </Badge>
+                                }
+                            })
+                            .collect_view()
+                    }}
+                    {move || {
+                        let unfiled: Vec<Label> = labels()
+                            .into_iter()
+                            .filter(|label| filed.with(|filed| !filed.contains(&label.label_id)))
+                            .collect();
+                        (!unfiled.is_empty())
+                            .then(|| {
+                                view! {
+                                    <select
+                                        class="py-0.5 px-1.5 text-xs rounded-md border border-zinc-800 bg-zinc-950 text-zinc-400"
+                                        prop:value=""
+                                        on:change=move |ev| {
+                                            let label_id = event_target_value(&ev);
+                                            if !label_id.is_empty() {
+                                                set_label.dispatch((label_id, true));
+                                            }
+                                        }
+                                    >
+                                        <option value="">"+ Label"</option>
+                                        {unfiled
+                                            .into_iter()
+                                            .map(|label| {
+                                                view! { <option value=label.label_id>{label.name}</option> }
+                                            })
+                                            .collect_view()}
+                                    </select>
+                                }
+                            })
+                    }}
+                </div>
+                <hr class="my-2.5 w-full border-zinc-800 box-border" />
+                <div class="flex gap-x-3 justify-between items-center text-sm">
+                    <a href="/ui/mail/".to_string() + &mail.message_id>
+                        Open Mail
+                    </a>
+                    <button
+                        class="text-zinc-400 hover:text-white"
+                        on:click=toggle(
+                            |seen| FlagsUpdate { seen: Some(seen), ..Default::default() },
+                            |flags| flags.seen,
+                        )
+                    >
+                        {move || if flags.get().seen { "Mark unread" } else { "Mark read" }}
+                    </button>
+                    <button
+                        class="text-zinc-400 hover:text-white"
+                        on:click=toggle(
+                            |archived| FlagsUpdate { archived: Some(archived), ..Default::default() },
+                            |flags| flags.archived,
+                        )
+                    >
+                        {move || if flags.get().archived { "Unarchive" } else { "Archive" }}
+                    </button>
+                    <button
+                        class="text-zinc-400 hover:text-white"
+                        on:click=toggle(
+                            |deleted| FlagsUpdate { deleted: Some(deleted), ..Default::default() },
+                            |flags| flags.deleted,
+                        )
+                    >
+                        {move || if flags.get().deleted { "Restore" } else { "Delete" }}
+                    </button>
+                    <div class="ml-auto text-zinc-400">
+                        <RelativeTime timestamp=mail.sk />
+                    </div>
                 </div>
             </div>
-        </div>
+        </Show>
     }
 }
 
@@ -3,7 +3,12 @@ use leptos::prelude::*;
 #[component]
 pub fn Input(
     #[prop(default = false)]
-    loading: bool
+    loading: bool,
+    #[prop(into, default = "".to_string())]
+    placeholder: String,
+    /// Kept in sync with what's typed, when given.
+    #[prop(optional)]
+    value: Option<RwSignal<String>>,
 ) -> impl IntoView {
     if loading {
         view! {
@@ -15,7 +20,13 @@ pub fn Input(
         view! {
             <input
                 class="flex py-2 px-3 w-full h-10 text-sm rounded-md border focus-visible:ring-2 focus-visible:ring-offset-2 focus-visible:outline-none disabled:opacity-50 disabled:cursor-not-allowed border-zinc-800 bg-zinc-950 ring-offset-zinc-950 file:border-0 file:bg-transparent file:text-sm file:font-medium file:text-white placeholder:text-muted-white focus-visible:ring-ring"
-                placeholder="placeholder"
+                placeholder=placeholder
+                prop:value=move || value.map(|value| value.get()).unwrap_or_default()
+                on:input=move |ev| {
+                    if let Some(value) = value {
+                        value.set(event_target_value(&ev));
+                    }
+                }
             />
         }.into_any()
     }
@@ -1,8 +1,7 @@
 use leptos::prelude::*;
 
 #[component]
-pub fn Switch() -> impl IntoView {
-    let (checked, set_checked) = signal(false);
+pub fn Switch(#[prop(optional)] checked: RwSignal<bool>) -> impl IntoView {
     let state = Memo::new(move |_| {
         if checked.get() {
             "checked"
@@ -15,7 +14,7 @@ pub fn Switch() -> impl IntoView {
         <button
             data-state=state
             class="inline-flex items-center w-11 h-6 rounded-full border-2 border-transparent transition-colors cursor-pointer focus-visible:ring-2 focus-visible:ring-offset-2 focus-visible:outline-none disabled:opacity-50 disabled:cursor-not-allowed peer shrink-0 data-[state=checked]:bg-white data-[state=unchecked]:bg-zinc-800 focus-visible:ring-ring focus-visible:ring-offset-background"
-            on:click=move |_| set_checked.set(!checked.get())
+            on:click=move |_| checked.update(|checked| *checked = !*checked)
         >
             <span
                 data-state=state
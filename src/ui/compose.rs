@@ -0,0 +1,190 @@
+use leptos::prelude::*;
+use leptos_router::hooks::{query_signal, use_navigate};
+
+use crate::api_types::{ErrorResponse, ReplyMode, SendRequest, SendResponse};
+use supermailer_core::mail::mailbox_address;
+
+#[server(SendEmail, "/api_fn")]
+pub async fn send_email_fn(request: SendRequest) -> Result<SendResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::send_email;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(send_email(state, &access, request).await?)
+}
+
+#[server(GetDraft, "/api_fn")]
+pub async fn get_draft_fn(
+    message_id: String,
+    mailbox: String,
+    mode: ReplyMode,
+) -> Result<SendRequest, ServerFnError<ErrorResponse>> {
+    use crate::api::{get_draft, DraftQuery};
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(get_draft(message_id, state, &access, DraftQuery { mode, mailbox }).await?)
+}
+
+/// Link to the compose view answering mail `message_id`, opened from the partition `mailbox`.
+pub fn draft_url(message_id: &str, mailbox: &str, mode: ReplyMode) -> String {
+    format!(
+        "/ui/compose?reply={}&mailbox={}&mode={mode}",
+        urlencoding::encode(message_id),
+        urlencoding::encode(mailbox)
+    )
+}
+
+/// Splits a comma separated list of addresses as typed in the To/Cc/Bcc fields.
+fn split_addresses(value: &str) -> Vec<String> {
+    value
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// New mail from the mailbox in `?e=`, at `/ui/compose`. With `?reply=` it starts from a draft
+/// answering that mail instead, see [`draft_url`].
+#[component]
+pub fn ComposePage() -> impl IntoView {
+    let (email, _set_email) = query_signal::<String>("e");
+    let (reply, _set_reply) = query_signal::<String>("reply");
+    let (mailbox, _set_mailbox) = query_signal::<String>("mailbox");
+    let (mode, _set_mode) = query_signal::<ReplyMode>("mode");
+    let sender = move || {
+        email
+            .get()
+            .or_else(|| mailbox.get().map(|mailbox| mailbox_address(&mailbox).to_string()))
+            .unwrap_or("web@alvinjanuar.com".to_string())
+    };
+
+    let to = RwSignal::new(String::new());
+    let cc = RwSignal::new(String::new());
+    let bcc = RwSignal::new(String::new());
+    let subject = RwSignal::new(String::new());
+    let text = RwSignal::new(String::new());
+    // threading headers and forwarded attachments of the draft, not editable
+    let draft = RwSignal::new(SendRequest::default());
+    let from = move || {
+        draft.with(|draft| (!draft.from.is_empty()).then(|| draft.from.clone())).unwrap_or_else(sender)
+    };
+
+    let loaded = Resource::new(
+        move || (reply.get(), mailbox.get(), mode.get()),
+        move |(reply, mailbox, mode)| async move {
+            match reply {
+                Some(message_id) => {
+                    let mailbox = mailbox.unwrap_or("web@alvinjanuar.com".to_string());
+                    Some(get_draft_fn(message_id, mailbox, mode.unwrap_or_default()).await)
+                }
+                None => None,
+            }
+        },
+    );
+    Effect::new(move |_| {
+        if let Some(Some(Ok(loaded))) = loaded.get() {
+            to.set(loaded.to.join(", "));
+            cc.set(loaded.cc.join(", "));
+            subject.set(loaded.subject.clone());
+            text.set(loaded.text.clone());
+            draft.set(loaded);
+        }
+    });
+
+    let send = Action::new(move |request: &SendRequest| send_email_fn(request.clone()));
+    Effect::new(move |_| {
+        if let Some(Ok(_)) = send.value().get() {
+            let from = from();
+            use_navigate()(&format!("/ui?e={from}&f=sent"), Default::default());
+        }
+    });
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        let draft = draft.get_untracked();
+        send.dispatch(SendRequest {
+            from: from(),
+            to: split_addresses(&to.get_untracked()),
+            cc: split_addresses(&cc.get_untracked()),
+            bcc: split_addresses(&bcc.get_untracked()),
+            subject: subject.get_untracked(),
+            text: text.get_untracked(),
+            in_reply_to: draft.in_reply_to,
+            references: draft.references,
+            attachments_from: draft.attachments_from,
+        });
+    };
+
+    view! {
+        <div class="flex flex-col py-6 px-8 min-h-screen text-white bg-black">
+            <a href=move || format!("/ui?e={}", from()) class="mb-4 text-zinc-400 hover:text-white">
+                "← Back to inbox"
+            </a>
+            <form class="flex flex-col flex-grow gap-y-3 sm:w-[600px]" on:submit=on_submit>
+                <h1 class="text-2xl font-semibold">
+                    {move || match reply.get().map(|_| mode.get().unwrap_or_default()) {
+                        Some(ReplyMode::Forward) => "Forward",
+                        Some(ReplyMode::Reply | ReplyMode::ReplyAll) => "Reply",
+                        None => "New mail",
+                    }}
+                </h1>
+                <p class="text-sm text-zinc-400">"From " {from}</p>
+                {move || {
+                    loaded
+                        .get()
+                        .flatten()
+                        .and_then(Result::err)
+                        .map(|e| view! { <p class="text-sm text-red-400">{e.to_string()}</p> })
+                }}
+                <Field label="To" value=to />
+                <Field label="Cc" value=cc />
+                <Field label="Bcc" value=bcc />
+                <Field label="Subject" value=subject />
+                <textarea
+                    class="p-3 text-sm rounded-md border border-zinc-800 bg-zinc-950 min-h-[40vh]"
+                    prop:value=move || text.get()
+                    on:input=move |ev| text.set(event_target_value(&ev))
+                />
+                {move || {
+                    send.value()
+                        .get()
+                        .and_then(Result::err)
+                        .map(|e| view! { <p class="text-sm text-red-400">{e.to_string()}</p> })
+                }}
+                {move || {
+                    draft
+                        .with(|draft| draft.attachments_from.is_some())
+                        .then(|| {
+                            view! {
+                                <p class="text-sm text-zinc-400">
+                                    "The attachments of the original mail are sent along."
+                                </p>
+                            }
+                        })
+                }}
+                <button
+                    type="submit"
+                    class="self-end py-2 px-4 text-black bg-white rounded-md hover:bg-zinc-200 disabled:opacity-50"
+                    disabled=move || send.pending().get()
+                >
+                    {move || if send.pending().get() { "Sending..." } else { "Send" }}
+                </button>
+            </form>
+        </div>
+    }
+}
+
+#[component]
+fn Field(label: &'static str, value: RwSignal<String>) -> impl IntoView {
+    view! {
+        <label class="flex gap-x-2 items-center text-sm">
+            <span class="w-16 text-zinc-400 shrink-0">{label}</span>
+            <input
+                class="flex py-2 px-3 w-full h-10 text-sm rounded-md border border-zinc-800 bg-zinc-950"
+                prop:value=move || value.get()
+                on:input=move |ev| value.set(event_target_value(&ev))
+            />
+        </label>
+    }
+}
@@ -0,0 +1,223 @@
+use leptos::prelude::*;
+use leptos_router::hooks::query_signal;
+
+use crate::api_types::{
+    ErrorResponse, Folder, Label, LabelRequest, ListLabelsResponse, MailLabelsResponse,
+};
+use crate::ui::components::badge::Badge;
+
+#[server(ListLabels, "/api_fn")]
+pub async fn list_labels_fn(
+    email: String,
+) -> Result<ListLabelsResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::list_labels;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(list_labels(state, &access, email).await?)
+}
+
+#[server(CreateLabel, "/api_fn")]
+pub async fn create_label_fn(
+    email: String,
+    request: LabelRequest,
+) -> Result<Label, ServerFnError<ErrorResponse>> {
+    use crate::api::create_label;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(create_label(state, &access, email, request).await?)
+}
+
+#[server(UpdateLabel, "/api_fn")]
+pub async fn update_label_fn(
+    email: String,
+    label_id: String,
+    request: LabelRequest,
+) -> Result<Label, ServerFnError<ErrorResponse>> {
+    use crate::api::update_label;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(update_label(state, &access, email, label_id, request).await?)
+}
+
+#[server(DeleteLabel, "/api_fn")]
+pub async fn delete_label_fn(
+    email: String,
+    label_id: String,
+) -> Result<(), ServerFnError<ErrorResponse>> {
+    use crate::api::delete_label;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(delete_label(state, &access, email, label_id).await?)
+}
+
+#[server(SetLabel, "/api_fn")]
+pub async fn set_label_fn(
+    email: String,
+    folder: Folder,
+    message_id: String,
+    label_id: String,
+    filed: bool,
+) -> Result<MailLabelsResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::set_label;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(set_label(state, &access, email, folder, message_id, label_id, filed).await?)
+}
+
+/// Color a new label starts with.
+const DEFAULT_COLOR: &str = "#a1a1aa";
+
+/// The labels of `email`, picking one lists only the mail filed under it (query signal `l`).
+/// Also creates, renames and deletes them, `on_change` runs after any of that.
+#[component]
+pub fn LabelBar(
+    email: Signal<String>,
+    labels: Signal<Vec<Label>>,
+    on_change: Callback<()>,
+) -> impl IntoView {
+    let (selected, set_selected) = query_signal::<String>("l");
+    let selected_label = Memo::new(move |_| {
+        let selected = selected.get()?;
+        labels.with(|labels| labels.iter().find(|label| label.label_id == selected).cloned())
+    });
+
+    let name = RwSignal::new(String::new());
+    let color = RwSignal::new(DEFAULT_COLOR.to_string());
+    // the fields edit the picked label, or describe a new one when none is
+    Effect::new(move |_| match selected_label.get() {
+        Some(label) => {
+            name.set(label.name);
+            color.set(label.color);
+        }
+        None => {
+            name.set(String::new());
+            color.set(DEFAULT_COLOR.to_string());
+        }
+    });
+
+    let save = Action::new(move |(label_id, request): &(Option<String>, LabelRequest)| {
+        let (email, label_id, request) = (email.get_untracked(), label_id.clone(), request.clone());
+        async move {
+            match label_id {
+                Some(label_id) => update_label_fn(email, label_id, request).await,
+                None => create_label_fn(email, request).await,
+            }
+        }
+    });
+    let delete = Action::new(move |label_id: &String| {
+        delete_label_fn(email.get_untracked(), label_id.clone())
+    });
+    Effect::new(move |_| {
+        if let Some(Ok(label)) = save.value().get() {
+            set_selected.set(Some(label.label_id));
+            on_change.run(());
+        }
+    });
+    Effect::new(move |_| {
+        if let Some(Ok(())) = delete.value().get() {
+            set_selected.set(None);
+            on_change.run(());
+        }
+    });
+    let error = move || {
+        save.value()
+            .get()
+            .and_then(Result::err)
+            .or_else(|| delete.value().get().and_then(Result::err))
+            .map(|e| view! { <p class="text-sm text-red-400">{e.to_string()}</p> })
+    };
+
+    view! {
+        <div class="flex flex-col gap-y-2 text-sm">
+            <div class="flex flex-wrap gap-2 items-center">
+                <button
+                    class="py-1 px-2.5 rounded-md text-zinc-400 hover:text-white"
+                    class=("text-white", move || selected.get().is_none())
+                    on:click=move |_| set_selected.set(None)
+                >
+                    "Any label"
+                </button>
+                <For
+                    each=move || labels.get()
+                    key=|label| (label.label_id.clone(), label.name.clone(), label.color.clone())
+                    children=move |label| {
+                        let label_id = label.label_id.clone();
+                        let is_selected = {
+                            let label_id = label_id.clone();
+                            move || selected.get().as_ref() == Some(&label_id)
+                        };
+                        view! {
+                            <button
+                                class="rounded-full ring-white"
+                                class=("ring-2", is_selected)
+                                on:click=move |_| set_selected.set(Some(label_id.clone()))
+                            >
+                                <Badge color=label.color>{label.name}</Badge>
+                            </button>
+                        }
+                    }
+                />
+            </div>
+            <form
+                class="flex gap-x-2 items-center"
+                on:submit=move |ev| {
+                    ev.prevent_default();
+                    let label_id = selected_label.get_untracked().map(|label| label.label_id);
+                    save.dispatch((
+                        label_id,
+                        LabelRequest {
+                            name: Some(name.get_untracked()),
+                            color: Some(color.get_untracked()),
+                        },
+                    ));
+                }
+            >
+                <input
+                    class="flex py-1.5 px-3 w-full text-sm rounded-md border border-zinc-800 bg-zinc-950"
+                    placeholder="Label name"
+                    prop:value=move || name.get()
+                    on:input=move |ev| name.set(event_target_value(&ev))
+                />
+                <input
+                    type="color"
+                    class="w-10 h-8 rounded-md border border-zinc-800 bg-zinc-950 shrink-0"
+                    prop:value=move || color.get()
+                    on:input=move |ev| color.set(event_target_value(&ev))
+                />
+                <button
+                    type="submit"
+                    class="py-1.5 px-3 text-black bg-white rounded-md hover:bg-zinc-200 disabled:opacity-50 shrink-0"
+                    disabled=move || save.pending().get()
+                >
+                    {move || if selected_label.get().is_some() { "Save" } else { "New label" }}
+                </button>
+                {move || {
+                    selected_label
+                        .get()
+                        .map(|label| {
+                            view! {
+                                <button
+                                    type="button"
+                                    class="py-1.5 px-3 rounded-md border border-zinc-800 text-zinc-400 hover:text-white shrink-0"
+                                    disabled=move || delete.pending().get()
+                                    on:click=move |_| {
+                                        delete.dispatch(label.label_id.clone());
+                                    }
+                                >
+                                    "Delete"
+                                </button>
+                            }
+                        })
+                }}
+            </form>
+            {error}
+        </div>
+    }
+    // keeps the view type of MailPage under the recursion limit
+    .into_any()
+}
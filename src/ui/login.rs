@@ -0,0 +1,133 @@
+use leptos::prelude::*;
+use leptos_router::hooks::use_navigate;
+
+use crate::api_types::{AccountInfo, ErrorResponse, LoginRequest};
+
+#[server(Login, "/api_fn")]
+pub async fn login_fn(request: LoginRequest) -> Result<AccountInfo, ServerFnError<ErrorResponse>> {
+    use crate::auth::{login, session_cookie};
+    use crate::error::ApiError;
+    use crate::state::AppState;
+    use axum::http::{header, HeaderValue};
+    use leptos_axum::ResponseOptions;
+    let state = use_context::<AppState>().ok_or(ApiError::MissingState)?;
+    let response = use_context::<ResponseOptions>().ok_or(ApiError::MissingState)?;
+
+    let (account, token) = login(state, request).await?;
+    if let Ok(cookie) = HeaderValue::from_str(&session_cookie(&token)) {
+        response.insert_header(header::SET_COOKIE, cookie);
+    }
+    Ok(account)
+}
+
+#[server(Logout, "/api_fn")]
+pub async fn logout_fn() -> Result<(), ServerFnError<ErrorResponse>> {
+    use crate::auth::{expired_cookie, logout};
+    use crate::error::ApiError;
+    use crate::state::AppState;
+    use axum::http::{header, HeaderMap, HeaderValue};
+    use leptos_axum::ResponseOptions;
+    let state = use_context::<AppState>().ok_or(ApiError::MissingState)?;
+    let response = use_context::<ResponseOptions>().ok_or(ApiError::MissingState)?;
+    let headers: HeaderMap = leptos_axum::extract()
+        .await
+        .map_err(|_| ApiError::MissingState)?;
+
+    logout(state, &headers).await?;
+    if let Ok(cookie) = HeaderValue::from_str(&expired_cookie()) {
+        response.insert_header(header::SET_COOKIE, cookie);
+    }
+    Ok(())
+}
+
+#[server(SignOnEnabled, "/api_fn")]
+pub async fn sign_on_enabled_fn() -> Result<bool, ServerFnError<ErrorResponse>> {
+    use crate::error::ApiError;
+    use crate::state::AppState;
+    let state = use_context::<AppState>().ok_or(ApiError::MissingState)?;
+
+    Ok(state.oidc.is_some())
+}
+
+/// Login form at `/login`, opens the first mailbox of the account afterwards. Offers single
+/// sign-on when it's configured.
+#[component]
+pub fn LoginPage() -> impl IntoView {
+    let username = RwSignal::new(String::new());
+    let password = RwSignal::new(String::new());
+
+    let sign_on = Resource::new(|| (), |_| async move { sign_on_enabled_fn().await });
+    let login = Action::new(move |request: &LoginRequest| login_fn(request.clone()));
+    Effect::new(move |_| {
+        if let Some(Ok(account)) = login.value().get() {
+            let target = match account.mailboxes.first() {
+                Some(mailbox) => format!("/ui?e={}", urlencoding::encode(mailbox)),
+                None => "/ui".to_string(),
+            };
+            use_navigate()(&target, Default::default());
+        }
+    });
+    let on_submit = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
+        login.dispatch(LoginRequest {
+            username: username.get_untracked(),
+            password: password.get_untracked(),
+        });
+    };
+
+    view! {
+        <div class="flex flex-col justify-center items-center min-h-screen text-white bg-black">
+            <form class="flex flex-col gap-y-3 w-full sm:w-[360px] px-8" on:submit=on_submit>
+                <h1 class="text-2xl font-semibold">"Log in"</h1>
+                <input
+                    class="flex py-2 px-3 w-full h-10 text-sm rounded-md border border-zinc-800 bg-zinc-950"
+                    placeholder="Username"
+                    autocomplete="username"
+                    prop:value=move || username.get()
+                    on:input=move |ev| username.set(event_target_value(&ev))
+                />
+                <input
+                    class="flex py-2 px-3 w-full h-10 text-sm rounded-md border border-zinc-800 bg-zinc-950"
+                    type="password"
+                    placeholder="Password"
+                    autocomplete="current-password"
+                    prop:value=move || password.get()
+                    on:input=move |ev| password.set(event_target_value(&ev))
+                />
+                {move || {
+                    login.value()
+                        .get()
+                        .and_then(Result::err)
+                        .map(|e| view! { <p class="text-sm text-red-400">{e.to_string()}</p> })
+                }}
+                <button
+                    type="submit"
+                    class="py-2 px-4 text-black bg-white rounded-md hover:bg-zinc-200 disabled:opacity-50"
+                    disabled=move || login.pending().get()
+                >
+                    {move || if login.pending().get() { "Logging in..." } else { "Log in" }}
+                </button>
+                <Suspense>
+                    {move || {
+                        sign_on
+                            .get()
+                            .and_then(Result::ok)
+                            .filter(|enabled| *enabled)
+                            .map(|_| {
+                                view! {
+                                    // a full page load, the provider's login page isn't ours
+                                    <a
+                                        rel="external"
+                                        href="/api/oidc/login"
+                                        class="py-2 px-4 text-center rounded-md border border-zinc-800 hover:bg-zinc-900"
+                                    >
+                                        "Log in with single sign-on"
+                                    </a>
+                                }
+                            })
+                    }}
+                </Suspense>
+            </form>
+        </div>
+    }
+}
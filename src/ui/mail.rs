@@ -1,48 +1,59 @@
 use leptos::logging;
 use leptos::prelude::*;
-use leptos_router::hooks::query_signal;
+use leptos_router::hooks::{query_signal, use_navigate};
 
-use crate::api_types::{ListEmailsResponse, ListUsersResponse};
+use crate::api_types::{
+    ErrorResponse, Folder, ListEmailsResponse, ListUsersResponse, Mail, MailFilter, UnreadResponse,
+};
 use crate::ui::components::badge::Badge;
 use crate::ui::components::input::Input;
 use crate::ui::components::switch::Switch;
 use crate::ui::components::card::{Card, CardLoading};
+use crate::ui::labels::{list_labels_fn, LabelBar};
+use crate::ui::login::logout_fn;
+use crate::ui::message::MessageView;
+use crate::ui::search::SearchResults;
+use crate::ui::thread::ThreadList;
 
 #[server(ListEmails, "/api_fn")]
-pub async fn list_emails_fn(email: String) -> Result<ListEmailsResponse, ServerFnError> {
-    use crate::api::list_emails;
-    use crate::state::AppState;
-    let state = use_context::<AppState>();
-
-    match state {
-        Some(state) => Ok(list_emails(state, email).await),
-        None => Err(ServerFnError::ServerError("error_state".to_string())),
-    }
+pub async fn list_emails_fn(
+    email: String,
+    folder: Folder,
+    filter: MailFilter,
+    label: Option<String>,
+    cursor: Option<String>,
+    limit: Option<i32>,
+) -> Result<ListEmailsResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::{list_emails, ListQuery};
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(list_emails(state, &access, email, folder, filter, label, ListQuery { cursor, limit }).await?)
 }
 
-#[server(ListUsers, "/api_fn")]
-pub async fn list_users_fn() -> Result<ListUsersResponse, ServerFnError> {
-    use crate::api::list_users;
-    use crate::state::AppState;
-    let state = use_context::<AppState>();
-
-    match state {
-        Some(state) => Ok(list_users(state).await),
-        None => Err(ServerFnError::ServerError("error_state".to_string())),
-    }
+#[server(CountUnread, "/api_fn")]
+pub async fn count_unread_fn(
+    email: String,
+    folder: Folder,
+) -> Result<UnreadResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::count_unread;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(count_unread(state, &access, email, folder).await?)
 }
 
-// #[server(GetEmailHtml, "/api_fn")]
-// pub async fn get_email_html_fn(key_id: String) -> Result<String, ServerFnError> {
-//     use crate::api::get_email_html;
-//     use crate::state::AppState;
-//     let state = use_context::<AppState>();
-//
-//     match state {
-//         Some(state) => Ok(get_email_html(key_id, state).await),
-//         None => Err(ServerFnError::ServerError("error_state".to_string())),
-//     }
-// }
+#[server(ListUsers, "/api_fn")]
+pub async fn list_users_fn(
+    cursor: Option<String>,
+    limit: Option<i32>,
+) -> Result<ListUsersResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::{list_users, ListQuery};
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(list_users(state, &access, ListQuery { cursor, limit }).await?)
+}
 
 /// Renders the home page of your application.
 #[component]
@@ -50,26 +61,120 @@ pub fn MailPage() -> impl IntoView {
     // Creates a reactive value to update the button
     let (count, _set_count) = signal(50.00);
     let (email, set_email) = query_signal::<String>("e");
-    // let (current_showing, _set_current_showing) = signal("".to_string());
+    let (showing, _set_showing) = query_signal::<String>("m");
+    let (folder, set_folder) = query_signal::<Folder>("f");
+    let folder = Signal::derive(move || folder.get().unwrap_or_default());
+    let (filter, set_filter) = query_signal::<MailFilter>("s");
+    let filter = Signal::derive(move || filter.get().unwrap_or_default());
+    let (label, _set_label) = query_signal::<String>("l");
+    let address = Signal::derive(move || email.get().unwrap_or("web@alvinjanuar.com".to_string()));
+    // conversations are collapsed unless switched off
+    let threaded = RwSignal::new(true);
+    // the search bar is only applied on submit, an empty search shows the mailbox again
+    let (search, set_search) = query_signal::<String>("q");
+    let search = Signal::derive(move || search.get().unwrap_or_default());
+    let typed = RwSignal::new(search.get_untracked());
+    let mailbox = Signal::derive(move || {
+        folder.get().mailbox(&email.get().unwrap_or("web@alvinjanuar.com".to_string()))
+    });
 
     let users = Resource::new(
         move || count.get(),
-        move |_value| async move { list_users_fn().await },
+        move |_value| async move { list_users_fn(None, Some(100)).await },
     );
 
     let mails = Resource::new(
-        move || email.get(),
-        move |value| async move {
-            list_emails_fn(value.unwrap_or("web@alvinjanuar.com".to_string())).await
+        move || (email.get(), folder.get(), filter.get(), label.get()),
+        move |(value, folder, filter, label)| async move {
+            list_emails_fn(
+                value.unwrap_or("web@alvinjanuar.com".to_string()),
+                folder,
+                filter,
+                label,
+                None,
+                None,
+            )
+            .await
             // TODO:
             // Change hardcoded value to first user
         },
     );
 
-    // let showing = Resource::new(
-    //     move || current_showing.get(),
-    //     move |value| async move { get_email_html_fn(value.to_string()).await },
-    // );
+    // counted again whenever a card changed flags
+    let flags_changed = RwSignal::new(0);
+    let unread = Resource::new(
+        move || (address.get(), folder.get(), flags_changed.get()),
+        move |(address, folder, _)| async move { count_unread_fn(address, folder).await },
+    );
+    let on_change = Callback::new(move |_| flags_changed.update(|changed| *changed += 1));
+
+    // fetched again whenever the label bar changed one
+    let labels_changed = RwSignal::new(0);
+    let label_list = Resource::new(
+        move || (address.get(), labels_changed.get()),
+        move |(address, _)| async move { list_labels_fn(address).await },
+    );
+    let labels = Signal::derive(move || {
+        label_list
+            .get()
+            .and_then(Result::ok)
+            .map(|labels| labels.data)
+            .unwrap_or_default()
+    });
+    let on_labels_change = Callback::new(move |_| labels_changed.update(|changed| *changed += 1));
+
+    let logout = Action::new(move |_: &()| logout_fn());
+    Effect::new(move |_| {
+        if let Some(Ok(())) = logout.value().get() {
+            use_navigate()("/login", Default::default());
+        }
+    });
+
+    // Pages after the first one are fetched by "Load more" and appended below `mails`.
+    // `more_cursor` is None until the first of them arrives, then it takes over from `mails`.
+    let more_mails = RwSignal::new(Vec::<Mail>::new());
+    let more_cursor = RwSignal::new(None::<Option<String>>);
+    let load_more = Action::new(
+        move |(email, folder, filter, label, cursor): &(
+            String,
+            Folder,
+            MailFilter,
+            Option<String>,
+            String,
+        )| {
+            let (email, folder, filter, label, cursor) =
+                (email.clone(), *folder, *filter, label.clone(), cursor.clone());
+            async move {
+                let page =
+                    list_emails_fn(email.clone(), folder, filter, label.clone(), Some(cursor), None)
+                        .await;
+                (email, folder, filter, label, page)
+            }
+        },
+    );
+    Effect::new(move |_| {
+        if let Some((from, from_folder, from_filter, from_label, Ok(page))) =
+            load_more.value().get()
+        {
+            // drop pages of a mailbox we've already switched away from
+            if from == address.get_untracked()
+                && from_folder == folder.get_untracked()
+                && from_filter == filter.get_untracked()
+                && from_label == label.get_untracked()
+            {
+                more_mails.update(|mails| mails.extend(page.data));
+                more_cursor.set(Some(page.next_cursor));
+            }
+        }
+    });
+    Effect::new(move |_| {
+        email.track();
+        folder.track();
+        filter.track();
+        label.track();
+        more_mails.set(Vec::new());
+        more_cursor.set(None);
+    });
 
     view! {
         <div class="bg-black">
@@ -77,7 +182,16 @@ pub fn MailPage() -> impl IntoView {
             <div class="flex items-center text-white">
                 <div class="flex flex-col flex-grow py-3 sm:mx-5 h-screen border-white w-full sm:w-[600px] border-x">
                     <div class="flex flex-col gap-y-3 px-3">
-                        // <Input />
+                        <form on:submit=move |ev| {
+                            ev.prevent_default();
+                            let q = typed.get_untracked();
+                            set_search.set(Some(q).filter(|q| !q.trim().is_empty()));
+                        }>
+                            <Input
+                                placeholder="Search, e.g. from:alice has:attachment after:2024/01/01"
+                                value=typed
+                            />
+                        </form>
                         // <input
                         // type="range"
                         // max="100"
@@ -134,18 +248,73 @@ pub fn MailPage() -> impl IntoView {
                             }}
                         </Suspense>
                     // </select>
+                    <div class="flex gap-x-2 text-sm">
+                        {[(Folder::Inbox, "Inbox"), (Folder::Sent, "Sent")]
+                            .map(|(value, label)| {
+                                view! {
+                                    <button
+                                        class="py-1.5 px-3 rounded-md border border-zinc-800 hover:bg-zinc-900"
+                                        class=("bg-zinc-800", move || folder.get() == value)
+                                        on:click=move |_| set_folder.set(Some(value))
+                                    >
+                                        {label}
+                                    </button>
+                                }
+                            })}
+                        <a
+                            class="py-1.5 px-3 ml-auto text-black bg-white rounded-md hover:bg-zinc-200"
+                            href=move || {
+                                format!(
+                                    "/ui/compose?e={}",
+                                    email.get().unwrap_or("web@alvinjanuar.com".to_string()),
+                                )
+                            }
+                        >
+                            "Compose"
+                        </a>
+                        <button
+                            class="py-1.5 px-3 rounded-md border border-zinc-800 hover:bg-zinc-900"
+                            on:click=move |_| {
+                                logout.dispatch(());
+                            }
+                        >
+                            "Log out"
+                        </button>
+                    </div>
+                    <div class="flex gap-x-2 text-sm">
+                        {[
+                            (MailFilter::All, "All"),
+                            (MailFilter::Unread, "Unread"),
+                            (MailFilter::Flagged, "Flagged"),
+                            (MailFilter::Archived, "Archived"),
+                            (MailFilter::Deleted, "Trash"),
+                        ]
+                            .map(|(value, label)| {
+                                view! {
+                                    <button
+                                        class="py-1 px-2.5 rounded-md text-zinc-400 hover:text-white"
+                                        class=("text-white", move || filter.get() == value)
+                                        on:click=move |_| set_filter.set(Some(value))
+                                    >
+                                        {label}
+                                    </button>
+                                }
+                            })}
+                    </div>
+                    <LabelBar email=address labels on_change=on_labels_change />
                     </div>
                     <div class="bg-transparent relative min-h-8 flex items-center z-10 backdrop-blur-sm">
                         <div class="flex absolute left-4 sm:-left-4">
                             <Suspense fallback=move || {
                                 view! { <Badge>...</Badge> }
                             }>
-                                {move || match mails.get() {
+                                {move || match unread.get() {
                                     None => view! { <Badge>...</Badge> }.into_any(),
                                     Some(data) => {
                                         match data {
                                             Ok(api) => {
-                                                view! { <Badge>{api.data.len()}</Badge> }.into_any()
+                                                view! { <Badge>{api.unread} " unread"</Badge> }
+                                                    .into_any()
                                             }
                                             Err(e) => view! { <p>{e.to_string()}</p> }.into_any(),
                                         }
@@ -154,10 +323,23 @@ pub fn MailPage() -> impl IntoView {
                             </Suspense>
                         </div>
                         <div class="flex absolute right-4 sm:right-0 sm:translate-x-1/2">
-                            <Switch />
+                            <label class="flex gap-x-2 items-center text-sm text-zinc-400">
+                                "Threads" <Switch checked=threaded />
+                            </label>
                         </div>
                         <hr class="w-full border-zinc-800 box-border pt-1" />
                     </div>
+                    <Show
+                        when=move || search.get().is_empty()
+                        fallback=move || view! { <SearchResults email=address q=search /> }
+                    >
+                    // conversations aren't filtered, a filter or label always lists single mails
+                    <Show
+                        when=move || {
+                            !threaded.get() || filter.get() != MailFilter::All || label.get().is_some()
+                        }
+                        fallback=move || view! { <ThreadList email=address folder /> }
+                    >
                     <Transition fallback=move || {
                         view! { <div class="px-3 py-4 -mt-4 z-0"><CardLoading /></div> }
                     }>
@@ -166,18 +348,61 @@ pub fn MailPage() -> impl IntoView {
                             Some(data) => {
                                 match data {
                                     Ok(api) => {
+                                        let first_cursor = api.next_cursor.clone();
+                                        let next_cursor = move || {
+                                            more_cursor.get().unwrap_or_else(|| first_cursor.clone())
+                                        };
                                         view! {
                                             <div class="flex overflow-y-auto flex-col gap-y-3 px-3 py-4 -mt-4 z-0">
                                                 <For
                                                     // a function that returns the items we're iterating over; a signal is fine
-                                                    each=move || api.data.clone()
+                                                    each=move || {
+                                                        api.data.clone().into_iter().chain(more_mails.get())
+                                                    }
                                                     // a unique key for each item
-                                                    key=|mail| mail.sk
+                                                    key=|mail| mail.message_id.clone()
                                                     // renders each item to a view
                                                     children=move |mail| {
-                                                        view! { <Card mail=mail /> }
+                                                        view! {
+                                                            <Card
+                                                                mail
+                                                                filter=filter.get_untracked()
+                                                                label=label.get_untracked()
+                                                                labels
+                                                                on_change
+                                                            />
+                                                        }
                                                     }
                                                 />
+                                                {move || {
+                                                    next_cursor()
+                                                        .map(|cursor| {
+                                                            view! {
+                                                                <button
+                                                                    class="p-3 rounded-md border border-zinc-800 bg-zinc-950 hover:bg-zinc-900 disabled:opacity-50"
+                                                                    disabled=move || load_more.pending().get()
+                                                                    on:click=move |_| {
+                                                                        load_more
+                                                                            .dispatch((
+                                                                                address.get_untracked(),
+                                                                                folder.get_untracked(),
+                                                                                filter.get_untracked(),
+                                                                                label.get_untracked(),
+                                                                                cursor.clone(),
+                                                                            ));
+                                                                    }
+                                                                >
+                                                                    {move || {
+                                                                        if load_more.pending().get() {
+                                                                            "Loading..."
+                                                                        } else {
+                                                                            "Load more"
+                                                                        }
+                                                                    }}
+                                                                </button>
+                                                            }
+                                                        })
+                                                }}
                                             </div>
                                         }
                                             .into_any()
@@ -187,20 +412,17 @@ pub fn MailPage() -> impl IntoView {
                             }
                         }}
                     </Transition>
+                    </Show>
+                    </Show>
                 </div>
-                <div class="hidden flex-col flex-grow py-6 px-8 h-screen sm:flex">
-                    <h1 class="text-2xl font-semibold">Teset Smith</h1>
-                    <p>[UPDATED] Need help ASAP</p>
-                    <div class="flex justify-between">
-                        <Badge>badge</Badge>
-                        <div class="text-zinc-400">01:16 am</div>
-                    </div>
-                    <hr class="my-2.5 w-full border-zinc-800 box-border" />
-                // <p class="overflow-y-hidden text-base">
-                // Deploy your new project in one-click.Deploy your new project in one-click.Deploy your new project in one-click.Deploy your new project in one-click.Deploy your new project in one-click.Deploy your new project in one-click.
-                // </p>
-                //
-                // <p class="overflow-y-hidden text-base">{move || showing.get()}</p>
+                <div class="hidden overflow-y-auto flex-col flex-grow py-6 px-8 h-screen sm:flex">
+                    {move || match showing.get() {
+                        Some(message_id) => view! { <MessageView message_id mailbox /> }.into_any(),
+                        None => {
+                            view! { <p class="text-zinc-400">"Select a mail to read it here."</p> }
+                                .into_any()
+                        }
+                    }}
                 </div>
             </div>
         </div>
@@ -0,0 +1,209 @@
+use chrono::DateTime;
+use leptos::prelude::*;
+use leptos_router::hooks::{query_signal, use_params_map};
+
+use crate::api_types::{Attachment, BodyFormat, ErrorResponse, Folder, MailDetail, ReplyMode};
+use crate::ui::components::switch::Switch;
+use crate::ui::compose::draft_url;
+
+#[server(GetEmail, "/api_fn")]
+pub async fn get_email_fn(
+    message_id: String,
+    load_images: bool,
+) -> Result<MailDetail, ServerFnError<ErrorResponse>> {
+    use crate::api::{get_email, EmailQuery};
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(get_email(message_id, state, &access, EmailQuery { load_images }).await?)
+}
+
+/// Renders a single mail on its own page at `/ui/mail/:message_id`.
+#[component]
+pub fn MessagePage() -> impl IntoView {
+    let params = use_params_map();
+    let message_id =
+        Signal::derive(move || params.with(|params| params.get("message_id").unwrap_or_default()));
+    let (email, _set_email) = query_signal::<String>("e");
+    let (folder, _set_folder) = query_signal::<Folder>("f");
+    let mailbox = Signal::derive(move || {
+        let email = email.get().unwrap_or("web@alvinjanuar.com".to_string());
+        folder.get().unwrap_or_default().mailbox(&email)
+    });
+
+    view! {
+        <div class="flex flex-col py-6 px-8 min-h-screen text-white bg-black">
+            <a href="/ui" class="mb-4 text-zinc-400 hover:text-white">
+                "← Back to inbox"
+            </a>
+            <MessageView message_id mailbox />
+        </div>
+    }
+}
+
+/// `mailbox` is the partition (`Mail.pk`) the mail is listed in, answers are sent from it.
+#[component]
+pub fn MessageView(
+    #[prop(into)] message_id: Signal<String>,
+    #[prop(into)] mailbox: Signal<String>,
+) -> impl IntoView {
+    // remote images are opt-in for every message
+    let load_images = RwSignal::new(false);
+    Effect::new(move |_| {
+        message_id.track();
+        load_images.set(false);
+    });
+    let mail = Resource::new(
+        move || (message_id.get(), load_images.get()),
+        move |(message_id, load_images)| async move { get_email_fn(message_id, load_images).await },
+    );
+
+    view! {
+        <Transition fallback=move || {
+            view! { <div class="rounded-lg animate-pulse bg-zinc-800 min-h-40" /> }
+        }>
+            {move || match mail.get() {
+                None => view! { <div class="rounded-lg animate-pulse bg-zinc-800 min-h-40" /> }.into_any(),
+                Some(Ok(mail)) => view! { <MessageDetail mail mailbox load_images /> }.into_any(),
+                Some(Err(e)) => view! { <p>{e.to_string()}</p> }.into_any(),
+            }}
+        </Transition>
+    }
+}
+
+#[component]
+fn MessageDetail(
+    mail: MailDetail,
+    mailbox: Signal<String>,
+    load_images: RwSignal<bool>,
+) -> impl IntoView {
+    let date = mail
+        .date
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        .map(|date| date.format("%a, %d %b %Y %H:%M UTC").to_string());
+    // both representations are sent along, the plain text one is a click away
+    let show_text = RwSignal::new(false);
+    let has_images = mail.format == Some(BodyFormat::Html);
+    let has_text = mail.html.is_some() && mail.text.is_some();
+    let (html, text) = (mail.html, mail.text);
+    let message_id = mail.message_id.clone();
+    let answer = move |mode: ReplyMode| {
+        let message_id = message_id.clone();
+        move || draft_url(&message_id, &mailbox.get(), mode)
+    };
+    let body = move || match (show_text.get(), html.clone(), text.clone()) {
+        (false, Some(html), _) => {
+            view! {
+                // no allow-scripts or allow-same-origin, whatever the sender put in there
+                // stays away from our origin
+                <iframe
+                    class="flex-grow w-full bg-white rounded-md min-h-[60vh]"
+                    sandbox="allow-popups allow-popups-to-escape-sandbox"
+                    srcdoc=html
+                />
+            }
+                .into_any()
+        }
+        (_, _, Some(text)) => {
+            view! { <pre class="font-sans text-base whitespace-pre-wrap">{text}</pre> }.into_any()
+        }
+        _ => view! { <p class="text-zinc-400">"This mail has no body."</p> }.into_any(),
+    };
+
+    view! {
+        <div class="flex flex-col flex-grow gap-y-1.5">
+            <h1 class="text-2xl font-semibold">{mail.subject}</h1>
+            <HeaderRow name="From" values=mail.from />
+            <HeaderRow name="To" values=mail.to />
+            <HeaderRow name="Cc" values=mail.cc />
+            {date.map(|date| view! { <HeaderRow name="Date" values=vec![date] /> })}
+            <div class="flex gap-x-2 mt-2 text-sm">
+                <a class=ACTION href=answer(ReplyMode::Reply)>
+                    "Reply"
+                </a>
+                <a class=ACTION href=answer(ReplyMode::ReplyAll)>
+                    "Reply all"
+                </a>
+                <a class=ACTION href=answer(ReplyMode::Forward)>
+                    "Forward"
+                </a>
+            </div>
+            <hr class="my-2.5 w-full border-zinc-800 box-border" />
+            <div class="flex gap-x-4 justify-end text-sm text-zinc-400">
+                {has_text
+                    .then(|| {
+                        view! {
+                            <label class="flex gap-x-2 items-center">
+                                "Plain text" <Switch checked=show_text />
+                            </label>
+                        }
+                    })}
+                {has_images
+                    .then(|| {
+                        view! {
+                            <label class="flex gap-x-2 items-center">
+                                "Load images" <Switch checked=load_images />
+                            </label>
+                        }
+                    })}
+            </div>
+            {body}
+            <Attachments message_id=mail.message_id attachments=mail.attachments />
+        </div>
+    }
+}
+
+const ACTION: &str = "py-1.5 px-3 rounded-md border border-zinc-800 bg-zinc-950 hover:bg-zinc-900";
+
+#[component]
+fn HeaderRow(name: &'static str, values: Vec<String>) -> impl IntoView {
+    (!values.is_empty())
+        .then(|| {
+            view! {
+                <div class="flex gap-x-2 text-sm">
+                    <span class="w-12 text-zinc-400 shrink-0">{name}</span>
+                    <span class="break-all">{values.join(", ")}</span>
+                </div>
+            }
+        })
+}
+
+#[component]
+fn Attachments(message_id: String, attachments: Vec<Attachment>) -> impl IntoView {
+    (!attachments.is_empty())
+        .then(|| {
+            view! {
+                <hr class="my-2.5 w-full border-zinc-800 box-border" />
+                <h2 class="text-lg font-semibold">"Attachments"</h2>
+                <ul class="flex flex-col gap-y-1.5">
+                    {attachments
+                        .into_iter()
+                        .map(|attachment| {
+                            view! {
+                                <li>
+                                    <a
+                                        class="flex justify-between p-3 rounded-md border border-zinc-800 bg-zinc-950 hover:bg-zinc-900"
+                                        href=Attachment::url(&message_id, attachment.index)
+                                        rel="external"
+                                    >
+                                        <span class="break-all">{attachment.name}</span>
+                                        <span class="text-sm text-zinc-400 shrink-0">
+                                            {attachment.content_type} " · " {format_size(attachment.size)}
+                                        </span>
+                                    </a>
+                                </li>
+                            }
+                        })
+                        .collect_view()}
+                </ul>
+            }
+        })
+}
+
+fn format_size(size: usize) -> String {
+    match size {
+        s if s < 1024 => format!("{s} B"),
+        s if s < 1024 * 1024 => format!("{:.1} KB", s as f64 / 1024.0),
+        s => format!("{:.1} MB", s as f64 / (1024.0 * 1024.0)),
+    }
+}
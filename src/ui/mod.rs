@@ -2,7 +2,7 @@ use leptos::prelude::*;
 use leptos_meta::{provide_meta_context, MetaTags, Stylesheet, Title, Link};
 use leptos_router::{
     components::{Route, Router, Routes},
-    StaticSegment,
+    ParamSegment, StaticSegment,
 };
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
@@ -23,10 +23,19 @@ pub fn shell(options: LeptosOptions) -> impl IntoView {
     }
 }
 
+pub mod compose;
+use crate::ui::compose::ComposePage;
 pub mod home;
 use crate::ui::home::HomePage;
+pub mod labels;
+pub mod login;
+use crate::ui::login::LoginPage;
 pub mod mail;
 use crate::ui::mail::MailPage;
+pub mod message;
+use crate::ui::message::MessagePage;
+pub mod search;
+pub mod thread;
 pub mod components;
 
 #[component]
@@ -51,7 +60,13 @@ pub fn Ui() -> impl IntoView {
                 // }>
                 <Routes fallback=|| "Page not found.".into_view() >
                     <Route path=StaticSegment("/") view=HomePage/>
+                    <Route path=StaticSegment("/login") view=LoginPage/>
                     <Route path=StaticSegment("/ui") view=MailPage/>
+                    <Route path=(StaticSegment("/ui"), StaticSegment("compose")) view=ComposePage/>
+                    <Route
+                        path=(StaticSegment("/ui"), StaticSegment("mail"), ParamSegment("message_id"))
+                        view=MessagePage
+                    />
                 </Routes>
             </main>
         </Router>
@@ -0,0 +1,112 @@
+use leptos::prelude::*;
+
+use crate::api_types::{ErrorResponse, ListEmailsResponse, Mail};
+use crate::ui::components::card::{Card, CardLoading};
+
+#[server(Search, "/api_fn")]
+pub async fn search_fn(
+    email: String,
+    q: String,
+    cursor: Option<String>,
+    limit: Option<i32>,
+) -> Result<ListEmailsResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::{search, ListQuery};
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(search(state, &access, email, q, ListQuery { cursor, limit }).await?)
+}
+
+/// Mail of a mailbox matching `q`, newest first.
+#[component]
+pub fn SearchResults(email: Signal<String>, q: Signal<String>) -> impl IntoView {
+    let mails = Resource::new(
+        move || (email.get(), q.get()),
+        move |(email, q)| async move { search_fn(email, q, None, None).await },
+    );
+
+    // same paging as the flat listing in MailPage
+    let more_mails = RwSignal::new(Vec::<Mail>::new());
+    let more_cursor = RwSignal::new(None::<Option<String>>);
+    let load_more = Action::new(move |(email, q, cursor): &(String, String, String)| {
+        let (email, q, cursor) = (email.clone(), q.clone(), cursor.clone());
+        async move {
+            let page = search_fn(email.clone(), q.clone(), Some(cursor), None).await;
+            (email, q, page)
+        }
+    });
+    Effect::new(move |_| {
+        if let Some((from, from_q, Ok(page))) = load_more.value().get() {
+            if from == email.get_untracked() && from_q == q.get_untracked() {
+                more_mails.update(|mails| mails.extend(page.data));
+                more_cursor.set(Some(page.next_cursor));
+            }
+        }
+    });
+    Effect::new(move |_| {
+        email.track();
+        q.track();
+        more_mails.set(Vec::new());
+        more_cursor.set(None);
+    });
+
+    view! {
+        <Transition fallback=move || {
+            view! { <div class="px-3 py-4 -mt-4 z-0"><CardLoading /></div> }
+        }>
+            {move || match mails.get() {
+                None => view! { <p>"No Data"</p> }.into_any(),
+                Some(Err(e)) => view! { <p class="px-3">{e.to_string()}</p> }.into_any(),
+                Some(Ok(api)) if api.data.is_empty() => {
+                    view! { <p class="px-3 text-zinc-400">"No mail matches this search."</p> }
+                        .into_any()
+                }
+                Some(Ok(api)) => {
+                    let first_cursor = api.next_cursor.clone();
+                    let next_cursor = move || {
+                        more_cursor.get().unwrap_or_else(|| first_cursor.clone())
+                    };
+                    view! {
+                        <div class="flex overflow-y-auto flex-col gap-y-3 px-3 py-4 -mt-4 z-0">
+                            <For
+                                each=move || api.data.clone().into_iter().chain(more_mails.get())
+                                key=|mail| mail.message_id.clone()
+                                children=move |mail| {
+                                    view! { <Card mail=mail /> }
+                                }
+                            />
+                            {move || {
+                                next_cursor()
+                                    .map(|cursor| {
+                                        view! {
+                                            <button
+                                                class="p-3 rounded-md border border-zinc-800 bg-zinc-950 hover:bg-zinc-900 disabled:opacity-50"
+                                                disabled=move || load_more.pending().get()
+                                                on:click=move |_| {
+                                                    load_more
+                                                        .dispatch((
+                                                            email.get_untracked(),
+                                                            q.get_untracked(),
+                                                            cursor.clone(),
+                                                        ));
+                                                }
+                                            >
+                                                {move || {
+                                                    if load_more.pending().get() {
+                                                        "Loading..."
+                                                    } else {
+                                                        "Load more"
+                                                    }
+                                                }}
+                                            </button>
+                                        }
+                                    })
+                            }}
+                        </div>
+                    }
+                        .into_any()
+                }
+            }}
+        </Transition>
+    }
+}
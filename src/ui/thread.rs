@@ -0,0 +1,208 @@
+use leptos::prelude::*;
+use leptos_router::hooks::query_signal;
+
+use crate::api_types::{ErrorResponse, Folder, ListEmailsResponse, ListThreadsResponse, Thread};
+use crate::ui::components::badge::Badge;
+use crate::ui::components::card::{CardLoading, RelativeTime};
+
+#[server(ListThreads, "/api_fn")]
+pub async fn list_threads_fn(
+    email: String,
+    folder: Folder,
+    cursor: Option<String>,
+    limit: Option<i32>,
+) -> Result<ListThreadsResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::{list_threads, ListQuery};
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(list_threads(state, &access, email, folder, ListQuery { cursor, limit }).await?)
+}
+
+#[server(ListThread, "/api_fn")]
+pub async fn list_thread_fn(
+    email: String,
+    folder: Folder,
+    thread_id: String,
+) -> Result<ListEmailsResponse, ServerFnError<ErrorResponse>> {
+    use crate::api::list_thread;
+    use crate::auth::server_context;
+    let (state, access) = server_context().await?;
+
+    Ok(list_thread(state, &access, email, folder, thread_id).await?)
+}
+
+/// The mailbox listing with every conversation collapsed into a single card.
+#[component]
+pub fn ThreadList(email: Signal<String>, folder: Signal<Folder>) -> impl IntoView {
+    let threads = Resource::new(
+        move || (email.get(), folder.get()),
+        move |(email, folder)| async move { list_threads_fn(email, folder, None, None).await },
+    );
+
+    // same paging as the flat listing in MailPage
+    let more_threads = RwSignal::new(Vec::<Thread>::new());
+    let more_cursor = RwSignal::new(None::<Option<String>>);
+    let load_more = Action::new(move |(email, folder, cursor): &(String, Folder, String)| {
+        let (email, folder, cursor) = (email.clone(), *folder, cursor.clone());
+        async move {
+            let page = list_threads_fn(email.clone(), folder, Some(cursor), None).await;
+            (email, folder, page)
+        }
+    });
+    Effect::new(move |_| {
+        if let Some((from, from_folder, Ok(page))) = load_more.value().get() {
+            if from == email.get_untracked() && from_folder == folder.get_untracked() {
+                more_threads.update(|threads| threads.extend(page.data));
+                more_cursor.set(Some(page.next_cursor));
+            }
+        }
+    });
+    Effect::new(move |_| {
+        email.track();
+        folder.track();
+        more_threads.set(Vec::new());
+        more_cursor.set(None);
+    });
+
+    view! {
+        <Transition fallback=move || {
+            view! { <div class="px-3 py-4 -mt-4 z-0"><CardLoading /></div> }
+        }>
+            {move || match threads.get() {
+                None => view! { <p>"No Data"</p> }.into_any(),
+                Some(Err(e)) => view! { <p>{e.to_string()}</p> }.into_any(),
+                Some(Ok(api)) => {
+                    let first_cursor = api.next_cursor.clone();
+                    let next_cursor = move || {
+                        more_cursor.get().unwrap_or_else(|| first_cursor.clone())
+                    };
+                    view! {
+                        <div class="flex overflow-y-auto flex-col gap-y-3 px-3 py-4 -mt-4 z-0">
+                            <For
+                                each=move || api.data.clone().into_iter().chain(more_threads.get())
+                                key=|thread| thread.thread_id.clone()
+                                children=move |thread| {
+                                    view! { <ThreadCard thread email folder /> }
+                                }
+                            />
+                            {move || {
+                                next_cursor()
+                                    .map(|cursor| {
+                                        view! {
+                                            <button
+                                                class="p-3 rounded-md border border-zinc-800 bg-zinc-950 hover:bg-zinc-900 disabled:opacity-50"
+                                                disabled=move || load_more.pending().get()
+                                                on:click=move |_| {
+                                                    load_more
+                                                        .dispatch((
+                                                            email.get_untracked(),
+                                                            folder.get_untracked(),
+                                                            cursor.clone(),
+                                                        ));
+                                                }
+                                            >
+                                                {move || {
+                                                    if load_more.pending().get() {
+                                                        "Loading..."
+                                                    } else {
+                                                        "Load more"
+                                                    }
+                                                }}
+                                            </button>
+                                        }
+                                    })
+                            }}
+                        </div>
+                    }
+                        .into_any()
+                }
+            }}
+        </Transition>
+    }
+}
+
+/// A conversation shown by its newest mail, the others are listed when expanded.
+#[component]
+fn ThreadCard(thread: Thread, email: Signal<String>, folder: Signal<Folder>) -> impl IntoView {
+    let (_, set_showing) = query_signal::<String>("m");
+    let expanded = RwSignal::new(false);
+    let latest = thread.latest;
+    let message_id = latest.message_id.clone();
+    let thread_id = thread.thread_id.clone();
+    let mails = Resource::new(
+        move || expanded.get(),
+        move |expanded| {
+            let thread_id = thread_id.clone();
+            async move {
+                if expanded {
+                    Some(list_thread_fn(email.get_untracked(), folder.get_untracked(), thread_id).await)
+                } else {
+                    None
+                }
+            }
+        },
+    );
+
+    view! {
+        <div class="flex flex-col gap-y-1.5 p-5 sm:p-6 rounded-lg border bg-zinc-950 border-zinc-800">
+            <h1 class="text-lg sm:text-2xl font-semibold line-clamp-2">{latest.from}</h1>
+            <button
+                class="text-left hover:underline"
+                on:click=move |_| set_showing.set(Some(message_id.clone()))
+            >
+                {latest.subject}
+            </button>
+            <p class="overflow-y-hidden text-sm sm:text-base text-zinc-400 h-[3lh] sm:h-[2lh] text-ellipsis line-clamp-3 sm:line-clamp-2">
+                {latest.first_sentence}
+            </p>
+            <Show when=move || expanded.get()>
+                <Suspense fallback=move || view! { <p class="text-sm text-zinc-400">"Loading..."</p> }>
+                    {move || match mails.get().flatten() {
+                        Some(Ok(api)) => {
+                            view! {
+                                <ul class="flex flex-col gap-y-1 text-sm">
+                                    {api
+                                        .data
+                                        .into_iter()
+                                        .map(|mail| {
+                                            let message_id = mail.message_id.clone();
+                                            view! {
+                                                <li>
+                                                    <button
+                                                        class="flex gap-x-2 justify-between w-full text-left hover:underline"
+                                                        on:click=move |_| set_showing.set(Some(message_id.clone()))
+                                                    >
+                                                        <span class="truncate">{mail.from.join(", ")}</span>
+                                                        <span class="text-zinc-400 shrink-0">
+                                                            <RelativeTime timestamp=mail.sk />
+                                                        </span>
+                                                    </button>
+                                                </li>
+                                            }
+                                        })
+                                        .collect_view()}
+                                </ul>
+                            }
+                                .into_any()
+                        }
+                        Some(Err(e)) => view! { <p>{e.to_string()}</p> }.into_any(),
+                        None => view! { <p class="text-sm text-zinc-400">"Loading..."</p> }.into_any(),
+                    }}
+                </Suspense>
+            </Show>
+            <hr class="my-2.5 w-full border-zinc-800 box-border" />
+            <div class="flex justify-between">
+                <button on:click=move |_| expanded.update(|expanded| *expanded = !*expanded)>
+                    <Badge>
+                        {thread.message_count}
+                        {if thread.message_count == 1 { " message" } else { " messages" }}
+                    </Badge>
+                </button>
+                <div class="text-zinc-400">
+                    <RelativeTime timestamp=latest.sk />
+                </div>
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,250 @@
+mod common;
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::routing::{get, post};
+use axum::Router;
+use common::{access, log_in, state, ALICE, BOB};
+use supermailer::api::{get_email, get_email_html, list_emails, EmailQuery, ListQuery};
+use supermailer::api_types::{AccountRequest, Folder, LoginRequest, MailFilter};
+use supermailer::auth::{login, put_account, Access, SESSION_COOKIE};
+use supermailer::error::ApiError;
+
+/// A session cookie among others the browser sends.
+fn cookie(token: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = format!("theme=dark; {SESSION_COOKIE}={token}");
+    headers.insert(header::COOKIE, HeaderValue::from_str(&value).unwrap());
+    headers
+}
+
+#[tokio::test]
+async fn logs_in_with_the_right_password() {
+    let state = state().await;
+
+    let access = access(&state, "bob", "battery staple").await;
+    assert_eq!(access.username, "bob");
+    assert_eq!(access.mailboxes, [BOB]);
+    assert!(!access.admin);
+
+    let wrong = LoginRequest {
+        username: "bob".to_string(),
+        password: "correct horse".to_string(),
+    };
+    assert!(matches!(
+        login(state.clone(), wrong).await,
+        Err(ApiError::InvalidCredentials)
+    ));
+    let missing = LoginRequest {
+        username: "mallory".to_string(),
+        password: "battery staple".to_string(),
+    };
+    assert!(matches!(
+        login(state.clone(), missing).await,
+        Err(ApiError::InvalidCredentials)
+    ));
+    // the session is found among the other cookies
+    let token = log_in(&state, "bob", "battery staple").await;
+    let access = Access::of_request(&state, &cookie(&token)).await.unwrap();
+    assert_eq!(access.username, "bob");
+    assert!(matches!(
+        Access::of_request(&state, &cookie("made-up")).await,
+        Err(ApiError::Unauthorized)
+    ));
+    assert!(matches!(
+        Access::of_request(&state, &HeaderMap::new()).await,
+        Err(ApiError::Unauthorized)
+    ));
+}
+
+#[tokio::test]
+async fn denies_other_mailboxes() {
+    let state = state().await;
+    let bob = access(&state, "bob", "battery staple").await;
+
+    let own = list_emails(
+        state.clone(),
+        &bob,
+        BOB.to_string(),
+        Folder::Inbox,
+        MailFilter::All,
+        None,
+        ListQuery::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(own.data.len(), 1);
+
+    for folder in [Folder::Inbox, Folder::Sent] {
+        let other = list_emails(
+            state.clone(),
+            &bob,
+            ALICE.to_string(),
+            folder,
+            MailFilter::All,
+            None,
+            ListQuery::default(),
+        )
+        .await;
+        assert!(matches!(other, Err(ApiError::Forbidden(_))));
+    }
+
+    // a mail of another mailbox looks like one that doesn't exist
+    let other = get_email("a".to_string(), state.clone(), &bob, EmailQuery::default()).await;
+    assert!(matches!(other, Err(ApiError::NotFound(_))));
+}
+
+#[tokio::test]
+async fn only_admins_manage_accounts() {
+    let state = state().await;
+    let bob = access(&state, "bob", "battery staple").await;
+
+    let request = AccountRequest {
+        password: Some("battery staple".to_string()),
+        mailboxes: vec![ALICE.to_string()],
+        admin: true,
+    };
+    let granted = put_account(state.clone(), &bob, "bob".to_string(), request).await;
+    assert!(matches!(granted, Err(ApiError::Forbidden(_))));
+    assert!(!bob.can_read(ALICE));
+}
+
+#[tokio::test]
+async fn enforces_access_in_the_router() {
+    use supermailer::api::list_emails_api;
+    use supermailer::auth::{login_api, logout_api};
+
+    let state = state().await;
+    let app = Router::new()
+        .route("/login", post(login_api))
+        .route("/logout", post(logout_api))
+        .route("/:email", get(list_emails_api))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let url = |path: &str| format!("http://{address}{path}");
+
+    let anonymous = client.get(url(&format!("/{BOB}"))).send().await.unwrap();
+    assert_eq!(anonymous.status(), 401);
+
+    let logged_in = client
+        .post(url("/login"))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(r#"{"username": "bob", "password": "battery staple"}"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(logged_in.status(), 200);
+    let set_cookie = logged_in.headers()[header::SET_COOKIE].to_str().unwrap();
+    assert!(set_cookie.contains("HttpOnly"));
+    let session = set_cookie.split(';').next().unwrap().to_string();
+
+    let own = client
+        .get(url(&format!("/{BOB}")))
+        .header(header::COOKIE, &session)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(own.status(), 200);
+    let other = client
+        .get(url(&format!("/{ALICE}")))
+        .header(header::COOKIE, &session)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(other.status(), 403);
+
+    let logged_out = client
+        .post(url("/logout"))
+        .header(header::COOKIE, &session)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(logged_out.status(), 204);
+    let ended = client
+        .get(url(&format!("/{BOB}")))
+        .header(header::COOKIE, &session)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(ended.status(), 401);
+}
+
+const INLINE_IMAGE: &str = "From: carol@example.com\r\n\
+To: bob@alvinjanuar.com\r\n\
+Subject: Our logo\r\n\
+Content-Type: multipart/related; boundary=b\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/html\r\n\
+\r\n\
+<img src=\"cid:logo@example.com\"><img src=\"http://localhost/tracker.png\">\r\n\
+--b\r\n\
+Content-Type: image/png\r\n\
+Content-ID: <logo@example.com>\r\n\
+\r\n\
+PNG\r\n\
+--b--\r\n";
+
+#[tokio::test]
+async fn loads_body_images_without_the_session() {
+    use supermailer::api::{get_attachment_api, proxy_image_api};
+
+    let state = state().await;
+    state
+        .mail_store
+        .put_raw("b", INLINE_IMAGE.as_bytes().to_vec())
+        .await
+        .unwrap();
+    let bob = access(&state, "bob", "battery staple").await;
+    let query = EmailQuery { load_images: true };
+    let html = get_email_html("b".to_string(), state.clone(), &bob, query)
+        .await
+        .unwrap();
+    let sources: Vec<String> = html
+        .split("src=\"")
+        .skip(1)
+        .map(|rest| rest.split('"').next().unwrap().replace("&amp;", "&"))
+        .collect();
+    let [inline, proxied] = sources.as_slice() else {
+        panic!("{html}");
+    };
+    assert!(
+        inline.starts_with("/api/email/b/attachments/0?"),
+        "{inline}"
+    );
+    assert!(proxied.starts_with("/api/proxy?url="), "{proxied}");
+
+    let api = Router::new()
+        .route("/email/:id/attachments/:index", get(get_attachment_api))
+        .route("/proxy", get(proxy_image_api));
+    let app = Router::new().nest("/api", api).with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    // the sandboxed iframe has an opaque origin, the browser leaves the session cookie out
+    let client = reqwest::Client::new();
+    let fetch = |path: String| {
+        let request = client.get(format!("http://{address}{path}")).send();
+        async move { request.await.unwrap() }
+    };
+
+    let image = fetch(inline.clone()).await;
+    assert_eq!(image.status(), 200);
+    assert_eq!(image.bytes().await.unwrap().as_ref(), b"PNG");
+    // let through to the proxy, which refuses to fetch from localhost
+    assert_eq!(fetch(proxied.clone()).await.status(), 400);
+
+    let (unsigned, _) = inline.split_once('?').unwrap();
+    let other = inline.replace("/attachments/0?", "/attachments/1?");
+    let extended = inline.replace("expires=", "expires=1");
+    for path in [
+        unsigned.to_string(),
+        other,
+        extended,
+        "/api/proxy?url=x".to_string(),
+    ] {
+        assert_eq!(fetch(path.clone()).await.status(), 401, "{path}");
+    }
+}
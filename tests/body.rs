@@ -0,0 +1,99 @@
+use mail_parser::Message;
+use supermailer::api_types::BodyFormat;
+use supermailer::body::{render, text_to_html};
+use supermailer::html::Options;
+
+/// `text_to_html` without the wrapping `<div>`.
+fn converted(text: &str) -> String {
+    let html = text_to_html(text);
+    html.strip_prefix("<div style=\"white-space: pre-wrap; font-family: sans-serif\">")
+        .and_then(|html| html.strip_suffix("</div>"))
+        .unwrap()
+        .to_string()
+}
+
+#[test]
+fn escapes_text() {
+    assert_eq!(
+        converted(r#"<script>alert("x & 'y'")</script>"#),
+        "&lt;script&gt;alert(&quot;x &amp; &#39;y&#39;&quot;)&lt;/script&gt;\n"
+    );
+}
+
+#[test]
+fn links_urls() {
+    assert_eq!(
+        converted("See https://example.com/a?b=1&c=2."),
+        "See <a href=\"https://example.com/a?b=1&amp;c=2\">https://example.com/a?b=1&amp;c=2</a>.\n"
+    );
+    assert_eq!(
+        converted("(at www.example.com)"),
+        "(at <a href=\"https://www.example.com\">www.example.com</a>)\n"
+    );
+    assert_eq!(
+        converted("<http://example.com>, then"),
+        "&lt;<a href=\"http://example.com\">http://example.com</a>&gt;, then\n"
+    );
+    // quotes can't break out of the attribute
+    assert_eq!(
+        converted("http://example.com/\"onmouseover=\"x"),
+        "<a href=\"http://example.com/\">http://example.com/</a>&quot;onmouseover=&quot;x\n"
+    );
+}
+
+#[test]
+fn leaves_what_only_looks_like_a_url() {
+    for text in [
+        "http://",
+        "www.",
+        "https:// example.com",
+        "mywww.example.com",
+        "/path/http://example.com",
+        "trailing punctuation only: http://.",
+    ] {
+        assert!(!converted(text).contains("<a "), "{text}");
+    }
+    // multibyte text around a URL doesn't split a character
+    assert_eq!(
+        converted("→https://example.com/é←"),
+        "→<a href=\"https://example.com/é←\">https://example.com/é←</a>\n"
+    );
+}
+
+#[test]
+fn folds_quoted_replies() {
+    let text = "Sounds good.\n\nOn Fri, Bob wrote:\n> Lunch?\n> > Are you free?\n>\nThanks";
+    assert_eq!(
+        converted(text),
+        "Sounds good.\n\n<details><summary>On Fri, Bob wrote:</summary>\
+         <blockquote>Lunch?\n&gt; Are you free?\n</blockquote></details>Thanks\n"
+    );
+    // a quote without an attribution still folds
+    assert_eq!(
+        converted("> <b>quoted</b>\nreply"),
+        "<details><summary>Quoted text</summary><blockquote>&lt;b&gt;quoted&lt;/b&gt;\
+         </blockquote></details>reply\n"
+    );
+    // "wrote:" without a quote after it is just text
+    assert_eq!(converted("Bob wrote:"), "Bob wrote:\n");
+    assert_eq!(converted(""), "");
+}
+
+#[test]
+fn renders_the_part_the_sender_wrote() {
+    let text = Message::parse(b"Subject: Hi\r\n\r\nHello <you>\r\n").unwrap();
+    let body = render(&text, Options::default()).unwrap();
+    assert_eq!(body.format, BodyFormat::Text);
+    assert!(body.html.contains("Hello &lt;you&gt;"), "{}", body.html);
+
+    let alternative = Message::parse(
+        b"Subject: Hi\r\nContent-Type: multipart/alternative; boundary=b\r\n\r\n\
+          --b\r\nContent-Type: text/plain\r\n\r\nHello\r\n\
+          --b\r\nContent-Type: text/html\r\n\r\n<p onclick=\"x()\">Hello</p>\r\n--b--\r\n",
+    )
+    .unwrap();
+    let body = render(&alternative, Options::default()).unwrap();
+    assert_eq!(body.format, BodyFormat::Html);
+    assert!(body.html.contains("<p>Hello</p>"), "{}", body.html);
+    assert_eq!(body.text.as_deref().map(str::trim), Some("Hello"));
+}
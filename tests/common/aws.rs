@@ -0,0 +1,498 @@
+//! AWS in memory, for tests of what the inbox and the store write: DynamoDB with the operations
+//! and the handful of expressions those use, S3 objects to read and SES sends to look at.
+//! Anything else fails the request.
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region, SharedCredentialsProvider};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{self, post};
+use axum::{Json, Router};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// An item in the JSON form DynamoDB sends, `{"pk": {"S": "..."}, ...}`.
+pub type JsonItem = Map<String, Value>;
+
+/// Items of every table by name, keyed on `pk` and `sk` like both tables are. Indexes are read
+/// like their table, the items they'd leave out don't have the attributes queried for.
+#[derive(Clone, Default)]
+pub struct FakeAws {
+    tables: Arc<Mutex<HashMap<String, Vec<JsonItem>>>>,
+    /// Contents by `bucket/key`.
+    objects: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    /// Requests to send mail, in the order they came.
+    sent: Arc<Mutex<Vec<Value>>>,
+}
+
+impl FakeAws {
+    /// Serves a new, empty fake and returns the config of clients for it.
+    pub async fn start() -> (SdkConfig, FakeAws) {
+        let aws = FakeAws::default();
+        let app = Router::new()
+            .route("/", post(dynamo))
+            .route("/v2/email/outbound-emails", post(send_email))
+            .route("/:bucket/*key", routing::get(get_object))
+            .fallback(|| async { s3_error(StatusCode::BAD_REQUEST, "NotImplemented") })
+            .with_state(aws.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let credentials = Credentials::new("test", "test", None, None, "fake");
+        // S3 goes by path rather than by bucket name for an endpoint that's an address
+        let config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .credentials_provider(SharedCredentialsProvider::new(credentials))
+            .endpoint_url(format!("http://{address}"))
+            .build();
+        (config, aws)
+    }
+
+    /// Writes `item` to `table` as it is, for what the code under test expects to find.
+    pub fn put(&self, table: &str, item: Value) {
+        let request = json!({ "TableName": table, "Item": item });
+        let mut tables = self.tables.lock().unwrap();
+        Write::new("Put", &request).apply(&mut tables).unwrap();
+    }
+
+    /// Items of `table` in partition `pk`, in the order they were first written.
+    pub fn partition(&self, table: &str, pk: &str) -> Vec<JsonItem> {
+        let tables = self.tables.lock().unwrap();
+        tables
+            .get(table)
+            .into_iter()
+            .flatten()
+            .filter(|item| item["pk"] == json!({ "S": pk }))
+            .cloned()
+            .collect()
+    }
+
+    /// Stores `contents` under `key` in `bucket`.
+    pub fn put_object(&self, bucket: &str, key: &str, contents: &[u8]) {
+        let mut objects = self.objects.lock().unwrap();
+        objects.insert(format!("{bucket}/{key}"), contents.to_vec());
+    }
+
+    /// The `SendEmail` requests made so far.
+    pub fn sent(&self) -> Vec<Value> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+async fn dynamo(
+    State(aws): State<FakeAws>,
+    headers: HeaderMap,
+    // sent as `application/x-amz-json-1.0`, which `Json` turns away
+    body: Bytes,
+) -> impl IntoResponse {
+    let request: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let target = headers
+        .get("x-amz-target")
+        .and_then(|target| target.to_str().ok())
+        .unwrap_or_default();
+    let mut tables = aws.tables.lock().unwrap();
+    let result = match target.strip_prefix("DynamoDB_20120810.") {
+        Some("TransactWriteItems") => transact_write(&mut tables, &request),
+        Some("PutItem") => write(&mut tables, &Write::new("Put", &request)),
+        Some("UpdateItem") => write(&mut tables, &Write::new("Update", &request)),
+        Some("DeleteItem") => write(&mut tables, &Write::new("Delete", &request)),
+        Some("GetItem") => Ok(get(&tables, &request)),
+        Some("Query") => query(&tables, &request),
+        _ => Err(error("UnknownOperationException", json!({}))),
+    };
+    match result {
+        Ok(response) => (StatusCode::OK, Json(response)),
+        Err(error) => (StatusCode::BAD_REQUEST, Json(error)),
+    }
+}
+
+async fn send_email(State(aws): State<FakeAws>, body: Bytes) -> impl IntoResponse {
+    let request: Value = serde_json::from_slice(&body).unwrap_or_default();
+    let mut sent = aws.sent.lock().unwrap();
+    sent.push(request);
+    Json(json!({ "MessageId": format!("fake-{}", sent.len()) }))
+}
+
+async fn get_object(
+    State(aws): State<FakeAws>,
+    Path((bucket, key)): Path<(String, String)>,
+) -> Response {
+    let objects = aws.objects.lock().unwrap();
+    match objects.get(&format!("{bucket}/{key}")) {
+        Some(contents) => contents.clone().into_response(),
+        None => s3_error(StatusCode::NOT_FOUND, "NoSuchKey"),
+    }
+}
+
+fn s3_error(status: StatusCode, code: &str) -> Response {
+    let body =
+        format!("<?xml version=\"1.0\" encoding=\"UTF-8\"?><Error><Code>{code}</Code></Error>");
+    (status, body).into_response()
+}
+
+fn error(code: &str, fields: Value) -> Value {
+    let mut error = json!({ "__type": format!("com.amazonaws.dynamodb.v20120810#{code}") });
+    error
+        .as_object_mut()
+        .unwrap()
+        .extend(fields.as_object().unwrap().clone());
+    error
+}
+
+fn validation(message: String) -> Value {
+    error("ValidationException", json!({ "message": message }))
+}
+
+type Tables = HashMap<String, Vec<JsonItem>>;
+
+/// A `Put`, `Update`, `Delete` or `ConditionCheck` of an item.
+struct Write<'a> {
+    kind: &'a str,
+    table: &'a str,
+    key: JsonItem,
+    request: &'a Value,
+}
+
+impl<'a> Write<'a> {
+    fn new(kind: &'a str, request: &'a Value) -> Self {
+        let key = if kind == "Put" {
+            &request["Item"]
+        } else {
+            &request["Key"]
+        };
+        Write {
+            kind,
+            table: request["TableName"].as_str().unwrap_or_default(),
+            key: ["pk", "sk"]
+                .into_iter()
+                .map(|name| (name.to_string(), key[name].clone()))
+                .collect(),
+            request,
+        }
+    }
+
+    fn position(&self, tables: &Tables) -> Option<usize> {
+        tables
+            .get(self.table)?
+            .iter()
+            .position(|item| item["pk"] == self.key["pk"] && item["sk"] == self.key["sk"])
+    }
+
+    /// Whether the condition of the write holds for what's stored.
+    fn holds(&self, tables: &Tables) -> Result<bool, Value> {
+        let Some(condition) = self.request.get("ConditionExpression") else {
+            return Ok(true);
+        };
+        let current = self.position(tables).map(|i| &tables[self.table][i]);
+        holds(
+            condition.as_str().unwrap_or_default(),
+            self.request,
+            current,
+        )
+    }
+
+    /// Makes the write, whose condition holds.
+    fn apply(&self, tables: &mut Tables) -> Result<Option<JsonItem>, Value> {
+        let position = self.position(tables);
+        let items = tables.entry(self.table.to_string()).or_default();
+        let item = match self.kind {
+            "Put" => self.request["Item"]
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+            "Update" => {
+                let mut item = position
+                    .map(|i| items[i].clone())
+                    .unwrap_or_else(|| self.key.clone());
+                update(&mut item, self.request)?;
+                item
+            }
+            "Delete" => {
+                if let Some(i) = position {
+                    items.remove(i);
+                }
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        };
+        match position {
+            Some(i) => items[i] = item.clone(),
+            None => items.push(item.clone()),
+        }
+        Ok(Some(item))
+    }
+}
+
+fn write(tables: &mut Tables, write: &Write) -> Result<Value, Value> {
+    if !write.holds(tables)? {
+        return Err(error(
+            "ConditionalCheckFailedException",
+            json!({ "message": "The conditional request failed" }),
+        ));
+    }
+    let item = write.apply(tables)?;
+    Ok(match (item, write.request["ReturnValues"].as_str()) {
+        (Some(item), Some("ALL_NEW")) => json!({ "Attributes": item }),
+        _ => json!({}),
+    })
+}
+
+fn transact_write(tables: &mut Tables, request: &Value) -> Result<Value, Value> {
+    let writes: Vec<Write> = request["TransactItems"]
+        .as_array()
+        .ok_or_else(|| validation("no TransactItems".to_string()))?
+        .iter()
+        .map(|item| {
+            let (kind, request) = item
+                .as_object()
+                .and_then(|item| item.iter().next())
+                .ok_or_else(|| validation(format!("unsupported write {item}")))?;
+            Ok::<_, Value>(Write::new(kind, request))
+        })
+        .collect::<Result<_, _>>()?;
+
+    // every condition holds or nothing is written
+    let mut reasons = Vec::new();
+    for write in &writes {
+        if write.holds(tables)? {
+            reasons.push(json!({ "Code": "None" }));
+            continue;
+        }
+        let mut reason = json!({ "Code": "ConditionalCheckFailed", "Message": "The conditional request failed" });
+        if write.request["ReturnValuesOnConditionCheckFailure"] == "ALL_OLD" {
+            if let Some(i) = write.position(tables) {
+                reason["Item"] = json!(tables[write.table][i]);
+            }
+        }
+        reasons.push(reason);
+    }
+    if reasons.iter().any(|reason| reason["Code"] != "None") {
+        return Err(error(
+            "TransactionCanceledException",
+            json!({ "message": "Transaction cancelled", "CancellationReasons": reasons }),
+        ));
+    }
+    for write in &writes {
+        write.apply(tables)?;
+    }
+    Ok(json!({}))
+}
+
+fn get(tables: &Tables, request: &Value) -> Value {
+    let write = Write::new("Get", request);
+    match write.position(tables) {
+        Some(i) => json!({ "Item": tables[write.table][i] }),
+        None => json!({}),
+    }
+}
+
+/// Items matching the key condition and the filter, whole whatever the projection. They're in
+/// the order of `sk`, which every index here sorts by as well.
+fn query(tables: &Tables, request: &Value) -> Result<Value, Value> {
+    let condition = request["KeyConditionExpression"]
+        .as_str()
+        .unwrap_or_default();
+    let filter = request["FilterExpression"].as_str();
+    let mut items = Vec::new();
+    let table = request["TableName"].as_str().unwrap_or_default();
+    for item in tables.get(table).into_iter().flatten() {
+        if holds(condition, request, Some(item))?
+            && filter.map_or(Ok(true), |filter| holds(filter, request, Some(item)))?
+        {
+            items.push(item.clone());
+        }
+    }
+    items.sort_by(|a, b| compare(&a["sk"], &b["sk"]));
+    if request["ScanIndexForward"] == json!(false) {
+        items.reverse();
+    }
+    if let Some(limit) = request["Limit"].as_u64() {
+        items.truncate(limit as usize);
+    }
+    Ok(json!({ "Items": items, "Count": items.len(), "ScannedCount": items.len() }))
+}
+
+/// The attribute name `name` stands for in the expression of `request`.
+fn name<'a>(name: &'a str, request: &'a Value) -> &'a str {
+    match name.strip_prefix('#') {
+        Some(_) => request["ExpressionAttributeNames"][name]
+            .as_str()
+            .unwrap_or(name),
+        None => name,
+    }
+}
+
+/// Value `value` of the expression of `request`.
+fn value(value: &str, request: &Value) -> Result<Value, Value> {
+    request["ExpressionAttributeValues"]
+        .get(value)
+        .cloned()
+        .ok_or_else(|| validation(format!("no value {value}")))
+}
+
+/// Whether `condition` holds for `item`: `attribute_exists(a)`, `attribute_not_exists(a)` and
+/// comparisons of `a` to `:v`, each maybe after a `NOT`, joined by `AND` and then by `OR`.
+fn holds(condition: &str, request: &Value, item: Option<&JsonItem>) -> Result<bool, Value> {
+    let attribute = |attribute: &str| item.and_then(|item| item.get(name(attribute, request)));
+    for any in condition.split(" OR ") {
+        let mut all = true;
+        for clause in any.split(" AND ") {
+            let clause = clause.trim();
+            let (negated, clause) = match clause.strip_prefix("NOT ") {
+                Some(clause) => (true, clause),
+                None => (false, clause),
+            };
+            let function = |function: &str| {
+                clause
+                    .strip_prefix(function)
+                    .and_then(|rest| rest.strip_prefix('('))
+                    .and_then(|rest| rest.strip_suffix(')'))
+            };
+            let holds = if let Some(name) = function("attribute_exists") {
+                attribute(name).is_some()
+            } else if let Some(name) = function("attribute_not_exists") {
+                attribute(name).is_none()
+            } else if let Some((name, expected)) = clause.split_once(" <> ") {
+                attribute(name) != Some(&value(expected, request)?)
+            } else if let Some((name, operator, expected)) = ["<=", ">=", "<", ">", "="]
+                .into_iter()
+                .find_map(|operator| {
+                    let (name, expected) = clause.split_once(&format!(" {operator} "))?;
+                    Some((name, operator, expected))
+                })
+            {
+                let expected = value(expected, request)?;
+                attribute(name).is_some_and(|actual| {
+                    let ordering = compare(actual, &expected);
+                    match operator {
+                        "<=" => ordering.is_le(),
+                        ">=" => ordering.is_ge(),
+                        "<" => ordering.is_lt(),
+                        ">" => ordering.is_gt(),
+                        _ => actual == &expected,
+                    }
+                })
+            } else {
+                return Err(validation(format!("unsupported condition {clause}")));
+            };
+            all &= holds != negated;
+        }
+        if all {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Applies the `SET`, `ADD`, `DELETE` and `REMOVE` actions of the update expression of
+/// `request` to `item`. Values are set plainly, numbers and string sets are added to.
+fn update(item: &mut JsonItem, request: &Value) -> Result<(), Value> {
+    let expression = request["UpdateExpression"].as_str().unwrap_or_default();
+    let mut actions: Vec<(&str, String)> = Vec::new();
+    for word in expression.split_whitespace() {
+        match (word, actions.last_mut()) {
+            ("SET" | "ADD" | "DELETE" | "REMOVE", _) => actions.push((word, String::new())),
+            (_, Some((_, clauses))) => {
+                clauses.push_str(word);
+                clauses.push(' ');
+            }
+            (_, None) => return Err(validation(format!("unsupported update {expression}"))),
+        }
+    }
+    for (action, clauses) in &actions {
+        for clause in clauses.split(',') {
+            let clause = clause.trim();
+            let (path, operand) = match *action {
+                "REMOVE" => (clause, None),
+                "SET" => clause
+                    .split_once('=')
+                    .map(|(path, operand)| (path.trim(), Some(operand.trim())))
+                    .ok_or_else(|| validation(format!("unsupported clause {clause}")))?,
+                _ => clause
+                    .split_once(' ')
+                    .map(|(path, operand)| (path, Some(operand.trim())))
+                    .ok_or_else(|| validation(format!("unsupported clause {clause}")))?,
+            };
+            let (parent, attribute) = parent(item, path, request)?;
+            let Some(operand) = operand else {
+                parent.remove(attribute);
+                continue;
+            };
+            let operand = value(operand, request)?;
+            let updated = match (*action, parent.get(attribute)) {
+                ("ADD", Some(current)) if current.get("N").is_some() => {
+                    let sum = number(current)? + number(&operand)?;
+                    json!({ "N": sum.to_string() })
+                }
+                ("ADD", Some(current)) => {
+                    let mut set = strings(current);
+                    for added in strings(&operand) {
+                        if !set.contains(&added) {
+                            set.push(added);
+                        }
+                    }
+                    json!({ "SS": set })
+                }
+                ("DELETE", Some(current)) => {
+                    let deleted = strings(&operand);
+                    let set: Vec<_> = strings(current)
+                        .into_iter()
+                        .filter(|member| !deleted.contains(member))
+                        .collect();
+                    if set.is_empty() {
+                        parent.remove(attribute);
+                        continue;
+                    }
+                    json!({ "SS": set })
+                }
+                ("DELETE", None) => continue,
+                _ => operand,
+            };
+            parent.insert(attribute.to_string(), updated);
+        }
+    }
+    Ok(())
+}
+
+/// The map holding the last attribute of `path`, which has to exist like in DynamoDB, and the
+/// name of that attribute.
+fn parent<'a>(
+    item: &'a mut JsonItem,
+    path: &'a str,
+    request: &'a Value,
+) -> Result<(&'a mut JsonItem, &'a str), Value> {
+    let mut names: Vec<&str> = path.split('.').map(|part| name(part, request)).collect();
+    let attribute = names.pop().unwrap_or_default();
+    let mut parent = item;
+    for part in names {
+        parent = parent
+            .get_mut(part)
+            .and_then(|map| map.get_mut("M"))
+            .and_then(Value::as_object_mut)
+            .ok_or_else(|| validation(format!("invalid document path {path}")))?;
+    }
+    Ok((parent, attribute))
+}
+
+/// Order of two values of the same type, numbers by their value.
+fn compare(a: &Value, b: &Value) -> std::cmp::Ordering {
+    match (number(a), number(b)) {
+        (Ok(a), Ok(b)) => a.cmp(&b),
+        _ => a["S"].as_str().cmp(&b["S"].as_str()),
+    }
+}
+
+fn number(value: &Value) -> Result<i64, Value> {
+    value["N"]
+        .as_str()
+        .and_then(|n| n.parse().ok())
+        .ok_or_else(|| validation(format!("{value} isn't a number")))
+}
+
+fn strings(value: &Value) -> Vec<Value> {
+    value["SS"].as_array().cloned().unwrap_or_default()
+}
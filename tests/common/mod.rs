@@ -0,0 +1,113 @@
+//! Fixtures shared by the tests, each test file picks what it needs with `mod common;`.
+#![allow(dead_code)]
+
+pub mod aws;
+
+use axum::http::{header, HeaderMap, HeaderValue};
+use leptos::prelude::LeptosOptions;
+use std::sync::Arc;
+use supermailer::api_types::{AccountRequest, Flags, LoginRequest, Mail};
+use supermailer::auth::{bootstrap_admin, login, put_account, Access, SESSION_COOKIE};
+use supermailer::oidc::Oidc;
+use supermailer::proxy::ImageProxy;
+use supermailer::send::LogSender;
+use supermailer::signing::UrlSigner;
+use supermailer::state::{AppState, MailConfig};
+use supermailer::store::local::{FsMailStore, LocalSearchStore, SqliteMetadataStore};
+
+pub const ALICE: &str = "alice@alvinjanuar.com";
+pub const WEB: &str = "web@alvinjanuar.com";
+pub const BOB: &str = "bob@alvinjanuar.com";
+
+/// An unread mail from carol in `mailbox`, a conversation of its own.
+pub fn mail(mailbox: &str, message_id: &str, sk: i64) -> Mail {
+    Mail {
+        pk: mailbox.to_string(),
+        sk,
+        message_id: message_id.to_string(),
+        subject: format!("Mail {message_id}"),
+        from: vec!["carol@example.com".to_string()],
+        first_sentence: String::new(),
+        thread_id: format!("<{message_id}>"),
+        flags: Flags::default(),
+        labels: Vec::new(),
+    }
+}
+
+/// State over an in-memory database and a directory of its own, with no accounts yet.
+pub fn empty_state(oidc: Option<Oidc>) -> AppState {
+    let dir = std::env::temp_dir().join(format!("supermailer-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    AppState {
+        mail_store: Arc::new(FsMailStore::new(&dir)),
+        metadata_store: Arc::new(SqliteMetadataStore::open(":memory:").unwrap()),
+        mail_sender: Arc::new(LogSender),
+        search_store: Arc::new(LocalSearchStore::open(dir.join("search")).unwrap()),
+        mail_config: MailConfig {
+            mail_bucket: dir.display().to_string(),
+            mail_db: ":memory:".to_string(),
+            user_db: ":memory:".to_string(),
+            configuration_set: None,
+            search_index: dir.join("search"),
+        },
+        image_proxy: ImageProxy::new(),
+        url_signer: UrlSigner::random(),
+        oidc,
+        leptos_options: LeptosOptions::default(),
+        routes: Vec::new(),
+    }
+}
+
+/// [`empty_state`] with an admin `alice` reading [`ALICE`] and [`WEB`] and a user `bob` reading
+/// [`BOB`], mail `a`, `w` and `b` in each.
+pub async fn state() -> AppState {
+    let state = empty_state(None);
+    let mailboxes = [ALICE.to_string(), WEB.to_string()];
+    let admin = bootstrap_admin(&state, "alice", "correct horse", &mailboxes)
+        .await
+        .unwrap();
+    assert!(admin);
+    let request = AccountRequest {
+        password: Some("battery staple".to_string()),
+        mailboxes: vec![BOB.to_string()],
+        admin: false,
+    };
+    let alice = access(&state, "alice", "correct horse").await;
+    put_account(state.clone(), &alice, "bob".to_string(), request)
+        .await
+        .unwrap();
+    for (mailbox, message_id) in [(ALICE, "a"), (WEB, "w"), (BOB, "b")] {
+        state
+            .metadata_store
+            .put_email(&mail(mailbox, message_id, 1))
+            .await
+            .unwrap();
+    }
+    state
+}
+
+/// Token of a new session of `username`.
+pub async fn log_in(state: &AppState, username: &str, password: &str) -> String {
+    let request = LoginRequest {
+        username: username.to_string(),
+        password: password.to_string(),
+    };
+    let (_, token) = login(state.clone(), request).await.unwrap();
+    token
+}
+
+/// Headers of a request in the session of `token`.
+pub fn session_cookie(token: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = format!("{SESSION_COOKIE}={token}");
+    headers.insert(header::COOKIE, HeaderValue::from_str(&value).unwrap());
+    headers
+}
+
+/// What `username` gets to do once logged in.
+pub async fn access(state: &AppState, username: &str, password: &str) -> Access {
+    let token = log_in(state, username, password).await;
+    Access::of_request(state, &session_cookie(&token))
+        .await
+        .unwrap()
+}
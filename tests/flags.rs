@@ -0,0 +1,146 @@
+mod common;
+
+use common::{mail, WEB as MAILBOX};
+use supermailer::api_types::{Flags, FlagsUpdate, MailFilter};
+use supermailer::store::local::SqliteMetadataStore;
+use supermailer::store::{MetadataStore, Page, StoreError};
+
+async fn listed(store: &SqliteMetadataStore, filter: MailFilter) -> Vec<String> {
+    let page = Page {
+        cursor: None,
+        limit: 10,
+    };
+    let mails = store
+        .list_emails(MAILBOX, filter, None, page)
+        .await
+        .unwrap();
+    mails
+        .items
+        .into_iter()
+        .map(|mail| mail.message_id)
+        .collect()
+}
+
+#[tokio::test]
+async fn filters_listing_by_flags() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    for (message_id, sk) in [("a", 1), ("b", 2), ("c", 3)] {
+        store
+            .put_email(&mail(MAILBOX, message_id, sk))
+            .await
+            .unwrap();
+    }
+    assert_eq!(store.count_unread(MAILBOX).await.unwrap(), 3);
+
+    let flags = store
+        .update_flags(
+            MAILBOX,
+            "a",
+            &FlagsUpdate {
+                seen: Some(true),
+                flagged: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        flags,
+        Flags {
+            seen: true,
+            flagged: true,
+            ..Flags::default()
+        }
+    );
+    store
+        .update_flags(
+            MAILBOX,
+            "b",
+            &FlagsUpdate {
+                archived: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    store
+        .update_flags(
+            MAILBOX,
+            "c",
+            &FlagsUpdate {
+                deleted: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(listed(&store, MailFilter::All).await, ["a"]);
+    assert_eq!(
+        listed(&store, MailFilter::Unread).await,
+        Vec::<String>::new()
+    );
+    assert_eq!(listed(&store, MailFilter::Flagged).await, ["a"]);
+    assert_eq!(listed(&store, MailFilter::Archived).await, ["b"]);
+    assert_eq!(listed(&store, MailFilter::Deleted).await, ["c"]);
+    assert_eq!(store.count_unread(MAILBOX).await.unwrap(), 0);
+
+    // flags left out of an update keep their value
+    let flags = store
+        .update_flags(
+            MAILBOX,
+            "a",
+            &FlagsUpdate {
+                seen: Some(false),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert!(!flags.seen && flags.flagged);
+    assert_eq!(store.count_unread(MAILBOX).await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn doesnt_flag_missing_mail() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    store.put_email(&mail(MAILBOX, "a", 1)).await.unwrap();
+
+    let update = FlagsUpdate {
+        seen: Some(true),
+        ..Default::default()
+    };
+    assert!(matches!(
+        store.update_flags(MAILBOX, "missing", &update).await,
+        Err(StoreError::NotFound(_))
+    ));
+    assert!(matches!(
+        store.update_flags("bob@example.com", "a", &update).await,
+        Err(StoreError::NotFound(_))
+    ));
+}
+
+#[test]
+fn applies_only_the_flags_changed() {
+    let update = FlagsUpdate {
+        seen: Some(false),
+        archived: Some(true),
+        ..Default::default()
+    };
+    let flags = update.apply(Flags {
+        seen: true,
+        flagged: true,
+        ..Flags::default()
+    });
+    assert_eq!(
+        flags,
+        Flags {
+            seen: false,
+            flagged: true,
+            archived: true,
+            deleted: false,
+        }
+    );
+    // archived mail no longer counts as unread
+    assert!(!flags.is_unread());
+}
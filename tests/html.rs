@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use supermailer::html::{document, proxy_url, sanitize, Images, Options, CONTENT_SECURITY_POLICY};
+
+#[test]
+fn strips_scripts_and_event_handlers() {
+    let html = r#"<p onclick="steal()">Hi<script>steal()</script></p>
+        <img src="https://example.com/a.png" onerror="steal()">
+        <a href="javascript:steal()">click</a>
+        <form action="https://example.com/login"><input name="password"></form>
+        <iframe src="https://example.com"></iframe>"#;
+    let clean = sanitize(html, Options::default());
+    for gone in [
+        "script",
+        "steal",
+        "onclick",
+        "onerror",
+        "javascript",
+        "form",
+        "input",
+        "iframe",
+    ] {
+        assert!(!clean.contains(gone), "{gone} left in {clean}");
+    }
+    assert!(clean.contains("<p>Hi</p>"), "{clean}");
+}
+
+#[test]
+fn keeps_the_layout_of_mail() {
+    let html = r##"<table bgcolor="#fff" width="600"><tr><td valign="top" style="color: red">
+        <font color="blue" face="Arial">Sale</font></td></tr></table>"##;
+    let clean = sanitize(html, Options::default());
+    assert!(
+        clean.contains(r##"<table bgcolor="#fff" width="600">"##),
+        "{clean}"
+    );
+    assert!(
+        clean.contains(r#"<td valign="top" style="color: red">"#),
+        "{clean}"
+    );
+    assert!(
+        clean.contains(r#"<font color="blue" face="Arial">Sale</font>"#),
+        "{clean}"
+    );
+}
+
+#[test]
+fn opens_links_in_a_new_tab_without_a_referrer() {
+    let clean = sanitize(
+        r#"<a href="https://example.com" target="_self" rel="opener">Example</a>"#,
+        Options::default(),
+    );
+    assert_eq!(
+        clean,
+        r#"<a href="https://example.com" target="_blank" rel="noopener noreferrer">Example</a>"#
+    );
+    // a relative link would point at our own API
+    let clean = sanitize(
+        r#"<a href="/api/accounts">Accounts</a>"#,
+        Options::default(),
+    );
+    assert_eq!(
+        clean,
+        r#"<a rel="noopener noreferrer" target="_blank">Accounts</a>"#
+    );
+}
+
+#[test]
+fn blocks_or_proxies_remote_images() {
+    let html = r#"<img src="https://tracker.example.com/open.gif?id=1" width="1">"#;
+
+    let blocked = sanitize(html, Options::default());
+    assert!(!blocked.contains("tracker"), "{blocked}");
+    assert!(blocked.contains(r#"src="data:image/svg+xml,"#), "{blocked}");
+    assert!(blocked.contains(r#"width="1""#), "{blocked}");
+
+    let options = Options {
+        images: Images::Proxied,
+        ..Options::default()
+    };
+    let proxied = sanitize(html, options);
+    let src = proxy_url("https://tracker.example.com/open.gif?id=1");
+    assert_eq!(
+        src,
+        "/api/proxy?url=https%3A%2F%2Ftracker.example.com%2Fopen.gif%3Fid%3D1"
+    );
+    assert_eq!(proxied, format!(r#"<img src="{src}" width="1">"#));
+}
+
+#[test]
+fn points_cid_links_at_the_parts_of_the_mail() {
+    let options = Options {
+        inline: HashMap::from([(
+            "logo@example.com".to_string(),
+            "/api/email/abc/attachments/1".to_string(),
+        )]),
+        ..Options::default()
+    };
+    let clean = sanitize(
+        r#"<img src="cid:logo@example.com"><img src="cid:%3Clogo@example.com%3E"><img src="cid:other@example.com">"#,
+        options,
+    );
+    assert_eq!(
+        clean,
+        r#"<img src="/api/email/abc/attachments/1"><img src="/api/email/abc/attachments/1"><img>"#
+    );
+}
+
+#[test]
+fn wraps_documents_in_the_content_security_policy() {
+    let html = document("<b>Hi</b><script>steal()</script>", Options::default());
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains(CONTENT_SECURITY_POLICY));
+    assert!(html.ends_with("<body><b>Hi</b></body></html>"), "{html}");
+}
@@ -0,0 +1,328 @@
+mod common;
+
+use aws_config::SdkConfig;
+use aws_lambda_events::ses::{SimpleEmailEvent, SimpleEmailRecord};
+use aws_sdk_dynamodb::Client;
+use chrono::{TimeZone, Utc};
+use common::aws::FakeAws;
+use inbox::{ingest, Inbox};
+use mail_parser::Message;
+use serde_json::{json, Value};
+use supermailer_core::dead_letter::{header_recipients, record_of, DeadLetter, DirQueue};
+use supermailer_core::item::{delivered_to, ItemError, MailItem, RulesItem, UserItem, USER_PK};
+use supermailer_core::mail::User;
+use supermailer_core::parse::is_received;
+use supermailer_core::rules::{Rule, RuleAction};
+
+fn event() -> SimpleEmailEvent {
+    let fixture = include_str!("fixtures/ses/three_recipients.json");
+    serde_json::from_str(fixture).unwrap()
+}
+
+#[test]
+fn lists_a_mail_in_every_recipients_mailbox() {
+    let event = event();
+    let items = MailItem::from_ses(&event.records[0].ses).unwrap();
+
+    // recipients outside our domain are in `destination` but not the receipt
+    let mailboxes: Vec<&str> = items.iter().map(|item| item.pk.as_str()).collect();
+    assert_eq!(
+        mailboxes,
+        [
+            "web@alvinjanuar.com",
+            "alice@alvinjanuar.com",
+            "bob@alvinjanuar.com"
+        ]
+    );
+    for item in &items {
+        // one object in the bucket, listed three times
+        assert_eq!(item.message_id, "o3vrnil0e2ic28trm7dfhrc2v0clambda");
+        assert_eq!(item.sk, 1710790481);
+        assert_eq!(item.subject, "Team lunch");
+    }
+}
+
+#[test]
+fn lists_a_mail_once_per_mailbox() {
+    let event = event();
+    let items = MailItem::from_ses(&event.records[1].ses).unwrap();
+    assert_eq!(items.len(), 1);
+    // SES hands the recipient over as `Web@`, the mailbox is keyed lowercase
+    assert_eq!(items[0].pk, "web@alvinjanuar.com");
+}
+
+#[test]
+fn needs_a_recipient() {
+    let mut event = event();
+    let ses = &mut event.records[0].ses;
+    ses.receipt.recipients.clear();
+    assert!(matches!(
+        MailItem::from_ses(ses),
+        Err(ItemError::MissingField("recipient"))
+    ));
+}
+
+const MAIL_BUCKET: &str = "mail-bucket";
+
+/// The raw message of `record`, going by the headers SES read from it.
+fn raw(record: &SimpleEmailRecord) -> String {
+    let headers = &record.ses.mail.common_headers;
+    format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nMessage-ID: {}\r\n\r\nSee you there\r\n",
+        headers.from.join(", "),
+        headers.to.join(", "),
+        headers.subject.as_deref().unwrap_or_default(),
+        headers.message_id.as_deref().unwrap_or_default(),
+    )
+}
+
+/// An inbox on `aws` with the raw messages of `event` in its bucket.
+fn inbox(config: &SdkConfig, aws: &FakeAws, event: &SimpleEmailEvent) -> Inbox {
+    for record in &event.records {
+        let key = record.ses.mail.message_id.as_deref().unwrap();
+        aws.put_object(MAIL_BUCKET, key, raw(record).as_bytes());
+    }
+    Inbox {
+        mail_bucket: MAIL_BUCKET.to_string(),
+        mail_db: "mail".to_string(),
+        user_db: "users".to_string(),
+        aws_config: config.clone(),
+        client: Client::new(config),
+    }
+}
+
+async fn set_rules(inbox: &Inbox, mailbox: &str, rules: Vec<Rule>) {
+    let item = RulesItem::new(mailbox, &rules).unwrap().to_item().unwrap();
+    inbox
+        .client
+        .put_item()
+        .table_name(&inbox.user_db)
+        .set_item(Some(item))
+        .send()
+        .await
+        .unwrap();
+}
+
+fn forward_to(to: &str) -> Rule {
+    Rule {
+        name: format!("forward to {to}"),
+        conditions: Vec::new(),
+        actions: vec![RuleAction::Forward { to: to.to_string() }],
+        stop: false,
+    }
+}
+
+/// Asserts `mailbox` has `messages` mails listed, each marked as delivered and counted.
+fn assert_delivered(aws: &FakeAws, mailbox: &str, messages: usize) {
+    assert_eq!(aws.partition("mail", mailbox).len(), messages, "{mailbox}");
+    assert_eq!(
+        aws.partition("users", &delivered_to(mailbox)).len(),
+        messages,
+        "{mailbox}"
+    );
+    let users = aws.partition("users", USER_PK);
+    let user = users
+        .iter()
+        .find(|user| user["sk"] == json!({ "S": mailbox }))
+        .unwrap();
+    assert_eq!(user["message_count"], json!({ "N": messages.to_string() }));
+    assert_eq!(user["unread_count"], json!({ "N": messages.to_string() }));
+}
+
+#[tokio::test]
+async fn replaying_an_event_delivers_it_once() {
+    let (config, aws) = FakeAws::start().await;
+    let event = event();
+    let inbox = inbox(&config, &aws, &event);
+    set_rules(
+        &inbox,
+        "alice@alvinjanuar.com",
+        vec![forward_to("alice@example.org")],
+    )
+    .await;
+
+    // SES retries with the same event, `inbox reprocess` runs it again
+    for _ in 0..3 {
+        let failures = ingest(&inbox, &event).await.unwrap();
+        assert!(failures.is_empty(), "{failures:?}");
+    }
+
+    assert_delivered(&aws, "web@alvinjanuar.com", 2);
+    assert_delivered(&aws, "alice@alvinjanuar.com", 1);
+    assert_delivered(&aws, "bob@alvinjanuar.com", 1);
+    let sent = aws.sent();
+    assert_eq!(sent.len(), 1, "{sent:?}");
+    assert_eq!(
+        sent[0]["Destination"]["ToAddresses"],
+        json!(["alice@example.org"])
+    );
+}
+
+#[tokio::test]
+async fn replaying_dropped_mail_forwards_it_once() {
+    let (config, aws) = FakeAws::start().await;
+    let event = event();
+    let inbox = inbox(&config, &aws, &event);
+    let mut rule = forward_to("bob@example.org");
+    rule.actions.push(RuleAction::Drop);
+    set_rules(&inbox, "bob@alvinjanuar.com", vec![rule]).await;
+
+    for _ in 0..3 {
+        let failures = ingest(&inbox, &event).await.unwrap();
+        assert!(failures.is_empty(), "{failures:?}");
+    }
+
+    // nothing listed, but marked so a retry knows the forward went out
+    assert!(aws.partition("mail", "bob@alvinjanuar.com").is_empty());
+    assert_eq!(
+        aws.partition("users", &delivered_to("bob@alvinjanuar.com"))
+            .len(),
+        1
+    );
+    assert_delivered(&aws, "alice@alvinjanuar.com", 1);
+    let sent = aws.sent();
+    assert_eq!(sent.len(), 1, "{sent:?}");
+    assert_eq!(
+        sent[0]["Destination"]["ToAddresses"],
+        json!(["bob@example.org"])
+    );
+}
+
+#[tokio::test]
+async fn lists_mail_that_arrived_in_the_same_second_after_each_other() {
+    let (config, aws) = FakeAws::start().await;
+    let mut event = event();
+    event.records[1].ses.mail.timestamp = event.records[0].ses.mail.timestamp;
+    let inbox = inbox(&config, &aws, &event);
+
+    for _ in 0..2 {
+        let failures = ingest(&inbox, &event).await.unwrap();
+        assert!(failures.is_empty(), "{failures:?}");
+    }
+
+    assert_delivered(&aws, "web@alvinjanuar.com", 2);
+    let sks: Vec<Value> = aws
+        .partition("mail", "web@alvinjanuar.com")
+        .iter()
+        .map(|mail| mail["sk"].clone())
+        .collect();
+    assert_eq!(
+        sks,
+        [json!({ "N": "1710790481" }), json!({ "N": "1710790482" })]
+    );
+}
+
+#[test]
+fn counts_the_labels_a_mail_arrives_with() {
+    let event = event();
+    let mut item = MailItem::from_ses(&event.records[0].ses).unwrap().remove(0);
+    item.labels = vec!["receipts".to_string(), "team lunch".to_string()];
+
+    let writes = item.delivery("mail", "users").unwrap();
+    let count = writes[2].update().unwrap();
+    assert_eq!(
+        count.update_expression(),
+        "ADD message_count :one, unread_count :one, label_counts.#label0 :one, \
+         label_counts.#label1 :one SET last_received = :sk"
+    );
+    let names = count.expression_attribute_names().unwrap();
+    assert_eq!(names["#label0"], "receipts");
+    assert_eq!(names["#label1"], "team lunch");
+}
+
+#[test]
+fn counts_mail_the_rules_read_as_read() {
+    let event = event();
+    let mut item = MailItem::from_ses(&event.records[0].ses).unwrap().remove(0);
+    item.flags.seen = true;
+
+    let writes = item.delivery("mail", "users").unwrap();
+    assert_eq!(
+        writes[2].update().unwrap().update_expression(),
+        "ADD message_count :one SET last_received = :sk"
+    );
+}
+
+#[test]
+fn registers_mailboxes_with_nothing_counted_yet() {
+    let item = UserItem::new("web@alvinjanuar.com", 1710790481)
+        .to_item()
+        .unwrap();
+    let user = User::try_from(item).unwrap();
+    assert_eq!(user.sk, "web@alvinjanuar.com");
+    assert_eq!(user.message_count, 0);
+    assert_eq!(user.first_seen, Some(1710790481));
+    assert_eq!(user.last_received, Some(1710790481));
+    assert!(user.label_counts.is_empty());
+}
+
+fn dead_letter(record: usize, failed_at: i64) -> DeadLetter {
+    let mut event = event();
+    DeadLetter {
+        record: event.records.swap_remove(record),
+        mailboxes: vec!["bob@alvinjanuar.com".to_string()],
+        error: "throttled".to_string(),
+        failed_at,
+    }
+}
+
+#[test]
+fn keeps_dead_letters_until_theyre_removed() {
+    let dir = std::env::temp_dir().join(format!("supermailer-dead-{}", uuid::Uuid::new_v4()));
+    let queue = DirQueue::new(&dir);
+    assert!(queue.list().unwrap().is_empty());
+
+    let lunch = dead_letter(0, 20);
+    let other = dead_letter(1, 10);
+    assert_ne!(lunch.message_id(), other.message_id());
+    queue.push(&lunch).unwrap();
+    queue.push(&other).unwrap();
+    assert_eq!(queue.list().unwrap(), [other.clone(), lunch.clone()]);
+
+    // failing again replaces the letter
+    let again = DeadLetter {
+        failed_at: 30,
+        ..lunch.clone()
+    };
+    queue.push(&again).unwrap();
+    assert_eq!(queue.list().unwrap(), [other.clone(), again]);
+
+    queue.remove(lunch.message_id()).unwrap();
+    queue.remove(lunch.message_id()).unwrap();
+    assert_eq!(queue.list().unwrap(), [other]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reprocesses_stored_mail_like_ses_delivered_it() {
+    let raw = include_bytes!("fixtures/sieve/personal.eml");
+    let message = Message::parse(raw).unwrap();
+    // Cc'd addresses outside our domain are left out by whoever has the user table
+    assert_eq!(
+        header_recipients(&message),
+        ["web@alvinjanuar.com", "alice@example.org"]
+    );
+
+    let received_at = Utc.timestamp_opt(1792229400, 0).unwrap();
+    let recipients = ["web@alvinjanuar.com".to_string()];
+    let record = record_of("lunch-key", &message, &recipients, received_at);
+    let items = MailItem::from_ses(&record.ses).unwrap();
+    assert_eq!(items.len(), 1);
+    let item = &items[0];
+    assert_eq!(item.pk, "web@alvinjanuar.com");
+    assert_eq!(item.message_id, "lunch-key");
+    assert_eq!(item.sk, 1792229400);
+    assert_eq!(item.subject, "Lunch on Friday?");
+    assert_eq!(record.ses.mail.source.as_deref(), Some("bob@example.com"));
+}
+
+#[test]
+fn tells_received_mail_from_sent_copies() {
+    let sent = "From: web@alvinjanuar.com\r\nTo: bob@example.com\r\nSubject: Hi\r\n\r\nHello\r\n";
+    let received = format!(
+        "Received: from mail.example.com by inbound-smtp.us-east-1.amazonaws.com\r\n{}",
+        sent.replace("web@alvinjanuar.com", "bob@example.com")
+    );
+    assert!(!is_received(&Message::parse(sent.as_bytes()).unwrap()));
+    assert!(is_received(&Message::parse(received.as_bytes()).unwrap()));
+}
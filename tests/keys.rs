@@ -0,0 +1,282 @@
+mod common;
+
+use axum::http::header;
+use axum::routing::get;
+use axum::{middleware, Router};
+use common::{access, state, ALICE, BOB, WEB};
+use supermailer::api::{create_label, put_rules, put_sieve, update_flags, RuleSet, SieveScript};
+use supermailer::api_types::{CreateApiKeyRequest, FlagsUpdate, Folder, LabelRequest, Scope};
+use supermailer::auth::AuthMethod;
+use supermailer::error::ApiError;
+use supermailer::keys::{access_of_token, create_api_key, list_api_keys, revoke_api_key};
+
+fn key_request(scopes: &[&str], expires_in_days: Option<i64>) -> CreateApiKeyRequest {
+    CreateApiKeyRequest {
+        name: "backup".to_string(),
+        scopes: scopes.iter().map(|scope| scope.parse().unwrap()).collect(),
+        expires_in_days,
+    }
+}
+
+#[tokio::test]
+async fn keys_only_get_their_scopes() {
+    let state = state().await;
+    let alice = access(&state, "alice", "correct horse").await;
+
+    let created = create_api_key(
+        state.clone(),
+        &alice,
+        key_request(&["read:WEB@alvinjanuar.com"], None),
+    )
+    .await
+    .unwrap();
+    assert!(created.token.starts_with("smk_"));
+    assert_eq!(created.key.scopes, [Scope::Read(WEB.to_string())]);
+
+    let key = access_of_token(&state, &created.token).await.unwrap();
+    assert_eq!(key.username, "alice");
+    assert!(key.can_read(WEB));
+    assert!(!key.can_read(ALICE));
+    assert!(!key.can_send(WEB));
+    assert!(!key.admin);
+
+    // the token is only ever handed out once
+    let listed = list_api_keys(state.clone(), &alice).await.unwrap();
+    assert_eq!(listed.data, [created.key]);
+    let bob = access(&state, "bob", "battery staple").await;
+    assert!(list_api_keys(state.clone(), &bob)
+        .await
+        .unwrap()
+        .data
+        .is_empty());
+}
+
+#[tokio::test]
+async fn keys_cant_exceed_their_creator() {
+    let state = state().await;
+    let bob = access(&state, "bob", "battery staple").await;
+
+    for scope in [
+        format!("read:{ALICE}"),
+        format!("send:{WEB}"),
+        "admin".to_string(),
+    ] {
+        let created = create_api_key(state.clone(), &bob, key_request(&[&scope], None)).await;
+        assert!(matches!(created, Err(ApiError::Forbidden(_))), "{scope}");
+    }
+    for request in [
+        key_request(&[], None),
+        key_request(&[&format!("read:{BOB}")], Some(0)),
+        key_request(&[&format!("read:{BOB}")], Some(366)),
+    ] {
+        let created = create_api_key(state.clone(), &bob, request).await;
+        assert!(matches!(created, Err(ApiError::InvalidParameter(_))));
+    }
+
+    // a key can't make a stronger one either
+    let alice = access(&state, "alice", "correct horse").await;
+    let created = create_api_key(
+        state.clone(),
+        &alice,
+        key_request(&[&format!("read:{ALICE}")], None),
+    )
+    .await
+    .unwrap();
+    let key = access_of_token(&state, &created.token).await.unwrap();
+    let stronger = create_api_key(state.clone(), &key, key_request(&["admin"], None)).await;
+    assert!(matches!(stronger, Err(ApiError::Forbidden(_))));
+}
+
+#[tokio::test]
+async fn expired_and_revoked_keys_stop_working() {
+    let state = state().await;
+    let alice = access(&state, "alice", "correct horse").await;
+    let bob = access(&state, "bob", "battery staple").await;
+
+    let created = create_api_key(
+        state.clone(),
+        &bob,
+        key_request(&[&format!("read:{BOB}")], Some(30)),
+    )
+    .await
+    .unwrap();
+    assert!(access_of_token(&state, &created.token).await.is_ok());
+    let forged = format!("{}0", created.token);
+    assert!(matches!(
+        access_of_token(&state, &forged).await,
+        Err(ApiError::Unauthorized)
+    ));
+
+    let mut key = state
+        .metadata_store
+        .get_api_key(&created.key.key_id)
+        .await
+        .unwrap()
+        .unwrap();
+    key.expires_at = Some(chrono::Utc::now().timestamp() - 1);
+    state.metadata_store.put_api_key(&key).await.unwrap();
+    assert!(matches!(
+        access_of_token(&state, &created.token).await,
+        Err(ApiError::Unauthorized)
+    ));
+
+    let created = create_api_key(
+        state.clone(),
+        &bob,
+        key_request(&[&format!("read:{BOB}")], None),
+    )
+    .await
+    .unwrap();
+    // other accounts' keys look like ones that don't exist, except to admins
+    let revoked = revoke_api_key(state.clone(), &bob, "made-up".to_string()).await;
+    assert!(matches!(revoked, Err(ApiError::NotFound(_))));
+    let alices = create_api_key(
+        state.clone(),
+        &alice,
+        key_request(&[&format!("read:{ALICE}")], None),
+    )
+    .await
+    .unwrap();
+    let revoked = revoke_api_key(state.clone(), &bob, alices.key.key_id.clone()).await;
+    assert!(matches!(revoked, Err(ApiError::NotFound(_))));
+    revoke_api_key(state.clone(), &alice, created.key.key_id.clone())
+        .await
+        .unwrap();
+    assert!(matches!(
+        access_of_token(&state, &created.token).await,
+        Err(ApiError::Unauthorized)
+    ));
+    assert!(access_of_token(&state, &alices.token).await.is_ok());
+}
+
+#[tokio::test]
+async fn authenticates_bearer_tokens_in_the_router() {
+    use supermailer::api::list_emails_api;
+    use supermailer::keys::api_key_layer;
+
+    let state = state().await;
+    let alice = access(&state, "alice", "correct horse").await;
+    let created = create_api_key(
+        state.clone(),
+        &alice,
+        key_request(&[&format!("read:{WEB}")], None),
+    )
+    .await
+    .unwrap();
+    let app = Router::new()
+        .route("/:email", get(list_emails_api))
+        .layer(middleware::from_fn_with_state(state.clone(), api_key_layer))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let get = |mailbox: &str, token: &str| {
+        client
+            .get(format!("http://{address}/{mailbox}"))
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .send()
+    };
+
+    assert_eq!(get(WEB, &created.token).await.unwrap().status(), 200);
+    assert_eq!(get(ALICE, &created.token).await.unwrap().status(), 403);
+    assert_eq!(get(WEB, "smk_made_up").await.unwrap().status(), 401);
+}
+
+#[tokio::test]
+async fn keys_cant_manage_keys() {
+    let state = state().await;
+    let alice = access(&state, "alice", "correct horse").await;
+    let created = create_api_key(
+        state.clone(),
+        &alice,
+        key_request(&[&format!("read:{ALICE}"), "admin"], None),
+    )
+    .await
+    .unwrap();
+    let key = access_of_token(&state, &created.token).await.unwrap();
+    assert_eq!(key.method, AuthMethod::ApiKey);
+    assert_eq!(alice.method, AuthMethod::Session);
+
+    let weaker = create_api_key(
+        state.clone(),
+        &key,
+        key_request(&[&format!("read:{ALICE}")], None),
+    )
+    .await;
+    assert!(matches!(weaker, Err(ApiError::Forbidden(_))));
+    // not even itself, a leaked key is revoked by someone logged in
+    let revoked = revoke_api_key(state.clone(), &key, created.key.key_id.clone()).await;
+    assert!(matches!(revoked, Err(ApiError::Forbidden(_))));
+    assert!(access_of_token(&state, &created.token).await.is_ok());
+}
+
+#[tokio::test]
+async fn keys_only_change_mailboxes_they_may_write() {
+    let state = state().await;
+    let alice = access(&state, "alice", "correct horse").await;
+    let key = |scopes: Vec<String>| {
+        let state = state.clone();
+        let alice = alice.clone();
+        async move {
+            let scopes: Vec<&str> = scopes.iter().map(String::as_str).collect();
+            let created = create_api_key(state.clone(), &alice, key_request(&scopes, None))
+                .await
+                .unwrap();
+            access_of_token(&state, &created.token).await.unwrap()
+        }
+    };
+    let seen = || FlagsUpdate {
+        seen: Some(true),
+        ..FlagsUpdate::default()
+    };
+    let label = || LabelRequest {
+        name: Some("receipts".to_string()),
+        color: Some("#336699".to_string()),
+    };
+
+    let reader = key(vec![format!("read:{WEB}")]).await;
+    assert!(reader.can_read(WEB));
+    assert!(!reader.can_write(WEB));
+    let flagged = update_flags(
+        state.clone(),
+        &reader,
+        WEB.to_string(),
+        Folder::Inbox,
+        "w".to_string(),
+        seen(),
+    )
+    .await;
+    assert!(matches!(flagged, Err(ApiError::Forbidden(_))));
+    let created = create_label(state.clone(), &reader, WEB.to_string(), label()).await;
+    assert!(matches!(created, Err(ApiError::Forbidden(_))));
+    let rules = put_rules(state.clone(), &reader, WEB.to_string(), RuleSet::default()).await;
+    assert!(matches!(rules, Err(ApiError::Forbidden(_))));
+    let script = SieveScript {
+        script: "keep;".to_string(),
+    };
+    let sieve = put_sieve(state.clone(), &reader, WEB.to_string(), script).await;
+    assert!(matches!(sieve, Err(ApiError::Forbidden(_))));
+
+    // writing goes along with reading
+    let writer = key(vec![format!("write:{WEB}")]).await;
+    assert!(!writer.can_write(WEB));
+    let writer = key(vec![format!("read:{WEB}"), format!("write:{WEB}")]).await;
+    assert!(writer.can_write(WEB));
+    assert!(!writer.can_write(ALICE));
+    let flags = update_flags(
+        state.clone(),
+        &writer,
+        WEB.to_string(),
+        Folder::Inbox,
+        "w".to_string(),
+        seen(),
+    )
+    .await
+    .unwrap();
+    assert!(flags.seen);
+    create_label(state.clone(), &writer, WEB.to_string(), label())
+        .await
+        .unwrap();
+    assert!(alice.can_write(WEB));
+}
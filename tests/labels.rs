@@ -0,0 +1,239 @@
+mod common;
+
+use common::aws::FakeAws;
+use common::{mail, WEB as MAILBOX};
+use serde_json::json;
+use std::collections::BTreeMap;
+use supermailer::api_types::{FlagsUpdate, Label, LabelRequest, Mail, MailFilter};
+use supermailer::state::MailConfig;
+use supermailer::store::aws::DynamoMetadataStore;
+use supermailer::store::local::SqliteMetadataStore;
+use supermailer::store::{MetadataStore, Page, StoreError};
+use supermailer_core::mail::sent_mailbox;
+
+fn label(label_id: &str, name: &str) -> Label {
+    Label {
+        label_id: label_id.to_string(),
+        name: name.to_string(),
+        color: "#ff0000".to_string(),
+    }
+}
+
+async fn listed(store: &SqliteMetadataStore, filter: MailFilter, label: Option<&str>) -> Vec<Mail> {
+    let page = Page {
+        cursor: None,
+        limit: 10,
+    };
+    store
+        .list_emails(MAILBOX, filter, label, page)
+        .await
+        .unwrap()
+        .items
+}
+
+fn ids(mails: &[Mail]) -> Vec<&str> {
+    mails.iter().map(|mail| mail.message_id.as_str()).collect()
+}
+
+#[tokio::test]
+async fn files_mail_under_labels() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    for (message_id, sk) in [("a", 1), ("b", 2), ("c", 3)] {
+        store
+            .put_email(&mail(MAILBOX, message_id, sk))
+            .await
+            .unwrap();
+    }
+    store
+        .put_label(MAILBOX, &label("work", "Work"))
+        .await
+        .unwrap();
+    store
+        .put_label(MAILBOX, &label("bills", "Bills"))
+        .await
+        .unwrap();
+    assert_eq!(
+        store.list_labels(MAILBOX).await.unwrap(),
+        [label("bills", "Bills"), label("work", "Work")]
+    );
+
+    // a mail can be under several labels and a label holds several mails
+    store.set_label(MAILBOX, "a", "work", true).await.unwrap();
+    store.set_label(MAILBOX, "c", "work", true).await.unwrap();
+    let labels = store.set_label(MAILBOX, "a", "bills", true).await.unwrap();
+    assert_eq!(labels.len(), 2);
+    // filing twice is a no-op
+    let labels = store.set_label(MAILBOX, "c", "work", true).await.unwrap();
+    assert_eq!(labels, ["work"]);
+
+    assert_eq!(
+        ids(&listed(&store, MailFilter::All, Some("work")).await),
+        ["c", "a"]
+    );
+    assert_eq!(
+        ids(&listed(&store, MailFilter::All, Some("bills")).await),
+        ["a"]
+    );
+    let all = listed(&store, MailFilter::All, None).await;
+    assert_eq!(ids(&all), ["c", "b", "a"]);
+    let mut labels = all[2].labels.clone();
+    labels.sort();
+    assert_eq!(labels, ["bills", "work"]);
+
+    // labels and flags combine
+    store
+        .update_flags(
+            MAILBOX,
+            "c",
+            &FlagsUpdate {
+                archived: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        ids(&listed(&store, MailFilter::All, Some("work")).await),
+        ["a"]
+    );
+    assert_eq!(
+        ids(&listed(&store, MailFilter::Archived, Some("work")).await),
+        ["c"]
+    );
+
+    let labels = store.set_label(MAILBOX, "a", "work", false).await.unwrap();
+    assert_eq!(labels, ["bills"]);
+    assert!(listed(&store, MailFilter::All, Some("work"))
+        .await
+        .is_empty());
+
+    let renamed = store
+        .update_label(
+            MAILBOX,
+            "bills",
+            &LabelRequest {
+                name: Some("Invoices".to_string()),
+                color: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(renamed, label("bills", "Invoices"));
+
+    // deleting a label takes it off its mails
+    store.delete_label(MAILBOX, "bills").await.unwrap();
+    assert_eq!(
+        store.list_labels(MAILBOX).await.unwrap(),
+        [label("work", "Work")]
+    );
+    let all = listed(&store, MailFilter::All, None).await;
+    assert!(all.iter().all(|mail| mail.labels.is_empty()));
+}
+
+#[tokio::test]
+async fn doesnt_file_under_missing_label() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    store.put_email(&mail(MAILBOX, "a", 1)).await.unwrap();
+    store
+        .put_label(MAILBOX, &label("work", "Work"))
+        .await
+        .unwrap();
+    // labels belong to one address
+    store
+        .put_label("bob@example.com", &label("private", "Private"))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        store.set_label(MAILBOX, "a", "private", true).await,
+        Err(StoreError::LabelNotFound(_))
+    ));
+    assert!(matches!(
+        store.set_label(MAILBOX, "missing", "work", true).await,
+        Err(StoreError::NotFound(_))
+    ));
+    assert!(matches!(
+        store
+            .update_label(MAILBOX, "private", &LabelRequest::default())
+            .await,
+        Err(StoreError::LabelNotFound(_))
+    ));
+    assert!(matches!(
+        store.delete_label(MAILBOX, "private").await,
+        Err(StoreError::LabelNotFound(_))
+    ));
+    assert!(store.list_labels(MAILBOX).await.unwrap().len() == 1);
+}
+
+/// Label counts of the inbox after filing mail under labels and taking it off again, the sent
+/// folder and changes that change nothing left uncounted.
+async fn counted_labels(store: &dyn MetadataStore) -> BTreeMap<String, i64> {
+    for (message_id, sk) in [("a", 1), ("b", 2), ("c", 3)] {
+        store
+            .put_email(&mail(MAILBOX, message_id, sk))
+            .await
+            .unwrap();
+    }
+    let sent = sent_mailbox(MAILBOX);
+    store.put_email(&mail(&sent, "s", 4)).await.unwrap();
+    store
+        .put_label(MAILBOX, &label("work", "Work"))
+        .await
+        .unwrap();
+    store
+        .put_label(MAILBOX, &label("bills", "Bills"))
+        .await
+        .unwrap();
+
+    for (mailbox, message_id, label_id, filed) in [
+        (MAILBOX, "a", "work", true),
+        (MAILBOX, "c", "work", true),
+        (MAILBOX, "a", "bills", true),
+        (MAILBOX, "c", "work", true),
+        (MAILBOX, "b", "work", false),
+        (MAILBOX, "c", "work", false),
+        (MAILBOX, "b", "bills", true),
+        (&sent, "s", "work", true),
+    ] {
+        store
+            .set_label(mailbox, message_id, label_id, filed)
+            .await
+            .unwrap();
+    }
+    store.delete_label(MAILBOX, "bills").await.unwrap();
+
+    let page = Page {
+        cursor: None,
+        limit: 10,
+    };
+    let users = store.list_users(page).await.unwrap().items;
+    let user = users.iter().find(|user| user.sk == MAILBOX).unwrap();
+    user.label_counts.clone()
+}
+
+#[tokio::test]
+async fn counts_mail_under_each_label_alike_in_both_stores() {
+    let expected = BTreeMap::from([("work".to_string(), 1)]);
+    let local = SqliteMetadataStore::open(":memory:").unwrap();
+    assert_eq!(counted_labels(&local).await, expected);
+
+    let (config, aws) = FakeAws::start().await;
+    // registered by the inbox before any mail arrived
+    aws.put(
+        "users",
+        json!({
+            "pk": { "S": "USER" },
+            "sk": { "S": MAILBOX },
+            "message_count": { "N": "3" },
+            "label_counts": { "M": {} },
+        }),
+    );
+    let state = common::empty_state(None);
+    let mail_config = MailConfig {
+        mail_db: "mail".to_string(),
+        user_db: "users".to_string(),
+        ..state.mail_config
+    };
+    let aws = DynamoMetadataStore::new(&config, &mail_config);
+    assert_eq!(counted_labels(&aws).await, expected);
+}
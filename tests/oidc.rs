@@ -0,0 +1,261 @@
+mod common;
+
+use axum::extract::{Form, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use common::{empty_state, ALICE};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use supermailer::api_types::AccountInfo;
+use supermailer::auth::{bootstrap_admin, Access};
+use supermailer::error::ApiError;
+use supermailer::oidc::{code_challenge, sign_on, sign_on_cookie, CallbackQuery, Oidc, OidcConfig};
+use supermailer::state::AppState;
+
+const CODE: &str = "good-code";
+
+/// What the mock provider was asked and answers with.
+#[derive(Clone, Default)]
+struct Provider {
+    /// Userinfo claims handed out for [`CODE`].
+    claims: Arc<Mutex<Value>>,
+    /// Forms posted to the token endpoint.
+    token_requests: Arc<Mutex<Vec<HashMap<String, String>>>>,
+}
+
+async fn token(
+    State(provider): State<Provider>,
+    Form(form): Form<HashMap<String, String>>,
+) -> impl IntoResponse {
+    let valid = form.get("code").map(String::as_str) == Some(CODE);
+    provider.token_requests.lock().unwrap().push(form);
+    if valid {
+        (
+            StatusCode::OK,
+            Json(json!({"access_token": "access", "token_type": "Bearer"})),
+        )
+    } else {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "invalid_grant"})),
+        )
+    }
+}
+
+async fn userinfo(State(provider): State<Provider>, headers: HeaderMap) -> impl IntoResponse {
+    if headers.get(header::AUTHORIZATION) != Some(&HeaderValue::from_static("Bearer access")) {
+        return (StatusCode::UNAUTHORIZED, Json(json!({})));
+    }
+    let claims = provider.claims.lock().unwrap().clone();
+    (StatusCode::OK, Json(claims))
+}
+
+/// A local OIDC provider handing out `claims`, and a client for it allowing `alvinjanuar.com`.
+async fn mock_provider(claims: Value) -> (Oidc, Provider) {
+    let provider = Provider {
+        claims: Arc::new(Mutex::new(claims)),
+        ..Provider::default()
+    };
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let discovery = json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{issuer}/authorize"),
+        "token_endpoint": format!("{issuer}/token"),
+        "userinfo_endpoint": format!("{issuer}/userinfo"),
+    });
+    let app = Router::new()
+        .route(
+            "/.well-known/openid-configuration",
+            get(move || async move { Json(discovery) }),
+        )
+        .route("/token", post(token))
+        .route("/userinfo", get(userinfo))
+        .with_state(provider.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let oidc = Oidc::new(OidcConfig {
+        issuer,
+        client_id: "supermailer".to_string(),
+        client_secret: None,
+        redirect_url: "http://localhost:3000/api/oidc/callback".to_string(),
+        allowed_domains: vec!["alvinjanuar.com".to_string()],
+        mailbox_claim: Some("mailboxes".to_string()),
+    });
+    (oidc, provider)
+}
+
+/// The cookies a browser sends along with `set_cookie`.
+fn cookie(set_cookie: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = set_cookie.split(';').next().unwrap();
+    headers.insert(header::COOKIE, HeaderValue::from_str(value).unwrap());
+    headers
+}
+
+fn callback(state: &str) -> CallbackQuery {
+    CallbackQuery {
+        code: Some(CODE.to_string()),
+        state: Some(state.to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn signs_on_with_pkce() {
+    let (oidc, provider) = mock_provider(json!({
+        "sub": "1234",
+        "email": "Alvin@alvinjanuar.com",
+        "email_verified": true,
+        "mailboxes": ["web@alvinjanuar.com", "someone@example.com"],
+    }))
+    .await;
+    let state = empty_state(Some(oidc.clone()));
+
+    let authorization = oidc.authorize().await.unwrap();
+    let url = reqwest::Url::parse(&authorization.url).unwrap();
+    assert_eq!(url.path(), "/authorize");
+    let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    assert_eq!(query["response_type"], "code");
+    assert_eq!(query["client_id"], "supermailer");
+    assert_eq!(query["state"], authorization.state);
+    assert_eq!(query["code_challenge_method"], "S256");
+    assert_eq!(
+        query["code_challenge"],
+        code_challenge(&authorization.verifier)
+    );
+
+    let headers = cookie(&sign_on_cookie(&authorization));
+    let (account, token) = sign_on(&state, callback(&authorization.state), &headers)
+        .await
+        .unwrap();
+    assert_eq!(account.username, "alvin@alvinjanuar.com");
+    // addresses outside the allowed domains are left out
+    assert_eq!(
+        account.mailboxes,
+        ["alvin@alvinjanuar.com", "web@alvinjanuar.com"]
+    );
+    assert!(!account.admin);
+    let token_requests = provider.token_requests.lock().unwrap().clone();
+    assert_eq!(token_requests[0]["code_verifier"], authorization.verifier);
+    assert_eq!(token_requests[0]["grant_type"], "authorization_code");
+
+    let mut session = HeaderMap::new();
+    session.insert(
+        header::COOKIE,
+        HeaderValue::from_str(&format!("session={token}")).unwrap(),
+    );
+    let access = Access::of_request(&state, &session).await.unwrap();
+    assert!(access.can_read("web@alvinjanuar.com"));
+    assert!(!access.can_read("someone@example.com"));
+}
+
+#[tokio::test]
+async fn rejects_a_foreign_state() {
+    let (oidc, _) = mock_provider(json!({
+        "email": "alvin@alvinjanuar.com",
+        "email_verified": true,
+    }))
+    .await;
+    let state = empty_state(Some(oidc.clone()));
+
+    let authorization = oidc.authorize().await.unwrap();
+    let other = oidc.authorize().await.unwrap();
+    let headers = cookie(&sign_on_cookie(&other));
+    let signed_on = sign_on(&state, callback(&authorization.state), &headers).await;
+    assert!(matches!(signed_on, Err(ApiError::InvalidParameter(_))));
+
+    let signed_on = sign_on(&state, callback(&authorization.state), &HeaderMap::new()).await;
+    assert!(matches!(signed_on, Err(ApiError::InvalidParameter(_))));
+}
+
+#[tokio::test]
+async fn rejects_other_domains_and_unverified_addresses() {
+    for claims in [
+        json!({"email": "mallory@example.com", "email_verified": true}),
+        json!({"email": "mallory@alvinjanuar.com", "email_verified": false}),
+        json!({"email_verified": true}),
+    ] {
+        let (oidc, _) = mock_provider(claims).await;
+        let state = empty_state(Some(oidc.clone()));
+
+        let authorization = oidc.authorize().await.unwrap();
+        let headers = cookie(&sign_on_cookie(&authorization));
+        let signed_on = sign_on(&state, callback(&authorization.state), &headers).await;
+        assert!(matches!(signed_on, Err(ApiError::Forbidden(_))));
+        assert!(state
+            .metadata_store
+            .list_accounts()
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
+
+/// Signs on through `oidc` start to finish.
+async fn sign_on_again(state: &AppState, oidc: &Oidc) -> Result<AccountInfo, ApiError> {
+    let authorization = oidc.authorize().await.unwrap();
+    let headers = cookie(&sign_on_cookie(&authorization));
+    let (account, _) = sign_on(state, callback(&authorization.state), &headers).await?;
+    Ok(account)
+}
+
+#[tokio::test]
+async fn reads_the_mailboxes_of_the_latest_claims() {
+    let (oidc, provider) = mock_provider(json!({
+        "email": "alvin@alvinjanuar.com",
+        "email_verified": true,
+        "mailboxes": ["web@alvinjanuar.com"],
+    }))
+    .await;
+    let state = empty_state(Some(oidc.clone()));
+    let account = sign_on_again(&state, &oidc).await.unwrap();
+    assert_eq!(
+        account.mailboxes,
+        ["alvin@alvinjanuar.com", "web@alvinjanuar.com"]
+    );
+
+    // taken off the shared mailbox at the provider
+    provider.claims.lock().unwrap()["mailboxes"] = json!(["billing@alvinjanuar.com"]);
+    let account = sign_on_again(&state, &oidc).await.unwrap();
+    assert_eq!(
+        account.mailboxes,
+        ["alvin@alvinjanuar.com", "billing@alvinjanuar.com"]
+    );
+    let stored = state
+        .metadata_store
+        .get_account("alvin@alvinjanuar.com")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stored.mailboxes, account.mailboxes);
+}
+
+#[tokio::test]
+async fn leaves_password_accounts_alone() {
+    let (oidc, _) = mock_provider(json!({
+        "email": "alice@alvinjanuar.com",
+        "email_verified": true,
+        "mailboxes": ["bob@alvinjanuar.com"],
+    }))
+    .await;
+    let state = empty_state(Some(oidc.clone()));
+    let mailboxes = [ALICE.to_string()];
+    bootstrap_admin(&state, ALICE, "correct horse", &mailboxes)
+        .await
+        .unwrap();
+
+    let signed_on = sign_on_again(&state, &oidc).await;
+    assert!(matches!(signed_on, Err(ApiError::Forbidden(_))));
+    let account = state
+        .metadata_store
+        .get_account(ALICE)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.mailboxes, [ALICE]);
+    assert!(account.admin);
+}
@@ -0,0 +1,37 @@
+use reqwest::dns::{Name, Resolve};
+use std::str::FromStr;
+use supermailer::proxy::{ImageProxy, ProxyError, PublicResolver};
+
+#[tokio::test]
+async fn refuses_names_resolving_to_internal_addresses() {
+    // what a DNS name pointed at 127.0.0.1 or 169.254.169.254 looks like to the proxy
+    let resolved = PublicResolver
+        .resolve(Name::from_str("localhost").unwrap())
+        .await;
+    let error = resolved.err().unwrap();
+    assert!(
+        error.to_string().contains("not a public address"),
+        "{error}"
+    );
+}
+
+#[tokio::test]
+async fn refuses_internal_urls() {
+    let proxy = ImageProxy::new();
+    for url in [
+        "http://169.254.169.254/latest/meta-data/",
+        "http://10.0.0.1/a.png",
+        "http://[::1]/a.png",
+        "http://[::ffff:127.0.0.1]/a.png",
+        "http://0.0.0.0/a.png",
+        "http://localhost/a.png",
+        "http://metadata.google.internal/a.png",
+        "file:///etc/passwd",
+    ] {
+        let fetched = proxy.fetch(url).await;
+        assert!(
+            matches!(fetched, Err(ProxyError::InvalidUrl(_))),
+            "{url}: {fetched:?}"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use mail_parser::{Message, MimeHeaders};
+use supermailer::api_types::ReplyMode;
+use supermailer::reply::draft;
+use supermailer::send::{build_message, Attachment};
+
+const RECEIVED: &[u8] = b"From: Bob <bob@example.com>\r\n\
+To: Web <Web@alvinjanuar.com>, alice@example.com\r\n\
+Cc: carol@example.com, bob@example.com\r\n\
+Subject: Lunch\r\n\
+Date: Sat, 17 Oct 2026 09:30:00 +0000\r\n\
+Message-ID: <lunch@example.com>\r\n\
+References: <plans@example.com> <dates@example.com>\r\n\
+\r\n\
+Are you free?\r\n";
+
+const SENT: &[u8] = b"From: web@alvinjanuar.com\r\n\
+To: bob@example.com\r\n\
+Subject: Re: Lunch\r\n\
+Message-ID: <reply@alvinjanuar.com>\r\n\
+In-Reply-To: <lunch@example.com>\r\n\
+\r\n\
+Sure\r\n";
+
+const WITH_ATTACHMENT: &[u8] = b"From: bob@example.com\r\n\
+To: web@alvinjanuar.com\r\n\
+Subject: Fwd: Invoice\r\n\
+Message-ID: <invoice@example.com>\r\n\
+Content-Type: multipart/mixed; boundary=b\r\n\
+\r\n\
+--b\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+Attached.\r\n\
+--b\r\n\
+Content-Type: application/pdf; name=invoice.pdf\r\n\
+Content-Disposition: attachment; filename=invoice.pdf\r\n\
+\r\n\
+%PDF-1.4\r\n\
+--b--\r\n";
+
+#[test]
+fn replies_in_the_thread() {
+    let message = Message::parse(RECEIVED).unwrap();
+    let reply = draft(&message, "key", "web@alvinjanuar.com", ReplyMode::Reply);
+
+    assert_eq!(reply.from, "web@alvinjanuar.com");
+    assert_eq!(reply.to, ["bob@example.com"]);
+    assert!(reply.cc.is_empty());
+    assert_eq!(reply.subject, "Re: Lunch");
+    assert_eq!(reply.in_reply_to.as_deref(), Some("lunch@example.com"));
+    assert_eq!(
+        reply.references,
+        [
+            "plans@example.com",
+            "dates@example.com",
+            "lunch@example.com"
+        ]
+    );
+    assert!(reply
+        .text
+        .contains("\"Bob\" <bob@example.com> wrote:\n> Are you free?"));
+    assert_eq!(reply.attachments_from, None);
+
+    // the threading headers make it into the mail sent
+    let raw = build_message(&reply, &[], 1_792_229_400).unwrap().raw;
+    let sent = Message::parse(&raw).unwrap();
+    assert_eq!(
+        sent.in_reply_to().as_text_list(),
+        Some(vec!["lunch@example.com"])
+    );
+    assert_eq!(
+        sent.references().as_text_list(),
+        Some(vec![
+            "plans@example.com",
+            "dates@example.com",
+            "lunch@example.com"
+        ])
+    );
+}
+
+#[test]
+fn replies_to_all_but_ourselves() {
+    let message = Message::parse(RECEIVED).unwrap();
+    let reply = draft(&message, "key", "web@alvinjanuar.com", ReplyMode::ReplyAll);
+
+    // our own address is left out whatever its case, and nobody is in both lists
+    assert_eq!(reply.to, ["bob@example.com", "alice@example.com"]);
+    assert_eq!(reply.cc, ["carol@example.com"]);
+    assert_eq!(reply.subject, "Re: Lunch");
+}
+
+#[test]
+fn answers_from_the_mailbox_it_was_opened_in() {
+    let message = Message::parse(RECEIVED).unwrap();
+    // the same mail delivered to alice is answered by alice
+    let reply = draft(&message, "key", "Alice@example.com", ReplyMode::ReplyAll);
+    assert_eq!(reply.from, "alice@example.com");
+    assert_eq!(reply.to, ["bob@example.com", "Web@alvinjanuar.com"]);
+
+    // our own sent mail, opened from the sent folder, goes back to its recipients
+    let message = Message::parse(SENT).unwrap();
+    let reply = draft(
+        &message,
+        "key",
+        "SENT#web@alvinjanuar.com",
+        ReplyMode::Reply,
+    );
+    assert_eq!(reply.from, "web@alvinjanuar.com");
+    assert_eq!(reply.to, ["bob@example.com"]);
+    // already a reply, not "Re: Re:"
+    assert_eq!(reply.subject, "Re: Lunch");
+    // without References the chain starts at the mail it answered
+    assert_eq!(
+        reply.references,
+        ["lunch@example.com", "reply@alvinjanuar.com"]
+    );
+}
+
+#[test]
+fn forwards_with_attachments() {
+    let message = Message::parse(WITH_ATTACHMENT).unwrap();
+    let forward = draft(
+        &message,
+        "invoice-key",
+        "web@alvinjanuar.com",
+        ReplyMode::Forward,
+    );
+
+    assert_eq!(forward.from, "web@alvinjanuar.com");
+    assert!(forward.to.is_empty());
+    assert_eq!(forward.subject, "Fwd: Invoice");
+    assert_eq!(forward.in_reply_to, None);
+    assert!(forward.references.is_empty());
+    assert_eq!(forward.attachments_from.as_deref(), Some("invoice-key"));
+    assert!(forward
+        .text
+        .contains("---------- Forwarded message ----------\nFrom: bob@example.com\n"));
+    assert!(forward.text.contains("Attached."));
+
+    // a forward of a mail without attachments has nothing to carry
+    let message = Message::parse(RECEIVED).unwrap();
+    let forward = draft(&message, "key", "web@alvinjanuar.com", ReplyMode::Forward);
+    assert_eq!(forward.attachments_from, None);
+    assert_eq!(forward.subject, "Fwd: Lunch");
+
+    // the attachments carried along end up in the forward sent
+    let mut forward = draft(
+        &Message::parse(WITH_ATTACHMENT).unwrap(),
+        "invoice-key",
+        "web@alvinjanuar.com",
+        ReplyMode::Forward,
+    );
+    forward.to = vec!["alice@example.com".to_string()];
+    let attachment = Attachment {
+        name: "invoice.pdf".to_string(),
+        content_type: "application/pdf".to_string(),
+        contents: b"%PDF-1.4".to_vec(),
+    };
+    let raw = build_message(&forward, &[attachment], 1_792_229_400)
+        .unwrap()
+        .raw;
+    let sent = Message::parse(&raw).unwrap();
+    let part = sent.attachments().next().unwrap();
+    assert_eq!(part.attachment_name(), Some("invoice.pdf"));
+    assert_eq!(part.contents(), b"%PDF-1.4");
+}
@@ -0,0 +1,342 @@
+mod common;
+
+use mail_parser::Message;
+use supermailer::store::local::SqliteMetadataStore;
+use supermailer::store::MetadataStore;
+use supermailer_core::parse::format_addresses;
+use supermailer_core::rules::{
+    evaluate, forwarded, forwarded_by, Condition, Outcome, Rule, RuleAction, TextMatch, TextOp,
+};
+
+const NEWSLETTER: &str = "From: Weekly News <news@example.com>\r
+To: web@alvinjanuar.com\r
+Cc: Bob <bob@example.com>\r
+Subject: [Weekly] Issue 42\r
+List-Id: <weekly.example.com>\r
+DKIM-Signature: v=1; a=rsa-sha256; d=example.com; b=abc\r
+Message-ID: <issue42@example.com>\r
+\r
+This week in news.\r
+";
+
+const INVOICE: &str = "From: billing@shop.example\r
+To: web@alvinjanuar.com\r
+Subject: Your invoice\r
+Message-ID: <invoice@shop.example>\r
+Content-Type: multipart/mixed; boundary=b\r
+\r
+--b\r
+Content-Type: text/plain\r
+\r
+Attached.\r
+--b\r
+Content-Type: application/pdf; name=invoice.pdf\r
+Content-Disposition: attachment; filename=invoice.pdf\r
+\r
+%PDF\r
+--b--\r
+";
+
+fn text(op: TextOp, value: &str) -> TextMatch {
+    TextMatch {
+        op,
+        value: value.to_string(),
+    }
+}
+
+fn rule(name: &str, conditions: Vec<Condition>, actions: Vec<RuleAction>) -> Rule {
+    Rule {
+        name: name.to_string(),
+        conditions,
+        actions,
+        stop: false,
+    }
+}
+
+fn outcome(rules: &[Rule], raw: &str) -> Outcome {
+    let message = Message::parse(raw.as_bytes()).unwrap();
+    evaluate(rules, &message, raw.len())
+}
+
+#[test]
+fn matches_conditions() {
+    let matched = |condition: Condition, raw: &str| {
+        let rules = [rule("rule", vec![condition], vec![RuleAction::MarkRead])];
+        outcome(&rules, raw).seen
+    };
+
+    assert!(matched(
+        Condition::From(text(TextOp::Is, "NEWS@example.com")),
+        NEWSLETTER
+    ));
+    assert!(matched(
+        Condition::From(text(TextOp::StartsWith, "weekly")),
+        NEWSLETTER
+    ));
+    assert!(!matched(
+        Condition::From(text(TextOp::Contains, "bob")),
+        NEWSLETTER
+    ));
+    assert!(matched(
+        Condition::To(text(TextOp::EndsWith, "@example.com")),
+        NEWSLETTER
+    ));
+    assert!(!matched(
+        Condition::To(text(TextOp::EndsWith, "@example.com")),
+        INVOICE
+    ));
+    assert!(matched(
+        Condition::Subject(text(TextOp::StartsWith, "[weekly]")),
+        NEWSLETTER
+    ));
+    assert!(matched(
+        Condition::Header {
+            name: "list-id".to_string(),
+            matches: text(TextOp::Contains, "weekly.example.com"),
+        },
+        NEWSLETTER
+    ));
+    assert!(!matched(
+        Condition::Header {
+            name: "List-Id".to_string(),
+            matches: text(TextOp::Contains, "weekly"),
+        },
+        INVOICE
+    ));
+    assert!(matched(Condition::LargerThan { bytes: 100 }, NEWSLETTER));
+    assert!(!matched(Condition::SmallerThan { bytes: 100 }, NEWSLETTER));
+    assert!(matched(Condition::HasAttachment, INVOICE));
+    assert!(!matched(Condition::HasAttachment, NEWSLETTER));
+}
+
+#[test]
+fn combines_actions_in_order() {
+    let rules = [
+        rule(
+            "news",
+            vec![
+                Condition::From(text(TextOp::Contains, "news")),
+                Condition::Subject(text(TextOp::Contains, "weekly")),
+            ],
+            vec![
+                RuleAction::AddLabel {
+                    label_id: "reading".to_string(),
+                },
+                RuleAction::Archive,
+            ],
+        ),
+        // only one condition holds
+        rule(
+            "attachments",
+            vec![
+                Condition::From(text(TextOp::Contains, "news")),
+                Condition::HasAttachment,
+            ],
+            vec![RuleAction::Drop],
+        ),
+        Rule {
+            stop: true,
+            ..rule(
+                "everything",
+                Vec::new(),
+                vec![
+                    RuleAction::AddLabel {
+                        label_id: "reading".to_string(),
+                    },
+                    RuleAction::Forward {
+                        to: "me@example.org".to_string(),
+                    },
+                ],
+            )
+        },
+        rule("after stop", Vec::new(), vec![RuleAction::MarkRead]),
+    ];
+
+    assert_eq!(
+        outcome(&rules, NEWSLETTER),
+        Outcome {
+            matched: vec!["news".to_string(), "everything".to_string()],
+            labels: vec!["reading".to_string()],
+            archived: true,
+            forward: vec!["me@example.org".to_string()],
+            ..Outcome::default()
+        }
+    );
+
+    // dropping ends the run like stop does
+    let rules = [
+        rule(
+            "spam",
+            vec![Condition::HasAttachment],
+            vec![RuleAction::Drop],
+        ),
+        rule("after drop", Vec::new(), vec![RuleAction::MarkRead]),
+    ];
+    assert_eq!(
+        outcome(&rules, INVOICE),
+        Outcome {
+            matched: vec!["spam".to_string()],
+            drop: true,
+            ..Outcome::default()
+        }
+    );
+    assert_eq!(outcome(&[], INVOICE), Outcome::default());
+}
+
+#[test]
+fn reads_rules_as_json() {
+    let rules: Vec<Rule> = serde_json::from_str(
+        r#"[{
+            "name": "news",
+            "conditions": [
+                {"type": "from", "op": "contains", "value": "news"},
+                {"type": "header", "name": "List-Id", "op": "is", "value": "<weekly.example.com>"},
+                {"type": "larger_than", "bytes": 10},
+                {"type": "has_attachment"}
+            ],
+            "actions": [
+                {"type": "add_label", "label_id": "reading"},
+                {"type": "mark_read"},
+                {"type": "forward", "to": "me@example.org"},
+                {"type": "drop"}
+            ]
+        }]"#,
+    )
+    .unwrap();
+    assert_eq!(rules[0].conditions.len(), 4);
+    assert_eq!(
+        rules[0].actions[0],
+        RuleAction::AddLabel {
+            label_id: "reading".to_string()
+        }
+    );
+    assert!(!rules[0].stop);
+}
+
+#[test]
+fn rewrites_forwarded_mail() {
+    let raw = forwarded(NEWSLETTER.as_bytes(), "web@alvinjanuar.com");
+    let message = Message::parse(&raw).unwrap();
+    assert_eq!(format_addresses(message.from()), ["web@alvinjanuar.com"]);
+    assert_eq!(
+        format_addresses(message.reply_to()),
+        ["\"Weekly News\" <news@example.com>"]
+    );
+    assert_eq!(message.subject(), Some("[Weekly] Issue 42"));
+    assert_eq!(message.message_id(), Some("issue42@example.com"));
+    assert_eq!(message.body_text(0).unwrap().trim(), "This week in news.");
+    assert!(!String::from_utf8_lossy(&raw).contains("DKIM-Signature"));
+
+    assert!(forwarded_by(&message, "Web@AlvinJanuar.com"));
+    assert!(!forwarded_by(&message, "other@alvinjanuar.com"));
+    let original = Message::parse(NEWSLETTER.as_bytes()).unwrap();
+    assert!(!forwarded_by(&original, "web@alvinjanuar.com"));
+}
+
+#[tokio::test]
+async fn stores_rules_per_address() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    assert!(store
+        .get_rules("web@alvinjanuar.com")
+        .await
+        .unwrap()
+        .is_empty());
+
+    let rules = vec![rule("read all", Vec::new(), vec![RuleAction::MarkRead])];
+    store
+        .put_rules("web@alvinjanuar.com", &rules)
+        .await
+        .unwrap();
+    assert_eq!(store.get_rules("web@alvinjanuar.com").await.unwrap(), rules);
+    assert!(store.get_rules("bob@example.com").await.unwrap().is_empty());
+
+    store.put_rules("web@alvinjanuar.com", &[]).await.unwrap();
+    assert!(store
+        .get_rules("web@alvinjanuar.com")
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn dry_runs_the_rules_sent_along() {
+    use axum::http::header;
+    use axum::routing::post;
+    use axum::Router;
+    use supermailer::api::dry_run_rules_api;
+
+    let state = common::state().await;
+    let token = common::log_in(&state, "alice", "correct horse").await;
+    let saved = vec![rule("read all", Vec::new(), vec![RuleAction::MarkRead])];
+    state
+        .metadata_store
+        .put_rules(common::WEB, &saved)
+        .await
+        .unwrap();
+    let app = Router::new()
+        .route("/:email/rules/dry_run", post(dry_run_rules_api))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let dry_run = |content_type: &'static str, body: String| {
+        client
+            .post(format!("http://{address}/{}/rules/dry_run", common::WEB))
+            .headers(common::session_cookie(&token))
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body)
+            .send()
+    };
+    let form = |parts: &[(&str, &str)]| {
+        let mut body = String::new();
+        for (name, value) in parts {
+            body += &format!(
+                "--b\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+            );
+        }
+        body + "--b--\r\n"
+    };
+    let outcome = |body: String| serde_json::from_str::<Outcome>(&body).unwrap();
+
+    // the message alone runs the saved rules
+    let saved = dry_run("message/rfc822", NEWSLETTER.to_string())
+        .await
+        .unwrap();
+    assert_eq!(saved.status(), 200);
+    assert_eq!(outcome(saved.text().await.unwrap()).matched, ["read all"]);
+
+    let tried = vec![rule(
+        "archive newsletters",
+        vec![Condition::Subject(text(TextOp::StartsWith, "[weekly]"))],
+        vec![RuleAction::Archive],
+    )];
+    let rules = serde_json::json!({ "rules": tried }).to_string();
+    let tried = dry_run(
+        "multipart/form-data; boundary=b",
+        form(&[("eml", NEWSLETTER), ("rules", &rules)]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(tried.status(), 200);
+    let tried = outcome(tried.text().await.unwrap());
+    assert_eq!(tried.matched, ["archive newsletters"]);
+    assert!(tried.archived && !tried.seen);
+
+    // rules sent along are checked like saved ones
+    let invalid = serde_json::json!({ "rules": [rule("nothing", Vec::new(), Vec::new())] });
+    let invalid = dry_run(
+        "multipart/form-data; boundary=b",
+        form(&[("eml", NEWSLETTER), ("rules", &invalid.to_string())]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(invalid.status(), 400);
+    let missing = dry_run(
+        "multipart/form-data; boundary=b",
+        form(&[("rules", &rules)]),
+    )
+    .await
+    .unwrap();
+    assert_eq!(missing.status(), 400);
+}
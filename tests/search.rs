@@ -0,0 +1,149 @@
+use mail_parser::Message;
+use supermailer::store::local::LocalSearchStore;
+use supermailer::store::{Page, SearchStore, StoreError, MAX_SEARCH_OFFSET};
+use supermailer_core::cursor;
+use supermailer_core::search::{SearchError, SearchIndex, SearchQuery};
+
+#[test]
+fn parses_operators() {
+    let query: SearchQuery = r#"invoice from:alice to:"Bob Smith" has:attachment after:2024/01/31 before:2024-02-01 http://x"#
+        .parse()
+        .unwrap();
+    assert_eq!(
+        query,
+        SearchQuery {
+            text: vec!["invoice".to_string(), "http://x".to_string()],
+            from: vec!["alice".to_string()],
+            to: vec!["Bob Smith".to_string()],
+            has_attachment: true,
+            before: Some(1_706_745_600),
+            after: Some(1_706_659_200),
+        }
+    );
+
+    assert!(matches!(
+        "has:star".parse::<SearchQuery>(),
+        Err(SearchError::InvalidQuery(_))
+    ));
+    assert!(matches!(
+        "before:yesterday".parse::<SearchQuery>(),
+        Err(SearchError::InvalidQuery(_))
+    ));
+}
+
+const INVOICE: &str = "From: Alice <alice@example.com>\r
+To: web@alvinjanuar.com\r
+Subject: Your invoice\r
+Message-ID: <invoice@example.com>\r
+Content-Type: multipart/mixed; boundary=b\r
+\r
+--b\r
+Content-Type: text/plain\r
+\r
+Please find the invoice for January attached.\r
+--b\r
+Content-Type: application/pdf; name=invoice.pdf\r
+Content-Disposition: attachment; filename=invoice.pdf\r
+\r
+%PDF\r
+--b--\r
+";
+
+const LUNCH: &str = "From: bob@example.com\r
+To: web@alvinjanuar.com, carol@example.com\r
+Subject: Lunch?\r
+Message-ID: <lunch@example.com>\r
+\r
+Are you free for lunch on Friday?\r
+";
+
+fn search(index: &SearchIndex, mailbox: &str, q: &str) -> Vec<String> {
+    let (mails, _) = index.search(mailbox, &q.parse().unwrap(), 0, 10).unwrap();
+    mails.into_iter().map(|mail| mail.message_id).collect()
+}
+
+#[test]
+fn searches_indexed_mail() {
+    let dir = std::env::temp_dir().join(format!("supermailer-search-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let index = SearchIndex::open(&dir).unwrap();
+    let mut writer = index.writer().unwrap();
+    let mailboxes = ["web@alvinjanuar.com".to_string()];
+    index
+        .add(
+            &writer,
+            "invoice",
+            &mailboxes,
+            1_706_700_000,
+            &Message::parse(INVOICE.as_bytes()).unwrap(),
+        )
+        .unwrap();
+    index
+        .add(
+            &writer,
+            "lunch",
+            &mailboxes,
+            1_706_800_000,
+            &Message::parse(LUNCH.as_bytes()).unwrap(),
+        )
+        .unwrap();
+    writer.commit().unwrap();
+    index.reload().unwrap();
+
+    assert!(index.contains("lunch").unwrap());
+    assert_eq!(
+        search(&index, "web@alvinjanuar.com", ""),
+        ["lunch", "invoice"]
+    );
+    assert_eq!(
+        search(&index, "Web@AlvinJanuar.com", "january"),
+        ["invoice"]
+    );
+    assert_eq!(
+        search(&index, "web@alvinjanuar.com", "from:alice"),
+        ["invoice"]
+    );
+    assert_eq!(search(&index, "web@alvinjanuar.com", "to:carol"), ["lunch"]);
+    assert_eq!(
+        search(&index, "web@alvinjanuar.com", "has:attachment"),
+        ["invoice"]
+    );
+    assert_eq!(
+        search(&index, "web@alvinjanuar.com", "after:2024/02/01"),
+        ["lunch"]
+    );
+    assert_eq!(
+        search(&index, "web@alvinjanuar.com", "before:2024/02/01 lunch"),
+        Vec::<String>::new()
+    );
+    // mail is only found in the mailboxes it was delivered to
+    assert_eq!(
+        search(&index, "carol@example.com", "lunch"),
+        Vec::<String>::new()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn refuses_cursors_past_the_deepest_page() {
+    let dir = std::env::temp_dir().join(format!("supermailer-search-{}", uuid::Uuid::new_v4()));
+    let store = LocalSearchStore::open(&dir).unwrap();
+    let query: SearchQuery = "lunch".parse().unwrap();
+    let search = |offset: usize| {
+        let page = Page {
+            cursor: Some(cursor::encode(&offset)),
+            limit: 10,
+        };
+        store.search("web@alvinjanuar.com", &query, page)
+    };
+
+    assert!(search(MAX_SEARCH_OFFSET).await.unwrap().items.is_empty());
+    for offset in [MAX_SEARCH_OFFSET + 1, usize::MAX] {
+        assert!(matches!(
+            search(offset).await,
+            Err(StoreError::InvalidCursor(_))
+        ));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,148 @@
+use aws_sdk_sesv2::config::{BehaviorVersion, Credentials, Region};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::post;
+use axum::Router;
+use mail_parser::{HeaderValue, Message};
+use std::sync::{Arc, Mutex};
+use supermailer::api_types::SendRequest;
+use supermailer::send::{build_message, MailSender, SendError, SesSender};
+use supermailer_core::parse::format_addresses;
+
+fn request() -> SendRequest {
+    SendRequest {
+        from: "web@alvinjanuar.com".to_string(),
+        to: vec!["alice@example.com".to_string()],
+        cc: vec!["bob@example.com".to_string()],
+        bcc: vec!["carol@example.com".to_string()],
+        subject: "Hello".to_string(),
+        text: "Hi Alice".to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn builds_message_without_bcc_header() {
+    let mail = build_message(&request(), &[], 1_700_000_000).unwrap();
+    assert!(mail.message_id.ends_with("@alvinjanuar.com"));
+    assert_eq!(
+        mail.recipients,
+        ["alice@example.com", "bob@example.com", "carol@example.com"]
+    );
+
+    let message = Message::parse(&mail.raw).unwrap();
+    assert_eq!(message.message_id(), Some(mail.message_id.as_str()));
+    assert_eq!(message.subject(), Some("Hello"));
+    assert_eq!(format_addresses(message.from()), ["web@alvinjanuar.com"]);
+    assert_eq!(format_addresses(message.to()), ["alice@example.com"]);
+    assert_eq!(format_addresses(message.cc()), ["bob@example.com"]);
+    assert!(matches!(message.bcc(), HeaderValue::Empty));
+    assert_eq!(message.body_text(0).unwrap().trim(), "Hi Alice");
+    assert!(!String::from_utf8_lossy(&mail.raw).contains("carol@example.com"));
+}
+
+#[test]
+fn rejects_invalid_addresses() {
+    let invalid = [
+        SendRequest {
+            from: "not an address".to_string(),
+            ..request()
+        },
+        SendRequest {
+            to: vec!["Alice <alice@example.com>".to_string()],
+            ..request()
+        },
+        SendRequest {
+            bcc: vec!["@example.com".to_string()],
+            ..request()
+        },
+        SendRequest {
+            to: vec![],
+            ..request()
+        },
+    ];
+    for request in invalid {
+        assert!(matches!(
+            build_message(&request, &[], 0),
+            Err(SendError::InvalidMessage(_))
+        ));
+    }
+}
+
+/// Serves `status` and `body` for every SendEmail call and records the request bodies.
+async fn mock_ses(
+    status: StatusCode,
+    headers: &'static [(&'static str, &'static str)],
+    body: &'static str,
+) -> (SesSender, Arc<Mutex<Vec<String>>>) {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    let app = Router::new().route(
+        "/v2/email/outbound-emails",
+        post(move |request: String| async move {
+            recorded.lock().unwrap().push(request);
+            let mut response_headers = HeaderMap::new();
+            for (name, value) in headers {
+                response_headers.insert(*name, value.parse().unwrap());
+            }
+            (status, response_headers, body).into_response()
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let config = aws_sdk_sesv2::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .credentials_provider(Credentials::new("key", "secret", None, None, "test"))
+        .endpoint_url(format!("http://{address}"))
+        .build();
+    let sender = SesSender::from_client(
+        aws_sdk_sesv2::Client::from_conf(config),
+        Some("supermailer".to_string()),
+    );
+    (sender, requests)
+}
+
+#[tokio::test]
+async fn sends_raw_message_through_ses() {
+    let (sender, requests) = mock_ses(
+        StatusCode::OK,
+        &[("content-type", "application/json")],
+        r#"{"MessageId":"0100018c-ses-id"}"#,
+    )
+    .await;
+    let mail = build_message(&request(), &[], 1_700_000_000).unwrap();
+
+    assert_eq!(sender.send(&mail).await.unwrap(), "0100018c-ses-id");
+
+    let requests = requests.lock().unwrap();
+    let body: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+    assert_eq!(body["FromEmailAddress"], "web@alvinjanuar.com");
+    assert_eq!(body["ConfigurationSetName"], "supermailer");
+    assert_eq!(
+        body["Destination"]["ToAddresses"],
+        serde_json::json!(["alice@example.com", "bob@example.com", "carol@example.com"])
+    );
+    assert!(body["Content"]["Raw"]["Data"].is_string());
+}
+
+#[tokio::test]
+async fn maps_rejected_message() {
+    let (sender, _) = mock_ses(
+        StatusCode::BAD_REQUEST,
+        &[
+            ("content-type", "application/json"),
+            ("x-amzn-errortype", "MessageRejected"),
+        ],
+        r#"{"message":"Email address is not verified."}"#,
+    )
+    .await;
+    let mail = build_message(&request(), &[], 1_700_000_000).unwrap();
+
+    assert!(matches!(
+        sender.send(&mail).await,
+        Err(SendError::Rejected(_))
+    ));
+}
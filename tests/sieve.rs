@@ -0,0 +1,388 @@
+mod common;
+
+use mail_parser::Message;
+use supermailer::api::{put_sieve, validate_sieve, SieveScript};
+use supermailer::error::ApiError;
+use supermailer::store::local::SqliteMetadataStore;
+use supermailer::store::MetadataStore;
+use supermailer_core::sieve::{rejection, Envelope, Outcome, Script};
+
+const MAILBOX: &str = "web@alvinjanuar.com";
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/fixtures/sieve/{name}", env!("CARGO_MANIFEST_DIR"));
+    std::fs::read(path).unwrap()
+}
+
+/// Runs `script` over the fixture `eml`, received from `sender`.
+fn run(script: &str, eml: &str, sender: &str) -> Outcome {
+    let raw = fixture(eml);
+    let message = Message::parse(&raw).unwrap();
+    let envelope = Envelope {
+        from: sender,
+        to: MAILBOX,
+    };
+    Script::parse(script)
+        .unwrap()
+        .run(&message, &envelope, raw.len())
+}
+
+fn kept(flags: &[&str]) -> Outcome {
+    Outcome {
+        keep: true,
+        flags: flags.iter().map(|flag| flag.to_string()).collect(),
+        ..Outcome::default()
+    }
+}
+
+#[test]
+fn files_and_flags_mail() {
+    let script = r#"
+        require ["fileinto", "imap4flags"];
+        # newsletters are read later
+        if header :contains "List-Id" "weekly.example.com" {
+            addflag "\\Seen";
+            fileinto "Reading";
+            stop;
+        }
+        if address :domain :is "from" "shop.example" {
+            fileinto :flags "\\Flagged" "Receipts";
+        }
+        addflag "$personal";
+    "#;
+
+    assert_eq!(
+        run(script, "newsletter.eml", "news@example.com"),
+        Outcome {
+            folders: vec!["Reading".to_string()],
+            flags: vec!["\\Seen".to_string()],
+            ..Outcome::default()
+        }
+    );
+    assert_eq!(
+        run(script, "invoice.eml", "billing@shop.example"),
+        Outcome {
+            folders: vec!["Receipts".to_string()],
+            flags: vec!["\\Flagged".to_string()],
+            ..Outcome::default()
+        }
+    );
+    assert_eq!(
+        run(script, "personal.eml", "bob@example.com"),
+        kept(&["$personal"])
+    );
+    assert_eq!(run("", "personal.eml", "bob@example.com"), kept(&[]));
+}
+
+#[test]
+fn tests_headers_addresses_and_envelope() {
+    let matches = |test: &str, eml: &str| {
+        let script = format!(
+            "require [\"envelope\", \"imap4flags\", \"comparator-i;octet\"];\n\
+             if {test} {{ addflag \"matched\"; }}"
+        );
+        run(&script, eml, "bounces+42@mail.example.com").flags == ["matched"]
+    };
+
+    assert!(matches(
+        r#"header :is "subject" "LUNCH ON FRIDAY?""#,
+        "personal.eml"
+    ));
+    assert!(!matches(
+        r#"header :comparator "i;octet" :is "subject" "LUNCH ON FRIDAY?""#,
+        "personal.eml"
+    ));
+    assert!(matches(
+        r#"header :matches "subject" "[weekly]*""#,
+        "newsletter.eml"
+    ));
+    assert!(matches(
+        r#"header :matches "subject" "Lunch on ??iday\?""#,
+        "personal.eml"
+    ));
+    assert!(matches(
+        r#"address :is "from" "BOB@example.com""#,
+        "personal.eml"
+    ));
+    assert!(matches(
+        r#"address :localpart :is ["to", "cc"] "alice""#,
+        "personal.eml"
+    ));
+    assert!(!matches(
+        r#"address :domain :is "to" "example.org""#,
+        "personal.eml"
+    ));
+    assert!(matches(
+        r#"envelope :domain :is "from" "mail.example.com""#,
+        "invoice.eml"
+    ));
+    assert!(matches(
+        r#"envelope :localpart :matches "from" "bounces+*""#,
+        "invoice.eml"
+    ));
+    assert!(matches(
+        r#"envelope :is "to" "web@alvinjanuar.com""#,
+        "invoice.eml"
+    ));
+    assert!(matches(
+        r#"exists ["List-Id", "Message-ID"]"#,
+        "newsletter.eml"
+    ));
+    assert!(!matches(
+        r#"exists ["List-Id", "Message-ID"]"#,
+        "personal.eml"
+    ));
+    assert!(matches("size :over 500", "invoice.eml"));
+    assert!(!matches("size :under 500", "invoice.eml"));
+    assert!(matches(
+        r#"allof (header :contains "from" "shop", not exists "List-Id")"#,
+        "invoice.eml"
+    ));
+    assert!(matches(
+        r#"anyof (false, header :is "x-spam-score" "7.5")"#,
+        "invoice.eml"
+    ));
+    assert!(!matches(r#"anyof (false, not true)"#, "invoice.eml"));
+}
+
+#[test]
+fn branches_and_flag_variables() {
+    let script = r#"
+        require ["imap4flags", "fileinto"];
+        setflag ["\\Flagged", "\\Seen"];
+        removeflag "\\Flagged";
+        if header :contains "from" "news" {
+            fileinto "News";
+        } elsif hasflag :is "\\seen" {
+            addflag "$seen-was-set";
+            keep :flags "\\Answered";
+        } else {
+            discard;
+        }
+    "#;
+    assert_eq!(
+        run(script, "newsletter.eml", "news@example.com"),
+        Outcome {
+            folders: vec!["News".to_string()],
+            flags: vec!["\\Seen".to_string()],
+            ..Outcome::default()
+        }
+    );
+    assert_eq!(
+        run(script, "personal.eml", "bob@example.com"),
+        kept(&["\\Answered"])
+    );
+
+    let discarding = r#"if header :is "subject" "Out of office" { discard; stop; } keep;"#;
+    let outcome = run(discarding, "auto-reply.eml", "carol@example.net");
+    assert!(!outcome.delivered());
+    assert!(run(discarding, "personal.eml", "bob@example.com").delivered());
+}
+
+#[test]
+fn rejects_and_redirects() {
+    let script = r#"
+        require ["reject", "copy"];
+        if header :contains "from" "shop.example" {
+            reject text:
+Invoices go to accounting@alvinjanuar.com,
+..please send them there.
+.
+;
+        }
+        if header :contains "from" "bob" {
+            redirect :copy "bob-mail@alvinjanuar.com";
+            redirect "archive@alvinjanuar.com";
+            keep;
+        }
+    "#;
+    let outcome = run(script, "invoice.eml", "billing@shop.example");
+    assert!(!outcome.delivered());
+    assert_eq!(
+        outcome.reject.as_deref(),
+        Some("Invoices go to accounting@alvinjanuar.com,\r\n.please send them there.\r\n")
+    );
+    assert_eq!(
+        run(script, "personal.eml", "bob@example.com"),
+        Outcome {
+            redirect: vec![
+                "bob-mail@alvinjanuar.com".to_string(),
+                "archive@alvinjanuar.com".to_string()
+            ],
+            ..kept(&[])
+        }
+    );
+
+    let raw = fixture("invoice.eml");
+    let message = Message::parse(&raw).unwrap();
+    let notice = rejection(
+        MAILBOX,
+        "billing@shop.example",
+        "No invoices here.",
+        &message,
+    );
+    let notice = Message::parse(&notice).unwrap();
+    assert_eq!(notice.subject(), Some("Rejected: Your invoice"));
+    assert_eq!(
+        notice.in_reply_to().as_text_ref(),
+        Some("invoice@shop.example")
+    );
+    assert!(notice.body_text(0).unwrap().contains("No invoices here."));
+}
+
+#[test]
+fn replies_while_on_vacation() {
+    let script = r#"
+        require "vacation";
+        vacation :days 90 :addresses "me@alvinjanuar.com" "I'm away until Monday.";
+    "#;
+
+    let outcome = run(script, "personal.eml", "bob@example.com");
+    assert!(outcome.keep);
+    let reply = outcome.vacation.unwrap();
+    assert_eq!(reply.to, "bob@example.com");
+    assert_eq!(reply.subject, "Auto: Lunch on Friday?");
+    assert_eq!(reply.days, 30);
+    // the handle stays the same while the script does
+    let again = run(script, "invoice.eml", "billing@shop.example");
+    assert_eq!(again.vacation.unwrap().handle, reply.handle);
+
+    // lists, robots and bounces aren't answered
+    assert_eq!(
+        run(script, "newsletter.eml", "news@example.com").vacation,
+        None
+    );
+    assert_eq!(
+        run(script, "auto-reply.eml", "carol@example.net").vacation,
+        None
+    );
+    assert_eq!(run(script, "personal.eml", "").vacation, None);
+    assert_eq!(
+        run(script, "personal.eml", "MAILER-DAEMON@example.com").vacation,
+        None
+    );
+
+    let raw = fixture("personal.eml");
+    let message = Message::parse(&raw).unwrap();
+    let reply = reply.message(MAILBOX, &message);
+    let reply = Message::parse(&reply).unwrap();
+    assert_eq!(reply.subject(), Some("Auto: Lunch on Friday?"));
+    assert_eq!(
+        reply.header_raw("Auto-Submitted").map(str::trim),
+        Some("auto-replied")
+    );
+    assert_eq!(
+        reply.references().as_text_list(),
+        Some(vec!["plans@example.com", "lunch@example.com"])
+    );
+    assert_eq!(reply.body_text(0).unwrap().trim(), "I'm away until Monday.");
+}
+
+#[test]
+fn replies_from_the_mailbox_or_its_addresses() {
+    let from = |from: &str| {
+        let script = format!(
+            "require \"vacation\";\n\
+             vacation :from \"{from}\" :addresses \"me@alvinjanuar.com\" \"Away.\";"
+        );
+        run(&script, "personal.eml", "bob@example.com")
+            .vacation
+            .unwrap()
+            .from
+    };
+    assert_eq!(
+        from("me@alvinjanuar.com").as_deref(),
+        Some("me@alvinjanuar.com")
+    );
+    assert_eq!(from(MAILBOX).as_deref(), Some(MAILBOX));
+    // anyone else is answered from the mailbox
+    assert_eq!(from("ceo@alvinjanuar.com"), None);
+}
+
+#[tokio::test]
+async fn only_saves_vacation_replies_from_senders_allowed() {
+    let state = common::state().await;
+    let alice = common::access(&state, "alice", "correct horse").await;
+    let bob = common::access(&state, "bob", "battery staple").await;
+    let script = |from: &str, addresses: &str| SieveScript {
+        script: format!(
+            "require \"vacation\";\n\
+             if true {{ vacation :from \"{from}\" :addresses [{addresses}] \"Away.\"; }}"
+        ),
+    };
+
+    let own = script(common::ALICE, &format!("\"{}\"", common::ALICE));
+    put_sieve(state.clone(), &alice, common::WEB.to_string(), own)
+        .await
+        .unwrap();
+    let elsewhere = script("ceo@alvinjanuar.com", "");
+    assert!(matches!(
+        put_sieve(state.clone(), &alice, common::WEB.to_string(), elsewhere).await,
+        Err(ApiError::InvalidParameter(_))
+    ));
+    let someone_elses = script(common::ALICE, &format!("\"{}\"", common::ALICE));
+    assert!(matches!(
+        put_sieve(state.clone(), &bob, common::BOB.to_string(), someone_elses).await,
+        Err(ApiError::Forbidden(_))
+    ));
+}
+
+#[test]
+fn reports_script_errors() {
+    let error = |script: &str| Script::parse(script).unwrap_err().to_string();
+
+    assert_eq!(
+        error("if true {\n  fileinto \"Reading\";\n}"),
+        "line 2: fileinto needs require \"fileinto\""
+    );
+    assert_eq!(
+        error("require \"body\";"),
+        "line 1: unsupported extension \"body\""
+    );
+    assert_eq!(
+        error("keep;\nfrobnicate;"),
+        "line 2: unknown command frobnicate"
+    );
+    assert_eq!(
+        error("keep;\nrequire \"fileinto\";"),
+        "line 2: require has to come before any other command"
+    );
+    assert_eq!(
+        error("keep;\nelse { keep; }"),
+        "line 2: else has to follow if or elsif"
+    );
+    assert_eq!(error("keep"), "line 1: expected \";\" after keep");
+    assert_eq!(
+        error("# open\nredirect \"bob@example.com;"),
+        "line 2: unterminated string"
+    );
+    assert_eq!(
+        error("if header :over \"subject\" \"x\" { keep; }"),
+        "line 1: header doesn't take :over"
+    );
+    assert_eq!(
+        error("redirect \"not an address\";"),
+        "line 1: \"not an address\" is not an address"
+    );
+
+    let check = validate_sieve("require \"vacation\";\nvacation :days 3 \"Away\";").unwrap();
+    assert!(check.valid);
+    let check = validate_sieve("/* never\nclosed").unwrap();
+    assert!(!check.valid);
+    assert_eq!(check.error.unwrap().line, 1);
+    assert!(validate_sieve(&"#".repeat(100_000)).is_err());
+}
+
+#[tokio::test]
+async fn stores_scripts_per_address() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    assert_eq!(store.get_sieve(MAILBOX).await.unwrap(), "");
+
+    let script = "require \"fileinto\";\nfileinto \"Reading\";\n";
+    store.put_sieve(MAILBOX, script).await.unwrap();
+    assert_eq!(store.get_sieve(MAILBOX).await.unwrap(), script);
+    assert_eq!(store.get_sieve("bob@example.com").await.unwrap(), "");
+
+    store.put_sieve(MAILBOX, "").await.unwrap();
+    assert_eq!(store.get_sieve(MAILBOX).await.unwrap(), "");
+}
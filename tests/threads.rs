@@ -0,0 +1,133 @@
+mod common;
+
+use common::WEB as MAILBOX;
+use mail_parser::Message;
+use supermailer::api_types::Mail;
+use supermailer::store::local::{FsMailStore, SqliteMetadataStore};
+use supermailer::store::{MetadataStore, Page};
+use supermailer_core::parse::{normalize_subject, thread_id};
+
+const ORIGINAL: &str = "From: bob@example.com\r\n\
+To: web@alvinjanuar.com\r\n\
+Subject: Lunch on Friday\r\n\
+Date: Fri, 16 Oct 2026 09:30:00 +0000\r\n\
+Message-ID: <lunch@example.com>\r\n\
+\r\n\
+Are you free?\r\n";
+
+const HEADERLESS_REPLY: &str = "From: bob@example.com\r\n\
+To: web@alvinjanuar.com\r\n\
+Subject: RE: Fwd:  lunch on   friday\r\n\
+Date: Sat, 17 Oct 2026 09:30:00 +0000\r\n\
+Message-ID: <again@example.com>\r\n\
+\r\n\
+Still free?\r\n";
+
+fn thread_of(message: &str) -> String {
+    thread_id(&Message::parse(message.as_bytes()).unwrap())
+}
+
+fn mail(message_id: &str, sk: i64, subject: &str, thread_id: &str) -> Mail {
+    Mail {
+        subject: subject.to_string(),
+        thread_id: thread_id.to_string(),
+        ..common::mail(MAILBOX, message_id, sk)
+    }
+}
+
+async fn threads(store: &SqliteMetadataStore) -> Vec<(String, i64)> {
+    let page = Page {
+        cursor: None,
+        limit: 10,
+    };
+    let threads = store.list_threads(MAILBOX, page).await.unwrap();
+    threads
+        .items
+        .into_iter()
+        .map(|thread| (thread.thread_id, thread.message_count))
+        .collect()
+}
+
+#[test]
+fn threads_by_the_root_of_the_references() {
+    let reply = "Subject: Re: Lunch\r\n\
+        Message-ID: <reply@example.com>\r\n\
+        In-Reply-To: <second@example.com>\r\n\
+        References: <first@example.com> <second@example.com>\r\n\
+        \r\n\
+        Sure\r\n";
+    assert_eq!(thread_of(reply), "<first@example.com>");
+}
+
+#[test]
+fn threads_by_the_mail_answered_without_references() {
+    let reply = "Subject: Re: Lunch\r\n\
+        Message-ID: <reply@example.com>\r\n\
+        In-Reply-To: <lunch@example.com>\r\n\
+        \r\n\
+        Sure\r\n";
+    assert_eq!(thread_of(reply), "<lunch@example.com>");
+}
+
+#[test]
+fn starts_a_thread_of_its_own() {
+    assert_eq!(thread_of(ORIGINAL), "<lunch@example.com>");
+}
+
+#[test]
+fn threads_replies_without_headers_by_subject() {
+    assert_eq!(thread_of(HEADERLESS_REPLY), "subject:lunch on friday");
+    assert_eq!(normalize_subject(" Re: AW: sv:FW: Lunch "), "lunch");
+    assert_eq!(normalize_subject("Regarding lunch"), "regarding lunch");
+}
+
+#[tokio::test]
+async fn joins_replies_without_headers_to_what_they_answer() {
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    let original = "<lunch@example.com>";
+    store
+        .put_email(&mail("a", 1, "Lunch on Friday", original))
+        .await
+        .unwrap();
+    store
+        .put_email(&mail(
+            "b",
+            2,
+            "Re: lunch on friday",
+            "subject:lunch on friday",
+        ))
+        .await
+        .unwrap();
+    // a new conversation that happens to share nothing with it stays apart
+    store
+        .put_email(&mail("c", 3, "Re: Dinner", "subject:dinner"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        threads(&store).await,
+        vec![("subject:dinner".to_string(), 1), (original.to_string(), 2)]
+    );
+    let thread = store.list_thread(MAILBOX, original).await.unwrap();
+    let ids: Vec<&str> = thread.iter().map(|mail| mail.message_id.as_str()).collect();
+    assert_eq!(ids, ["a", "b"]);
+}
+
+#[tokio::test]
+async fn joins_replies_indexed_before_what_they_answer() {
+    let dir = std::env::temp_dir().join(format!("supermailer-threads-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    // read back in whatever order the directory lists them
+    std::fs::write(dir.join("a-reply.eml"), HEADERLESS_REPLY).unwrap();
+    std::fs::write(dir.join("b-original.eml"), ORIGINAL).unwrap();
+
+    let store = SqliteMetadataStore::open(":memory:").unwrap();
+    let indexed = store.index(&FsMailStore::new(&dir)).await.unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(indexed, 2);
+    assert_eq!(
+        threads(&store).await,
+        vec![("<lunch@example.com>".to_string(), 2)]
+    );
+}